@@ -11,6 +11,8 @@ use bitcoin::{
 
 use super::constants::NETWORK;
 
+pub mod silent_payment;
+
 type AddressMapping = Box<(dyn Fn(&Secp256k1<All>, ExtendedPubKey) -> Address)>;
 type DeriveKeyMapping = Box<(dyn Fn(u32, u32) -> ExtendedPrivKey)>;
 
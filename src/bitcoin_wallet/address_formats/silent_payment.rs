@@ -0,0 +1,904 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use bitcoin::{
+    bech32::{self, FromBase32, ToBase32, Variant},
+    blockdata::script::Instruction,
+    consensus::encode::serialize,
+    hashes::{hash160, Hash},
+    secp256k1::{All, PublicKey, Scalar, Secp256k1, SecretKey},
+    Network, OutPoint, Script, Transaction, TxIn, TxOut, XOnlyPublicKey,
+};
+
+use crate::bitcoin_wallet::{
+    constants::nums_x_only,
+    schnorr::{ecdh::even_secret, lift_x, tagged_hash},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentError {
+    Bech32(bech32::Error),
+    UnknownHrp(String),
+    Version(u8),
+    InvalidKey,
+    // no input the receiver could take a public key from
+    NoEligibleInputs,
+    // the input keys or the input hash add up to zero, nobody can pay or scan this transaction
+    Degenerate,
+    // a sender input that is not among the transaction's outpoints
+    MissingOutpoint(OutPoint),
+    InsufficientFunds { balance: u64, fee: u64 },
+}
+
+impl fmt::Display for SilentPaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SilentPaymentError::Bech32(err) => write!(f, "{}", err),
+            SilentPaymentError::UnknownHrp(hrp) => write!(f, "{} is not a silent payment hrp", hrp),
+            SilentPaymentError::Version(version) => {
+                write!(f, "silent payment version {} is not supported", version)
+            }
+            SilentPaymentError::InvalidKey => write!(f, "address does not hold two valid keys"),
+            SilentPaymentError::NoEligibleInputs => {
+                write!(f, "transaction has no input a silent payment can use")
+            }
+            SilentPaymentError::Degenerate => {
+                write!(f, "input keys add up to the point at infinity")
+            }
+            SilentPaymentError::MissingOutpoint(outpoint) => {
+                write!(f, "input {} is not spent by the transaction", outpoint)
+            }
+            SilentPaymentError::InsufficientFunds { balance, fee } => write!(
+                f,
+                "balance of {} sats does not cover the fee of {}",
+                balance, fee
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SilentPaymentError {}
+
+impl From<bech32::Error> for SilentPaymentError {
+    fn from(err: bech32::Error) -> Self {
+        SilentPaymentError::Bech32(err)
+    }
+}
+
+// testnet and signet share "tsp", so a signet address parses back as a testnet one, compare
+// addresses with same_chain rather than == when that matters
+fn hrp(network: Network) -> &'static str {
+    return match network {
+        Network::Bitcoin => "sp",
+        Network::Testnet | Network::Signet => "tsp",
+        Network::Regtest => "sprt",
+    };
+}
+
+// sp1q... version 0 followed by the scan key and the (labeled) spend key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentAddress {
+    pub scan: PublicKey,
+    pub spend: PublicKey,
+    pub network: Network,
+}
+
+impl SilentPaymentAddress {
+    // the keys match and the network encodes to the same hrp
+    pub fn same_chain(&self, other: &SilentPaymentAddress) -> bool {
+        return self.scan == other.scan
+            && self.spend == other.spend
+            && hrp(self.network) == hrp(other.network);
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys = [self.scan.serialize(), self.spend.serialize()].concat();
+        let mut data = vec![bech32::u5::try_from_u8(0).unwrap()];
+        data.extend(keys.to_base32());
+        let encoded =
+            bech32::encode(hrp(self.network), data, Variant::Bech32m).map_err(|_| fmt::Error)?;
+        write!(f, "{}", encoded)
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = SilentPaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, data, variant) = bech32::decode(s)?;
+        let network = match hrp.as_str() {
+            "sp" => Network::Bitcoin,
+            "tsp" => Network::Testnet,
+            "sprt" => Network::Regtest,
+            _ => return Err(SilentPaymentError::UnknownHrp(hrp)),
+        };
+        let (version, data) = data.split_first().ok_or(SilentPaymentError::InvalidKey)?;
+        if variant != Variant::Bech32m {
+            return Err(SilentPaymentError::Bech32(bech32::Error::InvalidChecksum));
+        }
+        let keys = Vec::<u8>::from_base32(data)?;
+        // version 0 is exactly the two keys, later versions may append data we don't read yet,
+        // version 31 is kept for a change that isn't backwards compatible
+        let keys = match version.to_u8() {
+            0 if keys.len() == 66 => &keys[..],
+            1..=30 if keys.len() >= 66 => &keys[..66],
+            0..=30 => return Err(SilentPaymentError::InvalidKey),
+            version => return Err(SilentPaymentError::Version(version)),
+        };
+        let key =
+            |bytes: &[u8]| PublicKey::from_slice(bytes).map_err(|_| SilentPaymentError::InvalidKey);
+        return Ok(SilentPaymentAddress {
+            scan: key(&keys[..33])?,
+            spend: key(&keys[33..])?,
+            network,
+        });
+    }
+}
+
+fn scalar(bytes: [u8; 32]) -> Result<Scalar, SilentPaymentError> {
+    return Scalar::from_be_bytes(bytes).map_err(|_| SilentPaymentError::Degenerate);
+}
+
+// hash_BIP0352/Inputs(outpoint_L || A), outpoint_L is the smallest serialized outpoint of all inputs
+pub fn input_hash(
+    outpoints: &[OutPoint],
+    input_key: &PublicKey,
+) -> Result<Scalar, SilentPaymentError> {
+    let smallest = outpoints
+        .iter()
+        .map(serialize)
+        .min()
+        .ok_or(SilentPaymentError::NoEligibleInputs)?;
+    return scalar(tagged_hash(
+        "BIP0352/Inputs",
+        &[&smallest, &input_key.serialize()],
+    ));
+}
+
+// t_k = hash_BIP0352/SharedSecret(ser_P(ecdh_shared_secret) || ser_32(k))
+fn shared_tweak(shared_secret: &PublicKey, k: u32) -> Result<Scalar, SilentPaymentError> {
+    return scalar(tagged_hash(
+        "BIP0352/SharedSecret",
+        &[&shared_secret.serialize(), &k.to_be_bytes()],
+    ));
+}
+
+pub fn label_tweak(scan_secret: &SecretKey, m: u32) -> Result<Scalar, SilentPaymentError> {
+    return scalar(tagged_hash(
+        "BIP0352/Label",
+        &[&scan_secret.secret_bytes(), &m.to_be_bytes()],
+    ));
+}
+
+// a key the sender spends with, taproot keys are x only so their secret may get negated
+#[derive(Debug, Clone, Copy)]
+pub struct SenderInput {
+    pub outpoint: OutPoint,
+    pub secret_key: SecretKey,
+    pub taproot: bool,
+}
+
+// one taproot output key per recipient in the order given, recipients sharing a scan key count
+// k up together, outpoints are those of every input of the transaction, the ones that have no
+// key for silent payments as well
+pub fn sender_outputs(
+    secp: &Secp256k1<All>,
+    inputs: &[SenderInput],
+    outpoints: &[OutPoint],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<XOnlyPublicKey>, SilentPaymentError> {
+    if let Some(input) = inputs
+        .iter()
+        .find(|input| !outpoints.contains(&input.outpoint))
+    {
+        return Err(SilentPaymentError::MissingOutpoint(input.outpoint));
+    }
+    let (first, rest) = inputs
+        .split_first()
+        .ok_or(SilentPaymentError::NoEligibleInputs)?;
+    let secret = |input: &SenderInput| match input.taproot {
+        true => even_secret(secp, &input.secret_key),
+        false => input.secret_key,
+    };
+    let input_secret = rest.iter().try_fold(secret(first), |sum, input| {
+        return sum
+            .add_tweak(&Scalar::from(secret(input)))
+            .map_err(|_| SilentPaymentError::Degenerate);
+    })?;
+    let input_hash = input_hash(outpoints, &input_secret.public_key(secp))?;
+    let ecdh_secret = input_secret
+        .mul_tweak(&input_hash)
+        .map_err(|_| SilentPaymentError::Degenerate)?;
+
+    let mut counters: BTreeMap<PublicKey, u32> = BTreeMap::new();
+    return recipients
+        .iter()
+        .map(|recipient| {
+            let k = counters.entry(recipient.scan).or_insert(0);
+            let shared_secret = recipient
+                .scan
+                .mul_tweak(secp, &Scalar::from(ecdh_secret))
+                .map_err(|_| SilentPaymentError::Degenerate)?;
+            let output = recipient
+                .spend
+                .add_exp_tweak(secp, &shared_tweak(&shared_secret, *k)?)
+                .map_err(|_| SilentPaymentError::Degenerate)?;
+            *k += 1;
+            return Ok(output.x_only_public_key().0);
+        })
+        .collect();
+}
+
+// the key a receiver takes from an input, none for inputs silent payments skip
+pub fn input_public_key(tx_in: &TxIn, prevout: &TxOut) -> Option<PublicKey> {
+    let script_pubkey = &prevout.script_pubkey;
+    let compressed = |bytes: &[u8]| match bytes.len() {
+        33 => PublicKey::from_slice(bytes).ok(),
+        _ => None,
+    };
+    if script_pubkey.is_v1_p2tr() {
+        let mut witness = tx_in.witness.to_vec();
+        if witness.len() > 1 && witness.last().map(|item| item.first()) == Some(Some(&0x50)) {
+            witness.pop();
+        }
+        // a script path spend with the NUMS internal key has no key anyone knows
+        if witness.len() > 1 {
+            let control_block = witness.last()?;
+            if control_block.len() >= 33 && control_block[1..33] == nums_x_only().serialize() {
+                return None;
+            }
+        }
+        let x_only = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..34]).ok()?;
+        return Some(lift_x(&x_only));
+    }
+    let hash_matches = |bytes: &[u8], hash: &[u8]| hash160::Hash::hash(bytes)[..] == *hash;
+    if script_pubkey.is_v0_p2wpkh() {
+        return compressed(tx_in.witness.last()?);
+    }
+    if script_pubkey.is_p2sh() {
+        let redeem_script = match tx_in.script_sig.instructions().last()? {
+            Ok(Instruction::PushBytes(bytes)) => Script::from(bytes.to_vec()),
+            _ => return None,
+        };
+        if !redeem_script.is_v0_p2wpkh() {
+            return None;
+        }
+        let key = tx_in.witness.last()?;
+        return compressed(key).filter(|_| hash_matches(key, &redeem_script.as_bytes()[2..]));
+    }
+    if script_pubkey.is_p2pkh() {
+        // the key is the last push whose hash the output commits to
+        let hash = &script_pubkey.as_bytes()[3..23];
+        return tx_in
+            .script_sig
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes),
+                _ => None,
+            })
+            .filter(|bytes| hash_matches(bytes, hash))
+            .last()
+            .and_then(compressed);
+    }
+    return None;
+}
+
+// an output found while scanning, the tweak added to the spend secret gives its key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedOutput {
+    pub outpoint: OutPoint,
+    pub tx_out: TxOut,
+    pub tweak: SecretKey,
+    pub label: Option<u32>,
+}
+
+pub struct SilentPaymentReceiver {
+    scan_secret: SecretKey,
+    spend_secret: SecretKey,
+    network: Network,
+    // label point -> m
+    labels: BTreeMap<PublicKey, u32>,
+}
+
+impl SilentPaymentReceiver {
+    pub fn new(scan_secret: SecretKey, spend_secret: SecretKey, network: Network) -> Self {
+        return SilentPaymentReceiver {
+            scan_secret,
+            spend_secret,
+            network,
+            labels: BTreeMap::new(),
+        };
+    }
+
+    // label 0 is the one BIP352 keeps for change
+    pub fn add_label(&mut self, secp: &Secp256k1<All>, m: u32) -> Result<(), SilentPaymentError> {
+        let point = PublicKey::from_secret_key(
+            secp,
+            &SecretKey::from_slice(&label_tweak(&self.scan_secret, m)?.to_be_bytes())
+                .map_err(|_| SilentPaymentError::Degenerate)?,
+        );
+        self.labels.insert(point, m);
+        return Ok(());
+    }
+
+    pub fn address(
+        &self,
+        secp: &Secp256k1<All>,
+        label: Option<u32>,
+    ) -> Result<SilentPaymentAddress, SilentPaymentError> {
+        let spend = self.spend_secret.public_key(secp);
+        let spend = match label {
+            Some(m) => spend
+                .add_exp_tweak(secp, &label_tweak(&self.scan_secret, m)?)
+                .map_err(|_| SilentPaymentError::Degenerate)?,
+            None => spend,
+        };
+        return Ok(SilentPaymentAddress {
+            scan: self.scan_secret.public_key(secp),
+            spend,
+            network: self.network,
+        });
+    }
+
+    // prevouts line up with the transaction's inputs
+    pub fn scan(
+        &self,
+        secp: &Secp256k1<All>,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+    ) -> Result<Vec<ReceivedOutput>, SilentPaymentError> {
+        // inputs of a witness version above 1 make the transaction unusable for now
+        let unknown_version = prevouts.iter().any(|tx_out| {
+            return tx_out
+                .script_pubkey
+                .witness_version()
+                .map(|version| version.to_num() > 1)
+                == Some(true);
+        });
+        if unknown_version || prevouts.len() != tx.input.len() {
+            return Err(SilentPaymentError::NoEligibleInputs);
+        }
+        let keys = tx
+            .input
+            .iter()
+            .zip(prevouts)
+            .filter_map(|(tx_in, prevout)| input_public_key(tx_in, prevout))
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(SilentPaymentError::NoEligibleInputs);
+        }
+        let input_key = PublicKey::combine_keys(&keys.iter().collect::<Vec<_>>())
+            .map_err(|_| SilentPaymentError::Degenerate)?;
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|tx_in| tx_in.previous_output)
+            .collect::<Vec<_>>();
+        let input_hash = input_hash(&outpoints, &input_key)?;
+        let ecdh_secret = self
+            .scan_secret
+            .mul_tweak(&input_hash)
+            .map_err(|_| SilentPaymentError::Degenerate)?;
+        let shared_secret = input_key
+            .mul_tweak(secp, &Scalar::from(ecdh_secret))
+            .map_err(|_| SilentPaymentError::Degenerate)?;
+
+        let spend = self.spend_secret.public_key(secp);
+        let mut unmatched = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, tx_out)| tx_out.script_pubkey.is_v1_p2tr())
+            .collect::<Vec<_>>();
+        let mut received = vec![];
+        for k in 0.. {
+            let tweak = shared_tweak(&shared_secret, k)?;
+            let output_key = spend
+                .add_exp_tweak(secp, &tweak)
+                .map_err(|_| SilentPaymentError::Degenerate)?;
+            let found = unmatched
+                .iter()
+                .enumerate()
+                .find_map(|(position, (_, tx_out))| {
+                    let x_only =
+                        XOnlyPublicKey::from_slice(&tx_out.script_pubkey.as_bytes()[2..34]).ok()?;
+                    let t_k = SecretKey::from_slice(&tweak.to_be_bytes()).ok()?;
+                    if x_only == output_key.x_only_public_key().0 {
+                        return Some((position, t_k, None));
+                    }
+                    // output - P_k, or -output - P_k for an odd y, is one of our labels
+                    let output = lift_x(&x_only);
+                    let label = [output, output.negate(secp)].iter().find_map(|point| {
+                        let diff = point.combine(&output_key.negate(secp)).ok()?;
+                        return self.labels.get(&diff).copied();
+                    })?;
+                    let label_tweak = label_tweak(&self.scan_secret, label).ok()?;
+                    return Some((position, t_k.add_tweak(&label_tweak).ok()?, Some(label)));
+                });
+            let (position, tweak, label) = match found {
+                Some(found) => found,
+                None => break,
+            };
+            let (vout, tx_out) = unmatched.remove(position);
+            received.push(ReceivedOutput {
+                outpoint: OutPoint::new(tx.txid(), vout as u32),
+                tx_out: tx_out.clone(),
+                tweak,
+                label,
+            });
+        }
+        return Ok(received);
+    }
+
+    // b_spend + t_k (+ label tweak), the output key itself with no taproot tweak on top
+    pub fn spend_key(&self, received: &ReceivedOutput) -> Result<SecretKey, SilentPaymentError> {
+        return self
+            .spend_secret
+            .add_tweak(&Scalar::from(received.tweak))
+            .map_err(|_| SilentPaymentError::Degenerate);
+    }
+}
+
+// the silent payment output key goes straight into the script, it is not tweaked again
+pub fn output_script(output_key: &XOnlyPublicKey) -> Script {
+    return Script::new_v1_p2tr_tweaked(
+        bitcoin::util::schnorr::TweakedPublicKey::dangerous_assume_tweaked(*output_key),
+    );
+}
+
+#[test]
+fn inputs_silent_payments_can_and_cannot_use() {
+    use bitcoin::{blockdata::script::Builder, Witness};
+
+    let secp = Secp256k1::new();
+    let secret = SecretKey::from_slice(&[65u8; 32]).unwrap();
+    let key = bitcoin::PublicKey::new(secret.public_key(&secp));
+    let x_only = secret.x_only_public_key(&secp).0;
+    let tx_in = |script_sig: Script, witness: Vec<Vec<u8>>| TxIn {
+        script_sig,
+        witness: Witness::from_vec(witness),
+        ..Default::default()
+    };
+    let prevout = |script_pubkey: Script| TxOut {
+        value: 10_000,
+        script_pubkey,
+    };
+    let sig = vec![1u8; 64];
+
+    let tr = prevout(output_script(&x_only));
+    assert_eq!(
+        input_public_key(&tx_in(Script::new(), vec![sig.clone()]), &tr),
+        Some(lift_x(&x_only))
+    );
+    // an annex doesn't make the key path a script path
+    assert_eq!(
+        input_public_key(&tx_in(Script::new(), vec![sig.clone(), vec![0x50, 1]]), &tr),
+        Some(lift_x(&x_only))
+    );
+    let nums_control = [&[0xc0][..], &nums_x_only().serialize()].concat();
+    assert_eq!(
+        input_public_key(
+            &tx_in(Script::new(), vec![sig.clone(), vec![0x51], nums_control]),
+            &tr
+        ),
+        None
+    );
+
+    let wpkh = Script::new_v0_p2wpkh(&key.wpubkey_hash().unwrap());
+    let witness = vec![vec![2u8; 71], key.to_bytes()];
+    assert_eq!(
+        input_public_key(
+            &tx_in(Script::new(), witness.clone()),
+            &prevout(wpkh.clone())
+        ),
+        Some(key.inner)
+    );
+    let nested = Builder::new().push_slice(wpkh.as_bytes()).into_script();
+    assert_eq!(
+        input_public_key(
+            &tx_in(nested, witness),
+            &prevout(Script::new_p2sh(&wpkh.script_hash()))
+        ),
+        Some(key.inner)
+    );
+    let pkh_sig = Builder::new()
+        .push_slice(&[2u8; 71])
+        .push_key(&key)
+        .into_script();
+    assert_eq!(
+        input_public_key(
+            &tx_in(pkh_sig, vec![]),
+            &prevout(Script::new_p2pkh(&key.pubkey_hash()))
+        ),
+        Some(key.inner)
+    );
+    // uncompressed keys are never used
+    let uncompressed = bitcoin::PublicKey::new_uncompressed(key.inner);
+    let pkh_sig = Builder::new()
+        .push_slice(&[2u8; 71])
+        .push_key(&uncompressed)
+        .into_script();
+    assert_eq!(
+        input_public_key(
+            &tx_in(pkh_sig, vec![]),
+            &prevout(Script::new_p2pkh(&uncompressed.pubkey_hash()))
+        ),
+        None
+    );
+
+    // the smallest serialized outpoint goes into the input hash, not the first input
+    let txid = |byte: u8| bitcoin::Txid::from_slice(&[byte; 32]).unwrap();
+    let first = OutPoint::new(txid(9), 0);
+    let smallest = OutPoint::new(txid(1), 3);
+    let hash = |outpoints: &[OutPoint]| input_hash(outpoints, &key.inner).unwrap().to_be_bytes();
+    assert_eq!(hash(&[first, smallest]), hash(&[smallest]));
+    assert_ne!(hash(&[first]), hash(&[smallest]));
+
+    // longer than the 90 characters plain bech32 allows
+    let address = SilentPaymentAddress {
+        scan: key.inner,
+        spend: key.inner,
+        network: Network::Bitcoin,
+    };
+    assert!(address.to_string().len() > 90);
+    assert_eq!(address.to_string().parse(), Ok(address));
+    let signet = SilentPaymentAddress {
+        network: Network::Signet,
+        ..address
+    };
+    let parsed = signet.to_string().parse::<SilentPaymentAddress>().unwrap();
+    assert_eq!(parsed.network, Network::Testnet);
+    assert!(parsed.same_chain(&signet));
+    assert!(!parsed.same_chain(&address));
+
+    // later versions keep the two keys up front and may append more, version 31 is not ours
+    let encode = |version: u8, data: &[u8]| {
+        let mut base32 = vec![bech32::u5::try_from_u8(version).unwrap()];
+        base32.extend(data.to_base32());
+        return bech32::encode("sp", base32, Variant::Bech32m).unwrap();
+    };
+    let keys = [key.inner.serialize(), key.inner.serialize()].concat();
+    let longer = [&keys[..], &[7u8; 10]].concat();
+    assert_eq!(encode(1, &longer).parse(), Ok(address));
+    assert_eq!(encode(30, &keys).parse(), Ok(address));
+    assert_eq!(
+        encode(0, &longer).parse::<SilentPaymentAddress>(),
+        Err(SilentPaymentError::InvalidKey)
+    );
+    assert_eq!(
+        encode(1, &keys[..65]).parse::<SilentPaymentAddress>(),
+        Err(SilentPaymentError::InvalidKey)
+    );
+    assert_eq!(
+        encode(31, &keys).parse::<SilentPaymentAddress>(),
+        Err(SilentPaymentError::Version(31))
+    );
+}
+
+// "Simple send: two inputs" from the BIP352 send and receive vectors
+#[test]
+fn bip352_simple_send_vector() {
+    use bitcoin::{blockdata::script::Builder, hashes::hex::FromHex, PackedLockTime, Txid};
+
+    let secp = Secp256k1::new();
+    let secret = |hex: &str| SecretKey::from_str(hex).unwrap();
+    let inputs = [
+        SenderInput {
+            outpoint: OutPoint::new(
+                Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16")
+                    .unwrap(),
+                0,
+            ),
+            secret_key: secret("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1"),
+            taproot: false,
+        },
+        SenderInput {
+            outpoint: OutPoint::new(
+                Txid::from_str("a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d")
+                    .unwrap(),
+                0,
+            ),
+            secret_key: secret("93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16"),
+            taproot: false,
+        },
+    ];
+    let address: SilentPaymentAddress = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv"
+        .parse()
+        .unwrap();
+    let expected = XOnlyPublicKey::from_str(
+        "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+    )
+    .unwrap();
+    let outpoints = inputs.map(|input| input.outpoint);
+    assert_eq!(
+        sender_outputs(&secp, &inputs, &outpoints, &[address]).unwrap(),
+        vec![expected]
+    );
+    // the order of the inputs doesn't matter
+    let reversed = [inputs[1], inputs[0]];
+    assert_eq!(
+        sender_outputs(&secp, &reversed, &outpoints, &[address]).unwrap(),
+        vec![expected]
+    );
+
+    let receiver = SilentPaymentReceiver::new(
+        secret("0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c"),
+        secret("9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3"),
+        Network::Bitcoin,
+    );
+    assert_eq!(receiver.address(&secp, None), Ok(address));
+    // p2pkh inputs, the receiver reads each key from its script_sig
+    let (tx_in, prevouts): (Vec<TxIn>, Vec<TxOut>) = inputs
+        .iter()
+        .map(|input| {
+            let key = bitcoin::PublicKey::new(input.secret_key.public_key(&secp));
+            let tx_in = TxIn {
+                previous_output: input.outpoint,
+                script_sig: Builder::new()
+                    .push_slice(&[2u8; 71])
+                    .push_key(&key)
+                    .into_script(),
+                ..Default::default()
+            };
+            let prevout = TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_p2pkh(&key.pubkey_hash()),
+            };
+            return (tx_in, prevout);
+        })
+        .unzip();
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: tx_in,
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: output_script(&expected),
+        }],
+    };
+    let received = receiver.scan(&secp, &tx, &prevouts).unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(
+        received[0].tweak.secret_bytes().to_vec(),
+        Vec::<u8>::from_hex("f438b40179a3c4262de12986c0e6cce0634007cdc79c1dcd3e20b9ebc2e7eef6")
+            .unwrap()
+    );
+
+    // an input without a usable key still counts for the smallest outpoint
+    let smallest = OutPoint::new(Txid::from_slice(&[0u8; 32]).unwrap(), 0);
+    assert_ne!(
+        sender_outputs(
+            &secp,
+            &inputs,
+            &[outpoints[0], outpoints[1], smallest],
+            &[address]
+        )
+        .unwrap(),
+        vec![expected]
+    );
+    assert_eq!(
+        sender_outputs(&secp, &inputs, &[outpoints[0]], &[address]),
+        Err(SilentPaymentError::MissingOutpoint(outpoints[1]))
+    );
+}
+
+// the two inputs of the simple send vector, as p2pkh or as taproot key path spends
+#[cfg(test)]
+fn bip352_vector_tx(
+    secp: &Secp256k1<All>,
+    inputs: &[SenderInput],
+    output_keys: &[XOnlyPublicKey],
+) -> (Transaction, Vec<TxOut>) {
+    use bitcoin::{blockdata::script::Builder, PackedLockTime, Witness};
+
+    let (tx_in, prevouts): (Vec<TxIn>, Vec<TxOut>) = inputs
+        .iter()
+        .map(|input| {
+            let (script_sig, witness, script_pubkey) = match input.taproot {
+                true => (
+                    Script::new(),
+                    Witness::from_vec(vec![vec![1u8; 64]]),
+                    output_script(&input.secret_key.x_only_public_key(secp).0),
+                ),
+                false => {
+                    let key = bitcoin::PublicKey::new(input.secret_key.public_key(secp));
+                    (
+                        Builder::new()
+                            .push_slice(&[2u8; 71])
+                            .push_key(&key)
+                            .into_script(),
+                        Witness::default(),
+                        Script::new_p2pkh(&key.pubkey_hash()),
+                    )
+                }
+            };
+            let tx_in = TxIn {
+                previous_output: input.outpoint,
+                script_sig,
+                witness,
+                ..Default::default()
+            };
+            return (
+                tx_in,
+                TxOut {
+                    value: 10_000,
+                    script_pubkey,
+                },
+            );
+        })
+        .unzip();
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: tx_in,
+        output: output_keys
+            .iter()
+            .map(|output_key| TxOut {
+                value: 10_000,
+                script_pubkey: output_script(output_key),
+            })
+            .collect(),
+    };
+    return (tx, prevouts);
+}
+
+#[cfg(test)]
+fn bip352_vector_input(txid: &str, secret_key: &str, taproot: bool) -> SenderInput {
+    return SenderInput {
+        outpoint: OutPoint::new(bitcoin::Txid::from_str(txid).unwrap(), 0),
+        secret_key: SecretKey::from_str(secret_key).unwrap(),
+        taproot,
+    };
+}
+
+#[cfg(test)]
+fn bip352_vector_receiver() -> SilentPaymentReceiver {
+    return SilentPaymentReceiver::new(
+        SecretKey::from_str("0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c")
+            .unwrap(),
+        SecretKey::from_str("9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3")
+            .unwrap(),
+        Network::Bitcoin,
+    );
+}
+
+// "Single recipient: taproot only inputs with even y-values" and "Single recipient: taproot only
+// with mixed even/odd y-values" from the BIP352 send and receive vectors
+#[test]
+fn bip352_taproot_input_vectors() {
+    let secp = Secp256k1::new();
+    let first = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+    let second = "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d";
+    let even = "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1";
+    let receiver = bip352_vector_receiver();
+    let address = receiver.address(&secp, None).unwrap();
+
+    for (other, odd, expected) in [
+        (
+            "fc8716a97a48ba9a05a98ae47b5cd201a25a7fd5d8b73c203c5f7b6b6b3b6ad7",
+            false,
+            "de88bea8e7ffc9ce1af30d1132f910323c505185aec8eae361670421e749a1fb",
+        ),
+        (
+            "1d37787c2b7116ee983e9f9c13269df29091b391c04db94239e0d2bc2182c3bf",
+            true,
+            "77cab7dd12b10259ee82c6ea4b509774e33e7078e7138f568092241bf26b99f1",
+        ),
+    ] {
+        let inputs = [
+            bip352_vector_input(first, even, true),
+            bip352_vector_input(second, other, true),
+        ];
+        let parity = inputs[1].secret_key.x_only_public_key(&secp).1;
+        assert_eq!(parity == bitcoin::secp256k1::Parity::Odd, odd);
+        let expected = XOnlyPublicKey::from_str(expected).unwrap();
+        let outpoints = inputs.map(|input| input.outpoint);
+        assert_eq!(
+            sender_outputs(&secp, &inputs, &outpoints, &[address]).unwrap(),
+            vec![expected]
+        );
+
+        let (tx, prevouts) = bip352_vector_tx(&secp, &inputs, &[expected]);
+        let received = receiver.scan(&secp, &tx, &prevouts).unwrap();
+        assert_eq!(received.len(), 1);
+        let spend_key = receiver.spend_key(&received[0]).unwrap();
+        assert_eq!(spend_key.x_only_public_key(&secp).0, expected);
+    }
+}
+
+// the keys and inputs of the simple send vector, the expected output keys were computed with a
+// separate implementation of BIP352 that reproduces the official vectors above, they are not
+// taken from the BIP352 vector file
+#[test]
+fn bip352_labels_shared_scan_key_and_skipped_inputs() {
+    use bitcoin::{Txid, Witness};
+
+    let secp = Secp256k1::new();
+    let inputs = [
+        bip352_vector_input(
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+            "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+            false,
+        ),
+        bip352_vector_input(
+            "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+            "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+            false,
+        ),
+    ];
+    let outpoints = inputs.map(|input| input.outpoint);
+    let mut receiver = bip352_vector_receiver();
+    receiver.add_label(&secp, 2).unwrap();
+    let address = receiver.address(&secp, None).unwrap();
+    let labelled = receiver.address(&secp, Some(2)).unwrap();
+    assert_eq!(
+        label_tweak(&receiver.scan_secret, 2).unwrap().to_be_bytes(),
+        SecretKey::from_str("1e06e1749870cac2eda06a04a54fc4edba17cba9377ead25a69b6306711ea9f8")
+            .unwrap()
+            .secret_bytes()
+    );
+
+    // both addresses share the scan key, so the labelled one is paid with k = 1
+    let expected = [
+        "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+        "9a5325fe7d5e63029301de7bcbb03947e1435df069c6570a74d77990110b02fd",
+    ]
+    .map(|hex| XOnlyPublicKey::from_str(hex).unwrap());
+    assert_eq!(
+        sender_outputs(&secp, &inputs, &outpoints, &[address, labelled]).unwrap(),
+        expected.to_vec()
+    );
+    let (tx, prevouts) = bip352_vector_tx(&secp, &inputs, &expected);
+    let received = receiver.scan(&secp, &tx, &prevouts).unwrap();
+    assert_eq!(
+        received
+            .iter()
+            .map(|received| received.label)
+            .collect::<Vec<_>>(),
+        vec![None, Some(2)]
+    );
+    for (received, expected) in received.iter().zip(expected) {
+        let spend_key = receiver.spend_key(received).unwrap();
+        assert_eq!(spend_key.x_only_public_key(&secp).0, expected);
+    }
+
+    // a script path spend with the NUMS internal key gives no key, its outpoint is still the
+    // smallest one
+    let nums_outpoint = OutPoint::new(Txid::all_zeros(), 0);
+    let expected = XOnlyPublicKey::from_str(
+        "01284857b56c8b9afcd36059c6d9e05e51c5bee162ae691021806d31114b8968",
+    )
+    .unwrap();
+    assert_eq!(
+        sender_outputs(
+            &secp,
+            &inputs,
+            &[outpoints[0], outpoints[1], nums_outpoint],
+            &[address]
+        )
+        .unwrap(),
+        vec![expected]
+    );
+    let (mut tx, mut prevouts) = bip352_vector_tx(&secp, &inputs, &[expected]);
+    let nums_control = [&[0xc0][..], &nums_x_only().serialize()].concat();
+    tx.input.push(TxIn {
+        previous_output: nums_outpoint,
+        witness: Witness::from_vec(vec![vec![1u8; 64], vec![0x51], nums_control]),
+        ..Default::default()
+    });
+    prevouts.push(TxOut {
+        value: 10_000,
+        script_pubkey: output_script(&nums_x_only()),
+    });
+    let received = receiver.scan(&secp, &tx, &prevouts).unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(
+        receiver
+            .spend_key(&received[0])
+            .unwrap()
+            .x_only_public_key(&secp)
+            .0,
+        expected
+    );
+}
@@ -63,10 +63,7 @@ pub fn script_demo() {
     };
     let psbt = create_partially_signed_tx(output_factory(), lock_func, unlock_func())(&api);
 
-    let tx = TapScriptSendEx::finialize_script(
-        psbt,
-        &keys[bob_secret].public_key().x_only_public_key().0,
-    );
+    let tx = TapScriptSendEx::finialize_script(psbt);
 
     let tx_id = api.transaction_broadcast(&tx);
     println!("tx broadcasted successfully tx hash: {}", tx_id)
@@ -1,4 +1,9 @@
-use bitcoin::secp256k1::{All, Secp256k1};
+use std::str::FromStr;
+
+use bitcoin::{
+    secp256k1::{All, Secp256k1},
+    XOnlyPublicKey,
+};
 
 use super::input_data::regtest_rpc::RegtestRpc;
 
@@ -7,6 +12,11 @@ pub const TIP: u64 = 2000;
 pub const MINE: u8 = 0;
 pub const SEED: &str = "1d454c6ab705f999d97e6465300a79a9595fb5ae1186ae20e33e12bea606c094";
 pub const LOG: bool = true;
+// BIP341 nothing up my sleeve point, H = lift_x(sha256(G)), nobody knows its discrete log
+pub const NUMS_POINT: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+pub fn nums_x_only() -> XOnlyPublicKey {
+    return XOnlyPublicKey::from_str(NUMS_POINT).unwrap();
+}
 pub fn secp() -> Secp256k1<All> {
     return Secp256k1::new();
 }
@@ -0,0 +1,195 @@
+use bitcoin::{OutPoint, Transaction, TxOut};
+use bitcoincore_rpc::{jsonrpc, Client, RpcApi};
+
+use super::{ChainFeed, FeedError};
+
+// bitcoind's "No such mempool or blockchain transaction"
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+// reads blocks and the mempool from bitcoind, prevouts of confirmed transactions are only found
+// with -txindex
+impl ChainFeed for Client {
+    fn tip_height(&self) -> Result<u32, FeedError> {
+        let height = self.get_block_count().map_err(feed_error)?;
+        return u32::try_from(height).map_err(|_| FeedError(format!("block height {}", height)));
+    }
+
+    fn block_transactions(&self, height: u32) -> Result<Vec<Transaction>, FeedError> {
+        let hash = self.get_block_hash(height as u64).map_err(feed_error)?;
+        return Ok(self.get_block(&hash).map_err(feed_error)?.txdata);
+    }
+
+    fn mempool_transactions(&self) -> Result<Vec<Transaction>, FeedError> {
+        return self
+            .get_raw_mempool()
+            .map_err(feed_error)?
+            .iter()
+            .filter_map(|txid| match self.get_raw_transaction(txid, None) {
+                Ok(tx) => Some(Ok(tx)),
+                // mined or evicted since the mempool was listed
+                Err(err) if is_unknown_tx(&err) => None,
+                Err(err) => Some(Err(feed_error(err))),
+            })
+            .collect();
+    }
+
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<TxOut>, FeedError> {
+        return match self.get_raw_transaction(&outpoint.txid, None) {
+            Ok(tx) => Ok(tx.output.get(outpoint.vout as usize).cloned()),
+            Err(err) if is_unknown_tx(&err) => Ok(None),
+            Err(err) => Err(feed_error(err)),
+        };
+    }
+}
+
+fn is_unknown_tx(err: &bitcoincore_rpc::Error) -> bool {
+    return matches!(
+        err,
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(rpc))
+            if rpc.code == RPC_INVALID_ADDRESS_OR_KEY
+    );
+}
+
+fn feed_error(err: bitcoincore_rpc::Error) -> FeedError {
+    return FeedError(err.to_string());
+}
+
+// answers the json rpc calls ChainFeed makes from fixed blocks and a fixed mempool, so the
+// client code runs against a socket without a node
+#[cfg(test)]
+pub fn fake_node(blocks: Vec<Vec<Transaction>>, mempool: Vec<Transaction>) -> Client {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use bitcoin::{
+        blockdata::block::{Block, BlockHeader},
+        consensus::encode::serialize_hex,
+        hashes::Hash,
+        BlockHash, TxMerkleNode,
+    };
+    use serde_json::{json, Value};
+
+    let blocks = blocks
+        .into_iter()
+        .enumerate()
+        .map(|(height, txdata)| Block {
+            header: BlockHeader {
+                version: 2,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: height as u32,
+                bits: 0x207fffff,
+                nonce: 0,
+            },
+            txdata,
+        })
+        .collect::<Vec<Block>>();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse::<usize>().unwrap();
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            let request: Value = serde_json::from_slice(&body).unwrap();
+
+            let param = |index: usize| request["params"][index].as_str().unwrap().to_string();
+            let known = blocks
+                .iter()
+                .flat_map(|block| block.txdata.iter())
+                .chain(mempool.iter());
+            let result = match request["method"].as_str().unwrap() {
+                "getblockcount" => Ok(json!(blocks.len() - 1)),
+                "getblockhash" => {
+                    let height = request["params"][0].as_u64().unwrap() as usize;
+                    Ok(json!(blocks[height].block_hash()))
+                }
+                "getblock" => Ok(json!(serialize_hex(
+                    blocks
+                        .iter()
+                        .find(|block| block.block_hash().to_string() == param(0))
+                        .unwrap()
+                ))),
+                "getrawmempool" => Ok(json!(mempool
+                    .iter()
+                    .map(|tx| tx.txid())
+                    .collect::<Vec<_>>())),
+                "getrawtransaction" => known
+                    .clone()
+                    .find(|tx| tx.txid().to_string() == param(0))
+                    .map(|tx| json!(serialize_hex(tx)))
+                    .ok_or(json!({
+                        "code": RPC_INVALID_ADDRESS_OR_KEY,
+                        "message": "No such mempool or blockchain transaction"
+                    })),
+                method => panic!("fake node got an unexpected call to {}", method),
+            };
+            let response = match result {
+                Ok(result) => json!({ "result": result, "error": null, "id": request["id"] }),
+                Err(error) => json!({ "result": null, "error": error, "id": request["id"] }),
+            }
+            .to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}\n",
+                response.len() + 1,
+                response
+            )
+            .unwrap();
+        }
+    });
+    return Client::new(&url, bitcoincore_rpc::Auth::None).unwrap();
+}
+
+#[test]
+fn core_feed_reads_blocks_mempool_and_prevouts() {
+    use bitcoin::{PackedLockTime, Script, Sequence, TxIn, Txid, Witness};
+    use bitcoin_hashes::Hash;
+
+    let tx = |previous_output: OutPoint, value: u64| Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: vec![TxIn {
+            previous_output,
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value,
+            script_pubkey: Script::new(),
+        }],
+    };
+    let coinbase = tx(OutPoint::null(), 50_000);
+    let confirmed = tx(OutPoint::new(coinbase.txid(), 0), 40_000);
+    let unconfirmed = tx(OutPoint::new(confirmed.txid(), 0), 30_000);
+    let node = fake_node(
+        vec![vec![coinbase.clone()], vec![confirmed.clone()]],
+        vec![unconfirmed.clone()],
+    );
+
+    assert_eq!(node.tip_height(), Ok(1));
+    assert_eq!(node.block_transactions(1), Ok(vec![confirmed.clone()]));
+    assert_eq!(node.mempool_transactions(), Ok(vec![unconfirmed.clone()]));
+    assert_eq!(
+        node.prevout(&unconfirmed.input[0].previous_output),
+        Ok(Some(confirmed.output[0].clone()))
+    );
+    assert_eq!(node.prevout(&OutPoint::new(Txid::all_zeros(), 0)), Ok(None));
+}
@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+
+use bitcoin::{
+    OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoin_hashes::Hash;
+
+use crate::bitcoin_wallet::constants::TIP;
+
+use super::RpcCall;
+
+// in memory backend, funds a script without a node and keeps whatever gets broadcast
+pub struct MockCall {
+    previous_tx: Vec<Transaction>,
+    tx_in: Vec<TxIn>,
+    pub broadcasts: RefCell<Vec<Transaction>>,
+}
+
+impl RpcCall for MockCall {
+    fn contract_source(&self) -> Vec<Transaction> {
+        return self.previous_tx.clone();
+    }
+
+    fn prev_input(&self) -> Vec<TxIn> {
+        return self.tx_in.clone();
+    }
+
+    fn script_get_balance(&self) -> u64 {
+        return self
+            .tx_in
+            .iter()
+            .map(|tx_in| {
+                self.previous_tx
+                    .iter()
+                    .find(|tx| tx.txid() == tx_in.previous_output.txid)
+                    .map(|tx| tx.output[tx_in.previous_output.vout as usize].value)
+                    .unwrap_or_default()
+            })
+            .sum::<u64>();
+    }
+
+    fn fee(&self) -> u64 {
+        return TIP;
+    }
+
+    fn broadcasts_transacton(&self, transaction: &Transaction) {
+        println!("mock broadcast of transaction id: {}", transaction.txid());
+        self.broadcasts.borrow_mut().push(transaction.clone());
+    }
+}
+
+impl MockCall {
+    // one funding transaction per amount, each paying to the script in its first output
+    pub fn fund(script_pubkey: &Script, amounts: &[u64]) -> Self {
+        let previous_tx = amounts
+            .iter()
+            .enumerate()
+            .map(|(index, amount)| Transaction {
+                version: 2,
+                lock_time: PackedLockTime(0),
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(Txid::all_zeros(), index as u32),
+                    script_sig: Script::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::default(),
+                }],
+                output: vec![TxOut {
+                    value: *amount,
+                    script_pubkey: script_pubkey.clone(),
+                }],
+            })
+            .collect::<Vec<Transaction>>();
+        return MockCall::from_transactions(previous_tx, script_pubkey);
+    }
+
+    pub fn from_transactions(previous_tx: Vec<Transaction>, script_pubkey: &Script) -> Self {
+        let tx_in = previous_tx
+            .iter()
+            .flat_map(|tx| {
+                let txid = tx.txid();
+                tx.output
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, tx_out)| tx_out.script_pubkey.eq(script_pubkey))
+                    .map(move |(vout, _)| TxIn {
+                        previous_output: OutPoint::new(txid, vout as u32),
+                        script_sig: Script::new(),
+                        sequence: Sequence::MAX,
+                        witness: Witness::default(),
+                    })
+            })
+            .collect::<Vec<TxIn>>();
+        return MockCall {
+            previous_tx,
+            tx_in,
+            broadcasts: RefCell::new(vec![]),
+        };
+    }
+
+    pub fn last_broadcast(&self) -> Option<Transaction> {
+        return self.broadcasts.borrow().last().cloned();
+    }
+}
@@ -1,6 +1,10 @@
-use bitcoin::{blockdata::transaction, Transaction, TxIn, Txid};
+use std::fmt;
 
+use bitcoin::{blockdata::transaction, OutPoint, Transaction, TxIn, TxOut, Txid};
+
+pub mod chain_feed;
 pub mod electrum_rpc;
+pub mod mock_call;
 pub mod regtest_call;
 pub mod regtest_rpc;
 pub mod reuse_rpc_call;
@@ -13,3 +17,24 @@ pub trait RpcCall {
     fn fee(&self) -> u64;
     fn broadcasts_transacton(&self, transaction: &Transaction);
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedError(pub String);
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "chain backend failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+// every transaction the chain has, for wallets like silent payments that can't ask the backend
+// for their own scripts and have to look at all of them
+pub trait ChainFeed {
+    fn tip_height(&self) -> Result<u32, FeedError>;
+    fn block_transactions(&self, height: u32) -> Result<Vec<Transaction>, FeedError>;
+    fn mempool_transactions(&self) -> Result<Vec<Transaction>, FeedError>;
+    // None when the backend doesn't know the transaction
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<TxOut>, FeedError>;
+}
@@ -7,6 +7,8 @@ use bitcoincore_rpc::{
     Client, RpcApi,
 };
 
+use crate::bitcoin_wallet::scripts::timelock::{Maturity, TimeLock};
+
 use super::RpcCall;
 
 pub struct RegtestCall {
@@ -96,6 +98,55 @@ impl<'a> RegtestCall {
         return tx_id;
     }
 
+    // reports for every utxo of the wallet when the lock on it is satisfied
+    pub fn timelock_status(&self, lock: &TimeLock) -> Vec<(OutPoint, Maturity, bool)> {
+        let chain = self.client.get_blockchain_info().unwrap();
+        let tip_height = chain.blocks as u32;
+        let tip_median_time = chain.median_time as u32;
+
+        return self
+            .tx_in
+            .iter()
+            .filter_map(|tx_in| {
+                let outpoint = tx_in.previous_output;
+                let info = self
+                    .client
+                    .get_transaction(&outpoint.txid, Some(true))
+                    .unwrap()
+                    .info;
+                let block_hash = match info.blockhash {
+                    Some(block_hash) => block_hash,
+                    None => {
+                        println!("utxo {} is unconfirmed, timelock has not started", outpoint);
+                        return None;
+                    }
+                };
+
+                // relative time locks count from the median time past of the previous block
+                let header = self.client.get_block_header_info(&block_hash).unwrap();
+                let confirmed_median_time = header
+                    .previous_block_hash
+                    .map(|prev| self.client.get_block_header_info(&prev).unwrap())
+                    .and_then(|prev| prev.median_time)
+                    .unwrap_or_default() as u32;
+
+                let maturity = lock.maturity(header.height as u32, confirmed_median_time);
+                let spendable = maturity.is_reached(tip_height, tip_median_time);
+                match (spendable, maturity) {
+                    (true, _) => println!("utxo {} is spendable", outpoint),
+                    (false, Maturity::Height(height)) => {
+                        println!("utxo {} is spendable from block {}", outpoint, height)
+                    }
+                    (false, Maturity::MedianTime(time)) => println!(
+                        "utxo {} is spendable once median time past reaches {}",
+                        outpoint, time
+                    ),
+                }
+                return Some((outpoint, maturity, spendable));
+            })
+            .collect::<Vec<(OutPoint, Maturity, bool)>>();
+    }
+
     pub fn from_string(address_list: &'a Vec<&str>) -> RegtestCall {
         return RegtestCall::from_address(
             address_list
@@ -230,9 +281,9 @@ fn importdescriptors(client: &Client, desc: &String, mine: u8) {
                 if result.success {
                     println!("descriptor successfully imported");
                 }
-                result.error.iter().for_each(|err| {
+                if let Some(err) = &result.error {
                     panic!("error importing wallet {:#?}", err);
-                })
+                }
             });
 
             mine_to_descriptors(client, mine, desc);
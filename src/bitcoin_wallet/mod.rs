@@ -3,5 +3,6 @@ pub mod configuration;
 pub mod constants;
 pub mod input_data;
 pub mod script_services;
+pub mod schnorr;
 pub mod scripts;
 pub mod spending_path;
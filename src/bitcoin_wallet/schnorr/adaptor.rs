@@ -0,0 +1,151 @@
+use bitcoin::{
+    secp256k1::{schnorr::Signature, All, PublicKey, Secp256k1, SecretKey},
+    XOnlyPublicKey,
+};
+
+use super::{
+    base_mul,
+    bip340::{challenge, Bip340Error},
+    has_even_y, lift_x, point_add, point_mul, point_negate,
+    scalar::ScalarN,
+    secret_scalar, tagged_hash, xbytes,
+};
+
+// a pre signature (R, s') with R = kG + T, adding the secret t of T turns it into a valid BIP340
+// signature and anyone holding both learns t
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptorSignature {
+    r: PublicKey,
+    s: ScalarN,
+}
+
+impl AdaptorSignature {
+    pub(crate) fn new(r: PublicKey, s: ScalarN) -> Self {
+        return AdaptorSignature { r, s };
+    }
+
+    // R keeps its parity, completing needs to know whether it gets negated
+    pub fn serialize(&self) -> [u8; 65] {
+        let mut bytes = [0u8; 65];
+        bytes[..33].copy_from_slice(&self.r.serialize());
+        bytes[33..].copy_from_slice(&self.s.to_be_bytes());
+        return bytes;
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 65 {
+            return None;
+        }
+        let r = PublicKey::from_slice(&bytes[..33]).ok()?;
+        let s = ScalarN::from_slice(&bytes[33..])?;
+        return Some(AdaptorSignature { r, s });
+    }
+
+    // s'G = R - T + eP, both sides negated when R has an odd y
+    pub fn verify(
+        &self,
+        secp: &Secp256k1<All>,
+        pub_key: &XOnlyPublicKey,
+        msg: &[u8],
+        adaptor_point: &PublicKey,
+    ) -> bool {
+        let p = lift_x(pub_key);
+        let e = challenge(&xbytes(&self.r), &pub_key.serialize(), msg);
+        let nonce = point_add(Some(self.r), Some(adaptor_point.negate(secp)));
+        let nonce = match has_even_y(&self.r) {
+            true => nonce,
+            false => point_negate(secp, nonce),
+        };
+        if nonce.is_none() {
+            return false;
+        }
+        let expected = point_add(nonce, point_mul(secp, &p, &e));
+        return base_mul(secp, &self.s) == expected;
+    }
+
+    pub fn complete(&self, adaptor_secret: &SecretKey) -> Signature {
+        let t = secret_scalar(adaptor_secret);
+        let s = match has_even_y(&self.r) {
+            true => self.s.add(&t),
+            false => self.s.sub(&t),
+        };
+        let sig = [xbytes(&self.r), s.to_be_bytes()].concat();
+        return Signature::from_slice(&sig).unwrap();
+    }
+
+    // the adaptor secret, from the pre signature and the completed one seen on chain
+    pub fn extract(&self, sig: &Signature) -> Option<SecretKey> {
+        let sig = sig.as_ref();
+        if sig[..32] != xbytes(&self.r) {
+            return None;
+        }
+        let s = ScalarN::from_slice(&sig[32..])?;
+        let t = match has_even_y(&self.r) {
+            true => s.sub(&self.s),
+            false => self.s.sub(&s),
+        };
+        return SecretKey::from_slice(&t.to_be_bytes()).ok();
+    }
+}
+
+// BIP340 signing with T added to the nonce, the nonce hash also commits to T
+pub fn adaptor_sign(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    msg: &[u8],
+    adaptor_point: &PublicKey,
+    aux_rand: [u8; 32],
+) -> Result<AdaptorSignature, Bip340Error> {
+    let p = PublicKey::from_secret_key(secp, secret_key);
+    let d = match has_even_y(&p) {
+        true => secret_scalar(secret_key),
+        false => secret_scalar(secret_key).negate(),
+    };
+    let mut masked = d.to_be_bytes();
+    masked
+        .iter_mut()
+        .zip(tagged_hash("BIP0340/aux", &[&aux_rand]))
+        .for_each(|(byte, aux)| *byte ^= aux);
+
+    let px = xbytes(&p);
+    let k = ScalarN::from_be_bytes(tagged_hash(
+        "adaptor/nonce",
+        &[&masked, &px, &adaptor_point.serialize(), msg],
+    ));
+    let r = point_add(base_mul(secp, &k), Some(*adaptor_point)).ok_or(Bip340Error::ZeroNonce)?;
+    let k = match has_even_y(&r) {
+        true => k,
+        false => k.negate(),
+    };
+    let e = challenge(&xbytes(&r), &px, msg);
+    let pre_sig = AdaptorSignature::new(r, k.add(&e.mul(&d)));
+    if !pre_sig.verify(secp, &p.x_only_public_key().0, msg, adaptor_point) {
+        return Err(Bip340Error::VerificationFailed);
+    }
+    return Ok(pre_sig);
+}
+
+#[test]
+fn adaptor_signature_completes_and_reveals_the_secret() {
+    use bitcoin::secp256k1::Message;
+
+    let secp = Secp256k1::new();
+    let msg = [7u8; 32];
+    // enough keys that both parities of R show up
+    (1u8..=8).for_each(|seed| {
+        let secret_key = SecretKey::from_slice(&[seed; 32]).unwrap();
+        let adaptor_secret = SecretKey::from_slice(&[seed + 100; 32]).unwrap();
+        let adaptor_point = PublicKey::from_secret_key(&secp, &adaptor_secret);
+        let x_only = secret_key.x_only_public_key(&secp).0;
+
+        let pre_sig = adaptor_sign(&secp, &secret_key, &msg, &adaptor_point, [seed; 32]).unwrap();
+        assert!(pre_sig.verify(&secp, &x_only, &msg, &adaptor_point));
+        let other_point = PublicKey::from_secret_key(&secp, &secret_key);
+        assert!(!pre_sig.verify(&secp, &x_only, &msg, &other_point));
+
+        let sig = pre_sig.complete(&adaptor_secret);
+        secp.verify_schnorr(&sig, &Message::from_slice(&msg).unwrap(), &x_only)
+            .unwrap();
+        assert_eq!(pre_sig.extract(&sig), Some(adaptor_secret));
+    });
+}
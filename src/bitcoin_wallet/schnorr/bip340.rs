@@ -0,0 +1,205 @@
+use std::fmt;
+
+use bitcoin::{
+    secp256k1::{
+        schnorr::Signature, All, KeyPair, Parity, PublicKey, Scalar, Secp256k1, SecretKey,
+    },
+    XOnlyPublicKey,
+};
+
+use super::{
+    base_mul, has_even_y, lift_x, point_add, point_mul, point_negate, scalar::ScalarN, tagged_hash,
+    xbytes,
+};
+
+// the field size p, the x coordinate of R in a signature has to be below it
+const FIELD_SIZE: [u8; 32] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE, 0xFF, 0xFF, 0xFC, 0x2F,
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Bip340Error {
+    ZeroNonce,
+    VerificationFailed,
+}
+
+impl fmt::Display for Bip340Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bip340Error::ZeroNonce => write!(f, "derived nonce is zero"),
+            Bip340Error::VerificationFailed => write!(f, "signature does not verify"),
+        }
+    }
+}
+
+impl std::error::Error for Bip340Error {}
+
+// Sign(sk, m) from BIP340, written out step by step so it can be read next to the spec, d and k
+// only go through libsecp256k1 which is constant time, ScalarN is left for public values
+pub fn sign(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    msg: &[u8],
+    aux_rand: [u8; 32],
+) -> Result<Signature, Bip340Error> {
+    let (p, parity) = KeyPair::from_secret_key(secp, secret_key).x_only_public_key();
+    let d = match parity {
+        Parity::Even => *secret_key,
+        Parity::Odd => secret_key.negate(),
+    };
+    let mut t = d.secret_bytes();
+    t.iter_mut()
+        .zip(tagged_hash("BIP0340/aux", &[&aux_rand]))
+        .for_each(|(byte, aux)| *byte ^= aux);
+
+    let px = p.serialize();
+    let k = ScalarN::from_be_bytes(tagged_hash("BIP0340/nonce", &[&t, &px, msg]));
+    let k = SecretKey::from_slice(&k.to_be_bytes()).map_err(|_| Bip340Error::ZeroNonce)?;
+    let r = PublicKey::from_secret_key(secp, &k);
+    let k = match has_even_y(&r) {
+        true => k,
+        false => k.negate(),
+    };
+    let rx = xbytes(&r);
+    let e = challenge(&rx, &px, msg);
+    // e is zero or e·d cancels k only with negligible probability, s is then k or zero
+    let s = match d.mul_tweak(&Scalar::from_be_bytes(e.to_be_bytes()).unwrap()) {
+        Ok(ed) => k
+            .add_tweak(&Scalar::from(ed))
+            .map(|s| s.secret_bytes())
+            .unwrap_or([0u8; 32]),
+        Err(_) => k.secret_bytes(),
+    };
+    let sig = [rx, s].concat();
+
+    // a fault while signing must never leak a signature that could reveal the key
+    if !verify(secp, &px, msg, &sig) {
+        return Err(Bip340Error::VerificationFailed);
+    }
+    return Ok(Signature::from_slice(&sig).unwrap());
+}
+
+// Verify(pk, m, sig), takes raw bytes so keys and signatures that don't parse just fail
+pub fn verify(secp: &Secp256k1<All>, pub_key: &[u8], msg: &[u8], sig: &[u8]) -> bool {
+    let (p, rx, s) = match parse(pub_key, sig) {
+        Some(parsed) => parsed,
+        None => return false,
+    };
+    let e = challenge(&rx, pub_key, msg);
+    let r = point_add(
+        base_mul(secp, &s),
+        point_negate(secp, point_mul(secp, &p, &e)),
+    );
+    return match r {
+        Some(r) => has_even_y(&r) && xbytes(&r) == rx,
+        None => false,
+    };
+}
+
+// BatchVerify, one multi scalar check with random weights instead of a check per signature
+pub fn batch_verify(secp: &Secp256k1<All>, items: &[(&[u8], &[u8], &[u8])]) -> bool {
+    let mut lhs = ScalarN::ZERO;
+    let mut rhs: Option<PublicKey> = None;
+    for (index, (pub_key, msg, sig)) in items.iter().enumerate() {
+        let (p, rx, s) = match parse(pub_key, sig) {
+            Some(parsed) => parsed,
+            None => return false,
+        };
+        let r = match XOnlyPublicKey::from_slice(&rx) {
+            Ok(r) => lift_x(&r),
+            Err(_) => return false,
+        };
+        let a = match index {
+            0 => ScalarN::ONE,
+            _ => ScalarN::from_be_bytes(Scalar::random().to_be_bytes()),
+        };
+        let e = challenge(&rx, pub_key, msg);
+        lhs = lhs.add(&a.mul(&s));
+        rhs = point_add(rhs, point_mul(secp, &r, &a));
+        rhs = point_add(rhs, point_mul(secp, &p, &a.mul(&e)));
+    }
+    return base_mul(secp, &lhs) == rhs;
+}
+
+fn parse(pub_key: &[u8], sig: &[u8]) -> Option<(PublicKey, [u8; 32], ScalarN)> {
+    let p = lift_x(&XOnlyPublicKey::from_slice(pub_key).ok()?);
+    if sig.len() != 64 {
+        return None;
+    }
+    let rx: [u8; 32] = sig[..32].try_into().unwrap();
+    if rx >= FIELD_SIZE {
+        return None;
+    }
+    let s = ScalarN::from_slice(&sig[32..])?;
+    return Some((p, rx, s));
+}
+
+pub fn challenge(rx: &[u8], px: &[u8], msg: &[u8]) -> ScalarN {
+    return ScalarN::from_be_bytes(tagged_hash("BIP0340/challenge", &[rx, px, msg]));
+}
+
+#[test]
+fn bip340_test_vectors() {
+    use bitcoin::hashes::hex::FromHex;
+
+    // index, secret key, public key, aux rand, message, signature, valid
+    let vectors = [
+        (0, "0000000000000000000000000000000000000000000000000000000000000003", "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9", "0000000000000000000000000000000000000000000000000000000000000000", "0000000000000000000000000000000000000000000000000000000000000000", "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0", true),
+        (1, "B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "0000000000000000000000000000000000000000000000000000000000000001", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE33418906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A", true),
+        (2, "C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C9", "DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8", "C87AA53824B4D7AE2EB035A2B5BBBCCC080E76CDC6D1692C4B0B62D798E6D906", "7E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C", "5831AAEED7B44BB74E5EAB94BA9D4294C49BCF2A60728D8B4C200F50DD313C1BAB745879A5AD954A72C45A91C3A51D3C7ADEA98D82F8481E0E1E03674A6F3FB7", true),
+        (3, "0B432B2677937381AEF05BB02A66ECD012773062CF3FA2549E44F58ED2401710", "25D1DFF95105F5253C4022F628A996AD3A0D95FBF21D468A1B33F8C160D8F517", "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", "7EB0509757E246F19449885651611CB965ECC1A187DD51B64FDA1EDC9637D5EC97582B9CB13DB3933705B32BA982AF5AF25FD78881EBB32771FC5922EFC66EA3", true),
+        (4, "", "D69C3509BB99E412E68B0FE8544E72837DFA30746D8BE2AA65975F29D22DC7B9", "", "4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703", "00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C6376AFB1548AF603B3EB45C9F8207DEE1060CB71C04E80F593060B07D28308D7F4", true),
+        (5, "", "EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E17776969E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B", false),
+        (6, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "FFF97BD5755EEEA420453A14355235D382F6472F8568A18B2F057A14602975563CC27944640AC607CD107AE10923D9EF7A73C643E166BE5EBEAFA34B1AC553E2", false),
+        (7, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "1FA62E331EDBC21C394792D2AB1100A7B432B013DF3F6FF4F99FCB33E0E1515F28890B3EDB6E7189B630448B515CE4F8622A954CFE545735AAEA5134FCCDB2BD", false),
+        (8, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769961764B3AA9B2FFCB6EF947B6887A226E8D7C93E00C5ED0C1834FF0D0C2E6DA6", false),
+        (9, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "0000000000000000000000000000000000000000000000000000000000000000123DDA8328AF9C23A94C1FEECFD123BA4FB73476F0D594DCB65C6425BD186051", false),
+        (10, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "00000000000000000000000000000000000000000000000000000000000000017615FBAF5AE28864013C099742DEADB4DBA87F11AC6754F93780D5A1837CF197", false),
+        (11, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "4A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B", false),
+        (12, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B", false),
+        (13, "", "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141", false),
+        (14, "", "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30", "", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89", "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E17776969E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B", false),
+        (15, "0340034003400340034003400340034003400340034003400340034003400340", "778CAA53B4393AC467774D09497A87224BF9FAB6F6E68B23086497324D6FD117", "0000000000000000000000000000000000000000000000000000000000000000", "", "71535DB165ECD9FBBC046E5FFAEA61186BB6AD436732FCCC25291A55895464CF6069CE26BF03466228F19A3A62DB8A649F2D560FAC652827D1AF0574E427AB63", true),
+        (16, "0340034003400340034003400340034003400340034003400340034003400340", "778CAA53B4393AC467774D09497A87224BF9FAB6F6E68B23086497324D6FD117", "0000000000000000000000000000000000000000000000000000000000000000", "11", "08A20A0AFEF64124649232E0693C583AB1B9934AE63B4C3511F3AE1134C6A303EA3173BFEA6683BD101FA5AA5DBC1996FE7CACFC5A577D33EC14564CEC2BACBF", true),
+        (17, "0340034003400340034003400340034003400340034003400340034003400340", "778CAA53B4393AC467774D09497A87224BF9FAB6F6E68B23086497324D6FD117", "0000000000000000000000000000000000000000000000000000000000000000", "0102030405060708090A0B0C0D0E0F1011", "5130F39A4059B43BC7CAC09A19ECE52B5D8699D1A71E3C52DA9AFDB6B50AC370C4A482B77BF960F8681540E25B6771ECE1E5A37FD80E5A51897C5566A97EA5A5", true),
+        (18, "0340034003400340034003400340034003400340034003400340034003400340", "778CAA53B4393AC467774D09497A87224BF9FAB6F6E68B23086497324D6FD117", "0000000000000000000000000000000000000000000000000000000000000000", "99999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999", "403B12B0D8555A344175EA7EC746566303321E5DBFA8BE6F091635163ECA79A8585ED3E3170807E7C03B720FC54C7B23897FCBA0E9D0B4A06894CFD249F22367", true),
+    ];
+
+    let secp = Secp256k1::new();
+    let mut valid = vec![];
+    vectors
+        .iter()
+        .for_each(|(index, secret, pub_key, aux, msg, sig, result)| {
+            let pub_key = Vec::<u8>::from_hex(pub_key).unwrap();
+            let msg = Vec::<u8>::from_hex(msg).unwrap();
+            let sig = Vec::<u8>::from_hex(sig).unwrap();
+            if !secret.is_empty() {
+                let secret_key =
+                    SecretKey::from_slice(&Vec::<u8>::from_hex(secret).unwrap()).unwrap();
+                let aux: [u8; 32] = Vec::<u8>::from_hex(aux).unwrap().try_into().unwrap();
+                let ours = sign(&secp, &secret_key, &msg, aux).unwrap();
+                assert_eq!(ours.as_ref().to_vec(), sig, "signing vector {}", index);
+            }
+            assert_eq!(
+                verify(&secp, &pub_key, &msg, &sig),
+                *result,
+                "verify vector {}",
+                index
+            );
+            if *result {
+                valid.push((pub_key, msg, sig));
+            }
+        });
+
+    let batch = valid
+        .iter()
+        .map(|(pub_key, msg, sig)| (&pub_key[..], &msg[..], &sig[..]))
+        .collect::<Vec<_>>();
+    assert!(batch_verify(&secp, &batch));
+    let mut tampered = valid[1].2.clone();
+    tampered[63] ^= 1;
+    let mut batch = batch.clone();
+    batch[1].2 = &tampered;
+    assert!(!batch_verify(&secp, &batch));
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+
+use bitcoin::{
+    consensus::encode::serialize,
+    secp256k1::{All, PublicKey, Secp256k1, SecretKey},
+    util::taproot::{TapBranchHash, TapLeafHash},
+    SchnorrSighashType, Transaction, TxOut, XOnlyPublicKey,
+};
+use bitcoin_hashes::{sha256, Hash};
+
+use super::{base_mul, has_even_y, lift_x, point_add, scalar::ScalarN, secret_scalar, tagged_hash};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Bip341Error {
+    InvalidTweak,
+    InputIndex(usize),
+    PrevoutsLength,
+    SingleWithoutOutput(usize),
+}
+
+impl fmt::Display for Bip341Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bip341Error::InvalidTweak => write!(f, "tweak does not give a valid key"),
+            Bip341Error::InputIndex(index) => write!(f, "no input at index {}", index),
+            Bip341Error::PrevoutsLength => write!(f, "one previous output per input is needed"),
+            Bip341Error::SingleWithoutOutput(index) => {
+                write!(f, "SIGHASH_SINGLE without an output at index {}", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Bip341Error {}
+
+// t = hashTapTweak(P || merkle root), the root is left out for a key path only output
+pub fn tap_tweak(internal_key: &XOnlyPublicKey, merkle_root: Option<TapBranchHash>) -> [u8; 32] {
+    let internal = internal_key.serialize();
+    return match merkle_root {
+        Some(root) => tagged_hash("TapTweak", &[&internal, &root.into_inner()]),
+        None => tagged_hash("TapTweak", &[&internal]),
+    };
+}
+
+// Q = lift_x(P) + tG
+pub fn tweak_public_key(
+    secp: &Secp256k1<All>,
+    internal_key: &XOnlyPublicKey,
+    merkle_root: Option<TapBranchHash>,
+) -> Result<PublicKey, Bip341Error> {
+    let t = ScalarN::from_be_bytes_checked(tap_tweak(internal_key, merkle_root))
+        .ok_or(Bip341Error::InvalidTweak)?;
+    return point_add(Some(lift_x(internal_key)), base_mul(secp, &t))
+        .ok_or(Bip341Error::InvalidTweak);
+}
+
+// the secret of Q, d is negated first when P has an odd y
+pub fn tweak_secret_key(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    merkle_root: Option<TapBranchHash>,
+) -> Result<SecretKey, Bip341Error> {
+    let p = PublicKey::from_secret_key(secp, secret_key);
+    let d = match has_even_y(&p) {
+        true => secret_scalar(secret_key),
+        false => secret_scalar(secret_key).negate(),
+    };
+    let internal_key = p.x_only_public_key().0;
+    let t = ScalarN::from_be_bytes_checked(tap_tweak(&internal_key, merkle_root))
+        .ok_or(Bip341Error::InvalidTweak)?;
+    return SecretKey::from_slice(&d.add(&t).to_be_bytes()).map_err(|_| Bip341Error::InvalidTweak);
+}
+
+// hashTapSighash(0x00 || SigMsg(hash_type, ext_flag)), a leaf hash switches to the script path
+// message, annexes are not supported
+pub fn sighash(
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+    hash_ty: SchnorrSighashType,
+    leaf_hash: Option<TapLeafHash>,
+) -> Result<[u8; 32], Bip341Error> {
+    if input_index >= tx.input.len() {
+        return Err(Bip341Error::InputIndex(input_index));
+    }
+    if prevouts.len() != tx.input.len() {
+        return Err(Bip341Error::PrevoutsLength);
+    }
+    let hash_byte = hash_ty as u8;
+    let anyone_can_pay = hash_byte & 0x80 != 0;
+    let output_type = hash_byte & 0x03;
+
+    let mut msg = vec![0u8, hash_byte];
+    msg.extend(tx.version.to_le_bytes());
+    msg.extend(tx.lock_time.0.to_le_bytes());
+    if !anyone_can_pay {
+        msg.extend(sha(tx
+            .input
+            .iter()
+            .flat_map(|tx_in| serialize(&tx_in.previous_output))));
+        msg.extend(sha(prevouts
+            .iter()
+            .flat_map(|tx_out| tx_out.value.to_le_bytes())));
+        msg.extend(sha(prevouts
+            .iter()
+            .flat_map(|tx_out| serialize(&tx_out.script_pubkey))));
+        msg.extend(sha(tx
+            .input
+            .iter()
+            .flat_map(|tx_in| tx_in.sequence.0.to_le_bytes())));
+    }
+    if output_type != 0x02 && output_type != 0x03 {
+        msg.extend(sha(tx.output.iter().flat_map(serialize)));
+    }
+
+    let spend_type = match leaf_hash {
+        Some(_) => 2u8,
+        None => 0u8,
+    };
+    msg.push(spend_type);
+    if anyone_can_pay {
+        let tx_in = &tx.input[input_index];
+        let prevout = &prevouts[input_index];
+        msg.extend(serialize(&tx_in.previous_output));
+        msg.extend(prevout.value.to_le_bytes());
+        msg.extend(serialize(&prevout.script_pubkey));
+        msg.extend(tx_in.sequence.0.to_le_bytes());
+    } else {
+        msg.extend((input_index as u32).to_le_bytes());
+    }
+    if output_type == 0x03 {
+        let tx_out = tx
+            .output
+            .get(input_index)
+            .ok_or(Bip341Error::SingleWithoutOutput(input_index))?;
+        msg.extend(sha256::Hash::hash(&serialize(tx_out)).into_inner());
+    }
+
+    if let Some(leaf_hash) = leaf_hash {
+        msg.extend(leaf_hash.into_inner());
+        // key version 0 and no OP_CODESEPARATOR executed
+        msg.push(0x00);
+        msg.extend(u32::MAX.to_le_bytes());
+    }
+    return Ok(tagged_hash("TapSighash", &[&msg]));
+}
+
+fn sha(data: impl Iterator<Item = u8>) -> [u8; 32] {
+    return sha256::Hash::hash(&data.collect::<Vec<u8>>()).into_inner();
+}
+
+#[test]
+fn bip341_tweak_and_sighash_vectors() {
+    use std::str::FromStr;
+
+    use bitcoin::{consensus::deserialize, hashes::hex::FromHex, Script};
+
+    let secp = Secp256k1::new();
+
+    // scriptPubKey vectors from the BIP341 wallet test vectors
+    let internal_key = XOnlyPublicKey::from_str(
+        "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d",
+    )
+    .unwrap();
+    assert_eq!(
+        tap_tweak(&internal_key, None).to_vec(),
+        Vec::<u8>::from_hex("b86e7be8f39bab32a6f2c0443abbc210f0edac0e2c53d501b36b64437d9c6c70")
+            .unwrap()
+    );
+    assert_eq!(
+        tweak_public_key(&secp, &internal_key, None)
+            .unwrap()
+            .x_only_public_key()
+            .0,
+        XOnlyPublicKey::from_str(
+            "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+        )
+        .unwrap()
+    );
+
+    let internal_key = XOnlyPublicKey::from_str(
+        "187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27",
+    )
+    .unwrap();
+    let leaf =
+        Script::from_str("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac")
+            .unwrap();
+    let leaf_hash = TapLeafHash::from_script(&leaf, bitcoin::util::taproot::LeafVersion::TapScript);
+    let merkle_root = TapBranchHash::from_inner(leaf_hash.into_inner());
+    assert_eq!(
+        tap_tweak(&internal_key, Some(merkle_root)).to_vec(),
+        Vec::<u8>::from_hex("cbd8679ba636c1110ea247542cfbd964131a6be84f873f7f3b62a777528ed001")
+            .unwrap()
+    );
+    assert_eq!(
+        tweak_public_key(&secp, &internal_key, Some(merkle_root))
+            .unwrap()
+            .x_only_public_key()
+            .0,
+        XOnlyPublicKey::from_str(
+            "147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3"
+        )
+        .unwrap()
+    );
+
+    // key path sighashes from the taproot sighash tests of rust-bitcoin 0.29 (util/sighash.rs),
+    // one per hash type
+    let sighash_vectors = [
+        ("020000000164eb050a5e3da0c2a65e4786f26d753b7bc69691fabccafb11f7acef36641f1846010000003101b2b404392a22000000000017a9147f2bde86fe78bf68a0544a4f290e12f0b7e0a08c87580200000000000017a91425d11723074ecfb96a0a83c3956bfaf362ae0c908758020000000000001600147e20f938993641de67bb0cdd71682aa34c4d29ad5802000000000000160014c64984dc8761acfa99418bd6bedc79b9287d652d72000000", "01365724000000000023542156b39dab4f8f3508e0432cfb41fab110170acaa2d4c42539cb90a4dc7c093bc500", 0, "33ca0ebfb4a945eeee9569fc0f5040221275f88690b7f8592ada88ce3bdf6703", SchnorrSighashType::Default),
+        ("0200000002fff49be59befe7566050737910f6ccdc5e749c7f8860ddc140386463d88c5ad0f3000000002cf68eb4a3d67f9d4c079249f7e4f27b8854815cb1ed13842d4fbf395f9e217fd605ee24090100000065235d9203f458520000000000160014b6d48333bb13b4c644e57c43a9a26df3a44b785e58020000000000001976a914eea9461a9e1e3f765d3af3e726162e0229fe3eb688ac58020000000000001976a9143a8869c9f2b5ea1d4ff3aeeb6a8fb2fffb1ad5fe88ac0ad7125c", "02591f220000000000225120f25ad35583ea31998d968871d7de1abd2a52f6fe4178b54ea158274806ff4ece48fb310000000000225120f25ad35583ea31998d968871d7de1abd2a52f6fe4178b54ea158274806ff4ece", 1, "626ab955d58c9a8a600a0c580549d06dc7da4e802eb2a531f62a588e430967a8", SchnorrSighashType::All),
+        ("0200000001350005f65aa830ced2079df348e2d8c2bdb4f10e2dde6a161d8a07b40d1ad87dae000000001611d0d603d9dc0e000000000017a914459b6d7d6bbb4d8837b4bf7e9a4556f952da2f5c8758020000000000001976a9141dd70e1299ffc2d5b51f6f87de9dfe9398c33cbb88ac58020000000000001976a9141dd70e1299ffc2d5b51f6f87de9dfe9398c33cbb88aca71c1f4f", "01c4811000000000002251201bf9297d0a2968ae6693aadd0fa514717afefd218087a239afb7418e2d22e65c", 0, "dfa9437f9c9a1d1f9af271f79f2f5482f287cdb0d2e03fa92c8a9b216cc6061c", SchnorrSighashType::AllPlusAnyoneCanPay),
+        ("020000000185bed1a6da2bffbd60ec681a1bfb71c5111d6395b99b3f8b2bf90167111bcb18f5010000007c83ace802ded24a00000000001600142c4698f9f7a773866879755aa78c516fb332af8e5802000000000000160014d38639dfbac4259323b98a472405db0c461b31fa61073747", "0144c84d0000000000225120e3f2107989c88e67296ab2faca930efa2e3a5bd3ff0904835a11c9e807458621", 0, "3129de36a5d05fff97ffca31eb75fcccbbbc27b3147a7a36a9e4b45d8b625067", SchnorrSighashType::None),
+        ("eb93dbb901028c8515589dac980b6e7f8e4088b77ed866ca0d6d210a7218b6fd0f6b22dd6d7300000000eb4740a9047efc0e0000000000160014913da2128d8fcf292b3691db0e187414aa1783825802000000000000160014913da2128d8fcf292b3691db0e187414aa178382580200000000000017a9143dd27f01c6f7ef9bb9159937b17f17065ed01a0c875802000000000000160014d7630e19df70ada9905ede1722b800c0005f246641000000", "013fed110000000000225120eb536ae8c33580290630fc495046e998086a64f8f33b93b07967d9029b265c55", 0, "2441e8b0e063a2083ee790f14f2045022f07258ddde5ee01de543c9e789d80ae", SchnorrSighashType::NonePlusAnyoneCanPay),
+        ("02000000017836b409a5fed32211407e44b971591f2032053f14701fb5b3a30c0ff382f2cc9c0100000061ac55f60288fb5600000000001976a9144ea02f6f182b082fb6ce47e36bbde390b6a41b5088ac58020000000000001976a9144ea02f6f182b082fb6ce47e36bbde390b6a41b5088ace4000000", "01efa558000000000022512007071ea3dc7e331b0687d0193d1e6d6ed10e645ef36f10ef8831d5e522ac9e80", 0, "30239345177cadd0e3ea413d49803580abb6cb27971b481b7788a78d35117a88", SchnorrSighashType::Single),
+        ("0100000001aa6deae89d5e0aaca58714fc76ef6f3c8284224888089232d4e663843ed3ab3eae010000008b6657a60450cb4c0000000000160014a3d42b5413ef0c0701c4702f3cd7d4df222c147058020000000000001976a91430b4ed8723a4ee8992aa2c8814cfe5c3ad0ab9d988ac5802000000000000160014365b1166a6ed0a5e8e9dff17a6d00bbb43454bc758020000000000001976a914bc98c51a84fe7fad5dc380eb8b39586eff47241688ac4f313247", "0107af4e00000000002251202c36d243dfc06cb56a248e62df27ecba7417307511a81ae61aa41c597a929c69", 0, "bf9c83f26c6dd16449e4921f813f551c4218e86f2ec906ca8611175b41b566df", SchnorrSighashType::SinglePlusAnyoneCanPay),
+    ];
+    sighash_vectors
+        .iter()
+        .for_each(|(tx, prevouts, index, expected, hash_ty)| {
+            let tx: Transaction = deserialize(&Vec::<u8>::from_hex(tx).unwrap()).unwrap();
+            let prevouts: Vec<TxOut> =
+                deserialize(&Vec::<u8>::from_hex(prevouts).unwrap()).unwrap();
+            let hash = sighash(&tx, *index, &prevouts, *hash_ty, None).unwrap();
+            assert_eq!(
+                hash.to_vec(),
+                Vec::<u8>::from_hex(expected).unwrap(),
+                "{:?}",
+                hash_ty
+            );
+        });
+
+    // the script path sighash of the same tests, the leaf is <key> OP_CHECKSIG OP_CODESEPARATOR
+    // so no OP_CODESEPARATOR has run when the signature is checked
+    let tx: Transaction = deserialize(&Vec::<u8>::from_hex("020000000189fc651483f9296b906455dd939813bf086b1bbe7c77635e157c8e14ae29062195010000004445b5c7044561320000000000160014331414dbdada7fb578f700f38fb69995fc9b5ab958020000000000001976a914268db0a8104cc6d8afd91233cc8b3d1ace8ac3ef88ac580200000000000017a914ec00dcb368d6a693e11986d265f659d2f59e8be2875802000000000000160014c715799a49a0bae3956df9c17cb4440a673ac0df6f010000").unwrap()).unwrap();
+    let prevouts: Vec<TxOut> = deserialize(
+        &Vec::<u8>::from_hex(
+            "011bec34000000000022512028055142ea437db73382e991861446040b61dd2185c4891d7daf6893d79f7182",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let leaf =
+        Script::from_str("20cc4e1107aea1d170c5ff5b6817e1303010049724fb3caa7941792ea9d29b3e2bacab")
+            .unwrap();
+    let leaf_hash = TapLeafHash::from_script(&leaf, bitcoin::util::taproot::LeafVersion::TapScript);
+    assert_eq!(
+        leaf_hash.to_vec(),
+        Vec::<u8>::from_hex("15a2530514e399f8b5cf0b3d3112cf5b289eaa3e308ba2071b58392fdc6da68a")
+            .unwrap()
+    );
+    assert_eq!(
+        sighash(&tx, 0, &prevouts, SchnorrSighashType::All, Some(leaf_hash))
+            .unwrap()
+            .to_vec(),
+        Vec::<u8>::from_hex("d66de5274a60400c7b08c86ba6b7f198f40660079edf53aca89d2a9501317f2e")
+            .unwrap()
+    );
+}
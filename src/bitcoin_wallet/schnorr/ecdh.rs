@@ -0,0 +1,371 @@
+use std::fmt;
+
+use bitcoin::{
+    secp256k1::{rand, All, Parity, PublicKey, Scalar, Secp256k1, SecretKey},
+    XOnlyPublicKey,
+};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use super::{lift_x, tagged_hash, xbytes};
+
+const NONCE_LEN: usize = 12;
+const MAC_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EcdhError {
+    // the shared point or a tweaked key is the point at infinity
+    InvalidKey,
+    NoteTooShort(usize),
+    // wrong key, wrong context or the note was changed on the way
+    Decrypt,
+    NotAMember(XOnlyPublicKey),
+}
+
+impl fmt::Display for EcdhError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EcdhError::InvalidKey => write!(f, "key derivation hit the point at infinity"),
+            EcdhError::NoteTooShort(len) => write!(f, "note of {} bytes is too short", len),
+            EcdhError::Decrypt => write!(f, "note does not decrypt under the shared key"),
+            EcdhError::NotAMember(key) => write!(f, "{} is not a member of the group", key),
+        }
+    }
+}
+
+impl std::error::Error for EcdhError {}
+
+// 32 bytes both sides derive from their own secret and the other side's x only key
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SharedKey([u8; 32]);
+
+impl SharedKey {
+    pub fn to_bytes(&self) -> [u8; 32] {
+        return self.0;
+    }
+}
+
+// the key never shows up in logs
+impl fmt::Debug for SharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedKey(..)")
+    }
+}
+
+// keys are x only, so the secret is negated first when its point has an odd y, then a * lift_x(B)
+// and b * lift_x(A) are the same point
+pub fn even_secret(secp: &Secp256k1<All>, secret_key: &SecretKey) -> SecretKey {
+    return match secret_key.x_only_public_key(secp).1 {
+        Parity::Even => *secret_key,
+        Parity::Odd => secret_key.negate(),
+    };
+}
+
+pub fn shared_point(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    their_key: &XOnlyPublicKey,
+) -> Result<PublicKey, EcdhError> {
+    let tweak = Scalar::from(even_secret(secp, secret_key));
+    return lift_x(their_key)
+        .mul_tweak(secp, &tweak)
+        .map_err(|_| EcdhError::InvalidKey);
+}
+
+// the x coordinate of the shared point hashed together with both keys in sorted order, so
+// the key is bound to the pair and not only to the point
+pub fn pairwise_key(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    their_key: &XOnlyPublicKey,
+) -> Result<SharedKey, EcdhError> {
+    let point = shared_point(secp, secret_key, their_key)?;
+    let mut keys = [
+        secret_key.x_only_public_key(secp).0.serialize(),
+        their_key.serialize(),
+    ];
+    keys.sort();
+    return Ok(SharedKey(tagged_hash(
+        "ecdh/shared",
+        &[&xbytes(&point), &keys[0], &keys[1]],
+    )));
+}
+
+// a tweak per context and index, the same shared key gives a fresh tweak for every trade or swap
+pub fn derive_tweak(shared_key: &SharedKey, context: &[u8], index: u32) -> Scalar {
+    let hash = tagged_hash(
+        "ecdh/tweak",
+        &[&shared_key.0, context, &index.to_be_bytes()],
+    );
+    // a hash above the curve order has a chance of about 2^-128
+    return Scalar::from_be_bytes(hash).expect("tweak is below the curve order");
+}
+
+// lift_x(P) + tG, the full point so segwit v0 scripts can use it as well
+pub fn tweak_public(
+    secp: &Secp256k1<All>,
+    key: &XOnlyPublicKey,
+    tweak: &Scalar,
+) -> Result<PublicKey, EcdhError> {
+    return lift_x(key)
+        .add_exp_tweak(secp, tweak)
+        .map_err(|_| EcdhError::InvalidKey);
+}
+
+// the secret for tweak_public of its own x only key
+pub fn tweak_secret(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    tweak: &Scalar,
+) -> Result<SecretKey, EcdhError> {
+    return even_secret(secp, secret_key)
+        .add_tweak(tweak)
+        .map_err(|_| EcdhError::InvalidKey);
+}
+
+// both parties of a contract tweak both keys with the same pairwise tweak, every contract gets
+// keys nobody else can link to the long lived ones and nobody has to send new keys around
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContractKeys {
+    pub secret_key: SecretKey,
+    pub ours: PublicKey,
+    pub theirs: PublicKey,
+}
+
+pub fn contract_keys(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    their_key: &XOnlyPublicKey,
+    context: &[u8],
+    index: u32,
+) -> Result<ContractKeys, EcdhError> {
+    let tweak = derive_tweak(&pairwise_key(secp, secret_key, their_key)?, context, index);
+    let secret_key = tweak_secret(secp, secret_key, &tweak)?;
+    return Ok(ContractKeys {
+        secret_key,
+        ours: PublicKey::from_secret_key(secp, &secret_key),
+        theirs: tweak_public(secp, their_key, &tweak)?,
+    });
+}
+
+// nonce || aes-256-gcm ciphertext || tag, the context is authenticated but not sent
+pub fn seal_note(shared_key: &SharedKey, context: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    return seal_note_with_nonce(shared_key, &rand::random(), context, plaintext);
+}
+
+// a nonce must never be used twice with the same key, only the vectors pick it themselves
+fn seal_note_with_nonce(
+    shared_key: &SharedKey,
+    nonce: &[u8; NONCE_LEN],
+    context: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let mut mac = [0u8; MAC_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &shared_key.0,
+        Some(nonce),
+        context,
+        plaintext,
+        &mut mac,
+    )
+    .expect("aes-256-gcm takes a 32 byte key and a 12 byte nonce");
+    return [&nonce[..], &ciphertext, &mac].concat();
+}
+
+pub fn open_note(
+    shared_key: &SharedKey,
+    context: &[u8],
+    note: &[u8],
+) -> Result<Vec<u8>, EcdhError> {
+    if note.len() < NONCE_LEN + MAC_LEN {
+        return Err(EcdhError::NoteTooShort(note.len()));
+    }
+    let (nonce, rest) = note.split_at(NONCE_LEN);
+    let (ciphertext, mac) = rest.split_at(rest.len() - MAC_LEN);
+    return decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &shared_key.0,
+        Some(nonce),
+        context,
+        ciphertext,
+        mac,
+    )
+    .map_err(|_| EcdhError::Decrypt);
+}
+
+// there is no non interactive diffie hellman for more than two keys, so the coordinator derives
+// the group key from its own secret and the members and seals it to each of them with the
+// pairwise key, rederiving it later needs nothing but the same inputs
+pub fn group_key(
+    secp: &Secp256k1<All>,
+    coordinator: &SecretKey,
+    members: &[XOnlyPublicKey],
+    context: &[u8],
+) -> SharedKey {
+    let mut members = members
+        .iter()
+        .map(|key| key.serialize())
+        .collect::<Vec<_>>();
+    members.sort();
+    members.dedup();
+    let secret = even_secret(secp, coordinator).secret_bytes();
+    let mut data: Vec<&[u8]> = vec![&secret, context];
+    data.extend(members.iter().map(|key| &key[..]));
+    return SharedKey(tagged_hash("ecdh/group", &data));
+}
+
+// one sealed copy of the group key per member
+pub type GroupNotes = Vec<(XOnlyPublicKey, Vec<u8>)>;
+
+pub fn seal_group(
+    secp: &Secp256k1<All>,
+    coordinator: &SecretKey,
+    members: &[XOnlyPublicKey],
+    context: &[u8],
+) -> Result<(SharedKey, GroupNotes), EcdhError> {
+    let group = group_key(secp, coordinator, members, context);
+    let notes = members
+        .iter()
+        .map(|member| {
+            let pairwise = pairwise_key(secp, coordinator, member)?;
+            return Ok((*member, seal_note(&pairwise, context, &group.0)));
+        })
+        .collect::<Result<Vec<_>, EcdhError>>()?;
+    return Ok((group, notes));
+}
+
+pub fn open_group(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    coordinator: &XOnlyPublicKey,
+    notes: &[(XOnlyPublicKey, Vec<u8>)],
+    context: &[u8],
+) -> Result<SharedKey, EcdhError> {
+    let ours = secret_key.x_only_public_key(secp).0;
+    let (_, note) = notes
+        .iter()
+        .find(|(member, _)| *member == ours)
+        .ok_or(EcdhError::NotAMember(ours))?;
+    let bytes = open_note(&pairwise_key(secp, secret_key, coordinator)?, context, note)?;
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| EcdhError::Decrypt)?;
+    return Ok(SharedKey(bytes));
+}
+
+#[test]
+fn ecdh_vectors_and_derived_keys() {
+    use bitcoin::hashes::hex::FromHex;
+
+    let secp = Secp256k1::new();
+    let secret = |byte: u8| {
+        let mut bytes = [0u8; 32];
+        bytes[31] = byte;
+        return SecretKey::from_slice(&bytes).unwrap();
+    };
+    let x_only = |secret: &SecretKey| secret.x_only_public_key(&secp).0;
+    let x_hex = |hex: &str| XOnlyPublicKey::from_slice(&Vec::<u8>::from_hex(hex).unwrap()).unwrap();
+
+    // multiples of G: 1 * 2G = 2 * G, 2 * 3G = 3 * 2G = 6G
+    let g2 = x_hex("c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5");
+    let g6 = x_hex("fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556");
+    let point = |a: u8, b: u8| {
+        return shared_point(&secp, &secret(a), &x_only(&secret(b)))
+            .unwrap()
+            .x_only_public_key()
+            .0;
+    };
+    assert_eq!(point(1, 2), g2);
+    assert_eq!(point(2, 1), g2);
+    assert_eq!(point(2, 3), g6);
+    assert_eq!(point(3, 2), g6);
+
+    // odd y keys are negated first, both sides still agree
+    let alice = SecretKey::from_slice(&[51u8; 32]).unwrap();
+    let bob = SecretKey::from_slice(&[52u8; 32]).unwrap();
+    let carol = SecretKey::from_slice(&[53u8; 32]).unwrap();
+    let shared = pairwise_key(&secp, &alice, &x_only(&bob)).unwrap();
+    assert_eq!(shared, pairwise_key(&secp, &bob, &x_only(&alice)).unwrap());
+    assert_ne!(
+        shared,
+        pairwise_key(&secp, &alice, &x_only(&carol)).unwrap()
+    );
+
+    // both sides end up with the same pair of contract keys, a new index gives new keys
+    let a = contract_keys(&secp, &alice, &x_only(&bob), b"trade", 0).unwrap();
+    let b = contract_keys(&secp, &bob, &x_only(&alice), b"trade", 0).unwrap();
+    assert_eq!((a.ours, a.theirs), (b.theirs, b.ours));
+    assert_eq!(a.ours, a.secret_key.public_key(&secp));
+    assert_ne!(a.ours.x_only_public_key().0, x_only(&alice));
+    let next = contract_keys(&secp, &alice, &x_only(&bob), b"trade", 1).unwrap();
+    assert_ne!(next.ours, a.ours);
+
+    // known answers, computed separately from the tagged hashes and aes-256-gcm so another
+    // implementation can check it derives the same keys and reads the same notes
+    let bytes = |hex: &str| Vec::<u8>::from_hex(hex).unwrap();
+    assert_eq!(
+        shared.to_bytes().to_vec(),
+        bytes("307a3555fdffd5e0f301e0ec23793711c46946e800d50d926739a61385e98518")
+    );
+    assert_eq!(
+        derive_tweak(&shared, b"trade", 0).to_be_bytes().to_vec(),
+        bytes("1e02381e638be13ee9ef140dae7cc61bc86daaabea536518aec54f38cd973589")
+    );
+    assert_eq!(
+        a.secret_key.secret_bytes().to_vec(),
+        bytes("51356b5196bf14721d224740e1aff94efba0dddf1d86984be1f8826c00ca68bc")
+    );
+    assert_eq!(
+        a.ours.serialize().to_vec(),
+        bytes("02f6d2bedb99cb45d8254306aff06836bb374c8c4de1b2049d7e6469badaeba7c6")
+    );
+    assert_eq!(
+        a.theirs.serialize().to_vec(),
+        bytes("03e61567536e1ed444b5c5ef6d00cffb15a1eac088ae7207ec7bb0b3829589a32e")
+    );
+    let nonce: [u8; NONCE_LEN] = core::array::from_fn(|i| i as u8);
+    let pinned = seal_note_with_nonce(&shared, &nonce, b"offer 7", b"pay to the second output");
+    assert_eq!(
+        pinned,
+        bytes(concat!(
+            "000102030405060708090a0b",
+            "9a8767e4008a527a10d5b32b2c358ca4ab69601c3ab80cd9",
+            "1e20c39dc1a0c0792b751051540e2743"
+        ))
+    );
+    assert_eq!(
+        open_note(&shared, b"offer 7", &pinned).unwrap(),
+        b"pay to the second output"
+    );
+
+    let note = seal_note(&shared, b"offer 7", b"pay to the second output");
+    assert_eq!(
+        open_note(&shared, b"offer 7", &note).unwrap(),
+        b"pay to the second output"
+    );
+    assert_eq!(
+        open_note(&shared, b"offer 8", &note),
+        Err(EcdhError::Decrypt)
+    );
+    let mut changed = note.clone();
+    changed[NONCE_LEN] ^= 1;
+    assert_eq!(
+        open_note(&shared, b"offer 7", &changed),
+        Err(EcdhError::Decrypt)
+    );
+
+    let members = [x_only(&bob), x_only(&carol)];
+    let (group, notes) = seal_group(&secp, &alice, &members, b"escrow").unwrap();
+    assert_eq!(group, group_key(&secp, &alice, &members, b"escrow"));
+    assert_eq!(
+        open_group(&secp, &bob, &x_only(&alice), &notes, b"escrow").unwrap(),
+        group
+    );
+    assert_eq!(
+        open_group(&secp, &carol, &x_only(&alice), &notes, b"escrow").unwrap(),
+        group
+    );
+    let outsider = SecretKey::from_slice(&[54u8; 32]).unwrap();
+    assert_eq!(
+        open_group(&secp, &outsider, &x_only(&alice), &notes, b"escrow"),
+        Err(EcdhError::NotAMember(x_only(&outsider)))
+    );
+}
@@ -0,0 +1,885 @@
+use std::{collections::BTreeMap, fmt};
+
+use bitcoin::{
+    secp256k1::{schnorr::Signature, All, PublicKey, Scalar, Secp256k1},
+    util::taproot::TapBranchHash,
+    XOnlyPublicKey,
+};
+
+use super::{
+    base_mul, bip340::challenge, bip341::tap_tweak, has_even_y, lift_x, point_add, point_mul,
+    point_negate, scalar::ScalarN, tagged_hash, xbytes,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrostError {
+    InvalidThreshold,
+    InvalidProof(u32),
+    InvalidShare(u32),
+    UnknownSigner(u32),
+    NotEnoughSigners,
+    InvalidSignatureShare(u32),
+    InvalidTweak,
+    InfiniteNonce,
+    // participants are the x coordinates of the shares, 0 would hand out the group secret
+    InvalidIndex,
+    DuplicateIndex(u32),
+    InvalidCommitment(u32),
+    MissingShare(u32),
+}
+
+impl fmt::Display for FrostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrostError::InvalidThreshold => write!(f, "threshold has to be between 1 and n"),
+            FrostError::InvalidProof(index) => {
+                write!(f, "proof of knowledge of participant {} is invalid", index)
+            }
+            FrostError::InvalidShare(index) => {
+                write!(
+                    f,
+                    "share from participant {} does not match its commitment",
+                    index
+                )
+            }
+            FrostError::UnknownSigner(index) => write!(f, "participant {} has no key share", index),
+            FrostError::NotEnoughSigners => write!(f, "fewer signers than the threshold"),
+            FrostError::InvalidSignatureShare(index) => {
+                write!(f, "signature share of participant {} is invalid", index)
+            }
+            FrostError::InvalidTweak => write!(f, "tweak does not give a valid key"),
+            FrostError::InfiniteNonce => write!(f, "group nonce is the point at infinity"),
+            FrostError::InvalidIndex => write!(f, "participant indices start at 1"),
+            FrostError::DuplicateIndex(index) => {
+                write!(f, "participant {} shows up more than once", index)
+            }
+            FrostError::InvalidCommitment(index) => {
+                write!(
+                    f,
+                    "participant {} did not commit to one point per coefficient",
+                    index
+                )
+            }
+            FrostError::MissingShare(index) => {
+                write!(f, "participant {} did not send its share", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrostError {}
+
+// a participant's point on the shared polynomial, indices start at 1
+#[derive(Debug, Clone)]
+pub struct KeyShare {
+    pub index: u32,
+    secret: ScalarN,
+}
+
+// what everyone may know, the group key and the public side of every share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKeyPackage {
+    pub threshold: usize,
+    pub group_key: PublicKey,
+    pub verifying_shares: BTreeMap<u32, PublicKey>,
+}
+
+impl PublicKeyPackage {
+    pub fn x_only(&self) -> XOnlyPublicKey {
+        return self.group_key.x_only_public_key().0;
+    }
+}
+
+// a dealer that knows the whole key splits it into n shares, t of them can sign
+pub fn trusted_dealer_keygen(
+    secp: &Secp256k1<All>,
+    threshold: usize,
+    participants: u32,
+) -> Result<(Vec<KeyShare>, PublicKeyPackage), FrostError> {
+    if threshold == 0 || threshold > participants as usize {
+        return Err(FrostError::InvalidThreshold);
+    }
+    return Ok(split(secp, &random_polynomial(threshold), participants));
+}
+
+fn split(
+    secp: &Secp256k1<All>,
+    coefficients: &[ScalarN],
+    participants: u32,
+) -> (Vec<KeyShare>, PublicKeyPackage) {
+    let shares = (1..=participants)
+        .map(|index| KeyShare {
+            index,
+            secret: evaluate(coefficients, index),
+        })
+        .collect::<Vec<KeyShare>>();
+    let package = PublicKeyPackage {
+        threshold: coefficients.len(),
+        group_key: base_mul(secp, &coefficients[0]).unwrap(),
+        verifying_shares: shares
+            .iter()
+            .map(|share| (share.index, base_mul(secp, &share.secret).unwrap()))
+            .collect(),
+    };
+    return (shares, package);
+}
+
+// the polynomial a participant keeps to itself during the dkg
+pub struct DkgSecret {
+    index: u32,
+    coefficients: Vec<ScalarN>,
+}
+
+// broadcast in the first round, commitments to the coefficients and a proof of knowing the first
+#[derive(Debug, Clone)]
+pub struct DkgCommitment {
+    pub index: u32,
+    pub commitments: Vec<PublicKey>,
+    proof: (PublicKey, ScalarN),
+}
+
+// sent privately from one participant to another in the second round
+#[derive(Debug, Clone)]
+pub struct DkgShare {
+    pub from: u32,
+    pub to: u32,
+    value: ScalarN,
+}
+
+pub fn dkg_round1(
+    secp: &Secp256k1<All>,
+    index: u32,
+    threshold: usize,
+    context: &[u8],
+) -> Result<(DkgSecret, DkgCommitment), FrostError> {
+    if threshold == 0 {
+        return Err(FrostError::InvalidThreshold);
+    }
+    if index == 0 {
+        return Err(FrostError::InvalidIndex);
+    }
+    let coefficients = random_polynomial(threshold);
+    let commitments = coefficients
+        .iter()
+        .map(|coefficient| base_mul(secp, coefficient).unwrap())
+        .collect::<Vec<PublicKey>>();
+    let k = random_scalar();
+    let r = base_mul(secp, &k).unwrap();
+    let c = proof_challenge(index, context, &commitments[0], &r);
+    let proof = (r, k.add(&coefficients[0].mul(&c)));
+    return Ok((
+        DkgSecret {
+            index,
+            coefficients,
+        },
+        DkgCommitment {
+            index,
+            commitments,
+            proof,
+        },
+    ));
+}
+
+// checks every proof of knowledge and evaluates our polynomial for every other participant
+pub fn dkg_round2(
+    secp: &Secp256k1<All>,
+    secret: &DkgSecret,
+    commitments: &[DkgCommitment],
+    context: &[u8],
+) -> Result<Vec<DkgShare>, FrostError> {
+    check_commitments(secret, commitments)?;
+    for commitment in commitments {
+        let (r, mu) = &commitment.proof;
+        let c = proof_challenge(commitment.index, context, &commitment.commitments[0], r);
+        let expected = point_add(
+            base_mul(secp, mu),
+            point_negate(secp, point_mul(secp, &commitment.commitments[0], &c)),
+        );
+        if expected != Some(*r) {
+            return Err(FrostError::InvalidProof(commitment.index));
+        }
+    }
+    return Ok(commitments
+        .iter()
+        .filter(|commitment| commitment.index != secret.index)
+        .map(|commitment| DkgShare {
+            from: secret.index,
+            to: commitment.index,
+            value: evaluate(&secret.coefficients, commitment.index),
+        })
+        .collect());
+}
+
+// sums what everyone sent us, each piece checked against its sender's commitments
+pub fn dkg_finalize(
+    secp: &Secp256k1<All>,
+    secret: &DkgSecret,
+    commitments: &[DkgCommitment],
+    received: &[DkgShare],
+) -> Result<(KeyShare, PublicKeyPackage), FrostError> {
+    check_commitments(secret, commitments)?;
+    let received = received
+        .iter()
+        .filter(|piece| piece.to == secret.index)
+        .collect::<Vec<&DkgShare>>();
+    let mut share = evaluate(&secret.coefficients, secret.index);
+    for commitment in commitments
+        .iter()
+        .filter(|commitment| commitment.index != secret.index)
+    {
+        let mut pieces = received
+            .iter()
+            .filter(|piece| piece.from == commitment.index);
+        let piece = pieces
+            .next()
+            .ok_or(FrostError::MissingShare(commitment.index))?;
+        if pieces.next().is_some() {
+            return Err(FrostError::DuplicateIndex(commitment.index));
+        }
+        if base_mul(secp, &piece.value)
+            != commitment_at(secp, &commitment.commitments, secret.index)
+        {
+            return Err(FrostError::InvalidShare(piece.from));
+        }
+        share = share.add(&piece.value);
+    }
+    if let Some(piece) = received.iter().find(|piece| {
+        !commitments
+            .iter()
+            .any(|commitment| commitment.index == piece.from)
+    }) {
+        return Err(FrostError::UnknownSigner(piece.from));
+    }
+
+    let group_key = commitments.iter().fold(None, |acc, commitment| {
+        point_add(acc, Some(commitment.commitments[0]))
+    });
+    let verifying_shares = commitments
+        .iter()
+        .map(|participant| {
+            let verifying_share = commitments.iter().fold(None, |acc, commitment| {
+                point_add(
+                    acc,
+                    commitment_at(secp, &commitment.commitments, participant.index),
+                )
+            });
+            (participant.index, verifying_share.unwrap())
+        })
+        .collect();
+    let package = PublicKeyPackage {
+        threshold: secret.coefficients.len(),
+        group_key: group_key.unwrap(),
+        verifying_shares,
+    };
+    return Ok((
+        KeyShare {
+            index: secret.index,
+            secret: share,
+        },
+        package,
+    ));
+}
+
+// the key that ends up in the output, the group key made even and optionally taproot tweaked,
+// parity is applied to every share and tweak is added once when aggregating
+#[derive(Debug, Clone)]
+pub struct GroupKey {
+    q: PublicKey,
+    parity: ScalarN,
+    tweak: ScalarN,
+}
+
+impl GroupKey {
+    pub fn untweaked(package: &PublicKeyPackage) -> Self {
+        return GroupKey {
+            q: lift_x(&package.x_only()),
+            parity: sign_of(&package.group_key),
+            tweak: ScalarN::ZERO,
+        };
+    }
+
+    pub fn taproot(
+        secp: &Secp256k1<All>,
+        package: &PublicKeyPackage,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<Self, FrostError> {
+        let internal = package.x_only();
+        let t = ScalarN::from_be_bytes_checked(tap_tweak(&internal, merkle_root))
+            .ok_or(FrostError::InvalidTweak)?;
+        let q = point_add(Some(lift_x(&internal)), base_mul(secp, &t))
+            .ok_or(FrostError::InvalidTweak)?;
+        let g = sign_of(&q);
+        return Ok(GroupKey {
+            q,
+            parity: g.mul(&sign_of(&package.group_key)),
+            tweak: g.mul(&t),
+        });
+    }
+
+    pub fn x_only(&self) -> XOnlyPublicKey {
+        return self.q.x_only_public_key().0;
+    }
+}
+
+// single use nonces, consumed when signing
+pub struct SigningNonces {
+    pub index: u32,
+    hiding: ScalarN,
+    binding: ScalarN,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigningCommitment {
+    pub index: u32,
+    pub hiding: PublicKey,
+    pub binding: PublicKey,
+}
+
+pub fn preprocess(secp: &Secp256k1<All>, share: &KeyShare) -> (SigningNonces, SigningCommitment) {
+    let hiding = random_scalar();
+    let binding = random_scalar();
+    return (
+        SigningNonces {
+            index: share.index,
+            hiding,
+            binding,
+        },
+        SigningCommitment {
+            index: share.index,
+            hiding: base_mul(secp, &hiding).unwrap(),
+            binding: base_mul(secp, &binding).unwrap(),
+        },
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureShare {
+    pub index: u32,
+    z: ScalarN,
+}
+
+// the values the coordinator and every signer derive from the commitments of this signing set
+pub struct FrostSession {
+    key: GroupKey,
+    commitments: Vec<SigningCommitment>,
+    binding_factors: BTreeMap<u32, ScalarN>,
+    r: PublicKey,
+    c: ScalarN,
+}
+
+impl FrostSession {
+    pub fn new(
+        secp: &Secp256k1<All>,
+        key: &GroupKey,
+        commitments: &[SigningCommitment],
+        msg: &[u8],
+    ) -> Result<Self, FrostError> {
+        let mut commitments = commitments.to_vec();
+        commitments.sort_by_key(|commitment| commitment.index);
+        // lagrange divides by the difference of every pair of indices
+        if commitments.first().map(|commitment| commitment.index) == Some(0) {
+            return Err(FrostError::InvalidIndex);
+        }
+        if let Some(pair) = commitments
+            .windows(2)
+            .find(|pair| pair[0].index == pair[1].index)
+        {
+            return Err(FrostError::DuplicateIndex(pair[0].index));
+        }
+        let encoded = commitments
+            .iter()
+            .flat_map(|commitment| {
+                [
+                    &commitment.index.to_be_bytes()[..],
+                    &commitment.hiding.serialize(),
+                    &commitment.binding.serialize(),
+                ]
+                .concat()
+            })
+            .collect::<Vec<u8>>();
+        // RFC 9591 derives rho_i with hash_to_field over the FROST-secp256k1-SHA256-v1 context
+        // and signs with its own challenge, neither gives a signature a taproot output accepts.
+        // this keeps the RFC's inputs, the group key, the message, the encoded commitment list
+        // and the signer, but hashes them with a BIP340 tagged hash and uses the BIP340
+        // challenge, so the RFC vectors don't apply and frost_matches_pinned_vectors pins ours
+        let q = xbytes(&key.q);
+        let binding_factors = commitments
+            .iter()
+            .map(|commitment| {
+                let rho = tagged_hash(
+                    "FROST/binding",
+                    &[&q, msg, &encoded, &commitment.index.to_be_bytes()],
+                );
+                (commitment.index, ScalarN::from_be_bytes(rho))
+            })
+            .collect::<BTreeMap<u32, ScalarN>>();
+        let r = commitments.iter().fold(None, |acc, commitment| {
+            let rho = &binding_factors[&commitment.index];
+            point_add(
+                acc,
+                point_add(
+                    Some(commitment.hiding),
+                    point_mul(secp, &commitment.binding, rho),
+                ),
+            )
+        });
+        let r = r.ok_or(FrostError::InfiniteNonce)?;
+        let c = challenge(&xbytes(&r), &q, msg);
+        return Ok(FrostSession {
+            key: key.clone(),
+            commitments,
+            binding_factors,
+            r,
+            c,
+        });
+    }
+
+    pub fn sign(
+        &self,
+        nonces: SigningNonces,
+        share: &KeyShare,
+    ) -> Result<SignatureShare, FrostError> {
+        let rho = self
+            .binding_factors
+            .get(&share.index)
+            .ok_or(FrostError::UnknownSigner(share.index))?;
+        let lambda = self.lagrange(share.index);
+        let nonce = nonces.hiding.add(&nonces.binding.mul(rho));
+        let z = sign_of(&self.r)
+            .mul(&nonce)
+            .add(&lambda.mul(&self.key.parity).mul(&share.secret).mul(&self.c));
+        return Ok(SignatureShare {
+            index: share.index,
+            z,
+        });
+    }
+
+    pub fn verify_share(
+        &self,
+        secp: &Secp256k1<All>,
+        share: &SignatureShare,
+        verifying_share: &PublicKey,
+    ) -> bool {
+        let commitment = match self
+            .commitments
+            .iter()
+            .find(|commitment| commitment.index == share.index)
+        {
+            Some(commitment) => commitment,
+            None => return false,
+        };
+        let rho = &self.binding_factors[&share.index];
+        let nonce = point_add(
+            Some(commitment.hiding),
+            point_mul(secp, &commitment.binding, rho),
+        );
+        let nonce = match has_even_y(&self.r) {
+            true => nonce,
+            false => point_negate(secp, nonce),
+        };
+        let lambda = self.lagrange(share.index);
+        let expected = point_add(
+            nonce,
+            point_mul(
+                secp,
+                verifying_share,
+                &self.c.mul(&lambda).mul(&self.key.parity),
+            ),
+        );
+        return base_mul(secp, &share.z) == expected;
+    }
+
+    pub fn aggregate(&self, shares: &[SignatureShare]) -> Signature {
+        let z = shares
+            .iter()
+            .fold(ScalarN::ZERO, |acc, share| acc.add(&share.z))
+            .add(&self.c.mul(&self.key.tweak));
+        let sig = [xbytes(&self.r), z.to_be_bytes()].concat();
+        return Signature::from_slice(&sig).unwrap();
+    }
+
+    fn lagrange(&self, index: u32) -> ScalarN {
+        let x_i = ScalarN::from_u64(index as u64);
+        return self
+            .commitments
+            .iter()
+            .filter(|commitment| commitment.index != index)
+            .fold(ScalarN::ONE, |acc, commitment| {
+                let x_j = ScalarN::from_u64(commitment.index as u64);
+                let denominator = x_j.sub(&x_i).invert();
+                acc.mul(&x_j)
+                    .mul(&denominator.expect("duplicate indices are rejected in new"))
+            });
+    }
+}
+
+// runs preprocessing, signing and aggregation for signers that all live in this process
+pub fn sign_locally(
+    secp: &Secp256k1<All>,
+    key: &GroupKey,
+    package: &PublicKeyPackage,
+    shares: &[KeyShare],
+    msg: &[u8],
+) -> Result<Signature, FrostError> {
+    if shares.len() < package.threshold {
+        return Err(FrostError::NotEnoughSigners);
+    }
+    let (nonces, commitments): (Vec<SigningNonces>, Vec<SigningCommitment>) =
+        shares.iter().map(|share| preprocess(secp, share)).unzip();
+    let session = FrostSession::new(secp, key, &commitments, msg)?;
+    let mut signature_shares = vec![];
+    for (nonces, share) in nonces.into_iter().zip(shares) {
+        let verifying_share = package
+            .verifying_shares
+            .get(&share.index)
+            .ok_or(FrostError::UnknownSigner(share.index))?;
+        let signature_share = session.sign(nonces, share)?;
+        if !session.verify_share(secp, &signature_share, verifying_share) {
+            return Err(FrostError::InvalidSignatureShare(share.index));
+        }
+        signature_shares.push(signature_share);
+    }
+    return Ok(session.aggregate(&signature_shares));
+}
+
+// every participant shows up once, with a valid index and one commitment per coefficient of a
+// polynomial of our degree, and we are one of them
+fn check_commitments(secret: &DkgSecret, commitments: &[DkgCommitment]) -> Result<(), FrostError> {
+    let mut seen = vec![];
+    for commitment in commitments {
+        if commitment.index == 0 {
+            return Err(FrostError::InvalidIndex);
+        }
+        if seen.contains(&commitment.index) {
+            return Err(FrostError::DuplicateIndex(commitment.index));
+        }
+        if commitment.commitments.len() != secret.coefficients.len() {
+            return Err(FrostError::InvalidCommitment(commitment.index));
+        }
+        seen.push(commitment.index);
+    }
+    if !seen.contains(&secret.index) {
+        return Err(FrostError::UnknownSigner(secret.index));
+    }
+    return Ok(());
+}
+
+fn random_scalar() -> ScalarN {
+    return ScalarN::from_be_bytes(Scalar::random().to_be_bytes());
+}
+
+fn random_polynomial(threshold: usize) -> Vec<ScalarN> {
+    return (0..threshold).map(|_| random_scalar()).collect();
+}
+
+// horner, f(x) = a0 + a1 x + ... + a(t-1) x^(t-1)
+fn evaluate(coefficients: &[ScalarN], index: u32) -> ScalarN {
+    let x = ScalarN::from_u64(index as u64);
+    return coefficients
+        .iter()
+        .rev()
+        .fold(ScalarN::ZERO, |acc, coefficient| {
+            acc.mul(&x).add(coefficient)
+        });
+}
+
+// sum of C_k * x^k, the public counterpart of evaluate
+fn commitment_at(
+    secp: &Secp256k1<All>,
+    commitments: &[PublicKey],
+    index: u32,
+) -> Option<PublicKey> {
+    let x = ScalarN::from_u64(index as u64);
+    return commitments
+        .iter()
+        .fold((None, ScalarN::ONE), |(acc, power), commitment| {
+            (
+                point_add(acc, point_mul(secp, commitment, &power)),
+                power.mul(&x),
+            )
+        })
+        .0;
+}
+
+fn proof_challenge(index: u32, context: &[u8], commitment: &PublicKey, r: &PublicKey) -> ScalarN {
+    return ScalarN::from_be_bytes(tagged_hash(
+        "FROST/dkg",
+        &[
+            &index.to_be_bytes(),
+            context,
+            &commitment.serialize(),
+            &r.serialize(),
+        ],
+    ));
+}
+
+fn sign_of(point: &PublicKey) -> ScalarN {
+    return match has_even_y(point) {
+        true => ScalarN::ONE,
+        false => ScalarN::ONE.negate(),
+    };
+}
+
+#[test]
+fn any_threshold_subset_signs_for_the_group_key() {
+    use bitcoin::secp256k1::Message;
+
+    let secp = Secp256k1::new();
+    let msg = [3u8; 32];
+    let message = Message::from_slice(&msg).unwrap();
+
+    let (shares, package) = trusted_dealer_keygen(&secp, 2, 3).unwrap();
+    [[0, 1], [0, 2], [1, 2]].iter().for_each(|subset| {
+        let signers = subset
+            .iter()
+            .map(|index| shares[*index].clone())
+            .collect::<Vec<KeyShare>>();
+        let key = GroupKey::taproot(&secp, &package, None).unwrap();
+        let sig = sign_locally(&secp, &key, &package, &signers, &msg).unwrap();
+        secp.verify_schnorr(&sig, &message, &key.x_only()).unwrap();
+
+        let key = GroupKey::untweaked(&package);
+        let sig = sign_locally(&secp, &key, &package, &signers, &msg).unwrap();
+        secp.verify_schnorr(&sig, &message, &package.x_only())
+            .unwrap();
+    });
+    assert_eq!(
+        sign_locally(
+            &secp,
+            &GroupKey::untweaked(&package),
+            &package,
+            &shares[..1],
+            &msg
+        )
+        .err(),
+        Some(FrostError::NotEnoughSigners)
+    );
+
+    // a dkg between three participants ends up with one group key that two of them can use
+    let context = b"treasury";
+    let rounds = (1..=3)
+        .map(|index| dkg_round1(&secp, index, 2, context).unwrap())
+        .collect::<Vec<_>>();
+    let commitments = rounds
+        .iter()
+        .map(|(_, commitment)| commitment.clone())
+        .collect::<Vec<DkgCommitment>>();
+    let sent = rounds
+        .iter()
+        .flat_map(|(secret, _)| dkg_round2(&secp, secret, &commitments, context).unwrap())
+        .collect::<Vec<DkgShare>>();
+    let results = rounds
+        .iter()
+        .map(|(secret, _)| dkg_finalize(&secp, secret, &commitments, &sent).unwrap())
+        .collect::<Vec<_>>();
+    assert!(results.iter().all(|(_, package)| *package == results[0].1));
+
+    let package = &results[0].1;
+    let signers = vec![results[0].0.clone(), results[2].0.clone()];
+    let key = GroupKey::taproot(&secp, package, None).unwrap();
+    let sig = sign_locally(&secp, &key, package, &signers, &msg).unwrap();
+    secp.verify_schnorr(&sig, &message, &key.x_only()).unwrap();
+
+    let mut forged = commitments.clone();
+    forged[1].proof.1 = forged[1].proof.1.add(&ScalarN::ONE);
+    assert!(matches!(
+        dkg_round2(&secp, &rounds[0].0, &forged, context),
+        Err(FrostError::InvalidProof(2))
+    ));
+
+    // malformed rounds are refused instead of panicking
+    let secret = &rounds[0].0;
+    assert!(matches!(
+        dkg_round1(&secp, 0, 2, context),
+        Err(FrostError::InvalidIndex)
+    ));
+    assert!(matches!(
+        dkg_round2(&secp, secret, &[], context),
+        Err(FrostError::UnknownSigner(1))
+    ));
+    let mut short = commitments.clone();
+    short[2].commitments.truncate(1);
+    assert!(matches!(
+        dkg_round2(&secp, secret, &short, context),
+        Err(FrostError::InvalidCommitment(3))
+    ));
+    let mut empty = commitments.clone();
+    empty[2].commitments.clear();
+    assert!(matches!(
+        dkg_finalize(&secp, secret, &empty, &sent),
+        Err(FrostError::InvalidCommitment(3))
+    ));
+    let duplicated = [commitments.clone(), vec![commitments[1].clone()]].concat();
+    assert!(matches!(
+        dkg_round2(&secp, secret, &duplicated, context),
+        Err(FrostError::DuplicateIndex(2))
+    ));
+    let mut zero = commitments.clone();
+    zero[1].index = 0;
+    assert!(matches!(
+        dkg_finalize(&secp, secret, &zero, &sent),
+        Err(FrostError::InvalidIndex)
+    ));
+    let withheld = sent
+        .iter()
+        .filter(|piece| piece.from != 3)
+        .cloned()
+        .collect::<Vec<DkgShare>>();
+    assert!(matches!(
+        dkg_finalize(&secp, secret, &commitments, &withheld),
+        Err(FrostError::MissingShare(3))
+    ));
+    let twice = [sent.clone(), vec![sent[2].clone()]].concat();
+    assert!(matches!(
+        dkg_finalize(&secp, secret, &commitments, &twice),
+        Err(FrostError::DuplicateIndex(2))
+    ));
+
+    // the same signer twice would divide by zero in the lagrange coefficient
+    let (_, commitment) = preprocess(&secp, &signers[0]);
+    assert!(matches!(
+        FrostSession::new(&secp, &key, &[commitment, commitment], &msg),
+        Err(FrostError::DuplicateIndex(1))
+    ));
+}
+
+// the binding factors and challenge are our own construction, see FrostSession::new, so these
+// pin them for a fixed polynomial and fixed nonces
+#[test]
+fn frost_matches_pinned_vectors() {
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::secp256k1::Message;
+    use std::str::FromStr;
+
+    let secp = Secp256k1::new();
+    let scalar = |hex: &str| ScalarN::from_slice(&Vec::<u8>::from_hex(hex).unwrap()).unwrap();
+    let coefficients = [
+        ScalarN::from_be_bytes([0x11; 32]),
+        ScalarN::from_be_bytes([0x22; 32]),
+    ];
+    let (shares, package) = split(&secp, &coefficients, 3);
+    assert_eq!(
+        package.group_key,
+        PublicKey::from_str("034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa")
+            .unwrap()
+    );
+    assert_eq!(
+        shares
+            .iter()
+            .map(|share| share.secret.to_be_bytes())
+            .collect::<Vec<_>>(),
+        vec![[0x33; 32], [0x55; 32], [0x77; 32]]
+    );
+
+    let signers = [shares[0].clone(), shares[2].clone()];
+    let (nonces, commitments): (Vec<SigningNonces>, Vec<SigningCommitment>) = signers
+        .iter()
+        .zip([([0x31; 32], [0x32; 32]), ([0x33; 32], [0x34; 32])])
+        .map(|(share, (hiding, binding))| {
+            let hiding = ScalarN::from_be_bytes(hiding);
+            let binding = ScalarN::from_be_bytes(binding);
+            let commitment = SigningCommitment {
+                index: share.index,
+                hiding: base_mul(&secp, &hiding).unwrap(),
+                binding: base_mul(&secp, &binding).unwrap(),
+            };
+            let nonces = SigningNonces {
+                index: share.index,
+                hiding,
+                binding,
+            };
+            (nonces, commitment)
+        })
+        .unzip();
+    let key = GroupKey::untweaked(&package);
+    let msg = [0x42; 32];
+    let session = FrostSession::new(&secp, &key, &commitments, &msg).unwrap();
+    assert_eq!(
+        session.binding_factors[&1],
+        scalar("bc473abc832b3b5ed3c696b1d3e3ed519359c7ecd487974b1ca21fb27ce26e8a")
+    );
+    assert_eq!(
+        session.binding_factors[&3],
+        scalar("eb263347d129c261ab6b03353021ec02cbcecd61b8d40e2fb72d0e94aaa47897")
+    );
+    assert_eq!(
+        session.r,
+        PublicKey::from_str("027a529626538a1a61e03ff00c77c455f9ec152d87f294db3f74d121b876b7df44")
+            .unwrap()
+    );
+
+    let signature_shares = nonces
+        .into_iter()
+        .zip(&signers)
+        .map(|(nonces, share)| session.sign(nonces, share).unwrap())
+        .collect::<Vec<SignatureShare>>();
+    assert_eq!(
+        signature_shares
+            .iter()
+            .map(|share| share.z)
+            .collect::<Vec<_>>(),
+        vec![
+            scalar("b042aa6b11598c9d1fbc890ce78037860834b87fe136ae3f1edf336433c39191"),
+            scalar("dda29892a72c639b56ade4ac901b2276def7dd1909f1b75e2c06f72a25a16233"),
+        ]
+    );
+    let sig = session.aggregate(&signature_shares);
+    assert_eq!(
+        sig,
+        Signature::from_str("7a529626538a1a61e03ff00c77c455f9ec152d87f294db3f74d121b876b7df448de542fdb885f038766a6db9779b59fe2c7db8b23bdfc5618b13cc01892eb283").unwrap()
+    );
+    secp.verify_schnorr(&sig, &Message::from_slice(&msg).unwrap(), &key.x_only())
+        .unwrap();
+}
+
+#[test]
+fn frost_unlock_fn_spends_a_taproot_key_path() {
+    use bitcoin::{Script, Transaction};
+
+    use crate::bitcoin_wallet::{
+        input_data::mock_call::MockCall,
+        script_services::{
+            input_service::{insert_witness, insert_witness_tx_out, sign_frost_key_sig},
+            output_service::new_witness_pub_k,
+            psbt_factory::{create_partially_signed_tx, UnlockFn},
+            tap_finalizer::finalize_tap_psbt,
+        },
+        spending_path::single_create_tx,
+    };
+
+    let secp = Secp256k1::new();
+    let (shares, package) = trusted_dealer_keygen(&secp, 2, 3).unwrap();
+    let script_pubkey = Script::new_v1_p2tr(&secp, package.x_only(), None);
+    let client = MockCall::fund(&script_pubkey, &[40_000, 25_000]);
+    let signers = &shares[1..];
+
+    let psbt = create_partially_signed_tx(
+        vec![vec![new_witness_pub_k(script_pubkey.clone())]],
+        single_create_tx(),
+        Box::new(|previous_list: Vec<Transaction>, current_tx: Transaction| {
+            let prevouts = previous_list
+                .iter()
+                .flat_map(|tx| tx.output.clone())
+                .collect::<Vec<_>>();
+            return (0..previous_list.len())
+                .map(|index| {
+                    let unlock_vec: Vec<UnlockFn> = vec![
+                        insert_witness_tx_out(prevouts[index].clone()),
+                        insert_witness(script_pubkey.clone()),
+                        sign_frost_key_sig(
+                            &secp,
+                            signers,
+                            &package,
+                            current_tx.clone(),
+                            prevouts.clone(),
+                            index,
+                        ),
+                    ];
+                    unlock_vec
+                })
+                .collect();
+        }),
+    )(&client);
+    let tx = finalize_tap_psbt(psbt).unwrap();
+    assert!(tx.input.iter().all(|tx_in| tx_in.witness.len() == 1));
+}
@@ -0,0 +1,83 @@
+pub mod adaptor;
+pub mod bip340;
+pub mod bip341;
+pub mod ecdh;
+pub mod frost;
+pub mod musig2;
+pub mod scalar;
+
+use bitcoin::{
+    secp256k1::{All, PublicKey, Scalar, Secp256k1, SecretKey},
+    XOnlyPublicKey,
+};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+
+use self::scalar::ScalarN;
+
+// sha256(sha256(tag) || sha256(tag) || data...)
+pub fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash);
+    engine.input(&tag_hash);
+    data.iter().for_each(|bytes| engine.input(bytes));
+    return sha256::Hash::from_engine(engine).into_inner();
+}
+
+pub fn has_even_y(point: &PublicKey) -> bool {
+    return point.serialize()[0] == 0x02;
+}
+
+pub fn xbytes(point: &PublicKey) -> [u8; 32] {
+    return point.x_only_public_key().0.serialize();
+}
+
+// the point with even y for an x only key
+pub fn lift_x(x_only: &XOnlyPublicKey) -> PublicKey {
+    return x_only.public_key(bitcoin::secp256k1::Parity::Even);
+}
+
+// points are Option<PublicKey> wherever the sum can be the point at infinity
+pub fn base_mul(secp: &Secp256k1<All>, scalar: &ScalarN) -> Option<PublicKey> {
+    let secret = SecretKey::from_slice(&scalar.to_be_bytes()).ok()?;
+    return Some(PublicKey::from_secret_key(secp, &secret));
+}
+
+pub fn point_mul(secp: &Secp256k1<All>, point: &PublicKey, scalar: &ScalarN) -> Option<PublicKey> {
+    if scalar.is_zero() {
+        return None;
+    }
+    let tweak = Scalar::from_be_bytes(scalar.to_be_bytes()).unwrap();
+    return point.mul_tweak(secp, &tweak).ok();
+}
+
+pub fn point_add(a: Option<PublicKey>, b: Option<PublicKey>) -> Option<PublicKey> {
+    return match (a, b) {
+        (Some(a), Some(b)) => a.combine(&b).ok(),
+        (Some(a), None) => Some(a),
+        (None, b) => b,
+    };
+}
+
+pub fn point_negate(secp: &Secp256k1<All>, point: Option<PublicKey>) -> Option<PublicKey> {
+    return point.map(|point| point.negate(secp));
+}
+
+// 33 zero bytes stand for infinity, as in BIP327 cbytes_ext
+pub fn cbytes_ext(point: &Option<PublicKey>) -> [u8; 33] {
+    return match point {
+        Some(point) => point.serialize(),
+        None => [0u8; 33],
+    };
+}
+
+pub fn cpoint_ext(bytes: &[u8]) -> Option<Option<PublicKey>> {
+    if bytes.len() == 33 && bytes.iter().all(|byte| *byte == 0) {
+        return Some(None);
+    }
+    return PublicKey::from_slice(bytes).ok().map(Some);
+}
+
+pub fn secret_scalar(secret_key: &SecretKey) -> ScalarN {
+    return ScalarN::from_be_bytes(secret_key.secret_bytes());
+}
@@ -0,0 +1,902 @@
+use std::fmt;
+
+use bitcoin::{
+    secp256k1::{schnorr::Signature, All, PublicKey, Scalar, Secp256k1, SecretKey},
+    util::taproot::TapBranchHash,
+    XOnlyPublicKey,
+};
+
+use super::{
+    adaptor::AdaptorSignature, base_mul, bip341::tap_tweak, cbytes_ext, cpoint_ext, has_even_y,
+    point_add, point_mul, point_negate, scalar::ScalarN, secret_scalar, tagged_hash, xbytes,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MusigError {
+    NoKeys,
+    InfiniteAggregate,
+    InvalidTweak,
+    InvalidNonce,
+    UnknownSigner,
+    SecretKeyMismatch,
+    SignerCountMismatch,
+    InvalidPartialSig(usize),
+    InvalidSighash(usize),
+    InvalidAggregate,
+}
+
+impl fmt::Display for MusigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MusigError::NoKeys => write!(f, "no public keys to aggregate"),
+            MusigError::InfiniteAggregate => write!(f, "aggregate is the point at infinity"),
+            MusigError::InvalidTweak => write!(f, "tweak is not below the group order"),
+            MusigError::InvalidNonce => write!(f, "invalid nonce"),
+            MusigError::UnknownSigner => write!(f, "signer is not part of the aggregate key"),
+            MusigError::SecretKeyMismatch => {
+                write!(f, "secret key does not belong to the secret nonce")
+            }
+            MusigError::SignerCountMismatch => {
+                write!(f, "need one pubnonce and one partial signature per key")
+            }
+            MusigError::InvalidPartialSig(index) => {
+                write!(f, "partial signature {} does not verify", index)
+            }
+            MusigError::InvalidSighash(index) => {
+                write!(f, "no key path sighash for input {}", index)
+            }
+            MusigError::InvalidAggregate => {
+                write!(
+                    f,
+                    "aggregate signature does not verify against the output key"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MusigError {}
+
+// lexicographic order of the compressed keys, so every signer ends up with the same aggregate
+pub fn key_sort(pub_keys: &[PublicKey]) -> Vec<PublicKey> {
+    let mut sorted = pub_keys.to_vec();
+    sorted.sort_by_key(|pub_key| pub_key.serialize());
+    return sorted;
+}
+
+// BIP327 KeyAgg plus the tweaks applied so far, gacc and tacc track the sign and the sum of them
+#[derive(Debug, Clone)]
+pub struct KeyAggContext {
+    pub_keys: Vec<PublicKey>,
+    list_hash: [u8; 32],
+    second_key: Option<PublicKey>,
+    q: PublicKey,
+    gacc: ScalarN,
+    tacc: ScalarN,
+}
+
+impl KeyAggContext {
+    pub fn new(secp: &Secp256k1<All>, pub_keys: &[PublicKey]) -> Result<Self, MusigError> {
+        let first = *pub_keys.first().ok_or(MusigError::NoKeys)?;
+        let serialized = pub_keys
+            .iter()
+            .flat_map(|pub_key| pub_key.serialize())
+            .collect::<Vec<u8>>();
+        let list_hash = tagged_hash("KeyAgg list", &[&serialized]);
+        let second_key = pub_keys.iter().find(|pub_key| **pub_key != first).copied();
+        let mut ctx = KeyAggContext {
+            pub_keys: pub_keys.to_vec(),
+            list_hash,
+            second_key,
+            q: first,
+            gacc: ScalarN::ONE,
+            tacc: ScalarN::ZERO,
+        };
+        let q = pub_keys.iter().fold(None, |acc, pub_key| {
+            point_add(acc, point_mul(secp, pub_key, &ctx.coefficient(pub_key)))
+        });
+        ctx.q = q.ok_or(MusigError::InfiniteAggregate)?;
+        return Ok(ctx);
+    }
+
+    pub fn sorted(secp: &Secp256k1<All>, pub_keys: &[PublicKey]) -> Result<Self, MusigError> {
+        return KeyAggContext::new(secp, &key_sort(pub_keys));
+    }
+
+    pub fn aggregate_key(&self) -> PublicKey {
+        return self.q;
+    }
+
+    pub fn x_only(&self) -> XOnlyPublicKey {
+        return self.q.x_only_public_key().0;
+    }
+
+    pub fn apply_tweak(
+        mut self,
+        secp: &Secp256k1<All>,
+        tweak: [u8; 32],
+        is_xonly: bool,
+    ) -> Result<Self, MusigError> {
+        let g = match is_xonly && !has_even_y(&self.q) {
+            true => ScalarN::ONE.negate(),
+            false => ScalarN::ONE,
+        };
+        let t = ScalarN::from_be_bytes_checked(tweak).ok_or(MusigError::InvalidTweak)?;
+        let q = point_add(point_mul(secp, &self.q, &g), base_mul(secp, &t));
+        self.q = q.ok_or(MusigError::InfiniteAggregate)?;
+        self.gacc = g.mul(&self.gacc);
+        self.tacc = t.add(&g.mul(&self.tacc));
+        return Ok(self);
+    }
+
+    // turns the aggregate into the output key of a taproot output with that internal key
+    pub fn apply_taproot_tweak(
+        self,
+        secp: &Secp256k1<All>,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<Self, MusigError> {
+        let tweak = tap_tweak(&self.x_only(), merkle_root);
+        return self.apply_tweak(secp, tweak, true);
+    }
+
+    fn coefficient(&self, pub_key: &PublicKey) -> ScalarN {
+        if Some(*pub_key) == self.second_key {
+            return ScalarN::ONE;
+        }
+        let hash = tagged_hash(
+            "KeyAgg coefficient",
+            &[&self.list_hash, &pub_key.serialize()],
+        );
+        return ScalarN::from_be_bytes(hash);
+    }
+}
+
+// kept by the signer only, it is consumed by signing so a nonce can't be used twice
+pub struct SecNonce {
+    k1: ScalarN,
+    k2: ScalarN,
+    pub_key: PublicKey,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PubNonce {
+    r1: PublicKey,
+    r2: PublicKey,
+}
+
+impl PubNonce {
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0u8; 66];
+        bytes[..33].copy_from_slice(&self.r1.serialize());
+        bytes[33..].copy_from_slice(&self.r2.serialize());
+        return bytes;
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, MusigError> {
+        if bytes.len() != 66 {
+            return Err(MusigError::InvalidNonce);
+        }
+        let r1 = PublicKey::from_slice(&bytes[..33]).map_err(|_| MusigError::InvalidNonce)?;
+        let r2 = PublicKey::from_slice(&bytes[33..]).map_err(|_| MusigError::InvalidNonce)?;
+        return Ok(PubNonce { r1, r2 });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AggNonce {
+    r1: Option<PublicKey>,
+    r2: Option<PublicKey>,
+}
+
+impl AggNonce {
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0u8; 66];
+        bytes[..33].copy_from_slice(&cbytes_ext(&self.r1));
+        bytes[33..].copy_from_slice(&cbytes_ext(&self.r2));
+        return bytes;
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, MusigError> {
+        if bytes.len() != 66 {
+            return Err(MusigError::InvalidNonce);
+        }
+        let r1 = cpoint_ext(&bytes[..33]).ok_or(MusigError::InvalidNonce)?;
+        let r2 = cpoint_ext(&bytes[33..]).ok_or(MusigError::InvalidNonce)?;
+        return Ok(AggNonce { r1, r2 });
+    }
+}
+
+// BIP327 NonceGen, rand has to be fresh randomness for every signing session
+pub fn nonce_gen(
+    secp: &Secp256k1<All>,
+    rand: [u8; 32],
+    secret_key: Option<&SecretKey>,
+    pub_key: PublicKey,
+    agg_pk: Option<XOnlyPublicKey>,
+    msg: Option<&[u8]>,
+    extra_in: &[u8],
+) -> Result<(SecNonce, PubNonce), MusigError> {
+    let rand = match secret_key {
+        Some(secret_key) => {
+            let aux = tagged_hash("MuSig/aux", &[&rand]);
+            let mut masked = secret_key.secret_bytes();
+            masked
+                .iter_mut()
+                .zip(aux)
+                .for_each(|(byte, aux)| *byte ^= aux);
+            masked
+        }
+        None => rand,
+    };
+    let agg_pk = agg_pk
+        .map(|key| key.serialize().to_vec())
+        .unwrap_or_default();
+    let msg_prefixed = match msg {
+        Some(msg) => [&[1u8][..], &(msg.len() as u64).to_be_bytes(), msg].concat(),
+        None => vec![0u8],
+    };
+    let pub_key_bytes = pub_key.serialize();
+    let k = |index: u8| {
+        ScalarN::from_be_bytes(tagged_hash(
+            "MuSig/nonce",
+            &[
+                &rand,
+                &[pub_key_bytes.len() as u8],
+                &pub_key_bytes,
+                &[agg_pk.len() as u8],
+                &agg_pk,
+                &msg_prefixed,
+                &(extra_in.len() as u32).to_be_bytes(),
+                extra_in,
+                &[index],
+            ],
+        ))
+    };
+    let (k1, k2) = (k(0), k(1));
+    let r1 = base_mul(secp, &k1).ok_or(MusigError::InvalidNonce)?;
+    let r2 = base_mul(secp, &k2).ok_or(MusigError::InvalidNonce)?;
+    return Ok((SecNonce { k1, k2, pub_key }, PubNonce { r1, r2 }));
+}
+
+// nonce_gen with randomness from the rng and the session message mixed in
+pub fn random_nonce(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    ctx: &KeyAggContext,
+    msg: &[u8],
+) -> Result<(SecNonce, PubNonce), MusigError> {
+    return nonce_gen(
+        secp,
+        Scalar::random().to_be_bytes(),
+        Some(secret_key),
+        PublicKey::from_secret_key(secp, secret_key),
+        Some(ctx.x_only()),
+        Some(msg),
+        &[],
+    );
+}
+
+pub fn nonce_agg(pub_nonces: &[PubNonce]) -> AggNonce {
+    return pub_nonces
+        .iter()
+        .fold(AggNonce { r1: None, r2: None }, |acc, nonce| AggNonce {
+            r1: point_add(acc.r1, Some(nonce.r1)),
+            r2: point_add(acc.r2, Some(nonce.r2)),
+        });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialSig(ScalarN);
+
+impl PartialSig {
+    pub fn serialize(&self) -> [u8; 32] {
+        return self.0.to_be_bytes();
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        return ScalarN::from_slice(bytes).map(PartialSig);
+    }
+}
+
+// what the signers of one input exchange, the pubnonce and partial signature of every signer in
+// the same order as the keys, nothing in here is secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningRound {
+    pub pub_nonces: Vec<PubNonce>,
+    pub partial_sigs: Vec<PartialSig>,
+}
+
+// the values every signer derives from the aggregate nonce, the tweaked key and the message
+pub struct Session {
+    ctx: KeyAggContext,
+    b: ScalarN,
+    r: PublicKey,
+    e: ScalarN,
+}
+
+impl Session {
+    pub fn new(
+        secp: &Secp256k1<All>,
+        ctx: &KeyAggContext,
+        agg_nonce: &AggNonce,
+        msg: &[u8],
+    ) -> Self {
+        return Session::with_adaptor(secp, ctx, agg_nonce, msg, None);
+    }
+
+    // the adaptor point is added to the final nonce, the aggregate is then a pre signature that
+    // only becomes valid with the adaptor secret
+    pub fn with_adaptor(
+        secp: &Secp256k1<All>,
+        ctx: &KeyAggContext,
+        agg_nonce: &AggNonce,
+        msg: &[u8],
+        adaptor_point: Option<PublicKey>,
+    ) -> Self {
+        let q = xbytes(&ctx.q);
+        let b = ScalarN::from_be_bytes(tagged_hash(
+            "MuSig/noncecoef",
+            &[&agg_nonce.serialize(), &q, msg],
+        ));
+        let r = point_add(
+            agg_nonce.r1,
+            agg_nonce.r2.and_then(|r2| point_mul(secp, &r2, &b)),
+        );
+        let r = point_add(r, adaptor_point);
+        let r = r.unwrap_or_else(|| base_mul(secp, &ScalarN::ONE).unwrap());
+        let e = ScalarN::from_be_bytes(tagged_hash("BIP0340/challenge", &[&xbytes(&r), &q, msg]));
+        return Session {
+            ctx: ctx.clone(),
+            b,
+            r,
+            e,
+        };
+    }
+
+    pub fn sign(
+        &self,
+        secp: &Secp256k1<All>,
+        sec_nonce: SecNonce,
+        secret_key: &SecretKey,
+    ) -> Result<PartialSig, MusigError> {
+        if PublicKey::from_secret_key(secp, secret_key) != sec_nonce.pub_key {
+            return Err(MusigError::SecretKeyMismatch);
+        }
+        if !self.ctx.pub_keys.contains(&sec_nonce.pub_key) {
+            return Err(MusigError::UnknownSigner);
+        }
+        // a zeroed secnonce is what a signer is left with after signing once, never sign with it
+        if sec_nonce.k1 == ScalarN::ZERO || sec_nonce.k2 == ScalarN::ZERO {
+            return Err(MusigError::InvalidNonce);
+        }
+        let (k1, k2) = match has_even_y(&self.r) {
+            true => (sec_nonce.k1, sec_nonce.k2),
+            false => (sec_nonce.k1.negate(), sec_nonce.k2.negate()),
+        };
+        let a = self.ctx.coefficient(&sec_nonce.pub_key);
+        let d = self
+            .key_sign()
+            .mul(&self.ctx.gacc)
+            .mul(&secret_scalar(secret_key));
+        let s = k1.add(&self.b.mul(&k2)).add(&self.e.mul(&a).mul(&d));
+        return Ok(PartialSig(s));
+    }
+
+    pub fn verify(
+        &self,
+        secp: &Secp256k1<All>,
+        partial_sig: &PartialSig,
+        pub_nonce: &PubNonce,
+        pub_key: &PublicKey,
+    ) -> bool {
+        if !self.ctx.pub_keys.contains(pub_key) {
+            return false;
+        }
+        let r = point_add(Some(pub_nonce.r1), point_mul(secp, &pub_nonce.r2, &self.b));
+        let r = match has_even_y(&self.r) {
+            true => r,
+            false => point_negate(secp, r),
+        };
+        let a = self.ctx.coefficient(pub_key);
+        let g = self.key_sign().mul(&self.ctx.gacc);
+        let expected = point_add(r, point_mul(secp, pub_key, &self.e.mul(&a).mul(&g)));
+        return base_mul(secp, &partial_sig.0) == expected;
+    }
+
+    // checks the partial signature of every signer before they are aggregated, pub_keys,
+    // pub_nonces and partial_sigs are in the same order
+    pub fn verify_all(
+        &self,
+        secp: &Secp256k1<All>,
+        pub_keys: &[PublicKey],
+        pub_nonces: &[PubNonce],
+        partial_sigs: &[PartialSig],
+    ) -> Result<(), MusigError> {
+        if pub_nonces.len() != pub_keys.len() || partial_sigs.len() != pub_keys.len() {
+            return Err(MusigError::SignerCountMismatch);
+        }
+        for (index, ((pub_key, pub_nonce), partial_sig)) in pub_keys
+            .iter()
+            .zip(pub_nonces)
+            .zip(partial_sigs)
+            .enumerate()
+        {
+            if !self.verify(secp, partial_sig, pub_nonce, pub_key) {
+                return Err(MusigError::InvalidPartialSig(index));
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn aggregate(&self, partial_sigs: &[PartialSig]) -> Signature {
+        let sig = [
+            xbytes(&self.r),
+            self.aggregate_s(partial_sigs).to_be_bytes(),
+        ]
+        .concat();
+        return Signature::from_slice(&sig).unwrap();
+    }
+
+    pub fn aggregate_adaptor(&self, partial_sigs: &[PartialSig]) -> AdaptorSignature {
+        return AdaptorSignature::new(self.r, self.aggregate_s(partial_sigs));
+    }
+
+    fn aggregate_s(&self, partial_sigs: &[PartialSig]) -> ScalarN {
+        let s = partial_sigs
+            .iter()
+            .fold(ScalarN::ZERO, |acc, partial_sig| acc.add(&partial_sig.0));
+        return s.add(&self.e.mul(&self.key_sign()).mul(&self.ctx.tacc));
+    }
+
+    fn key_sign(&self) -> ScalarN {
+        return match has_even_y(&self.ctx.q) {
+            true => ScalarN::ONE,
+            false => ScalarN::ONE.negate(),
+        };
+    }
+}
+
+// runs every round for signers that all live in this process, e.g. both halves of a 2-of-2
+pub fn sign_locally(
+    secp: &Secp256k1<All>,
+    ctx: &KeyAggContext,
+    secret_keys: &[SecretKey],
+    msg: &[u8],
+) -> Result<Signature, MusigError> {
+    let (session, partial_sigs) = run_rounds(secp, ctx, secret_keys, msg)?;
+    return Ok(session.aggregate(&partial_sigs));
+}
+
+fn run_rounds(
+    secp: &Secp256k1<All>,
+    ctx: &KeyAggContext,
+    secret_keys: &[SecretKey],
+    msg: &[u8],
+) -> Result<(Session, Vec<PartialSig>), MusigError> {
+    let nonces = secret_keys
+        .iter()
+        .map(|secret_key| random_nonce(secp, secret_key, ctx, msg))
+        .collect::<Result<Vec<_>, MusigError>>()?;
+    let agg_nonce = nonce_agg(
+        &nonces
+            .iter()
+            .map(|(_, pub_nonce)| *pub_nonce)
+            .collect::<Vec<_>>(),
+    );
+    let session = Session::new(secp, ctx, &agg_nonce, msg);
+    let mut partial_sigs = vec![];
+    let mut pub_nonces = vec![];
+    for (secret_key, (sec_nonce, pub_nonce)) in secret_keys.iter().zip(nonces) {
+        partial_sigs.push(session.sign(secp, sec_nonce, secret_key)?);
+        pub_nonces.push(pub_nonce);
+    }
+    let pub_keys = secret_keys
+        .iter()
+        .map(|secret_key| PublicKey::from_secret_key(secp, secret_key))
+        .collect::<Vec<PublicKey>>();
+    session.verify_all(secp, &pub_keys, &pub_nonces, &partial_sigs)?;
+    return Ok((session, partial_sigs));
+}
+
+#[test]
+fn key_sort_and_key_agg_match_bip327_vectors() {
+    use bitcoin::hashes::hex::FromHex;
+    use std::str::FromStr;
+
+    let secp = Secp256k1::new();
+    let parse = |hex: &&str| PublicKey::from_str(hex).unwrap();
+
+    let unsorted = [
+        "02DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+        "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+        "02DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EFF",
+        "02DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+    ];
+    let sorted = [
+        "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+        "02DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+        "02DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+        "02DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EFF",
+        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+    ];
+    assert_eq!(
+        key_sort(&unsorted.iter().map(parse).collect::<Vec<_>>()),
+        sorted.iter().map(parse).collect::<Vec<_>>()
+    );
+
+    let keys = [
+        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+        "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+    ]
+    .iter()
+    .map(parse)
+    .collect::<Vec<PublicKey>>();
+    let vectors = [
+        (
+            vec![0, 1, 2],
+            "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C",
+        ),
+        (
+            vec![2, 1, 0],
+            "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B",
+        ),
+        (
+            vec![0, 0, 0],
+            "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935",
+        ),
+        (
+            vec![0, 0, 1, 1],
+            "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E",
+        ),
+    ];
+    vectors.iter().for_each(|(indices, expected)| {
+        let pub_keys = indices.iter().map(|index| keys[*index]).collect::<Vec<_>>();
+        let ctx = KeyAggContext::new(&secp, &pub_keys).unwrap();
+        assert_eq!(ctx.x_only(), XOnlyPublicKey::from_str(expected).unwrap());
+    });
+
+    // error cases, invalid keys are already refused when they are parsed
+    [
+        "020000000000000000000000000000000000000000000000000000000000000005",
+        "02FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30",
+        "04F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+    ]
+    .iter()
+    .for_each(|hex| assert!(PublicKey::from_str(hex).is_err(), "{}", hex));
+    let tweak = |hex: &str| -> [u8; 32] { Vec::<u8>::from_hex(hex).unwrap().try_into().unwrap() };
+    let ctx = KeyAggContext::new(&secp, &keys[..2]).unwrap();
+    assert_eq!(
+        ctx.apply_tweak(
+            &secp,
+            tweak("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"),
+            true
+        )
+        .err(),
+        Some(MusigError::InvalidTweak)
+    );
+    let single = parse(&"03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9");
+    assert_eq!(
+        KeyAggContext::new(&secp, &[single])
+            .unwrap()
+            .apply_tweak(
+                &secp,
+                tweak("252E4BD67410A76CDF933D30EAA1608214037F1B105A013ECCD3C5C184A6110B"),
+                false
+            )
+            .err(),
+        Some(MusigError::InfiniteAggregate)
+    );
+    assert_eq!(
+        KeyAggContext::new(&secp, &[]).err(),
+        Some(MusigError::NoKeys)
+    );
+}
+
+#[test]
+fn nonce_gen_and_nonce_agg_match_bip327_vectors() {
+    use bitcoin::hashes::hex::FromHex;
+    use std::str::FromStr;
+
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_slice(&[2u8; 32]).unwrap();
+    let pub_key = PublicKey::from_secret_key(&secp, &secret_key);
+    let agg_pk = XOnlyPublicKey::from_slice(&[7u8; 32]).unwrap();
+    let msg = [1u8; 32];
+    let long_msg = [0x26u8; 38];
+    let extra_in = [8u8; 32];
+
+    // secret key, aggregate key, message, extra input, expected k1 and k2, expected pubnonce
+    let vectors: [(Option<&SecretKey>, Option<XOnlyPublicKey>, Option<&[u8]>, &[u8], &str, &str); 4] = [
+        (Some(&secret_key), Some(agg_pk), Some(&msg), &extra_in, "B114E502BEAA4E301DD08A50264172C84E41650E6CB726B410C0694D59EFFB6495B5CAF28D045B973D63E3C99A44B807BDE375FD6CB39E46DC4A511708D0E9D2", "02F7BE7089E8376EB355272368766B17E88E7DB72047D05E56AA881EA52B3B35DF02C29C8046FDD0DED4C7E55869137200FBDBFE2EB654267B6D7013602CAED3115A"),
+        (Some(&secret_key), Some(agg_pk), Some(&[]), &extra_in, "E862B068500320088138468D47E0E6F147E01B6024244AE45EAC40ACE5929B9F0789E051170B9E705D0B9EB49049A323BBBBB206D8E05C19F46C6228742AA7A9", "023034FA5E2679F01EE66E12225882A7A48CC66719B1B9D3B6C4DBD743EFEDA2C503F3FD6F01EB3A8E9CB315D73F1F3D287CAFBB44AB321153C6287F407600205109"),
+        (Some(&secret_key), Some(agg_pk), Some(&long_msg), &extra_in, "3221975ACBDEA6820EABF02A02B7F27D3A8EF68EE42787B88CBEFD9AA06AF3632EE85B1A61D8EF31126D4663A00DD96E9D1D4959E72D70FE5EBB6E7696EBA66F", "02E5BBC21C69270F59BD634FCBFA281BE9D76601295345112C58954625BF23793A021307511C79F95D38ACACFF1B4DA98228B77E65AA216AD075E9673286EFB4EAF3"),
+        (None, None, None, &[], "89BDD787D0284E5E4D5FC572E49E316BAB7E21E3B1830DE37DFE80156FA41A6D0B17AE8D024C53679699A6FD7944D9C4A366B514BAF43088E0708B1023DD2897", "02C96E7CB1E8AA5DAC64D872947914198F607D90ECDE5200DE52978AD5DED63C000299EC5117C2D29EDEE8A2092587C3909BE694D5CFF0667D6C02EA4059F7CD9786"),
+    ];
+    let last_pub_key =
+        PublicKey::from_str("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9")
+            .unwrap();
+    vectors.iter().enumerate().for_each(
+        |(index, (secret, agg_pk, msg, extra_in, sec_nonce, pub_nonce))| {
+            let pub_key = match secret {
+                Some(_) => pub_key,
+                None => last_pub_key,
+            };
+            let (ours, our_pub_nonce) =
+                nonce_gen(&secp, [0x0f; 32], *secret, pub_key, *agg_pk, *msg, extra_in).unwrap();
+            let ours = [ours.k1.to_be_bytes(), ours.k2.to_be_bytes()].concat();
+            assert_eq!(
+                ours,
+                Vec::<u8>::from_hex(sec_nonce).unwrap(),
+                "vector {}",
+                index
+            );
+            assert_eq!(
+                our_pub_nonce.serialize().to_vec(),
+                Vec::<u8>::from_hex(pub_nonce).unwrap(),
+                "vector {}",
+                index
+            );
+        },
+    );
+
+    let pub_nonces = [
+        "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E66603BA47FBC1834437B3212E89A84D8425E7BF12E0245D98262268EBDCB385D50641",
+        "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B833",
+        "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E6660279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+        "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60379BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+    ]
+    .iter()
+    .map(|hex| PubNonce::from_slice(&Vec::<u8>::from_hex(hex).unwrap()).unwrap())
+    .collect::<Vec<PubNonce>>();
+    assert_eq!(
+        nonce_agg(&pub_nonces[..2]).serialize().to_vec(),
+        Vec::<u8>::from_hex("035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B024725377345BDE0E9C33AF3C43C0A29A9249F2F2956FA8CFEB55C8573D0262DC8").unwrap()
+    );
+    // the second halves cancel out, infinity is encoded as 33 zero bytes
+    assert_eq!(
+        nonce_agg(&pub_nonces[2..]).serialize().to_vec(),
+        Vec::<u8>::from_hex("035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B000000000000000000000000000000000000000000000000000000000000000000").unwrap()
+    );
+    // a pubnonce with an invalid point or prefix is refused
+    let mut invalid = pub_nonces[0].serialize();
+    invalid[0] = 0x04;
+    assert_eq!(
+        PubNonce::from_slice(&invalid),
+        Err(MusigError::InvalidNonce)
+    );
+    assert_eq!(
+        PubNonce::from_slice(&Vec::<u8>::from_hex("0200000000000000000000000000000000000000000000000000000000000000090287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480").unwrap()),
+        Err(MusigError::InvalidNonce)
+    );
+}
+
+#[test]
+fn sign_verify_and_tweak_match_bip327_vectors() {
+    use bitcoin::hashes::hex::FromHex;
+    use std::str::FromStr;
+
+    let secp = Secp256k1::new();
+    let hex = |hex: &str| Vec::<u8>::from_hex(hex).unwrap();
+    let secret_key =
+        SecretKey::from_str("7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671")
+            .unwrap();
+    let sec_nonce_bytes = hex("508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F7");
+    let sec_nonce = || SecNonce {
+        k1: ScalarN::from_slice(&sec_nonce_bytes[..32]).unwrap(),
+        k2: ScalarN::from_slice(&sec_nonce_bytes[32..]).unwrap(),
+        pub_key: PublicKey::from_secret_key(&secp, &secret_key),
+    };
+    let keys = [
+        "03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9",
+        "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+        "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661",
+        "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+    ]
+    .iter()
+    .map(|key| PublicKey::from_str(key).unwrap())
+    .collect::<Vec<PublicKey>>();
+    let pub_nonces = [
+        "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480",
+        "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+        "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046",
+        "0237C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0387BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480",
+    ]
+    .iter()
+    .map(|nonce| PubNonce::from_slice(&hex(nonce)).unwrap())
+    .collect::<Vec<PubNonce>>();
+    let msgs = [
+        hex("F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF"),
+        vec![],
+        vec![0x26; 38],
+    ];
+
+    // keys, nonces, message, expected partial signature of the first key
+    let vectors = [
+        (
+            vec![0, 1, 2],
+            vec![0, 1, 2],
+            0,
+            "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB",
+        ),
+        (
+            vec![1, 0, 2],
+            vec![1, 0, 2],
+            0,
+            "9FF2F7AAA856150CC8819254218D3ADEEB0535269051897724F9DB3789513A52",
+        ),
+        (
+            vec![1, 2, 0],
+            vec![1, 2, 0],
+            0,
+            "FA23C359F6FAC4E7796BB93BC9F0532A95468C539BA20FF86D7C76ED92227900",
+        ),
+        // the aggregate nonce is infinity
+        (
+            vec![0, 1],
+            vec![0, 3],
+            0,
+            "AE386064B26105404798F75DE2EB9AF5EDA5387B064B83D049CB7C5E08879531",
+        ),
+        (
+            vec![0, 1, 2],
+            vec![0, 1, 2],
+            1,
+            "D7D63FFD644CCDA4E62BC2BC0B1D02DD32A1DC3030E155195810231D1037D82D",
+        ),
+        (
+            vec![0, 1, 2],
+            vec![0, 1, 2],
+            2,
+            "E184351828DA5094A97C79CABDAAA0BFB87608C32E8829A4DF5340A6F243B78C",
+        ),
+    ];
+    let session = |key_indices: &[usize], nonce_indices: &[usize], msg: &[u8]| {
+        let pub_keys = key_indices
+            .iter()
+            .map(|index| keys[*index])
+            .collect::<Vec<_>>();
+        let ctx = KeyAggContext::new(&secp, &pub_keys).unwrap();
+        let agg_nonce = nonce_agg(
+            &nonce_indices
+                .iter()
+                .map(|index| pub_nonces[*index])
+                .collect::<Vec<_>>(),
+        );
+        return Session::new(&secp, &ctx, &agg_nonce, msg);
+    };
+    vectors
+        .iter()
+        .enumerate()
+        .for_each(|(index, (key_indices, nonce_indices, msg, expected))| {
+            let session = session(key_indices, nonce_indices, &msgs[*msg]);
+            let partial_sig = session.sign(&secp, sec_nonce(), &secret_key).unwrap();
+            assert_eq!(
+                partial_sig.serialize().to_vec(),
+                hex(expected),
+                "vector {}",
+                index
+            );
+            assert!(session.verify(&secp, &partial_sig, &pub_nonces[0], &keys[0]));
+        });
+
+    // verify fail cases
+    let valid = session(&[0, 1, 2], &[0, 1, 2], &msgs[0]);
+    let partial_sig = PartialSig::from_slice(&hex(vectors[0].3)).unwrap();
+    let negated = PartialSig(partial_sig.0.negate());
+    assert!(!valid.verify(&secp, &negated, &pub_nonces[0], &keys[0]));
+    assert!(!valid.verify(&secp, &partial_sig, &pub_nonces[1], &keys[1]));
+    assert!(PartialSig::from_slice(&hex(
+        "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141"
+    ))
+    .is_none());
+
+    // sign error cases
+    let without_signer = session(&[1, 2], &[0, 1, 2], &msgs[0]);
+    assert_eq!(
+        without_signer.sign(&secp, sec_nonce(), &secret_key),
+        Err(MusigError::UnknownSigner)
+    );
+    let mut reused = sec_nonce();
+    reused.k1 = ScalarN::ZERO;
+    assert_eq!(
+        valid.sign(&secp, reused, &secret_key),
+        Err(MusigError::InvalidNonce)
+    );
+    let mut invalid_agg_nonce = nonce_agg(&pub_nonces[..3]).serialize();
+    invalid_agg_nonce[0] = 0x04;
+    assert_eq!(
+        AggNonce::from_slice(&invalid_agg_nonce),
+        Err(MusigError::InvalidNonce)
+    );
+
+    // tweaked aggregate keys, every vector signs for the key at index 2 of [1, 2, 0]
+    let tweaks = [
+        "E8F791FF9225A2AF0102AFFF4A9A723D9612A682A25EBE79802B263CDFCD83BB",
+        "AE2EA797CC0FE72AC5B97B97F3C6957D7E4199A167A58EB08BCAFFDA70AC0455",
+        "F52ECBC565B3D8BEA2DFD5B75A4F457E54369809322E4120831626F290FA87E0",
+        "1969AD73CC177FA0B4FCED6DF1F7BF9907E665FDE9BA196A74FED0A3CF5AEF9D",
+    ]
+    .map(|tweak| <[u8; 32]>::try_from(hex(tweak)).unwrap());
+    let tweak_vectors = [
+        (
+            vec![0],
+            vec![true],
+            "E28A5C66E61E178C2BA19DB77B6CF9F7E2F0F56C17918CD13135E60CC848FE91",
+        ),
+        (
+            vec![0],
+            vec![false],
+            "38B0767798252F21BF5702C48028B095428320F73A4B14DB1E25DE58543D2D2D",
+        ),
+        (
+            vec![0, 1],
+            vec![false, true],
+            "408A0A21C4A0F5DACAF9646AD6EB6FECD7F7A11F03ED1F48DFFF2185BC2C2408",
+        ),
+        (
+            vec![0, 1, 2, 3],
+            vec![false, false, true, true],
+            "45ABD206E61E3DF2EC9E264A6FEC8292141A633C28586388235541F9ADE75435",
+        ),
+        (
+            vec![0, 1, 2, 3],
+            vec![true, false, true, false],
+            "B255FDCAC27B40C7CE7848E2D3B7BF5EA0ED756DA81565AC804CCCA3E1D5D239",
+        ),
+    ];
+    let agg_nonce = nonce_agg(&[pub_nonces[1], pub_nonces[2], pub_nonces[0]]);
+    tweak_vectors
+        .iter()
+        .enumerate()
+        .for_each(|(index, (tweak_indices, is_xonly, expected))| {
+            let ctx = tweak_indices.iter().zip(is_xonly).fold(
+                KeyAggContext::new(&secp, &[keys[1], keys[3], keys[0]]).unwrap(),
+                |ctx, (tweak, is_xonly)| ctx.apply_tweak(&secp, tweaks[*tweak], *is_xonly).unwrap(),
+            );
+            let session = Session::new(&secp, &ctx, &agg_nonce, &msgs[0]);
+            let partial_sig = session.sign(&secp, sec_nonce(), &secret_key).unwrap();
+            assert_eq!(
+                partial_sig.serialize().to_vec(),
+                hex(expected),
+                "tweak vector {}",
+                index
+            );
+            assert!(session.verify(&secp, &partial_sig, &pub_nonces[0], &keys[0]));
+        });
+}
+
+#[test]
+fn musig_signature_verifies_under_the_tweaked_key() {
+    use bitcoin::secp256k1::Message;
+
+    let secp = Secp256k1::new();
+    let secret_keys = [[5u8; 32], [6u8; 32], [7u8; 32]]
+        .iter()
+        .map(|secret| SecretKey::from_slice(secret).unwrap())
+        .collect::<Vec<SecretKey>>();
+    let pub_keys = secret_keys
+        .iter()
+        .map(|secret_key| PublicKey::from_secret_key(&secp, secret_key))
+        .collect::<Vec<PublicKey>>();
+    let ctx = KeyAggContext::sorted(&secp, &pub_keys)
+        .unwrap()
+        .apply_taproot_tweak(&secp, None)
+        .unwrap();
+    let msg = [42u8; 32];
+
+    let sig = sign_locally(&secp, &ctx, &secret_keys, &msg).unwrap();
+    secp.verify_schnorr(&sig, &Message::from_slice(&msg).unwrap(), &ctx.x_only())
+        .unwrap();
+
+    // a partial signature made with someone else's nonce is rejected
+    let (sec_nonce, pub_nonce) = random_nonce(&secp, &secret_keys[0], &ctx, &msg).unwrap();
+    let (_, other_nonce) = random_nonce(&secp, &secret_keys[1], &ctx, &msg).unwrap();
+    let session = Session::new(&secp, &ctx, &nonce_agg(&[pub_nonce, other_nonce]), &msg);
+    let partial_sig = session.sign(&secp, sec_nonce, &secret_keys[0]).unwrap();
+    assert!(session.verify(&secp, &partial_sig, &pub_nonce, &pub_keys[0]));
+    assert!(!session.verify(&secp, &partial_sig, &other_nonce, &pub_keys[0]));
+}
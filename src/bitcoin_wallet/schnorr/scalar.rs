@@ -0,0 +1,161 @@
+use std::cmp::Ordering;
+
+use bitcoin::secp256k1::{Scalar, SecretKey};
+
+// the order of the secp256k1 group, little endian limbs
+const ORDER: [u64; 4] = [
+    0xBFD2_5E8C_D036_4141,
+    0xBAAE_DCE6_AF48_A03B,
+    0xFFFF_FFFF_FFFF_FFFE,
+    0xFFFF_FFFF_FFFF_FFFF,
+];
+
+// an integer mod n, the secp256k1 types can't hold zero which the protocols need
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ScalarN([u64; 4]);
+
+impl ScalarN {
+    pub const ZERO: ScalarN = ScalarN([0, 0, 0, 0]);
+    pub const ONE: ScalarN = ScalarN([1, 0, 0, 0]);
+
+    pub fn from_u64(value: u64) -> Self {
+        return ScalarN([value, 0, 0, 0]);
+    }
+
+    // reduces mod n, 2^256 < 2n so one subtraction is enough
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let limbs = limbs_from_be(&bytes);
+        if compare(&limbs, &ORDER) == Ordering::Less {
+            return ScalarN(limbs);
+        }
+        return ScalarN(sub_limbs(&limbs, &ORDER).0);
+    }
+
+    // None for values that are not below n, the way BIP327 rejects tweaks and partial signatures
+    pub fn from_be_bytes_checked(bytes: [u8; 32]) -> Option<Self> {
+        let limbs = limbs_from_be(&bytes);
+        if compare(&limbs, &ORDER) != Ordering::Less {
+            return None;
+        }
+        return Some(ScalarN(limbs));
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        return ScalarN::from_be_bytes_checked(bytes.try_into().ok()?);
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        self.0.iter().rev().enumerate().for_each(|(index, limb)| {
+            bytes[index * 8..(index + 1) * 8].copy_from_slice(&limb.to_be_bytes())
+        });
+        return bytes;
+    }
+
+    pub fn is_zero(&self) -> bool {
+        return self.0 == [0, 0, 0, 0];
+    }
+
+    // the arithmetic goes through libsecp256k1, which runs in constant time, since nonces and
+    // secret keys pass through here. Only whether an operand is zero shows in the timing, zero is
+    // never a valid secret
+    pub fn add(&self, other: &ScalarN) -> ScalarN {
+        let secret = match self.secret_key() {
+            Some(secret) => secret,
+            None => return *other,
+        };
+        // the tweak is only rejected when the sum is zero
+        return match secret.add_tweak(&other.scalar()) {
+            Ok(sum) => ScalarN::from_secret_key(&sum),
+            Err(_) => ScalarN::ZERO,
+        };
+    }
+
+    pub fn sub(&self, other: &ScalarN) -> ScalarN {
+        return self.add(&other.negate());
+    }
+
+    pub fn negate(&self) -> ScalarN {
+        return match self.secret_key() {
+            Some(secret) => ScalarN::from_secret_key(&secret.negate()),
+            None => ScalarN::ZERO,
+        };
+    }
+
+    pub fn mul(&self, other: &ScalarN) -> ScalarN {
+        let secret = match self.secret_key() {
+            Some(secret) if !other.is_zero() => secret,
+            _ => return ScalarN::ZERO,
+        };
+        // n is prime, so the product of two non zero scalars is never zero
+        return match secret.mul_tweak(&other.scalar()) {
+            Ok(product) => ScalarN::from_secret_key(&product),
+            Err(_) => ScalarN::ZERO,
+        };
+    }
+
+    // square and multiply branches on the exponent, only for public exponents
+    pub fn pow(&self, exponent: &ScalarN) -> ScalarN {
+        return (0..256).rev().fold(ScalarN::ONE, |acc, bit| {
+            let squared = acc.mul(&acc);
+            if exponent.bit(bit) {
+                return squared.mul(self);
+            }
+            return squared;
+        });
+    }
+
+    // fermat, n is prime, None for zero
+    pub fn invert(&self) -> Option<ScalarN> {
+        if self.is_zero() {
+            return None;
+        }
+        let exponent = ScalarN(sub_limbs(&ORDER, &[2, 0, 0, 0]).0);
+        return Some(self.pow(&exponent));
+    }
+
+    fn secret_key(&self) -> Option<SecretKey> {
+        return SecretKey::from_slice(&self.to_be_bytes()).ok();
+    }
+
+    fn scalar(&self) -> Scalar {
+        // every ScalarN is below n, which is all Scalar checks
+        return Scalar::from_be_bytes(self.to_be_bytes()).unwrap();
+    }
+
+    fn from_secret_key(secret: &SecretKey) -> ScalarN {
+        return ScalarN(limbs_from_be(&secret.secret_bytes()));
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        return (self.0[index / 64] >> (index % 64)) & 1 == 1;
+    }
+}
+
+fn limbs_from_be(bytes: &[u8; 32]) -> [u64; 4] {
+    let mut limbs = [0u64; 4];
+    limbs
+        .iter_mut()
+        .rev()
+        .enumerate()
+        .for_each(|(index, limb)| {
+            *limb = u64::from_be_bytes(bytes[index * 8..(index + 1) * 8].try_into().unwrap())
+        });
+    return limbs;
+}
+
+fn compare(a: &[u64; 4], b: &[u64; 4]) -> Ordering {
+    return a.iter().rev().cmp(b.iter().rev());
+}
+
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut result = [0u64; 4];
+    let mut borrow = false;
+    for index in 0..4 {
+        let (difference, underflow_a) = a[index].overflowing_sub(b[index]);
+        let (difference, underflow_b) = difference.overflowing_sub(borrow as u64);
+        result[index] = difference;
+        borrow = underflow_a || underflow_b;
+    }
+    return (result, borrow);
+}
@@ -1,17 +1,23 @@
 use bitcoin::{
     psbt::Input,
     schnorr::TapTweak,
-    secp256k1::{All, Message, Secp256k1, SecretKey},
+    secp256k1::{schnorr, All, Message, PublicKey, Secp256k1, SecretKey, XOnlyPublicKey},
     util::{
         sighash::{Error, Prevouts, ScriptPath, SighashCache},
-        taproot::{LeafVersion, TapLeafHash, TaprootSpendInfo},
+        taproot::{LeafVersion, TapBranchHash, TapLeafHash, TapSighashHash, TaprootSpendInfo},
     },
     Address, EcdsaSig, KeyPair, PrivateKey, SchnorrSig, SchnorrSighashType, Script, Transaction,
     TxIn, TxOut,
 };
-use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{hex::ToHex, Hash};
 
-use crate::bitcoin_wallet::constants::NETWORK;
+use crate::bitcoin_wallet::{
+    constants::NETWORK,
+    schnorr::{
+        frost::{self, GroupKey, KeyShare, PublicKeyPackage},
+        musig2::{nonce_agg, KeyAggContext, MusigError, PubNonce, Session, SigningRound},
+    },
+};
 
 pub fn insert_control_block<'a>(
     secp: &'a Secp256k1<All>,
@@ -49,6 +55,13 @@ pub fn insert_witness<'a>(script: Script) -> Box<impl FnOnce(&mut Input) + 'a> {
         input.witness_script = Some(script.clone());
     });
 }
+pub fn insert_sha256_preimage<'a>(preimage: Vec<u8>) -> Box<impl FnOnce(&mut Input) + 'a> {
+    return Box::new(move |input: &mut Input| {
+        let hash = bitcoin_hashes::sha256::Hash::hash(&preimage);
+        input.sha256_preimages.insert(hash, preimage);
+    });
+}
+
 pub fn sign_2_of_2<'a>(
     secp: &'a Secp256k1<All>,
     current_tx: Transaction,
@@ -163,6 +176,142 @@ pub fn sign_key_sig<'a>(
     });
 }
 
+// the session of a key path spend of an output whose internal key is the MuSig2 aggregate of
+// pub_keys, every signer derives it from the same unsigned tx and prevouts of that output and
+// signs with only its own secret
+#[allow(clippy::too_many_arguments)]
+pub fn musig_key_spend_session(
+    secp: &Secp256k1<All>,
+    pub_keys: &[PublicKey],
+    pub_nonces: &[PubNonce],
+    current_tx: &Transaction,
+    prevouts: &[TxOut],
+    input_index: usize,
+    merkle_root: Option<TapBranchHash>,
+    sighash_type: SchnorrSighashType,
+) -> Result<Session, MusigError> {
+    let tap_sig = musig_key_spend_sighash(current_tx, prevouts, input_index, sighash_type)?;
+    let ctx = KeyAggContext::sorted(secp, pub_keys)?.apply_taproot_tweak(secp, merkle_root)?;
+    return Ok(Session::new(
+        secp,
+        &ctx,
+        &nonce_agg(pub_nonces),
+        &tap_sig.into_inner(),
+    ));
+}
+
+fn musig_key_spend_sighash(
+    current_tx: &Transaction,
+    prevouts: &[TxOut],
+    input_index: usize,
+    sighash_type: SchnorrSighashType,
+) -> Result<TapSighashHash, MusigError> {
+    return SighashCache::new(current_tx)
+        .taproot_key_spend_signature_hash(input_index, &Prevouts::All(prevouts), sighash_type)
+        .map_err(|_| MusigError::InvalidSighash(input_index));
+}
+
+// aggregates the partial signatures the signers sent back, no secret key is needed here. the
+// aggregate is checked against the tweaked output key and left out of the input when it doesn't
+// verify or there is no round for the input, so finalizing reports the input as unsatisfiable
+pub fn sign_musig_key_sig<'a>(
+    secp: &'a Secp256k1<All>,
+    pub_keys: &'a [PublicKey],
+    round: Option<&'a SigningRound>,
+    current_tx: Transaction,
+    previous_tx: Vec<TxOut>,
+    input_index: usize,
+    sighash_type: SchnorrSighashType,
+) -> Box<impl FnOnce(&mut Input) + 'a> {
+    return Box::new(move |input: &mut Input| {
+        let witness_script = input.clone().witness_script.unwrap();
+        let prev = filter_for_wit(&previous_tx, &witness_script);
+        let round = match round {
+            Some(round) => round,
+            None => return,
+        };
+        if let Ok((internal, sig)) = aggregate_musig_key_sig(
+            secp,
+            pub_keys,
+            round,
+            &current_tx,
+            &prev,
+            input_index,
+            input.tap_merkle_root,
+            sighash_type,
+        ) {
+            input.tap_internal_key = Some(internal);
+            input.tap_key_sig = Some(SchnorrSig {
+                sig,
+                hash_ty: sighash_type,
+            });
+        }
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn aggregate_musig_key_sig(
+    secp: &Secp256k1<All>,
+    pub_keys: &[PublicKey],
+    round: &SigningRound,
+    current_tx: &Transaction,
+    prevouts: &[TxOut],
+    input_index: usize,
+    merkle_root: Option<TapBranchHash>,
+    sighash_type: SchnorrSighashType,
+) -> Result<(XOnlyPublicKey, schnorr::Signature), MusigError> {
+    let session = musig_key_spend_session(
+        secp,
+        pub_keys,
+        &round.pub_nonces,
+        current_tx,
+        prevouts,
+        input_index,
+        merkle_root,
+        sighash_type,
+    )?;
+    session.verify_all(secp, pub_keys, &round.pub_nonces, &round.partial_sigs)?;
+    let sig = session.aggregate(&round.partial_sigs);
+
+    let internal = KeyAggContext::sorted(secp, pub_keys)?.x_only();
+    let output_key = internal.tap_tweak(secp, merkle_root).0.to_inner();
+    let tap_sig = musig_key_spend_sighash(current_tx, prevouts, input_index, sighash_type)?;
+    secp.verify_schnorr(&sig, &Message::from(tap_sig), &output_key)
+        .map_err(|_| MusigError::InvalidAggregate)?;
+    return Ok((internal, sig));
+}
+
+// the key path of an output locked to a FROST group key, any threshold of the shares can sign
+pub fn sign_frost_key_sig<'a>(
+    secp: &'a Secp256k1<All>,
+    shares: &'a [KeyShare],
+    package: &'a PublicKeyPackage,
+    current_tx: Transaction,
+    previous_tx: Vec<TxOut>,
+    input_index: usize,
+) -> Box<impl FnOnce(&mut Input) + 'a> {
+    return Box::new(move |input: &mut Input| {
+        let witness_script = input.clone().witness_script.unwrap();
+        let prev = filter_for_wit(&previous_tx, &witness_script);
+        let tap_sig = SighashCache::new(&current_tx)
+            .taproot_key_spend_signature_hash(
+                input_index,
+                &Prevouts::All(&prev),
+                SchnorrSighashType::AllPlusAnyoneCanPay,
+            )
+            .unwrap();
+        let key = GroupKey::taproot(secp, package, input.tap_merkle_root).unwrap();
+        let sig = frost::sign_locally(secp, &key, package, shares, &tap_sig.into_inner()).unwrap();
+        let msg = Message::from_slice(&tap_sig).unwrap();
+        secp.verify_schnorr(&sig, &msg, &key.x_only()).unwrap();
+        input.tap_internal_key = Some(package.x_only());
+        input.tap_key_sig = Some(SchnorrSig {
+            sig,
+            hash_ty: SchnorrSighashType::AllPlusAnyoneCanPay,
+        });
+    });
+}
+
 pub fn sign_segwit_v0<'a>(
     secp: &'a Secp256k1<All>,
     current_tx: Transaction,
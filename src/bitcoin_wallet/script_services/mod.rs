@@ -1,3 +1,7 @@
 pub mod input_service;
 pub mod output_service;
+pub mod policy_service;
 pub mod psbt_factory;
+pub mod psbt_report;
+pub mod psbt_service;
+pub mod tap_finalizer;
@@ -1,8 +1,6 @@
-use std::str::FromStr;
-
 use bitcoin::{
     psbt::{Output, TapTree},
-    secp256k1::{ecdh::SharedSecret, All, Parity, PublicKey, Secp256k1, SecretKey},
+    secp256k1::{All, PublicKey, Secp256k1},
     util::{
         bip32::{DerivationPath, Fingerprint, KeySource},
         taproot::{LeafVersion, TapLeafHash, TaprootBuilder},
@@ -85,23 +83,3 @@ pub fn segwit_v0_agg_witness<'a>() -> Box<impl Fn(&mut Output) + 'a> {
         output.witness_script = Some(script);
     });
 }
-
-fn new_shared_secret<'a>(
-    mut iter: impl Iterator<Item = &'a XOnlyPublicKey>,
-    secret: String,
-) -> SecretKey {
-    match iter.next() {
-        Some(x_only) => {
-            return SecretKey::from_slice(
-                &SharedSecret::new(
-                    &x_only.public_key(Parity::Even),
-                    &new_shared_secret(iter, secret),
-                )
-                .secret_bytes(),
-            )
-            .unwrap()
-        }
-
-        None => SecretKey::from_str(&secret).unwrap(),
-    }
-}
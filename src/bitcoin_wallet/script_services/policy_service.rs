@@ -0,0 +1,284 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use bitcoin::{
+    psbt::{Input, Output, PartiallySignedTransaction, TapTree},
+    secp256k1::{All, Secp256k1},
+    util::{
+        bip32::{DerivationPath, Fingerprint},
+        taproot::{LeafVersion, TapLeafHash, TaprootBuilder},
+    },
+    Transaction, XOnlyPublicKey,
+};
+use miniscript::{
+    descriptor::Tr, policy::Concrete, psbt::PsbtExt, Descriptor, MiniscriptKey, Translator,
+};
+
+use crate::bitcoin_wallet::constants::nums_x_only;
+
+#[derive(Debug)]
+pub enum PolicyError {
+    UnknownKey(String),
+    InvalidHash(String),
+    Miniscript(miniscript::Error),
+    NotTaproot,
+    Finalize(Vec<miniscript::psbt::Error>),
+    Extract(miniscript::psbt::Error),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::UnknownKey(name) => write!(f, "policy uses unknown key {}", name),
+            PolicyError::InvalidHash(hash) => write!(f, "policy uses invalid hash {}", hash),
+            PolicyError::Miniscript(err) => write!(f, "policy does not compile: {}", err),
+            PolicyError::NotTaproot => write!(f, "descriptor is not a tr() descriptor"),
+            PolicyError::Finalize(errs) => {
+                let errs = errs.iter().map(|err| err.to_string()).collect::<Vec<_>>();
+                write!(f, "failed to finalize psbt: {}", errs.join(", "))
+            }
+            PolicyError::Extract(err) => write!(f, "failed to extract transaction: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+impl From<miniscript::Error> for PolicyError {
+    fn from(err: miniscript::Error) -> Self {
+        PolicyError::Miniscript(err)
+    }
+}
+
+// policies name their keys, e.g. pk(A), and hashes are given in hex
+struct NamedKeys<'a>(&'a BTreeMap<String, XOnlyPublicKey>);
+
+impl<'a> Translator<String, XOnlyPublicKey, PolicyError> for NamedKeys<'a> {
+    fn pk(&mut self, pk: &String) -> Result<XOnlyPublicKey, PolicyError> {
+        return self
+            .0
+            .get(pk)
+            .copied()
+            .ok_or_else(|| PolicyError::UnknownKey(pk.clone()));
+    }
+
+    fn sha256(
+        &mut self,
+        hash: &String,
+    ) -> Result<<XOnlyPublicKey as MiniscriptKey>::Sha256, PolicyError> {
+        return parse_hash(hash);
+    }
+
+    fn hash256(
+        &mut self,
+        hash: &String,
+    ) -> Result<<XOnlyPublicKey as MiniscriptKey>::Hash256, PolicyError> {
+        return parse_hash(hash);
+    }
+
+    fn ripemd160(
+        &mut self,
+        hash: &String,
+    ) -> Result<<XOnlyPublicKey as MiniscriptKey>::Ripemd160, PolicyError> {
+        return parse_hash(hash);
+    }
+
+    fn hash160(
+        &mut self,
+        hash: &String,
+    ) -> Result<<XOnlyPublicKey as MiniscriptKey>::Hash160, PolicyError> {
+        return parse_hash(hash);
+    }
+}
+
+fn parse_hash<H: FromStr>(hash: &str) -> Result<H, PolicyError> {
+    return H::from_str(hash).map_err(|_| PolicyError::InvalidHash(hash.to_string()));
+}
+
+// or(99@thresh(2,pk(A),pk(B)),and(pk(C),older(144))) becomes a tr() descriptor, a key that can
+// spend alone ends up as the internal key, otherwise the key path is locked with the NUMS point
+pub fn compile_policy(
+    policy: &str,
+    keys: &BTreeMap<String, XOnlyPublicKey>,
+) -> Result<Descriptor<XOnlyPublicKey>, PolicyError> {
+    let named = Concrete::<String>::from_str(policy)?;
+    let policy = named.translate_pk(&mut NamedKeys(keys))?;
+    return Ok(policy.compile_tr(Some(nums_x_only()))?);
+}
+
+// every leaf of the descriptor together with the depth TaprootBuilder expects it at
+pub fn policy_leaves(
+    descriptor: &Descriptor<XOnlyPublicKey>,
+) -> Result<Vec<(u8, bitcoin::Script, Vec<XOnlyPublicKey>)>, PolicyError> {
+    return Ok(taproot(descriptor)?
+        .iter_scripts()
+        .map(|(depth, ms)| (depth, ms.encode(), ms.iter_pk().collect()))
+        .collect());
+}
+
+pub fn insert_policy_output<'a>(
+    descriptor: Descriptor<XOnlyPublicKey>,
+) -> Result<Box<impl FnMut(&mut Output) + 'a>, PolicyError> {
+    let tr = taproot(&descriptor)?;
+    let leaves = policy_leaves(&descriptor)?;
+    return Ok(Box::new(move |output: &mut Output| {
+        output.tap_internal_key = Some(*tr.internal_key());
+        output.witness_script = Some(descriptor.script_pubkey());
+        if !leaves.is_empty() {
+            let builder = leaves
+                .iter()
+                .fold(TaprootBuilder::new(), |builder, (depth, script, _)| {
+                    builder.add_leaf(*depth, script.clone()).unwrap()
+                });
+            output.tap_tree = Some(TapTree::try_from(builder).unwrap());
+        }
+        output.tap_key_origins = key_origins(*tr.internal_key(), &leaves);
+    }));
+}
+
+// the input side of the same descriptor, enough for the miniscript finalizer to pick a leaf
+pub fn insert_policy_input<'a>(
+    descriptor: Descriptor<XOnlyPublicKey>,
+) -> Result<Box<impl FnOnce(&mut Input) + 'a>, PolicyError> {
+    let tr = taproot(&descriptor)?;
+    let leaves = policy_leaves(&descriptor)?;
+    return Ok(Box::new(move |input: &mut Input| {
+        let spend_info = tr.spend_info();
+
+        input.tap_internal_key = Some(*tr.internal_key());
+        input.tap_merkle_root = spend_info.merkle_root();
+        input.witness_script = Some(descriptor.script_pubkey());
+        leaves.iter().for_each(|(_, script, _)| {
+            let leaf = (script.clone(), LeafVersion::TapScript);
+            let control_block = spend_info.control_block(&leaf).unwrap();
+            input.tap_scripts.insert(control_block, leaf);
+        });
+        input.tap_key_origins = key_origins(*tr.internal_key(), &leaves);
+    }));
+}
+
+fn taproot(descriptor: &Descriptor<XOnlyPublicKey>) -> Result<Tr<XOnlyPublicKey>, PolicyError> {
+    return match descriptor {
+        Descriptor::Tr(tr) => Ok(tr.clone()),
+        _ => Err(PolicyError::NotTaproot),
+    };
+}
+
+pub fn finalize_policy_psbt(
+    secp: &Secp256k1<All>,
+    mut psbt: PartiallySignedTransaction,
+) -> Result<Transaction, PolicyError> {
+    psbt.finalize_mut(secp).map_err(PolicyError::Finalize)?;
+    return psbt.extract(secp).map_err(PolicyError::Extract);
+}
+
+fn key_origins(
+    internal_key: XOnlyPublicKey,
+    leaves: &[(u8, bitcoin::Script, Vec<XOnlyPublicKey>)],
+) -> BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, (Fingerprint, DerivationPath))> {
+    let mut origins = BTreeMap::new();
+    origins.insert(
+        internal_key,
+        (vec![], (Fingerprint::default(), DerivationPath::default())),
+    );
+    leaves.iter().for_each(|(_, script, keys)| {
+        let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+        keys.iter().for_each(|key| {
+            let (leaf_hashes, _) = origins
+                .entry(*key)
+                .or_insert((vec![], (Fingerprint::default(), DerivationPath::default())));
+            if !leaf_hashes.contains(&leaf_hash) {
+                leaf_hashes.push(leaf_hash);
+            }
+        });
+    });
+    return origins;
+}
+
+#[test]
+fn policy_compiles_into_a_spendable_tap_tree() {
+    use crate::bitcoin_wallet::{
+        input_data::mock_call::MockCall,
+        script_services::{
+            input_service::{insert_witness_tx_out, sign_tapleaf},
+            output_service::new_witness_pub_k,
+            psbt_factory::{create_partially_signed_tx, get_output, UnlockFn},
+        },
+        spending_path::single_create_tx,
+    };
+    use bitcoin::KeyPair;
+
+    let secp = Secp256k1::new();
+    let key_pairs = [[1u8; 32], [2u8; 32], [3u8; 32]]
+        .iter()
+        .map(|secret| KeyPair::from_seckey_slice(&secp, secret).unwrap())
+        .collect::<Vec<KeyPair>>();
+    let keys = ["A", "B", "C"]
+        .iter()
+        .zip(key_pairs.iter())
+        .map(|(name, key_pair)| (name.to_string(), key_pair.x_only_public_key().0))
+        .collect::<BTreeMap<String, XOnlyPublicKey>>();
+
+    let descriptor =
+        compile_policy("or(99@thresh(2,pk(A),pk(B)),and(pk(C),older(144)))", &keys).unwrap();
+    assert!(matches!(
+        compile_policy("pk(D)", &keys),
+        Err(PolicyError::UnknownKey(_))
+    ));
+
+    let leaves = policy_leaves(&descriptor).unwrap();
+    assert_eq!(leaves.len(), 2);
+    let (_, multisig, _) = leaves
+        .iter()
+        .find(|(_, _, leaf_keys)| leaf_keys.len() == 2)
+        .unwrap()
+        .clone();
+
+    let output = get_output(
+        vec![vec![insert_policy_output(descriptor.clone()).unwrap()]],
+        &mut vec![],
+    )[0]
+    .clone();
+    assert_eq!(output.tap_internal_key, Some(nums_x_only()));
+    assert_eq!(output.tap_key_origins.len(), 4);
+    assert_eq!(output.tap_tree.unwrap().script_leaves().count(), 2);
+
+    // a descriptor that isn't tr() can't fill in taproot fields
+    let pkh = Descriptor::new_pkh(keys["A"]);
+    assert!(matches!(
+        insert_policy_output(pkh.clone()),
+        Err(PolicyError::NotTaproot)
+    ));
+    assert!(matches!(
+        insert_policy_input(pkh),
+        Err(PolicyError::NotTaproot)
+    ));
+
+    let client = MockCall::fund(&descriptor.script_pubkey(), &[60_000]);
+    let script_pubkey = descriptor.script_pubkey();
+    let psbt = create_partially_signed_tx(
+        vec![vec![new_witness_pub_k(script_pubkey.clone())]],
+        single_create_tx(),
+        Box::new(|previous_list: Vec<Transaction>, current_tx: Transaction| {
+            let prevouts = previous_list[0].output.clone();
+            let mut unlock_vec: Vec<UnlockFn> = vec![
+                insert_witness_tx_out(prevouts[0].clone()),
+                insert_policy_input(descriptor.clone()).unwrap(),
+            ];
+            key_pairs[..2].iter().for_each(|key_pair| {
+                unlock_vec.push(sign_tapleaf(
+                    &secp,
+                    key_pair,
+                    current_tx.clone(),
+                    prevouts.clone(),
+                    0,
+                    multisig.clone(),
+                ));
+            });
+            return vec![unlock_vec];
+        }),
+    )(&client);
+
+    let tx = finalize_policy_psbt(&secp, psbt).unwrap();
+    let witness = tx.input[0].witness.to_vec();
+    assert_eq!(witness[witness.len() - 2], multisig.to_bytes());
+}
@@ -0,0 +1,410 @@
+use std::fmt;
+
+use bitcoin::{
+    blockdata::{opcodes::all, script::Instruction},
+    psbt::{Input, PartiallySignedTransaction},
+    Address, Script, TxOut,
+};
+use serde::Serialize;
+
+use crate::bitcoin_wallet::constants::NETWORK;
+
+// size in bytes of the witness items we expect before the input is signed
+const SCHNORR_SIG_LEN: usize = 65;
+const ECDSA_SIG_LEN: usize = 73;
+const COMPRESSED_KEY_LEN: usize = 33;
+const PREIMAGE_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptType {
+    P2tr,
+    P2wpkh,
+    P2wsh,
+    P2pkh,
+    P2sh,
+    Unknown,
+}
+
+impl ScriptType {
+    pub fn from_script(script: &Script) -> Self {
+        if script.is_v1_p2tr() {
+            return ScriptType::P2tr;
+        }
+        if script.is_v0_p2wpkh() {
+            return ScriptType::P2wpkh;
+        }
+        if script.is_v0_p2wsh() {
+            return ScriptType::P2wsh;
+        }
+        if script.is_p2pkh() {
+            return ScriptType::P2pkh;
+        }
+        if script.is_p2sh() {
+            return ScriptType::P2sh;
+        }
+        return ScriptType::Unknown;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "count")]
+pub enum SignatureStatus {
+    Unsigned,
+    Partial(usize),
+    KeyPathSigned,
+    ScriptPathSigned(usize),
+    Finalized,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputReport {
+    pub index: usize,
+    pub previous_output: String,
+    pub value: Option<u64>,
+    pub script_type: Option<ScriptType>,
+    pub address: Option<String>,
+    pub signature_status: SignatureStatus,
+    pub sighash: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputReport {
+    pub index: usize,
+    pub address: Option<String>,
+    pub script_type: ScriptType,
+    pub value: u64,
+    pub is_change: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PsbtReport {
+    pub txid: String,
+    pub inputs: Vec<InputReport>,
+    pub outputs: Vec<OutputReport>,
+    pub fee: Option<u64>,
+    pub fee_rate: Option<f64>,
+    pub estimated_weight: usize,
+    pub estimated_vsize: usize,
+}
+
+impl PsbtReport {
+    // outputs paying to one of the change scripts are flagged so the reviewer can tell
+    // what actually leaves the wallet
+    pub fn new(psbt: &PartiallySignedTransaction, change_scripts: &[Script]) -> Self {
+        let tx = &psbt.unsigned_tx;
+
+        let inputs = tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(index, tx_in)| {
+                let input = psbt.inputs.get(index).cloned().unwrap_or_default();
+                let prevout = spent_output(&input, tx_in.previous_output.vout);
+                return InputReport {
+                    index,
+                    previous_output: tx_in.previous_output.to_string(),
+                    value: prevout.as_ref().map(|tx_out| tx_out.value),
+                    script_type: prevout
+                        .as_ref()
+                        .map(|tx_out| ScriptType::from_script(&tx_out.script_pubkey)),
+                    address: prevout
+                        .as_ref()
+                        .and_then(|tx_out| script_address(&tx_out.script_pubkey)),
+                    signature_status: signature_status(&input),
+                    sighash: sighash_types(&input),
+                };
+            })
+            .collect::<Vec<InputReport>>();
+
+        let outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(index, tx_out)| OutputReport {
+                index,
+                address: script_address(&tx_out.script_pubkey),
+                script_type: ScriptType::from_script(&tx_out.script_pubkey),
+                value: tx_out.value,
+                is_change: change_scripts.contains(&tx_out.script_pubkey),
+            })
+            .collect::<Vec<OutputReport>>();
+
+        let total_in = inputs.iter().map(|input| input.value).sum::<Option<u64>>();
+        let total_out = outputs.iter().map(|output| output.value).sum::<u64>();
+        let fee = total_in.and_then(|total| total.checked_sub(total_out));
+
+        let estimated_weight = estimate_weight(psbt);
+        let estimated_vsize = estimated_weight.div_ceil(4);
+
+        return PsbtReport {
+            txid: tx.txid().to_string(),
+            inputs,
+            outputs,
+            fee,
+            fee_rate: fee.map(|fee| fee as f64 / estimated_vsize as f64),
+            estimated_weight,
+            estimated_vsize,
+        };
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        return serde_json::to_string_pretty(self);
+    }
+}
+
+impl fmt::Display for PsbtReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "transaction {}", self.txid)?;
+        writeln!(f, "inputs:")?;
+        for input in &self.inputs {
+            writeln!(
+                f,
+                "  #{} {} value: {} type: {} address: {} signatures: {} sighash: {}",
+                input.index,
+                input.previous_output,
+                display_or_unknown(input.value.map(|value| format!("{} sat", value))),
+                display_or_unknown(input.script_type.map(|t| format!("{:?}", t).to_lowercase())),
+                display_or_unknown(input.address.clone()),
+                input.signature_status,
+                display_or_unknown(Some(input.sighash.join(",")).filter(|s| !s.is_empty())),
+            )?;
+        }
+        writeln!(f, "outputs:")?;
+        for output in &self.outputs {
+            writeln!(
+                f,
+                "  #{} {} value: {} sat type: {} change: {}",
+                output.index,
+                display_or_unknown(output.address.clone()),
+                output.value,
+                format!("{:?}", output.script_type).to_lowercase(),
+                output.is_change,
+            )?;
+        }
+        writeln!(
+            f,
+            "fee: {} feerate: {}",
+            display_or_unknown(self.fee.map(|fee| format!("{} sat", fee))),
+            display_or_unknown(self.fee_rate.map(|rate| format!("{:.2} sat/vB", rate))),
+        )?;
+        write!(
+            f,
+            "estimated weight: {} wu ({} vB)",
+            self.estimated_weight, self.estimated_vsize
+        )
+    }
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureStatus::Unsigned => write!(f, "unsigned"),
+            SignatureStatus::Partial(count) => write!(f, "{} partial signature(s)", count),
+            SignatureStatus::KeyPathSigned => write!(f, "key path signed"),
+            SignatureStatus::ScriptPathSigned(count) => {
+                write!(f, "{} script path signature(s)", count)
+            }
+            SignatureStatus::Finalized => write!(f, "finalized"),
+        }
+    }
+}
+
+fn display_or_unknown(value: Option<String>) -> String {
+    return value.unwrap_or_else(|| "unknown".to_owned());
+}
+
+fn script_address(script: &Script) -> Option<String> {
+    return Address::from_script(script, NETWORK)
+        .map(|address| address.to_string())
+        .ok();
+}
+
+fn spent_output(input: &Input, vout: u32) -> Option<TxOut> {
+    return input.witness_utxo.clone().or_else(|| {
+        input
+            .non_witness_utxo
+            .as_ref()
+            .and_then(|tx| tx.output.get(vout as usize).cloned())
+    });
+}
+
+fn signature_status(input: &Input) -> SignatureStatus {
+    if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+        return SignatureStatus::Finalized;
+    }
+    if input.tap_key_sig.is_some() {
+        return SignatureStatus::KeyPathSigned;
+    }
+    if !input.tap_script_sigs.is_empty() {
+        return SignatureStatus::ScriptPathSigned(input.tap_script_sigs.len());
+    }
+    if !input.partial_sigs.is_empty() {
+        return SignatureStatus::Partial(input.partial_sigs.len());
+    }
+    return SignatureStatus::Unsigned;
+}
+
+fn sighash_types(input: &Input) -> Vec<String> {
+    let mut sighash = input
+        .sighash_type
+        .iter()
+        .map(|ty| ty.to_string())
+        .chain(input.tap_key_sig.iter().map(|sig| sig.hash_ty.to_string()))
+        .chain(
+            input
+                .tap_script_sigs
+                .values()
+                .map(|sig| sig.hash_ty.to_string()),
+        )
+        .chain(
+            input
+                .partial_sigs
+                .values()
+                .map(|sig| sig.hash_ty.to_string()),
+        )
+        .collect::<Vec<String>>();
+    sighash.sort();
+    sighash.dedup();
+    return sighash;
+}
+
+pub fn estimate_weight(psbt: &PartiallySignedTransaction) -> usize {
+    let witness_weight = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .enumerate()
+        .map(|(index, tx_in)| {
+            let input = psbt.inputs.get(index).cloned().unwrap_or_default();
+            let script_pubkey =
+                spent_output(&input, tx_in.previous_output.vout).map(|tx_out| tx_out.script_pubkey);
+            estimate_witness_len(&input, script_pubkey.as_ref())
+        })
+        .sum::<usize>();
+
+    // the segwit marker and flag only count once, and only when there is a witness at all
+    let marker = if witness_weight > 0 { 2 } else { 0 };
+    return psbt.unsigned_tx.weight() + marker + witness_weight;
+}
+
+fn estimate_witness_len(input: &Input, script_pubkey: Option<&Script>) -> usize {
+    if let Some(witness) = &input.final_script_witness {
+        return witness.serialized_len();
+    }
+    if !input.tap_scripts.is_empty() {
+        return input
+            .tap_scripts
+            .iter()
+            .map(|(control, (script, _))| {
+                let control_len = control.serialize().len();
+                1 + leaf_stack_len(script) + var_len(script.len()) + var_len(control_len)
+            })
+            .min()
+            .unwrap();
+    }
+    if input.tap_key_sig.is_some() || script_pubkey.is_some_and(|s| s.is_v1_p2tr()) {
+        return 1 + var_len(SCHNORR_SIG_LEN);
+    }
+    if let Some(witness_script) = input
+        .witness_script
+        .as_ref()
+        .filter(|_| script_pubkey.is_some_and(|s| s.is_v0_p2wsh()))
+    {
+        // OP_CHECKMULTISIG consumes an extra dummy element
+        let (sig_count, dummy) = match multisig_threshold(witness_script) {
+            Some(threshold) => (threshold, 1),
+            None => (1, 0),
+        };
+        return 1 + dummy + sig_count * var_len(ECDSA_SIG_LEN) + var_len(witness_script.len());
+    }
+    if script_pubkey.is_some_and(|s| s.is_v0_p2wpkh()) {
+        return 1 + var_len(ECDSA_SIG_LEN) + var_len(COMPRESSED_KEY_LEN);
+    }
+    return 0;
+}
+
+// every signature check in a leaf needs a signature, every hash lock needs a preimage
+fn leaf_stack_len(script: &Script) -> usize {
+    return script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::Op(all::OP_CHECKSIG))
+            | Ok(Instruction::Op(all::OP_CHECKSIGVERIFY))
+            | Ok(Instruction::Op(all::OP_CHECKSIGADD)) => Some(var_len(SCHNORR_SIG_LEN)),
+            Ok(Instruction::Op(all::OP_SHA256))
+            | Ok(Instruction::Op(all::OP_HASH256))
+            | Ok(Instruction::Op(all::OP_RIPEMD160))
+            | Ok(Instruction::Op(all::OP_HASH160)) => Some(var_len(PREIMAGE_LEN)),
+            _ => None,
+        })
+        .sum::<usize>();
+}
+
+fn multisig_threshold(script: &Script) -> Option<usize> {
+    let instructions = script
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()
+        .ok()?;
+    if !matches!(
+        instructions.last(),
+        Some(Instruction::Op(all::OP_CHECKMULTISIG))
+    ) {
+        return None;
+    }
+    return match instructions.first() {
+        Some(Instruction::Op(op)) => {
+            let code = op.to_u8();
+            let first = all::OP_PUSHNUM_1.to_u8();
+            let last = all::OP_PUSHNUM_16.to_u8();
+            (first..=last)
+                .contains(&code)
+                .then(|| (code - first + 1) as usize)
+        }
+        _ => None,
+    };
+}
+
+fn var_len(len: usize) -> usize {
+    let prefix = match len {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        _ => 5,
+    };
+    return prefix + len;
+}
+
+#[test]
+fn report_lists_fee_and_change() {
+    use bitcoin::psbt::Input;
+
+    use crate::bitcoin_wallet::input_data::tapscript_ex_input::{get_tx, tx_as_hash};
+
+    let unsigned_tx = tx_as_hash();
+    let funding = get_tx();
+    let change = unsigned_tx.output[0].script_pubkey.clone();
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx.clone()).unwrap();
+    psbt.inputs = vec![Input {
+        witness_utxo: Some(funding.output[0].clone()),
+        ..Default::default()
+    }];
+
+    let report = PsbtReport::new(&psbt, &[change]);
+
+    let spent = funding.output[0].value;
+    let sent = unsigned_tx.output[0].value;
+    assert_eq!(report.fee, Some(spent - sent));
+    assert_eq!(report.inputs[0].script_type, Some(ScriptType::P2tr));
+    assert_eq!(report.inputs[0].signature_status, SignatureStatus::Unsigned);
+    assert_eq!(report.outputs[0].script_type, ScriptType::P2wpkh);
+    assert!(report.outputs[0].is_change);
+
+    // one key path signature plus the segwit marker on top of the stripped transaction
+    assert_eq!(report.estimated_weight, unsigned_tx.weight() + 2 + 67);
+
+    let json = serde_json::from_str::<serde_json::Value>(&report.to_json().unwrap()).unwrap();
+    assert_eq!(json["fee"], spent - sent);
+    assert_eq!(json["inputs"][0]["signature_status"]["status"], "unsigned");
+    assert!(report.to_string().contains("change: true"));
+}
@@ -0,0 +1,135 @@
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use bitcoin::{
+    consensus::encode,
+    psbt::{PartiallySignedTransaction, PsbtParseError},
+};
+use bitcoin_hashes::hex::{FromHex, ToHex};
+
+// every binary psbt starts with the magic bytes "psbt" followed by the 0xff separator
+const PSBT_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xff];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsbtFormat {
+    Binary,
+    Base64,
+    Hex,
+}
+
+#[derive(Debug)]
+pub enum PsbtIoError {
+    Io(std::io::Error),
+    Encoding(encode::Error),
+    Base64(PsbtParseError),
+    Hex(bitcoin_hashes::hex::Error),
+    UnknownFormat,
+}
+
+impl fmt::Display for PsbtIoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsbtIoError::Io(err) => write!(f, "failed to access psbt file: {}", err),
+            PsbtIoError::Encoding(err) => write!(f, "invalid psbt encoding: {}", err),
+            PsbtIoError::Base64(err) => write!(f, "invalid base64 psbt: {}", err),
+            PsbtIoError::Hex(err) => write!(f, "invalid hex psbt: {}", err),
+            PsbtIoError::UnknownFormat => write!(f, "data is not a binary, base64 or hex psbt"),
+        }
+    }
+}
+
+impl std::error::Error for PsbtIoError {}
+
+impl From<std::io::Error> for PsbtIoError {
+    fn from(err: std::io::Error) -> Self {
+        PsbtIoError::Io(err)
+    }
+}
+
+pub fn serialize_psbt(psbt: &PartiallySignedTransaction, format: PsbtFormat) -> Vec<u8> {
+    let binary = encode::serialize(psbt);
+    return match format {
+        PsbtFormat::Binary => binary,
+        PsbtFormat::Base64 => psbt.to_string().into_bytes(),
+        PsbtFormat::Hex => binary.to_hex().into_bytes(),
+    };
+}
+
+pub fn deserialize_psbt(
+    data: &[u8],
+    format: PsbtFormat,
+) -> Result<PartiallySignedTransaction, PsbtIoError> {
+    return match format {
+        PsbtFormat::Binary => encode::deserialize(data).map_err(PsbtIoError::Encoding),
+        PsbtFormat::Base64 => {
+            PartiallySignedTransaction::from_str(ascii_trimmed(data)?).map_err(PsbtIoError::Base64)
+        }
+        PsbtFormat::Hex => {
+            let binary = Vec::<u8>::from_hex(ascii_trimmed(data)?).map_err(PsbtIoError::Hex)?;
+            encode::deserialize(&binary).map_err(PsbtIoError::Encoding)
+        }
+    };
+}
+
+// binary psbts start with the magic bytes, hex and base64 encode those same magic bytes
+// as "70736274ff" and "cHNidP" respectively
+pub fn detect_format(data: &[u8]) -> Option<PsbtFormat> {
+    if data.starts_with(&PSBT_MAGIC) {
+        return Some(PsbtFormat::Binary);
+    }
+    let text = std::str::from_utf8(data).ok()?.trim_start();
+    if text.to_lowercase().starts_with(&PSBT_MAGIC.to_hex()) {
+        return Some(PsbtFormat::Hex);
+    }
+    if text.starts_with("cHNidP") {
+        return Some(PsbtFormat::Base64);
+    }
+    return None;
+}
+
+pub fn decode_psbt(data: &[u8]) -> Result<PartiallySignedTransaction, PsbtIoError> {
+    let format = detect_format(data).ok_or(PsbtIoError::UnknownFormat)?;
+    return deserialize_psbt(data, format);
+}
+
+pub fn save_psbt(
+    psbt: &PartiallySignedTransaction,
+    path: impl AsRef<Path>,
+    format: PsbtFormat,
+) -> Result<(), PsbtIoError> {
+    fs::write(path, serialize_psbt(psbt, format))?;
+    return Ok(());
+}
+
+pub fn load_psbt(path: impl AsRef<Path>) -> Result<PartiallySignedTransaction, PsbtIoError> {
+    return decode_psbt(&fs::read(path)?);
+}
+
+fn ascii_trimmed(data: &[u8]) -> Result<&str, PsbtIoError> {
+    return std::str::from_utf8(data)
+        .map(|text| text.trim())
+        .map_err(|_| PsbtIoError::UnknownFormat);
+}
+
+#[test]
+fn psbt_round_trips_through_every_format() {
+    use crate::bitcoin_wallet::input_data::tapscript_ex_input::tx_as_hash;
+
+    let psbt = PartiallySignedTransaction::from_unsigned_tx(tx_as_hash()).unwrap();
+
+    for format in [PsbtFormat::Binary, PsbtFormat::Base64, PsbtFormat::Hex] {
+        let data = serialize_psbt(&psbt, format);
+        assert_eq!(detect_format(&data), Some(format));
+        assert_eq!(deserialize_psbt(&data, format).unwrap(), psbt);
+        assert_eq!(decode_psbt(&data).unwrap(), psbt);
+    }
+
+    let path = std::env::temp_dir().join("psbt_round_trip.psbt");
+    save_psbt(&psbt, &path, PsbtFormat::Base64).unwrap();
+    assert_eq!(load_psbt(&path).unwrap(), psbt);
+    fs::remove_file(path).unwrap();
+
+    assert!(matches!(
+        decode_psbt(b"not a psbt"),
+        Err(PsbtIoError::UnknownFormat)
+    ));
+}
@@ -0,0 +1,353 @@
+use std::fmt;
+
+use bitcoin::{
+    blockdata::{
+        opcodes::{all, All},
+        script::Instruction,
+    },
+    hashes::{hash160, ripemd160, sha256, sha256d, Hash},
+    psbt::{Input, PartiallySignedTransaction},
+    util::taproot::{LeafVersion, TapLeafHash},
+    LockTime, SchnorrSig, Script, Sequence, Transaction, Witness, XOnlyPublicKey,
+};
+use miniscript::{hash256, miniscript::satisfy::Preimage32, ExtParams, Miniscript, Satisfier, Tap};
+
+use crate::bitcoin_wallet::scripts::read_script_int;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsatisfiableInput(pub usize);
+
+impl fmt::Display for UnsatisfiableInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "no key path signature or satisfiable leaf for input {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsatisfiableInput {}
+
+// everything a psbt input offers to satisfy one of its leaves
+struct InputSatisfier<'a> {
+    input: &'a Input,
+    sequence: Sequence,
+    lock_time: LockTime,
+}
+
+impl<'a> Satisfier<XOnlyPublicKey> for InputSatisfier<'a> {
+    fn lookup_tap_leaf_script_sig(
+        &self,
+        x_only: &XOnlyPublicKey,
+        leaf_hash: &TapLeafHash,
+    ) -> Option<SchnorrSig> {
+        return self
+            .input
+            .tap_script_sigs
+            .get(&(*x_only, *leaf_hash))
+            .copied();
+    }
+
+    fn lookup_sha256(&self, hash: &sha256::Hash) -> Option<Preimage32> {
+        return preimage32(self.input.sha256_preimages.get(hash));
+    }
+
+    fn lookup_hash256(&self, hash: &hash256::Hash) -> Option<Preimage32> {
+        let hash = sha256d::Hash::from_inner(hash.into_inner());
+        return preimage32(self.input.hash256_preimages.get(&hash));
+    }
+
+    fn lookup_ripemd160(&self, hash: &ripemd160::Hash) -> Option<Preimage32> {
+        return preimage32(self.input.ripemd160_preimages.get(hash));
+    }
+
+    fn lookup_hash160(&self, hash: &hash160::Hash) -> Option<Preimage32> {
+        return preimage32(self.input.hash160_preimages.get(hash));
+    }
+
+    fn check_older(&self, n: Sequence) -> bool {
+        return <Sequence as Satisfier<XOnlyPublicKey>>::check_older(&self.sequence, n);
+    }
+
+    fn check_after(&self, n: LockTime) -> bool {
+        return <LockTime as Satisfier<XOnlyPublicKey>>::check_after(&self.lock_time, n);
+    }
+}
+
+fn preimage32(preimage: Option<&Vec<u8>>) -> Option<Preimage32> {
+    return preimage.and_then(|preimage| preimage.as_slice().try_into().ok());
+}
+
+impl<'a> InputSatisfier<'a> {
+    fn preimage(&self, hash_op: All, hash: &[u8]) -> Option<Vec<u8>> {
+        let preimage = match hash_op {
+            all::OP_SHA256 => self
+                .input
+                .sha256_preimages
+                .get(&sha256::Hash::from_slice(hash).ok()?),
+            all::OP_HASH256 => self
+                .input
+                .hash256_preimages
+                .get(&sha256d::Hash::from_slice(hash).ok()?),
+            all::OP_RIPEMD160 => self
+                .input
+                .ripemd160_preimages
+                .get(&ripemd160::Hash::from_slice(hash).ok()?),
+            all::OP_HASH160 => self
+                .input
+                .hash160_preimages
+                .get(&hash160::Hash::from_slice(hash).ok()?),
+            _ => None,
+        };
+        return preimage.cloned();
+    }
+
+    // stack items for leaves that are not miniscript, like the repo's preimage and CSV leaves,
+    // as long as they are a straight line of signature, hash and timelock checks
+    fn satisfy_template(&self, script: &Script, leaf_hash: &TapLeafHash) -> Option<Vec<Vec<u8>>> {
+        let instructions = script
+            .instructions()
+            .collect::<Result<Vec<Instruction>, _>>()
+            .ok()?;
+        let sig = |x_only: &[u8]| {
+            let x_only = XOnlyPublicKey::from_slice(x_only).ok()?;
+            return self.lookup_tap_leaf_script_sig(&x_only, leaf_hash);
+        };
+
+        // items in the order the script consumes them, the first one ends up on top
+        let mut consumed: Vec<Vec<u8>> = vec![];
+        let mut index = 0;
+        while index < instructions.len() {
+            match &instructions[index..] {
+                [Instruction::Op(all::OP_SIZE), size, Instruction::Op(all::OP_EQUALVERIFY), ..]
+                    if read_script_int(size) == Some(32) =>
+                {
+                    index += 3;
+                }
+                [Instruction::Op(hash_op), Instruction::PushBytes(hash), Instruction::Op(equal), ..]
+                    if [all::OP_EQUAL, all::OP_EQUALVERIFY].contains(equal) =>
+                {
+                    consumed.push(self.preimage(*hash_op, hash)?);
+                    index += 3;
+                }
+                [value, Instruction::Op(lock_op), Instruction::Op(drop), ..]
+                    if [all::OP_CSV, all::OP_CLTV].contains(lock_op)
+                        && [all::OP_DROP, all::OP_VERIFY].contains(drop) =>
+                {
+                    let value = u32::try_from(read_script_int(value)?).ok()?;
+                    let satisfied = match *lock_op {
+                        all::OP_CSV => self.check_older(Sequence(value)),
+                        _ => self.check_after(LockTime::from_consensus(value)),
+                    };
+                    if !satisfied {
+                        return None;
+                    }
+                    index += 3;
+                }
+                [Instruction::PushBytes(x_only), Instruction::Op(all::OP_CHECKSIGVERIFY), ..] => {
+                    consumed.push(sig(x_only)?.to_vec());
+                    index += 2;
+                }
+                [Instruction::PushBytes(x_only), Instruction::Op(all::OP_CHECKSIG), ..] => {
+                    // a CHECKSIGADD chain counts signatures, a lone CHECKSIG needs one
+                    let mut keys = vec![x_only.to_vec()];
+                    index += 2;
+                    while let [Instruction::PushBytes(x_only), Instruction::Op(all::OP_CHECKSIGADD), ..] =
+                        &instructions[index..]
+                    {
+                        keys.push(x_only.to_vec());
+                        index += 2;
+                    }
+                    let threshold = match &instructions[index..] {
+                        [threshold, Instruction::Op(equal), ..]
+                            if keys.len() > 1
+                                && [
+                                    all::OP_NUMEQUAL,
+                                    all::OP_NUMEQUALVERIFY,
+                                    all::OP_EQUAL,
+                                    all::OP_EQUALVERIFY,
+                                ]
+                                .contains(equal) =>
+                        {
+                            index += 2;
+                            usize::try_from(read_script_int(threshold)?).ok()?
+                        }
+                        _ if keys.len() == 1 => 1,
+                        _ => return None,
+                    };
+                    let mut remaining = threshold;
+                    keys.iter().for_each(|x_only| match sig(x_only) {
+                        Some(sig) if remaining > 0 => {
+                            remaining -= 1;
+                            consumed.push(sig.to_vec());
+                        }
+                        _ => consumed.push(vec![]),
+                    });
+                    if remaining > 0 {
+                        return None;
+                    }
+                }
+                _ => return None,
+            }
+        }
+        consumed.reverse();
+        return Some(consumed);
+    }
+
+    fn satisfy_leaf(&self, script: &Script) -> Option<Vec<Vec<u8>>> {
+        let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+        return match Miniscript::<XOnlyPublicKey, Tap>::parse_with_ext(
+            script,
+            &ExtParams::allow_all(),
+        ) {
+            Ok(miniscript) => miniscript.satisfy(self).ok(),
+            Err(_) => self.satisfy_template(script, &leaf_hash),
+        };
+    }
+}
+
+fn witness_size(stack: &[Vec<u8>]) -> usize {
+    return stack
+        .iter()
+        .map(|item| bitcoin::VarInt(item.len() as u64).len() + item.len())
+        .sum::<usize>();
+}
+
+// a key path signature always wins, otherwise every leaf in tap_scripts is tried with the
+// signatures and preimages in the input and the smallest witness is used
+pub fn finalize_tap_input(
+    input: &Input,
+    sequence: Sequence,
+    lock_time: LockTime,
+) -> Option<Witness> {
+    if let Some(sig) = input.tap_key_sig {
+        return Some(Witness::from_vec(vec![sig.to_vec()]));
+    }
+    let satisfier = InputSatisfier {
+        input,
+        sequence,
+        lock_time,
+    };
+    return input
+        .tap_scripts
+        .iter()
+        .filter(|(_, (_, version))| *version == LeafVersion::TapScript)
+        .filter_map(|(control_block, (script, _))| {
+            let mut stack = satisfier.satisfy_leaf(script)?;
+            stack.push(script.to_bytes());
+            stack.push(control_block.serialize());
+            return Some(stack);
+        })
+        .min_by_key(|stack| witness_size(stack))
+        .map(Witness::from_vec);
+}
+
+pub fn finalize_tap_psbt(
+    psbt: PartiallySignedTransaction,
+) -> Result<Transaction, UnsatisfiableInput> {
+    let mut tx = psbt.unsigned_tx.clone();
+    let lock_time = LockTime::from(tx.lock_time);
+    for (index, tx_in) in tx.input.iter_mut().enumerate() {
+        let input = psbt.inputs.get(index).ok_or(UnsatisfiableInput(index))?;
+        tx_in.witness = finalize_tap_input(input, tx_in.sequence, lock_time)
+            .ok_or(UnsatisfiableInput(index))?;
+    }
+    return Ok(tx);
+}
+
+#[test]
+fn finalizer_picks_the_cheapest_satisfiable_leaf() {
+    use crate::bitcoin_wallet::scripts::timelock::{relative_delay, RelativeLock};
+    use bitcoin::{
+        secp256k1::{Message, Secp256k1},
+        util::taproot::TaprootBuilder,
+        KeyPair, SchnorrSighashType,
+    };
+
+    let secp = Secp256k1::new();
+    let alice = KeyPair::from_seckey_slice(&secp, &[1u8; 32]).unwrap();
+    let bob = KeyPair::from_seckey_slice(&secp, &[2u8; 32]).unwrap();
+    let x_alice = alice.x_only_public_key().0;
+    let x_bob = bob.x_only_public_key().0;
+    let preimage = vec![5u8; 32];
+
+    // the preimage leaf from TapScriptSendEx, not miniscript because of the missing size check
+    let hash_leaf = bitcoin::blockdata::script::Builder::new()
+        .push_opcode(all::OP_SHA256)
+        .push_slice(&sha256::Hash::hash(&preimage))
+        .push_opcode(all::OP_EQUALVERIFY)
+        .push_x_only_key(&x_bob)
+        .push_opcode(all::OP_CHECKSIG)
+        .into_script();
+    let delay_leaf = relative_delay(&x_alice, RelativeLock::Blocks(144));
+    let multisig_leaf =
+        crate::bitcoin_wallet::scripts::multisig::k_of_n_tapscript(1, &[x_alice, x_bob]).unwrap();
+
+    let spend_info = TaprootBuilder::with_huffman_tree(vec![
+        (1, hash_leaf.clone()),
+        (1, delay_leaf.clone()),
+        (1, multisig_leaf.clone()),
+    ])
+    .unwrap()
+    .finalize(&secp, x_alice)
+    .unwrap();
+
+    let mut input = Input::default();
+    for leaf in [&hash_leaf, &delay_leaf, &multisig_leaf] {
+        let script = (leaf.clone(), LeafVersion::TapScript);
+        input
+            .tap_scripts
+            .insert(spend_info.control_block(&script).unwrap(), script);
+    }
+    let message = Message::from_slice(&[3u8; 32]).unwrap();
+    let sign = |key_pair: &KeyPair, leaf: &Script| {
+        let leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
+        let sig = SchnorrSig {
+            sig: secp.sign_schnorr_no_aux_rand(&message, key_pair),
+            hash_ty: SchnorrSighashType::Default,
+        };
+        ((key_pair.x_only_public_key().0, leaf_hash), sig)
+    };
+
+    // only bob signed the preimage leaf, which needs the preimage before it can be used
+    let (key, sig) = sign(&bob, &hash_leaf);
+    input.tap_script_sigs.insert(key, sig);
+    assert!(finalize_tap_input(&input, Sequence::MAX, LockTime::ZERO).is_none());
+
+    input
+        .sha256_preimages
+        .insert(sha256::Hash::hash(&preimage), preimage.clone());
+    let witness = finalize_tap_input(&input, Sequence::MAX, LockTime::ZERO)
+        .unwrap()
+        .to_vec();
+    assert_eq!(
+        witness[..3].to_vec(),
+        vec![sig.to_vec(), preimage, hash_leaf.to_bytes()]
+    );
+
+    // alice's delayed leaf only counts once the sequence satisfies the CSV, and then wins
+    // over the preimage leaf because its witness is smaller
+    let (key, sig) = sign(&alice, &delay_leaf);
+    input.tap_script_sigs.insert(key, sig);
+    let witness = finalize_tap_input(&input, Sequence::from_height(144), LockTime::ZERO)
+        .unwrap()
+        .to_vec();
+    assert_eq!(
+        witness[..2].to_vec(),
+        vec![sig.to_vec(), delay_leaf.to_bytes()]
+    );
+
+    // the 1-of-2 leaf is miniscript and takes an empty placeholder for the missing signer
+    input.sha256_preimages.clear();
+    let (key, sig) = sign(&alice, &multisig_leaf);
+    input.tap_script_sigs.insert(key, sig);
+    let witness = finalize_tap_input(&input, Sequence::MAX, LockTime::ZERO)
+        .unwrap()
+        .to_vec();
+    assert_eq!(witness[2], multisig_leaf.to_bytes());
+    assert_eq!(
+        witness[..2].iter().filter(|item| item.is_empty()).count(),
+        1
+    );
+}
@@ -1,10 +1,15 @@
 use bitcoin::{
-    blockdata::{opcodes::all, script::Builder},
+    blockdata::{opcodes::all, script::Builder, script::Instruction},
     secp256k1::PublicKey,
     Script, XOnlyPublicKey,
 };
 use miniscript::ToPublicKey;
 
+use self::timelock::RelativeLock;
+
+pub mod multisig;
+pub mod timelock;
+
 // revocation_script
 pub fn check_single_sig(x_only: &XOnlyPublicKey) -> Script {
     return Builder::new()
@@ -14,14 +19,8 @@ pub fn check_single_sig(x_only: &XOnlyPublicKey) -> Script {
 }
 
 // local_delayedsig
-pub fn delay(x_only: &XOnlyPublicKey) -> Script {
-    return Builder::new()
-        .push_int(144)
-        .push_opcode(all::OP_CSV)
-        .push_opcode(all::OP_DROP)
-        .push_x_only_key(&x_only)
-        .push_opcode(all::OP_CHECKSIG)
-        .into_script();
+pub fn delay(x_only: &XOnlyPublicKey, lock: RelativeLock) -> Script {
+    return timelock::relative_delay(x_only, lock);
 }
 
 // https://github.com/Xekyo/murch.one/blob/243b052aad05531f7afcedee45dde1a7594248f6/content/posts/2-of-3-using-P2TR.md
@@ -63,3 +62,30 @@ pub fn p2wsh_multi_sig(pub_keys: &Vec<PublicKey>) -> Script {
         .push_opcode(all::OP_CHECKMULTISIG)
         .into_script();
 }
+
+// reads a small integer push, either OP_0..OP_16 or a minimally encoded script number
+pub(crate) fn read_script_int(instruction: &Instruction) -> Option<i64> {
+    return match instruction {
+        Instruction::Op(op) => {
+            let first = all::OP_PUSHNUM_1.to_u8();
+            let last = all::OP_PUSHNUM_16.to_u8();
+            (first..=last)
+                .contains(&op.to_u8())
+                .then(|| (op.to_u8() - first + 1) as i64)
+        }
+        Instruction::PushBytes(bytes) if bytes.is_empty() => Some(0),
+        // CLTV accepts 5 byte numbers so lock times above 2^31 still fit
+        Instruction::PushBytes(bytes) if bytes.len() <= 5 => {
+            let magnitude = bytes
+                .iter()
+                .rev()
+                .fold(0i64, |acc, byte| (acc << 8) | *byte as i64);
+            let sign_bit = 0x80i64 << (8 * (bytes.len() - 1));
+            match magnitude & sign_bit {
+                0 => Some(magnitude),
+                _ => Some(-(magnitude ^ sign_bit)),
+            }
+        }
+        _ => None,
+    };
+}
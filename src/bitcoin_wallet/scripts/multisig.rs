@@ -0,0 +1,294 @@
+use std::{collections::BTreeMap, fmt};
+
+use bitcoin::{
+    blockdata::{opcodes::all, script::Builder, script::Instruction},
+    util::taproot::ControlBlock,
+    EcdsaSig, PublicKey, SchnorrSig, Script, Witness, XOnlyPublicKey,
+};
+
+use super::read_script_int;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MultisigError {
+    InvalidThreshold { threshold: usize, key_count: usize },
+    // a key given twice would count twice towards the threshold
+    DuplicateKey(String),
+}
+
+impl fmt::Display for MultisigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultisigError::InvalidThreshold {
+                threshold,
+                key_count,
+            } => write!(f, "invalid threshold {} for {} keys", threshold, key_count),
+            MultisigError::DuplicateKey(key) => write!(f, "key {} is given more than once", key),
+        }
+    }
+}
+
+impl std::error::Error for MultisigError {}
+
+// BIP67: keys are sorted by their compressed serialization before the script is built
+pub fn sort_pub_keys(pub_keys: &[PublicKey]) -> Result<Vec<PublicKey>, MultisigError> {
+    let mut sorted = pub_keys.to_vec();
+    sorted.sort_by_key(|pub_k| pub_k.to_bytes());
+    if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(MultisigError::DuplicateKey(pair[0].to_string()));
+    }
+    return Ok(sorted);
+}
+
+pub fn sort_x_only(x_onlys: &[XOnlyPublicKey]) -> Result<Vec<XOnlyPublicKey>, MultisigError> {
+    let mut sorted = x_onlys.to_vec();
+    sorted.sort_by_key(|x_only| x_only.serialize());
+    if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(MultisigError::DuplicateKey(pair[0].to_string()));
+    }
+    return Ok(sorted);
+}
+
+// <k> <pk_1> ... <pk_n> <n> OP_CHECKMULTISIG
+pub fn k_of_n_multisig(threshold: usize, pub_keys: &[PublicKey]) -> Result<Script, MultisigError> {
+    let sorted = sort_pub_keys(pub_keys)?;
+    check_threshold(threshold, sorted.len())?;
+    let script = sorted
+        .iter()
+        .fold(
+            Builder::new().push_int(threshold as i64),
+            |builder, pub_k| builder.push_key(pub_k),
+        )
+        .push_int(sorted.len() as i64)
+        .push_opcode(all::OP_CHECKMULTISIG)
+        .into_script();
+    return Ok(script);
+}
+
+// <pk_1> OP_CHECKSIG <pk_2> OP_CHECKSIGADD ... <pk_n> OP_CHECKSIGADD <k> OP_NUMEQUAL
+pub fn k_of_n_tapscript(
+    threshold: usize,
+    x_onlys: &[XOnlyPublicKey],
+) -> Result<Script, MultisigError> {
+    let sorted = sort_x_only(x_onlys)?;
+    check_threshold(threshold, sorted.len())?;
+    let script = sorted
+        .iter()
+        .enumerate()
+        .fold(Builder::new(), |builder, (index, x_only)| {
+            let opcode = match index {
+                0 => all::OP_CHECKSIG,
+                _ => all::OP_CHECKSIGADD,
+            };
+            builder.push_x_only_key(x_only).push_opcode(opcode)
+        })
+        .push_int(threshold as i64)
+        .push_opcode(all::OP_NUMEQUAL)
+        .into_script();
+    return Ok(script);
+}
+
+// the first key is checked against the top of the stack, so signatures are pushed in reverse
+// script order and signers that don't take part leave an empty element behind
+pub fn k_of_n_tapscript_witness(
+    script: &Script,
+    control_block: &ControlBlock,
+    signatures: &BTreeMap<XOnlyPublicKey, SchnorrSig>,
+) -> Option<Witness> {
+    let (threshold, keys) = parse_k_of_n(script, 32)?;
+    let keys = keys
+        .iter()
+        .map(|key| XOnlyPublicKey::from_slice(key).ok())
+        .collect::<Option<Vec<XOnlyPublicKey>>>()?;
+
+    let mut remaining = threshold;
+    let stack = keys
+        .iter()
+        .map(|x_only| match signatures.get(x_only) {
+            Some(sig) if remaining > 0 => {
+                remaining -= 1;
+                sig.to_vec()
+            }
+            _ => vec![],
+        })
+        .collect::<Vec<Vec<u8>>>();
+
+    if remaining > 0 {
+        return None;
+    }
+
+    let mut witness = Witness::new();
+    stack.iter().rev().for_each(|item| witness.push(item));
+    witness.push(script.as_bytes());
+    witness.push(control_block.serialize());
+    return Some(witness);
+}
+
+// OP_CHECKMULTISIG pops one element too many, hence the leading empty dummy, and expects the
+// signatures in the same order as the keys they belong to
+pub fn k_of_n_multisig_witness(
+    witness_script: &Script,
+    signatures: &BTreeMap<PublicKey, EcdsaSig>,
+) -> Option<Witness> {
+    let (threshold, keys) = parse_k_of_n(witness_script, 33)?;
+    let sigs = keys
+        .iter()
+        .filter_map(|key| PublicKey::from_slice(key).ok())
+        .filter_map(|pub_k| signatures.get(&pub_k))
+        .take(threshold)
+        .collect::<Vec<&EcdsaSig>>();
+
+    if sigs.len() < threshold {
+        return None;
+    }
+
+    let mut witness = Witness::new();
+    witness.push(vec![]);
+    sigs.iter().for_each(|sig| witness.push(sig.to_vec()));
+    witness.push(witness_script.as_bytes());
+    return Some(witness);
+}
+
+// returns the threshold and the keys in script order for both multisig layouts above
+pub fn parse_k_of_n(script: &Script, key_len: usize) -> Option<(usize, Vec<Vec<u8>>)> {
+    let instructions = script
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()
+        .ok()?;
+
+    let keys = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::PushBytes(bytes) if bytes.len() == key_len => Some(bytes.to_vec()),
+            _ => None,
+        })
+        .collect::<Vec<Vec<u8>>>();
+
+    let threshold = match instructions.as_slice() {
+        [.., threshold, Instruction::Op(all::OP_NUMEQUAL)] => read_script_int(threshold)?,
+        [threshold, .., _, Instruction::Op(all::OP_CHECKMULTISIG)] => read_script_int(threshold)?,
+        _ => return None,
+    };
+    let threshold = usize::try_from(threshold).ok()?;
+
+    if keys.is_empty() || threshold == 0 || threshold > keys.len() {
+        return None;
+    }
+    return Some((threshold, keys));
+}
+
+fn check_threshold(threshold: usize, key_count: usize) -> Result<(), MultisigError> {
+    if threshold == 0 || threshold > key_count {
+        return Err(MultisigError::InvalidThreshold {
+            threshold,
+            key_count,
+        });
+    }
+    return Ok(());
+}
+
+#[test]
+fn multisig_keys_are_sorted_bip67() {
+    use std::str::FromStr;
+
+    let first =
+        PublicKey::from_str("02ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f8")
+            .unwrap();
+    let second =
+        PublicKey::from_str("02fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f")
+            .unwrap();
+
+    let script = k_of_n_multisig(2, &[first, second]).unwrap();
+    let (threshold, keys) = parse_k_of_n(&script, 33).unwrap();
+
+    assert_eq!(threshold, 2);
+    assert_eq!(keys, vec![second.to_bytes(), first.to_bytes()]);
+    assert_eq!(script, k_of_n_multisig(2, &[second, first]).unwrap());
+
+    assert_eq!(
+        k_of_n_multisig(3, &[first, second]),
+        Err(MultisigError::InvalidThreshold {
+            threshold: 3,
+            key_count: 2
+        })
+    );
+    assert_eq!(
+        k_of_n_multisig(0, &[first, second]),
+        Err(MultisigError::InvalidThreshold {
+            threshold: 0,
+            key_count: 2
+        })
+    );
+    // a repeated key would silently turn 2-of-3 into 2-of-2
+    assert_eq!(
+        k_of_n_multisig(2, &[first, second, first]),
+        Err(MultisigError::DuplicateKey(first.to_string()))
+    );
+}
+
+#[test]
+fn tapscript_witness_orders_signatures_with_placeholders() {
+    use bitcoin::{
+        secp256k1::{Message, Secp256k1},
+        util::taproot::{LeafVersion, TaprootBuilder},
+        KeyPair, SchnorrSighashType,
+    };
+    use miniscript::{Miniscript, Tap};
+
+    let secp = Secp256k1::new();
+    let key_pairs = [[1u8; 32], [2u8; 32], [3u8; 32]]
+        .iter()
+        .map(|secret| KeyPair::from_seckey_slice(&secp, secret).unwrap())
+        .collect::<Vec<KeyPair>>();
+    let x_onlys = key_pairs
+        .iter()
+        .map(|key_pair| key_pair.x_only_public_key().0)
+        .collect::<Vec<XOnlyPublicKey>>();
+
+    let sorted = sort_x_only(&x_onlys).unwrap();
+    let script = k_of_n_tapscript(2, &x_onlys).unwrap();
+    let miniscript = Miniscript::<XOnlyPublicKey, Tap>::parse(&script).unwrap();
+    assert_eq!(
+        miniscript.to_string(),
+        format!("multi_a(2,{},{},{})", sorted[0], sorted[1], sorted[2])
+    );
+
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, script.clone())
+        .unwrap()
+        .finalize(&secp, x_onlys[0])
+        .unwrap();
+    let control_block = spend_info
+        .control_block(&(script.clone(), LeafVersion::TapScript))
+        .unwrap();
+
+    let message = Message::from_slice(&[7u8; 32]).unwrap();
+    let sign = |key_pair: &KeyPair| SchnorrSig {
+        sig: secp.sign_schnorr_no_aux_rand(&message, key_pair),
+        hash_ty: SchnorrSighashType::Default,
+    };
+
+    let mut signatures = BTreeMap::new();
+    signatures.insert(x_onlys[0], sign(&key_pairs[0]));
+    assert!(k_of_n_tapscript_witness(&script, &control_block, &signatures).is_none());
+
+    signatures.insert(x_onlys[2], sign(&key_pairs[2]));
+    let witness = k_of_n_tapscript_witness(&script, &control_block, &signatures)
+        .unwrap()
+        .to_vec();
+
+    let expected_stack = sorted
+        .iter()
+        .rev()
+        .map(|x_only| {
+            signatures
+                .get(x_only)
+                .map(|sig| sig.to_vec())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<Vec<u8>>>();
+
+    assert_eq!(witness.len(), 5);
+    assert_eq!(witness[..3].to_vec(), expected_stack);
+    assert_eq!(witness[3], script.to_bytes());
+    assert_eq!(witness[4], control_block.serialize());
+}
@@ -0,0 +1,299 @@
+use std::fmt;
+
+use bitcoin::{
+    blockdata::{opcodes::all, script::Builder, script::Instruction},
+    PackedLockTime, Script, Sequence, Transaction, XOnlyPublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+use super::read_script_int;
+
+// nLockTime values below this are block heights, everything above is a unix timestamp
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+// BIP68 relative lock, time locks are counted in units of 512 seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelativeLock {
+    Blocks(u16),
+    Time(u16),
+}
+
+// BIP65 absolute lock, either a block height or a unix timestamp compared against median time past
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbsoluteLock {
+    Height(u32),
+    Time(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeLock {
+    Relative(RelativeLock),
+    Absolute(AbsoluteLock),
+}
+
+// a transaction has a single nLockTime, so it can't wait for a height and a timestamp at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MixedLockUnits;
+
+impl fmt::Display for MixedLockUnits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "absolute locks by height and by time can't be combined in one transaction"
+        )
+    }
+}
+
+impl std::error::Error for MixedLockUnits {}
+
+// the first block height, or the median time past of the tip, at which a spend can be mined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Maturity {
+    Height(u32),
+    MedianTime(u32),
+}
+
+// the tip of a chain and the seconds it targets between blocks, enough to estimate when an
+// absolute lock on that chain matures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainTip {
+    pub height: u32,
+    pub median_time: u32,
+    pub block_interval: u32,
+}
+
+impl RelativeLock {
+    pub fn from_seconds(seconds: u32) -> Option<Self> {
+        return Sequence::from_seconds_ceil(seconds)
+            .ok()
+            .and_then(RelativeLock::from_sequence);
+    }
+
+    pub fn from_sequence(sequence: Sequence) -> Option<Self> {
+        if !sequence.is_relative_lock_time() {
+            return None;
+        }
+        let value = (sequence.to_consensus_u32() & 0xFFFF) as u16;
+        if sequence.is_time_locked() {
+            return Some(RelativeLock::Time(value));
+        }
+        return Some(RelativeLock::Blocks(value));
+    }
+
+    pub fn to_sequence(&self) -> Sequence {
+        return match self {
+            RelativeLock::Blocks(blocks) => Sequence::from_height(*blocks),
+            RelativeLock::Time(intervals) => Sequence::from_512_second_intervals(*intervals),
+        };
+    }
+
+    // BIP68 only applies from version 2 on
+    pub fn apply(&self, tx: &mut Transaction, input_index: usize) {
+        tx.version = tx.version.max(2);
+        tx.input[input_index].sequence = self.to_sequence();
+    }
+}
+
+impl AbsoluteLock {
+    pub fn from_consensus(lock_time: u32) -> Self {
+        if lock_time < LOCK_TIME_THRESHOLD {
+            return AbsoluteLock::Height(lock_time);
+        }
+        return AbsoluteLock::Time(lock_time);
+    }
+
+    pub fn to_consensus_u32(&self) -> u32 {
+        return match self {
+            AbsoluteLock::Height(height) => *height,
+            AbsoluteLock::Time(time) => *time,
+        };
+    }
+
+    pub fn to_lock_time(&self) -> PackedLockTime {
+        return PackedLockTime(self.to_consensus_u32());
+    }
+}
+
+impl TimeLock {
+    // sets the fields consensus checks the lock against, absolute locks are ignored while every
+    // input is final. an nLockTime of 0 counts as unset, any other has to be in the same unit as
+    // the lock
+    pub fn apply(&self, tx: &mut Transaction, input_index: usize) -> Result<(), MixedLockUnits> {
+        match self {
+            TimeLock::Relative(lock) => lock.apply(tx, input_index),
+            TimeLock::Absolute(lock) => {
+                let current = AbsoluteLock::from_consensus(tx.lock_time.0);
+                let same_unit = matches!(
+                    (current, lock),
+                    (AbsoluteLock::Height(_), AbsoluteLock::Height(_))
+                        | (AbsoluteLock::Time(_), AbsoluteLock::Time(_))
+                );
+                if tx.lock_time.0 != 0 && !same_unit {
+                    return Err(MixedLockUnits);
+                }
+                tx.lock_time = PackedLockTime(tx.lock_time.0.max(lock.to_consensus_u32()));
+                if tx.input[input_index].sequence == Sequence::MAX {
+                    tx.input[input_index].sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // confirmed_median_time is the median time past of the block before the one that
+    // confirmed the output, which is what BIP68 measures relative time locks from
+    pub fn maturity(&self, confirmed_height: u32, confirmed_median_time: u32) -> Maturity {
+        return match self {
+            TimeLock::Relative(RelativeLock::Blocks(blocks)) => {
+                Maturity::Height(confirmed_height + *blocks as u32)
+            }
+            TimeLock::Relative(RelativeLock::Time(intervals)) => {
+                Maturity::MedianTime(confirmed_median_time + ((*intervals as u32) << 9))
+            }
+            TimeLock::Absolute(AbsoluteLock::Height(height)) => Maturity::Height(height + 1),
+            TimeLock::Absolute(AbsoluteLock::Time(time)) => Maturity::MedianTime(time + 1),
+        };
+    }
+}
+
+impl ChainTip {
+    // the median time past the chain is expected to reach once the lock can be spent, heights
+    // are projected forward at the block interval. relative locks count from whenever the
+    // output confirms, so they have no such time
+    pub fn expected_median_time(&self, lock: &TimeLock) -> Option<u64> {
+        return match lock {
+            TimeLock::Relative(_) => None,
+            TimeLock::Absolute(AbsoluteLock::Height(height)) => {
+                let blocks = height.saturating_sub(self.height) as u64;
+                Some(self.median_time as u64 + blocks * self.block_interval as u64)
+            }
+            TimeLock::Absolute(AbsoluteLock::Time(time)) => Some(*time as u64),
+        };
+    }
+}
+
+impl Maturity {
+    // tip_median_time is the median time past of the current tip, the next block is
+    // validated against it
+    pub fn is_reached(&self, tip_height: u32, tip_median_time: u32) -> bool {
+        return match self {
+            Maturity::Height(height) => tip_height + 1 >= *height,
+            Maturity::MedianTime(time) => tip_median_time >= *time,
+        };
+    }
+}
+
+// <sequence> OP_CSV OP_DROP <x_only> OP_CHECKSIG
+pub fn relative_delay(x_only: &XOnlyPublicKey, lock: RelativeLock) -> Script {
+    return Builder::new()
+        .push_int(lock.to_sequence().to_consensus_u32() as i64)
+        .push_opcode(all::OP_CSV)
+        .push_opcode(all::OP_DROP)
+        .push_x_only_key(x_only)
+        .push_opcode(all::OP_CHECKSIG)
+        .into_script();
+}
+
+// <lock_time> OP_CLTV OP_DROP <x_only> OP_CHECKSIG
+pub fn absolute_delay(x_only: &XOnlyPublicKey, lock: AbsoluteLock) -> Script {
+    return Builder::new()
+        .push_int(lock.to_consensus_u32() as i64)
+        .push_opcode(all::OP_CLTV)
+        .push_opcode(all::OP_DROP)
+        .push_x_only_key(x_only)
+        .push_opcode(all::OP_CHECKSIG)
+        .into_script();
+}
+
+// every lock a leaf commits to through OP_CSV or OP_CLTV, so the spending tx can be built to match
+pub fn script_timelocks(script: &Script) -> Vec<TimeLock> {
+    let instructions = match script
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()
+    {
+        Ok(instructions) => instructions,
+        Err(_) => return vec![],
+    };
+
+    return instructions
+        .windows(2)
+        .filter_map(|window| {
+            let value = u32::try_from(read_script_int(&window[0])?).ok()?;
+            match window[1] {
+                Instruction::Op(all::OP_CSV) => {
+                    RelativeLock::from_sequence(Sequence(value)).map(TimeLock::Relative)
+                }
+                Instruction::Op(all::OP_CLTV) => {
+                    Some(TimeLock::Absolute(AbsoluteLock::from_consensus(value)))
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<TimeLock>>();
+}
+
+#[test]
+fn timelocks_round_trip_through_scripts_and_transactions() {
+    use bitcoin::{OutPoint, TxIn, Witness};
+    use std::str::FromStr;
+
+    let x_only = XOnlyPublicKey::from_str(
+        "9997a497d964fc1a62885b05a51166a65a90df00492c8d7cf61d6accf54803be",
+    )
+    .unwrap();
+
+    // the script TapScriptSendEx has always used for alice
+    assert_eq!(
+        relative_delay(&x_only, RelativeLock::Blocks(144)),
+        Script::from_str(
+            "029000b275209997a497d964fc1a62885b05a51166a65a90df00492c8d7cf61d6accf54803beac"
+        )
+        .unwrap()
+    );
+
+    let relative = RelativeLock::from_seconds(3600).unwrap();
+    assert_eq!(relative, RelativeLock::Time(8));
+    let absolute = AbsoluteLock::Time(1_700_000_000);
+    let mut script = relative_delay(&x_only, relative).to_bytes();
+    script.extend(absolute_delay(&x_only, absolute).to_bytes());
+    let locks = script_timelocks(&Script::from(script));
+    assert_eq!(
+        locks,
+        vec![TimeLock::Relative(relative), TimeLock::Absolute(absolute)]
+    );
+
+    let mut tx = Transaction {
+        version: 1,
+        lock_time: PackedLockTime(0),
+        input: vec![TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::default(),
+        }],
+        output: vec![],
+    };
+    locks
+        .iter()
+        .try_for_each(|lock| lock.apply(&mut tx, 0))
+        .unwrap();
+    assert_eq!(tx.version, 2);
+    assert_eq!(tx.lock_time, PackedLockTime(1_700_000_000));
+    assert_eq!(tx.input[0].sequence, Sequence::from_512_second_intervals(8));
+
+    // a later timestamp moves the lock time, a height can't share it
+    TimeLock::Absolute(AbsoluteLock::Time(1_700_000_600))
+        .apply(&mut tx, 0)
+        .unwrap();
+    assert_eq!(tx.lock_time, PackedLockTime(1_700_000_600));
+    assert_eq!(
+        TimeLock::Absolute(AbsoluteLock::Height(800_000)).apply(&mut tx, 0),
+        Err(MixedLockUnits)
+    );
+    assert_eq!(tx.lock_time, PackedLockTime(1_700_000_600));
+
+    let maturity = TimeLock::Relative(RelativeLock::Blocks(144)).maturity(100, 0);
+    assert_eq!(maturity, Maturity::Height(244));
+    assert!(!maturity.is_reached(242, 0));
+    assert!(maturity.is_reached(243, 0));
+}
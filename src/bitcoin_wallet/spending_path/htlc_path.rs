@@ -0,0 +1,521 @@
+use std::fmt;
+
+use bitcoin::{
+    blockdata::{opcodes::all, script::Builder},
+    psbt::{Input, PartiallySignedTransaction},
+    secp256k1::{All, PublicKey, Secp256k1},
+    util::taproot::{LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo},
+    Address, KeyPair, Script, Transaction, TxIn, TxOut, Witness, XOnlyPublicKey,
+};
+use bitcoin_hashes::{sha256, Hash};
+
+use crate::bitcoin_wallet::{
+    constants::{nums_x_only, NETWORK},
+    script_services::{
+        input_service::{
+            insert_control_block, insert_sha256_preimage, insert_witness, insert_witness_tx_out,
+            sign_segwit_v0, sign_tapleaf,
+        },
+        output_service::{
+            insert_tap_key_origin, insert_tap_tree, insert_tree_witness, new_tap_internal_key,
+            new_witness_pub_k,
+        },
+        psbt_factory::{CreateTxFn, LockFn, SpendFn, UnlockFn},
+    },
+    scripts::timelock::{ChainTip, TimeLock},
+};
+
+use super::{single_create_tx, timelocked_create_tx};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HtlcKind {
+    SegwitV0,
+    Taproot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HtlcPath {
+    Claim,
+    Refund,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HtlcError {
+    // a claim needs the preimage of the payment hash
+    MissingPreimage,
+    // neither the receiver's signature with the preimage nor the sender's signature
+    UnsatisfiableInput(usize),
+}
+
+impl fmt::Display for HtlcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HtlcError::MissingPreimage => write!(f, "claiming an htlc needs the preimage"),
+            HtlcError::UnsatisfiableInput(index) => write!(
+                f,
+                "input {} has neither the receiver preimage and signature nor the sender signature",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HtlcError {}
+
+// pays the receiver against the preimage of payment_hash, or the sender once timeout passes
+#[derive(Debug, Clone)]
+pub struct Htlc {
+    pub kind: HtlcKind,
+    pub payment_hash: sha256::Hash,
+    pub receiver: PublicKey,
+    pub sender: PublicKey,
+    pub timeout: TimeLock,
+    secp: Secp256k1<All>,
+    internal_key: XOnlyPublicKey,
+    receiver_x_only: XOnlyPublicKey,
+    sender_x_only: XOnlyPublicKey,
+}
+
+pub fn payment_hash(preimage: &[u8]) -> sha256::Hash {
+    return sha256::Hash::hash(preimage);
+}
+
+impl Htlc {
+    pub fn new(
+        secp: &Secp256k1<All>,
+        kind: HtlcKind,
+        payment_hash: sha256::Hash,
+        receiver: PublicKey,
+        sender: PublicKey,
+        timeout: TimeLock,
+    ) -> Self {
+        return Htlc {
+            kind,
+            payment_hash,
+            receiver,
+            sender,
+            timeout,
+            secp: secp.clone(),
+            internal_key: nums_x_only(),
+            receiver_x_only: receiver.x_only_public_key().0,
+            sender_x_only: sender.x_only_public_key().0,
+        };
+    }
+
+    // c:or_i(and_v(v:sha256(H),pk_k(receiver)),and_v(v:older(n),pk_k(sender)))
+    pub fn witness_script(&self) -> Script {
+        let builder = Builder::new().push_opcode(all::OP_IF);
+        let builder = hash_lock(builder, &self.payment_hash)
+            .push_key(&bitcoin::PublicKey::new(self.receiver))
+            .push_opcode(all::OP_ELSE);
+        return time_lock(builder, &self.timeout)
+            .push_key(&bitcoin::PublicKey::new(self.sender))
+            .push_opcode(all::OP_ENDIF)
+            .push_opcode(all::OP_CHECKSIG)
+            .into_script();
+    }
+
+    // and_v(v:sha256(H),pk(receiver)), the size check keeps the preimage usable on both chains
+    pub fn claim_script(&self) -> Script {
+        return hash_lock(Builder::new(), &self.payment_hash)
+            .push_x_only_key(&self.receiver_x_only)
+            .push_opcode(all::OP_CHECKSIG)
+            .into_script();
+    }
+
+    // and_v(v:older(n),pk(sender)) or and_v(v:after(n),pk(sender))
+    pub fn refund_script(&self) -> Script {
+        return time_lock(Builder::new(), &self.timeout)
+            .push_x_only_key(&self.sender_x_only)
+            .push_opcode(all::OP_CHECKSIG)
+            .into_script();
+    }
+
+    pub fn spend_info(&self) -> TaprootSpendInfo {
+        return TaprootBuilder::with_huffman_tree(self.script_weights())
+            .unwrap()
+            .finalize(&self.secp, self.internal_key)
+            .unwrap();
+    }
+
+    pub fn script_pubkey(&self) -> Script {
+        return match self.kind {
+            HtlcKind::SegwitV0 => self.witness_script().to_v0_p2wsh(),
+            HtlcKind::Taproot => Script::new_v1_p2tr_tweaked(self.spend_info().output_key()),
+        };
+    }
+
+    pub fn address(&self) -> Address {
+        return Address::from_script(&self.script_pubkey(), NETWORK).unwrap();
+    }
+
+    pub fn output_factory<'a>(&'a self) -> Vec<LockFn<'a>> {
+        return match self.kind {
+            HtlcKind::SegwitV0 => vec![new_witness_pub_k(self.script_pubkey())],
+            HtlcKind::Taproot => vec![
+                new_tap_internal_key(&self.internal_key),
+                insert_tap_key_origin(vec![(1, self.claim_script())], &self.receiver_x_only),
+                insert_tap_key_origin(vec![(1, self.refund_script())], &self.sender_x_only),
+                insert_tap_tree(self.script_weights()),
+                insert_tree_witness(&self.secp),
+            ],
+        };
+    }
+
+    // the refund path only becomes valid once nSequence or nLockTime carries the timeout
+    pub fn create_tx<'a>(&self, path: HtlcPath) -> CreateTxFn<'a> {
+        return match path {
+            HtlcPath::Claim => single_create_tx(),
+            HtlcPath::Refund => timelocked_create_tx(single_create_tx(), self.timeout),
+        };
+    }
+
+    pub fn claim_input_factory<'a>(
+        &'a self,
+        key_pair: &'a KeyPair,
+        preimage: Vec<u8>,
+    ) -> SpendFn<'a> {
+        return self.input_factory(HtlcPath::Claim, key_pair, Some(preimage));
+    }
+
+    pub fn refund_input_factory<'a>(&'a self, key_pair: &'a KeyPair) -> SpendFn<'a> {
+        return self.input_factory(HtlcPath::Refund, key_pair, None);
+    }
+
+    fn input_factory<'a>(
+        &'a self,
+        path: HtlcPath,
+        key_pair: &'a KeyPair,
+        preimage: Option<Vec<u8>>,
+    ) -> SpendFn<'a> {
+        let script_pubkey = self.script_pubkey();
+        let leaf = match path {
+            HtlcPath::Claim => self.claim_script(),
+            HtlcPath::Refund => self.refund_script(),
+        };
+        let spend_info = self.spend_info();
+
+        return Box::new(
+            move |previous_list: Vec<Transaction>, current_tx: Transaction| {
+                let prev_output_list = current_tx
+                    .input
+                    .iter()
+                    .map(|tx_in| find_prev_out(&previous_list, tx_in))
+                    .collect::<Vec<TxOut>>();
+
+                let mut unlock_vec_vec: Vec<Vec<UnlockFn>> = vec![];
+                for (input_index, tx_out) in prev_output_list.iter().enumerate() {
+                    let mut unlock_vec: Vec<UnlockFn> = vec![];
+                    if !tx_out.script_pubkey.eq(&script_pubkey) {
+                        panic!("input {} does not spend the htlc", input_index);
+                    }
+                    unlock_vec.push(insert_witness_tx_out(tx_out.clone()));
+
+                    match self.kind {
+                        HtlcKind::SegwitV0 => {
+                            unlock_vec.push(insert_witness(self.witness_script()));
+                            unlock_vec.push(sign_segwit_v0(
+                                &self.secp,
+                                current_tx.clone(),
+                                tx_out.value,
+                                input_index,
+                                self.witness_script(),
+                                key_pair.secret_key(),
+                            ));
+                        }
+                        HtlcKind::Taproot => {
+                            unlock_vec.push(insert_witness(script_pubkey.clone()));
+                            unlock_vec.push(insert_control_block(
+                                &self.secp,
+                                leaf.clone(),
+                                spend_info.clone(),
+                            ));
+                            unlock_vec.push(sign_tapleaf(
+                                &self.secp,
+                                key_pair,
+                                current_tx.clone(),
+                                prev_output_list.clone(),
+                                input_index,
+                                leaf.clone(),
+                            ));
+                        }
+                    }
+
+                    if let Some(preimage) = &preimage {
+                        unlock_vec.push(insert_sha256_preimage(preimage.clone()));
+                    }
+                    unlock_vec_vec.push(unlock_vec);
+                }
+                return unlock_vec_vec;
+            },
+        );
+    }
+
+    // claims when the receiver signed and the preimage is known, refunds when the sender signed
+    pub fn finalize(&self, psbt: PartiallySignedTransaction) -> Result<Transaction, HtlcError> {
+        let tx = psbt.clone().extract_tx();
+        let tx_in = psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                let witness = match self.kind {
+                    HtlcKind::SegwitV0 => self.segwit_v0_witness(input),
+                    HtlcKind::Taproot => self.taproot_witness(input),
+                };
+                return witness.ok_or(HtlcError::UnsatisfiableInput(index));
+            })
+            .zip(tx.input)
+            .map(|(witness, tx_input)| {
+                return Ok(TxIn {
+                    previous_output: tx_input.previous_output,
+                    script_sig: tx_input.script_sig,
+                    sequence: tx_input.sequence,
+                    witness: witness?,
+                });
+            })
+            .collect::<Result<Vec<TxIn>, HtlcError>>()?;
+
+        return Ok(Transaction {
+            version: tx.version,
+            lock_time: tx.lock_time,
+            input: tx_in,
+            output: tx.output,
+        });
+    }
+
+    // a claim publishes the preimage in its witness, which is all the other side of a swap needs
+    pub fn extract_preimage(&self, tx: &Transaction) -> Option<Vec<u8>> {
+        return tx
+            .input
+            .iter()
+            .flat_map(|tx_in| tx_in.witness.iter())
+            .find(|item| item.len() == 32 && payment_hash(item) == self.payment_hash)
+            .map(|item| item.to_vec());
+    }
+
+    fn script_weights(&self) -> Vec<(u32, Script)> {
+        return vec![(1, self.claim_script()), (1, self.refund_script())];
+    }
+
+    fn segwit_v0_witness(&self, input: &Input) -> Option<Witness> {
+        let receiver = bitcoin::PublicKey::new(self.receiver);
+        let sender = bitcoin::PublicKey::new(self.sender);
+        let preimage = input.sha256_preimages.get(&self.payment_hash);
+
+        let mut witness = Witness::new();
+        match (input.partial_sigs.get(&receiver), preimage) {
+            (Some(sig), Some(preimage)) => {
+                witness.push(sig.to_vec());
+                witness.push(preimage);
+                witness.push(vec![1]);
+            }
+            _ => {
+                let sig = input.partial_sigs.get(&sender)?;
+                witness.push(sig.to_vec());
+                witness.push(vec![]);
+            }
+        }
+        witness.push(self.witness_script().as_bytes());
+        return Some(witness);
+    }
+
+    fn taproot_witness(&self, input: &Input) -> Option<Witness> {
+        let claim = self.claim_script();
+        let claim_leaf = TapLeafHash::from_script(&claim, LeafVersion::TapScript);
+        let refund = self.refund_script();
+        let refund_leaf = TapLeafHash::from_script(&refund, LeafVersion::TapScript);
+        let preimage = input.sha256_preimages.get(&self.payment_hash);
+
+        let claim_sig = input
+            .tap_script_sigs
+            .get(&(self.receiver_x_only, claim_leaf));
+        let mut witness = Witness::new();
+        let leaf = match (claim_sig, preimage) {
+            (Some(sig), Some(preimage)) => {
+                witness.push(sig.to_vec());
+                witness.push(preimage);
+                claim
+            }
+            _ => {
+                let sig = input
+                    .tap_script_sigs
+                    .get(&(self.sender_x_only, refund_leaf))?;
+                witness.push(sig.to_vec());
+                refund
+            }
+        };
+
+        let control_block = input
+            .tap_scripts
+            .iter()
+            .find(|(_, (script, _))| script.eq(&leaf))
+            .map(|(control_block, _)| control_block.serialize())
+            .unwrap_or_else(|| {
+                self.spend_info()
+                    .control_block(&(leaf.clone(), LeafVersion::TapScript))
+                    .unwrap()
+                    .serialize()
+            });
+        witness.push(leaf.as_bytes());
+        witness.push(control_block);
+        return Some(witness);
+    }
+}
+
+fn hash_lock(builder: Builder, payment_hash: &sha256::Hash) -> Builder {
+    return builder
+        .push_opcode(all::OP_SIZE)
+        .push_int(32)
+        .push_opcode(all::OP_EQUALVERIFY)
+        .push_opcode(all::OP_SHA256)
+        .push_slice(&payment_hash.into_inner())
+        .push_opcode(all::OP_EQUALVERIFY);
+}
+
+fn time_lock(builder: Builder, timeout: &TimeLock) -> Builder {
+    let (value, opcode) = match timeout {
+        TimeLock::Relative(lock) => (lock.to_sequence().to_consensus_u32(), all::OP_CSV),
+        TimeLock::Absolute(lock) => (lock.to_consensus_u32(), all::OP_CLTV),
+    };
+    return builder
+        .push_int(value as i64)
+        .push_opcode(opcode)
+        .push_opcode(all::OP_VERIFY);
+}
+
+fn find_prev_out(previous_list: &[Transaction], tx_in: &TxIn) -> TxOut {
+    let outpoint = tx_in.previous_output;
+    return previous_list
+        .iter()
+        .find(|tx| tx.txid() == outpoint.txid)
+        .and_then(|tx| tx.output.get(outpoint.vout as usize))
+        .expect("missing previous transaction for input")
+        .clone();
+}
+
+// whether the first timeout matures at least margin seconds before the second, each lock is
+// read against the tip of the chain enforcing it since heights on two chains say nothing about
+// each other. None for relative locks, they count from when each leg confirms
+pub fn timeout_precedes(
+    first: &TimeLock,
+    first_tip: &ChainTip,
+    second: &TimeLock,
+    second_tip: &ChainTip,
+    margin: u32,
+) -> Option<bool> {
+    let first = first_tip.expected_median_time(first)?;
+    let second = second_tip.expected_median_time(second)?;
+    return Some(first + margin as u64 <= second);
+}
+
+#[test]
+fn htlc_claim_and_refund_satisfy_the_interpreter() {
+    use crate::bitcoin_wallet::{
+        input_data::{mock_call::MockCall, RpcCall},
+        script_services::psbt_factory::create_partially_signed_tx,
+        scripts::timelock::RelativeLock,
+    };
+    use bitcoin::{util::sighash::Prevouts, LockTime};
+    use miniscript::interpreter::Interpreter;
+
+    let secp = Secp256k1::new();
+    let receiver = KeyPair::from_seckey_slice(&secp, &[1u8; 32]).unwrap();
+    let sender = KeyPair::from_seckey_slice(&secp, &[2u8; 32]).unwrap();
+    let destination = Script::new_v1_p2tr(&secp, nums_x_only(), None);
+    let preimage = vec![42u8; 32];
+
+    for kind in [HtlcKind::SegwitV0, HtlcKind::Taproot] {
+        let htlc = Htlc::new(
+            &secp,
+            kind,
+            payment_hash(&preimage),
+            receiver.public_key(),
+            sender.public_key(),
+            TimeLock::Relative(RelativeLock::Blocks(144)),
+        );
+
+        for path in [HtlcPath::Claim, HtlcPath::Refund] {
+            let client = MockCall::fund(&htlc.script_pubkey(), &[50_000]);
+            let unlock = match path {
+                HtlcPath::Claim => htlc.claim_input_factory(&receiver, preimage.clone()),
+                HtlcPath::Refund => htlc.refund_input_factory(&sender),
+            };
+            let psbt = create_partially_signed_tx(
+                vec![vec![new_witness_pub_k(destination.clone())]],
+                htlc.create_tx(path),
+                unlock,
+            )(&client);
+            let tx = htlc.finalize(psbt).unwrap();
+            let prevouts = client.contract_source()[0].output.clone();
+
+            let interpreter = Interpreter::from_txdata(
+                &htlc.script_pubkey(),
+                &tx.input[0].script_sig,
+                &tx.input[0].witness,
+                tx.input[0].sequence,
+                LockTime::from(tx.lock_time),
+            )
+            .unwrap();
+            let satisfied = interpreter
+                .iter(&secp, &tx, 0, &Prevouts::All(&prevouts))
+                .collect::<Result<Vec<_>, _>>();
+            assert!(satisfied.is_ok(), "{:?} {:?} {:?}", kind, path, satisfied);
+
+            let revealed = htlc.extract_preimage(&tx);
+            match path {
+                HtlcPath::Claim => assert_eq!(revealed, Some(preimage.clone())),
+                HtlcPath::Refund => assert_eq!(revealed, None),
+            }
+        }
+    }
+}
+
+#[test]
+fn timeout_order_is_compared_in_median_time() {
+    use crate::bitcoin_wallet::scripts::timelock::{AbsoluteLock, RelativeLock};
+
+    let bitcoin = ChainTip {
+        height: 800_000,
+        median_time: 1_700_000_000,
+        block_interval: 600,
+    };
+    let litecoin = ChainTip {
+        height: 2_500_000,
+        median_time: 1_700_000_000,
+        block_interval: 150,
+    };
+    let hour = 3_600;
+
+    // the smaller height is 100 bitcoin blocks out, the larger one only 200 litecoin blocks
+    let bitcoin_lock = TimeLock::Absolute(AbsoluteLock::Height(800_100));
+    let litecoin_lock = TimeLock::Absolute(AbsoluteLock::Height(2_500_200));
+    assert_eq!(
+        timeout_precedes(&bitcoin_lock, &bitcoin, &litecoin_lock, &litecoin, 0),
+        Some(false)
+    );
+    assert_eq!(
+        timeout_precedes(&litecoin_lock, &litecoin, &bitcoin_lock, &bitcoin, hour),
+        Some(true)
+    );
+
+    // a block apart is not enough room for the other side to react
+    let next_block = TimeLock::Absolute(AbsoluteLock::Height(800_101));
+    assert_eq!(
+        timeout_precedes(&bitcoin_lock, &bitcoin, &next_block, &bitcoin, hour),
+        Some(false)
+    );
+
+    // timestamps and heights share the median time basis
+    let timestamp = TimeLock::Absolute(AbsoluteLock::Time(1_700_000_000 + 60_000 + hour));
+    assert_eq!(
+        timeout_precedes(&bitcoin_lock, &bitcoin, &timestamp, &litecoin, hour),
+        Some(true)
+    );
+
+    let relative = TimeLock::Relative(RelativeLock::Blocks(144));
+    assert_eq!(
+        timeout_precedes(&relative, &bitcoin, &bitcoin_lock, &bitcoin, hour),
+        None
+    );
+}
@@ -2,9 +2,14 @@ use bitcoin::{psbt::Output, Address, Script, Transaction, TxIn, TxOut};
 
 use super::{
     constants::{NETWORK, TIP},
-    script_services::{output_service::new_witness_pub_k, psbt_factory::LockFn},
+    script_services::{
+        output_service::new_witness_pub_k,
+        psbt_factory::{CreateTxFn, LockFn},
+    },
+    scripts::timelock::TimeLock,
 };
 
+pub mod htlc_path;
 pub mod p2tr_key_path;
 pub mod p2wpkh_script_path;
 pub mod p2wsh_path;
@@ -24,6 +29,19 @@ pub fn single_create_tx() -> Box<dyn Fn(Vec<Output>, Vec<TxIn>, u64) -> Transact
         };
     });
 }
+// wraps any tx factory so every input satisfies the lock, the sighash commits to nSequence and
+// nLockTime so this has to happen before the inputs are signed
+pub fn timelocked_create_tx<'a>(create_tx: CreateTxFn<'a>, lock: TimeLock) -> CreateTxFn<'a> {
+    return Box::new(move |outputs: Vec<Output>, tx_in: Vec<TxIn>, total: u64| {
+        let mut tx = create_tx(outputs, tx_in, total);
+        for index in 0..tx.input.len() {
+            lock.apply(&mut tx, index)
+                .expect("tx factories leave nLockTime unset for the lock");
+        }
+        return tx;
+    });
+}
+
 pub fn single_output<'a>(send: &'a Script) -> Vec<LockFn<'a>> {
     return vec![new_witness_pub_k(send.clone())];
 }
@@ -1,15 +1,16 @@
 use bitcoin::{
-    psbt::Output,
-    secp256k1::{All, Secp256k1},
-    KeyPair, Script, Transaction, TxIn, TxOut,
+    psbt::{Input, Output},
+    secp256k1::{All, PublicKey, Secp256k1},
+    KeyPair, SchnorrSighashType, Script, Transaction, TxIn, TxOut,
 };
 
 use crate::bitcoin_wallet::{
     constants::TIP,
+    schnorr::musig2::{KeyAggContext, SigningRound},
     script_services::{
-        input_service::{insert_witness, insert_witness_tx_out, sign_key_sig},
+        input_service::{insert_witness, insert_witness_tx_out, sign_key_sig, sign_musig_key_sig},
         output_service::new_witness_pub_k,
-        psbt_factory::{LockFn, UnlockFn},
+        psbt_factory::{LockFn, SpendFn, UnlockFn},
     },
 };
 
@@ -55,6 +56,51 @@ impl P2tr {
         keypair: &'a KeyPair,
         script_pubkey: Script,
     ) -> Box<dyn Fn(Vec<Transaction>, Transaction) -> Vec<Vec<UnlockFn<'a>>> + 'a> {
+        return P2tr::key_path_factory(
+            script_pubkey,
+            move |current_tx: Transaction, prev_output_list: Vec<TxOut>, size: usize| {
+                return sign_key_sig(&self.secp, keypair, current_tx, prev_output_list, size);
+            },
+        );
+    }
+
+    // a cooperative n-of-n output, every key is folded into one MuSig2 key so it spends like a
+    // single key instead of revealing a multisig leaf
+    pub fn musig_script_pubkey(&self, pub_keys: &[PublicKey]) -> Script {
+        let ctx = KeyAggContext::sorted(&self.secp, pub_keys).unwrap();
+        return Script::new_v1_p2tr(&self.secp, ctx.x_only(), None);
+    }
+
+    // rounds holds the pubnonces and partial signatures of every signer for each input, the
+    // secrets stay with their signers. an input without a round is left unsigned
+    pub fn musig_input_factory<'a>(
+        &'a self,
+        pub_keys: &'a [PublicKey],
+        rounds: &'a [SigningRound],
+        script_pubkey: Script,
+        sighash_type: SchnorrSighashType,
+    ) -> SpendFn<'a> {
+        return P2tr::key_path_factory(
+            script_pubkey,
+            move |current_tx: Transaction, prev_output_list: Vec<TxOut>, size: usize| {
+                return sign_musig_key_sig(
+                    &self.secp,
+                    pub_keys,
+                    rounds.get(size),
+                    current_tx,
+                    prev_output_list,
+                    size,
+                    sighash_type,
+                );
+            },
+        );
+    }
+
+    fn key_path_factory<'a, S, F>(script_pubkey: Script, sign: F) -> SpendFn<'a>
+    where
+        S: FnOnce(&mut Input) + 'a,
+        F: Fn(Transaction, Vec<TxOut>, usize) -> Box<S> + 'a,
+    {
         return Box::new(
             move |previous_list: Vec<Transaction>, current_tx: Transaction| {
                 let prev_output_list = previous_list
@@ -72,13 +118,7 @@ impl P2tr {
                         .unwrap();
                     unlock_vec.push(insert_witness_tx_out(tx_out.clone()));
                     unlock_vec.push(insert_witness(tx_out.clone().script_pubkey));
-                    unlock_vec.push(sign_key_sig(
-                        &self.secp,
-                        &keypair,
-                        current_tx.clone(),
-                        prev_output_list.clone(),
-                        size,
-                    ));
+                    unlock_vec.push(sign(current_tx.clone(), prev_output_list.clone(), size));
                     unlock_vec_vec.push(unlock_vec);
                 }
                 return unlock_vec_vec;
@@ -86,3 +126,128 @@ impl P2tr {
         );
     }
 }
+
+#[test]
+fn musig_output_spends_through_the_key_path() {
+    use crate::bitcoin_wallet::{
+        input_data::{mock_call::MockCall, RpcCall},
+        schnorr::musig2::nonce_gen,
+        script_services::{
+            input_service::musig_key_spend_session,
+            psbt_factory::{create_partially_signed_tx, get_output},
+            tap_finalizer::{finalize_tap_psbt, UnsatisfiableInput},
+        },
+    };
+    use bitcoin::{
+        secp256k1::{schnorr::Signature, Message, XOnlyPublicKey},
+        util::sighash::{Prevouts, SighashCache},
+    };
+
+    let p2tr = P2tr::new(&Secp256k1::new());
+    let key_pairs = [[11u8; 32], [12u8; 32]]
+        .iter()
+        .map(|secret| KeyPair::from_seckey_slice(&p2tr.secp, secret).unwrap())
+        .collect::<Vec<KeyPair>>();
+    let pub_keys = key_pairs
+        .iter()
+        .map(|key_pair| key_pair.public_key())
+        .collect::<Vec<PublicKey>>();
+    let script_pubkey = p2tr.musig_script_pubkey(&pub_keys);
+    let client = MockCall::fund(&script_pubkey, &[50_000]);
+
+    // both signers build the same unsigned tx
+    let outputs = get_output(
+        p2tr.output_factory(script_pubkey.clone(), script_pubkey.clone()),
+        &mut vec![],
+    );
+    let unsigned_tx =
+        P2tr::create_tx(20_000)(outputs, client.prev_input(), client.script_get_balance());
+    let prevouts = client
+        .contract_source()
+        .iter()
+        .flat_map(|tx| tx.output.clone())
+        .collect::<Vec<TxOut>>();
+
+    // first round, each signer only publishes its pubnonce
+    let nonces = key_pairs
+        .iter()
+        .enumerate()
+        .map(|(index, key_pair)| {
+            nonce_gen(
+                &p2tr.secp,
+                [index as u8 + 1; 32],
+                Some(&key_pair.secret_key()),
+                key_pair.public_key(),
+                None,
+                None,
+                &[],
+            )
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let pub_nonces = nonces
+        .iter()
+        .map(|(_, pub_nonce)| *pub_nonce)
+        .collect::<Vec<_>>();
+
+    // second round, each signer signs with its own secret and secnonce
+    let partial_sigs = key_pairs
+        .iter()
+        .zip(nonces)
+        .map(|(key_pair, (sec_nonce, _))| {
+            musig_key_spend_session(
+                &p2tr.secp,
+                &pub_keys,
+                &pub_nonces,
+                &unsigned_tx,
+                &prevouts,
+                0,
+                None,
+                SchnorrSighashType::Default,
+            )
+            .unwrap()
+            .sign(&p2tr.secp, sec_nonce, &key_pair.secret_key())
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let rounds = [SigningRound {
+        pub_nonces,
+        partial_sigs,
+    }];
+
+    let spend = |rounds: &[SigningRound]| {
+        let psbt = create_partially_signed_tx(
+            p2tr.output_factory(script_pubkey.clone(), script_pubkey.clone()),
+            P2tr::create_tx(20_000),
+            p2tr.musig_input_factory(
+                &pub_keys,
+                rounds,
+                script_pubkey.clone(),
+                SchnorrSighashType::Default,
+            ),
+        )(&client);
+        return finalize_tap_psbt(psbt);
+    };
+    let tx = spend(&rounds).unwrap();
+
+    // a single signature, nothing about the two signers ends up on chain
+    assert_eq!(tx.input[0].witness.len(), 1);
+    let sig = tx.input[0].witness.to_vec()[0].clone();
+    assert_eq!(sig.len(), 64);
+
+    // the aggregate verifies against the tweaked output key over the key path sighash
+    let sighash = SighashCache::new(&tx)
+        .taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), SchnorrSighashType::Default)
+        .unwrap();
+    let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).unwrap();
+    p2tr.secp
+        .verify_schnorr(
+            &Signature::from_slice(&sig).unwrap(),
+            &Message::from(sighash),
+            &output_key,
+        )
+        .unwrap();
+
+    // without a round for the input the spend can't be finalized
+    assert_eq!(spend(&[]), Err(UnsatisfiableInput(0)));
+}
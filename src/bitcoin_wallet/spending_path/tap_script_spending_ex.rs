@@ -4,16 +4,20 @@ use bitcoin::{
     psbt::PartiallySignedTransaction,
     secp256k1::{All, Secp256k1},
     util::taproot::TaprootBuilder,
-    KeyPair, Script, Transaction, TxIn, TxOut, Witness, XOnlyPublicKey,
+    KeyPair, Script, Transaction, TxOut, XOnlyPublicKey,
 };
 use bitcoin_hashes::Hash;
 
 use crate::bitcoin_wallet::script_services::{
-    input_service::{insert_control_block, insert_witness, insert_witness_tx_out, sign_tapleaf},
+    input_service::{
+        insert_control_block, insert_sha256_preimage, insert_witness, insert_witness_tx_out,
+        sign_tapleaf,
+    },
     output_service::{
         insert_tap_key_origin, insert_tap_tree, insert_tree_witness, new_tap_internal_key,
     },
     psbt_factory::{LockFn, UnlockFn},
+    tap_finalizer::finalize_tap_psbt,
 };
 
 pub struct TapScriptSendEx<'a> {
@@ -107,6 +111,7 @@ impl<'a> TapScriptSendEx<'a> {
                         bob_script.clone(),
                         tap_spending_info.clone(),
                     ));
+                    unlock_vec.push(insert_sha256_preimage(get_preimage()));
                     unlock_vec.push(sign_tapleaf(
                         &self.secp,
                         &bob_keypair,
@@ -122,48 +127,7 @@ impl<'a> TapScriptSendEx<'a> {
         );
     }
 
-    pub fn finialize_script(
-        psbt: PartiallySignedTransaction,
-        x_only: &XOnlyPublicKey,
-    ) -> Transaction {
-        let tx = psbt.clone().extract_tx().clone();
-        let tx_in = psbt
-            .inputs
-            .iter()
-            .map(|input| {
-                let mut witness = Witness::new();
-
-                input.tap_script_sigs.iter().for_each(|sig| {
-                    let shnor = sig.1;
-                    witness.push(shnor.to_vec());
-                });
-
-                witness.push(get_preimage());
-                let bob_script = bob_scripts(x_only);
-                witness.push(bob_script.as_bytes());
-
-                input.tap_scripts.iter().for_each(|control| {
-                    witness.push(control.0.serialize());
-                });
-
-                return witness;
-            })
-            .zip(tx.input)
-            .map(|(witness, tx_input)| {
-                return TxIn {
-                    previous_output: tx_input.previous_output,
-                    script_sig: tx_input.script_sig,
-                    sequence: tx_input.sequence,
-                    witness,
-                };
-            })
-            .collect::<Vec<TxIn>>();
-
-        return Transaction {
-            version: tx.version,
-            lock_time: tx.lock_time,
-            input: tx_in,
-            output: tx.output,
-        };
+    pub fn finialize_script(psbt: PartiallySignedTransaction) -> Transaction {
+        return finalize_tap_psbt(psbt).unwrap();
     }
 }
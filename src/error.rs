@@ -23,6 +23,10 @@ pub(crate) enum InternalConnectError {
         file: PathBuf,
         error: std::io::Error,
     },
+    LndConnect {
+        reason: &'static str,
+    },
+    Certificate(openssl::error::ErrorStack),
 }
 
 impl fmt::Display for ConnectError {
@@ -31,6 +35,8 @@ impl fmt::Display for ConnectError {
 
         match &self.internal {
             ReadFile { file, .. } => write!(f, "failed to read file {}", file.display()),
+            LndConnect { reason } => write!(f, "invalid lndconnect uri: {}", reason),
+            Certificate(_) => write!(f, "invalid TLS certificate"),
         }
     }
 }
@@ -41,6 +47,59 @@ impl std::error::Error for ConnectError {
 
         match &self.internal {
             ReadFile { error, .. } => Some(error),
+            LndConnect { .. } => None,
+            Certificate(error) => Some(error),
+        }
+    }
+}
+
+/// Error a request over [`MyChannel`](crate::MyChannel) failed with
+#[derive(Debug)]
+pub enum TransportError {
+    /// The request path could not be joined with the address of the node
+    InvalidUri(hyper::http::Error),
+    Http(hyper::Error),
+    /// No response arrived within the deadline of the call
+    Timeout(std::time::Duration),
+    /// The request body could not be buffered for retrying
+    Body(tonic::Status),
+}
+
+impl TransportError {
+    /// Whether sending the same idempotent call again may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, TransportError::Http(_) | TransportError::Timeout(_))
+    }
+}
+
+impl From<hyper::Error> for TransportError {
+    fn from(value: hyper::Error) -> Self {
+        TransportError::Http(value)
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportError::InvalidUri(_) => write!(f, "invalid request uri"),
+            TransportError::Http(_) => write!(f, "request to LND failed"),
+            TransportError::Timeout(timeout) => {
+                write!(f, "LND did not respond within {:?}", timeout)
+            }
+            TransportError::Body(status) => {
+                write!(f, "failed to read request body: {}", status.message())
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransportError::InvalidUri(error) => Some(error),
+            TransportError::Http(error) => Some(error),
+            TransportError::Timeout(_) => None,
+            TransportError::Body(status) => Some(status),
         }
     }
 }
@@ -15,7 +15,7 @@ There's no setup needed beyond adding the crate to your `Cargo.toml`.
 If you need to change the `rpc.proto` input set the environment variable `LND_REPO_DIR` to the directory with cloned `lnd` during build.
 Here's an example of retrieving information from LND (`getinfo` call).
 You can find the same example in crate root for your convenience.
-```rust
+```no_run
 // This program accepts four arguments: host, port, cert file, macaroon file
 #[tokio::main]
 async fn main() {
@@ -43,13 +43,13 @@ async fn main() {
         .expect("macaroon_file is not UTF-8");
 
     // Connecting to LND requires only host, port, cert file, macaroon file
-    let mut client = tonic_openssl_lnd::connect_lightning(host, port, cert_file, macaroon_file)
+    let mut client = traproot_bdk::connect_lightning(host, port, cert_file, macaroon_file)
         .await
         .expect("failed to connect");
 
     let info = client
         // All calls require at least empty parameter
-        .get_info(tonic_openssl_lnd::lnrpc::GetInfoRequest {})
+        .get_info(traproot_bdk::lnrpc::GetInfoRequest {})
         .await
         .expect("failed to get info");
 
@@ -64,16 +64,19 @@ Undetermined yet, please make suggestions.
 MITNFA
 "###]
 
-use crate::error::ConnectError;
+pub use crate::error::{ConnectError, TransportError};
 use error::InternalConnectError;
 use hyper::client::connect::HttpConnector;
-use hyper::{client::ResponseFuture, Body, Client, Request, Response, Uri};
+use hyper::http::uri::{Authority, PathAndQuery, Scheme};
+use hyper::{Body, Client, Request, Response, Uri};
 use hyper_openssl::HttpsConnector;
 use openssl::{
-    ssl::{SslConnector, SslMethod},
-    x509::X509,
+    ssl::{SslConnector, SslMethod, SslVerifyMode},
+    x509::{store::X509StoreBuilder, X509},
 };
+use std::future::Future;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::{error::Error, task::Poll};
 use tonic::body::BoxBody;
 use tonic_openssl::ALPN_H2_WIRE;
@@ -190,12 +193,101 @@ pub type LndWtcClient = crate::wtclientrpc::watchtower_client_client::Watchtower
     tonic::codegen::InterceptedService<MyChannel, MacaroonInterceptor>,
 >;
 
+/// Every LND sub-service client over one HTTP/2 channel and macaroon interceptor
+///
+/// The `connect_*` functions each open their own connection, this connects once and the clients
+/// share it. Cloning the bundle is cheap and also shares the connection.
+#[derive(Clone)]
+pub struct LndClient {
+    pub autopilot: LndAutopilotClient,
+    pub chain_notifier: LndChainClient,
+    pub dev: LndDevClient,
+    pub invoices: LndInvoicesClient,
+    pub lightning: LndLightningClient,
+    pub neutrino: LndNeutrinoClient,
+    pub peers: LndPeersClient,
+    pub router: LndRouterClient,
+    pub signer: LndSignerClient,
+    pub versioner: LndVersionerClient,
+    pub wallet: LndWalletClient,
+    pub watchtower: LndWatchtowerClient,
+    pub wtc: LndWtcClient,
+    macaroon: MacaroonHandle,
+}
+
+impl LndClient {
+    pub async fn connect(config: &LndConnectConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let (channel, interceptor) = config.connect().await?;
+        Ok(LndClient::with_channel(channel, interceptor))
+    }
+
+    pub fn with_channel(channel: MyChannel, interceptor: MacaroonInterceptor) -> Self {
+        let service =
+            || tonic::codegen::InterceptedService::new(channel.clone(), interceptor.clone());
+        LndClient {
+            macaroon: interceptor.handle(),
+            autopilot: crate::autopilotrpc::autopilot_client::AutopilotClient::new(service()),
+            chain_notifier: crate::chainrpc::chain_notifier_client::ChainNotifierClient::new(
+                service(),
+            ),
+            dev: crate::devrpc::dev_client::DevClient::new(service()),
+            invoices: crate::invoicesrpc::invoices_client::InvoicesClient::new(service()),
+            lightning: crate::lnrpc::lightning_client::LightningClient::new(service()),
+            neutrino: crate::neutrinorpc::neutrino_kit_client::NeutrinoKitClient::new(service()),
+            peers: crate::peersrpc::peers_client::PeersClient::new(service()),
+            router: crate::routerrpc::router_client::RouterClient::new(service()),
+            signer: crate::signrpc::signer_client::SignerClient::new(service()),
+            versioner: crate::verrpc::versioner_client::VersionerClient::new(service()),
+            wallet: crate::walletrpc::wallet_kit_client::WalletKitClient::new(service()),
+            watchtower: crate::watchtowerrpc::watchtower_client::WatchtowerClient::new(service()),
+            wtc: crate::wtclientrpc::watchtower_client_client::WatchtowerClientClient::new(
+                service(),
+            ),
+        }
+    }
+
+    /// The macaroon every client of the bundle sends, rotate it to change credentials without
+    /// reconnecting
+    pub fn macaroon(&self) -> &MacaroonHandle {
+        &self.macaroon
+    }
+}
+
 mod error;
+mod lndconnect;
+mod macaroon;
+mod transport;
+
+pub use lndconnect::LndConnectConfig;
+pub use macaroon::{
+    with_macaroon, Caveat, Macaroon, MacaroonError, MacaroonHandle, MacaroonPreset, Permission,
+};
+pub use transport::{is_idempotent, RetryPolicy, TransportConfig};
 
 /// Supplies requests with macaroon
+///
+/// Requests that already carry a `macaroon`, see [`with_macaroon`], are sent with their own.
 #[derive(Clone)]
 pub struct MacaroonInterceptor {
-    macaroon: String,
+    macaroon: MacaroonHandle,
+}
+
+impl MacaroonInterceptor {
+    /// Creates an interceptor from the raw (not hex-encoded) macaroon
+    pub fn new(macaroon: &[u8]) -> Self {
+        MacaroonInterceptor {
+            macaroon: MacaroonHandle::new(macaroon),
+        }
+    }
+
+    /// Creates an interceptor sending whatever macaroon the handle holds at the time of a call
+    pub fn with_handle(macaroon: MacaroonHandle) -> Self {
+        MacaroonInterceptor { macaroon }
+    }
+
+    pub fn handle(&self) -> MacaroonHandle {
+        self.macaroon.clone()
+    }
 }
 
 impl tonic::service::Interceptor for MacaroonInterceptor {
@@ -203,10 +295,13 @@ impl tonic::service::Interceptor for MacaroonInterceptor {
         &mut self,
         mut request: tonic::Request<()>,
     ) -> Result<tonic::Request<()>, LndClientError> {
+        if request.metadata().contains_key("macaroon") {
+            return Ok(request);
+        }
         request.metadata_mut().insert(
             "macaroon",
             #[allow(deprecated)]
-            tonic::metadata::MetadataValue::from_str(&self.macaroon)
+            tonic::metadata::MetadataValue::from_str(&self.macaroon.hex())
                 .expect("hex produced non-ascii"),
         );
         Ok(request)
@@ -215,7 +310,7 @@ impl tonic::service::Interceptor for MacaroonInterceptor {
 
 async fn load_macaroon(
     path: impl AsRef<Path> + Into<PathBuf>,
-) -> Result<String, InternalConnectError> {
+) -> Result<Vec<u8>, InternalConnectError> {
     let macaroon =
         tokio::fs::read(&path)
             .await
@@ -223,7 +318,7 @@ async fn load_macaroon(
                 file: path.into(),
                 error,
             })?;
-    Ok(hex::encode(&macaroon))
+    Ok(macaroon)
 }
 
 async fn get_channel(
@@ -232,9 +327,9 @@ async fn get_channel(
     lnd_tls_cert_path: String,
 ) -> Result<MyChannel, Box<dyn std::error::Error>> {
     let lnd_address = format!("https://{}:{}", lnd_host, lnd_port).to_string();
-    let pem = tokio::fs::read(lnd_tls_cert_path).await.ok();
-    let uri = lnd_address.parse::<Uri>().unwrap();
-    let channel = MyChannel::new(pem, uri).await?;
+    let pem = lndconnect::read_file(lnd_tls_cert_path).await?;
+    let uri = lnd_address.parse::<Uri>()?;
+    let channel = MyChannel::new(TlsMode::Pinned(pem), uri).await?;
     Ok(channel)
 }
 
@@ -244,7 +339,7 @@ async fn get_macaroon_interceptor(
     let macaroon = load_macaroon(lnd_macaroon_path)
         .await
         .map_err(|e| ConnectError::from(e))?;
-    Ok(MacaroonInterceptor { macaroon })
+    Ok(MacaroonInterceptor::new(&macaroon))
 }
 
 pub async fn connect_autopilot(
@@ -427,62 +522,99 @@ pub async fn connect_wtc(
     Ok(client)
 }
 
-#[derive(Clone)]
-pub struct MyChannel {
-    uri: Uri,
-    client: MyClient,
+/// How [`MyChannel`] authenticates the node it connects to
+///
+/// Hostnames are checked against the certificate in every mode but `Insecure`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Trust only this PEM certificate, e.g. the self-signed `tls.cert` LND writes
+    Pinned(Vec<u8>),
+    /// Trust the certificate authorities of the system, for nodes with a public certificate
+    SystemRoots,
+    /// Accept any certificate for any host, only meant for local testing
+    Insecure,
 }
 
 #[derive(Clone)]
-enum MyClient {
-    ClearText(Client<HttpConnector, BoxBody>),
-    Tls(Client<HttpsConnector<HttpConnector>, BoxBody>),
+pub struct MyChannel {
+    scheme: Scheme,
+    authority: Authority,
+    client: Client<HttpsConnector<HttpConnector>, BoxBody>,
+    transport: TransportConfig,
 }
 
 impl MyChannel {
-    pub async fn new(certificate: Option<Vec<u8>>, uri: Uri) -> Result<Self, Box<dyn Error>> {
+    pub async fn new(tls: TlsMode, uri: Uri) -> Result<Self, Box<dyn Error>> {
+        MyChannel::with_transport(tls, uri, TransportConfig::default()).await
+    }
+
+    pub async fn with_transport(
+        tls: TlsMode,
+        uri: Uri,
+        transport: TransportConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        let parts = uri.into_parts();
+        let scheme = parts.scheme.ok_or("uri has no scheme")?;
+        let authority = parts.authority.ok_or("uri has no host")?;
+
         let mut http = HttpConnector::new();
         http.enforce_http(false);
-        let client = match certificate {
-            None => MyClient::ClearText(Client::builder().http2_only(true).build(http)),
-            Some(pem) => {
-                let ca = X509::from_pem(&pem[..])?;
-                let mut connector = SslConnector::builder(SslMethod::tls())?;
-                connector.cert_store_mut().add_cert(ca)?;
-                connector.set_alpn_protos(ALPN_H2_WIRE)?;
-                let mut https = HttpsConnector::with_connector(http, connector)?;
-                https.set_callback(|c, _| {
-                    c.set_verify_hostname(false);
-                    Ok(())
-                });
-                MyClient::Tls(Client::builder().http2_only(true).build(https))
+        let mut connector = SslConnector::builder(SslMethod::tls())?;
+        match &tls {
+            TlsMode::Pinned(pem) => {
+                let mut store = X509StoreBuilder::new()?;
+                store.add_cert(X509::from_pem(pem)?)?;
+                connector.set_cert_store(store.build());
             }
-        };
-
-        Ok(Self { client, uri })
+            TlsMode::SystemRoots => {}
+            TlsMode::Insecure => connector.set_verify(SslVerifyMode::NONE),
+        }
+        connector.set_alpn_protos(ALPN_H2_WIRE)?;
+        let client = transport.client(http, connector)?;
+
+        Ok(Self {
+            scheme,
+            authority,
+            client,
+            transport,
+        })
     }
 }
 
 impl Service<Request<BoxBody>> for MyChannel {
     type Response = Response<Body>;
-    type Error = hyper::Error;
-    type Future = ResponseFuture;
+    type Error = TransportError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Response<Body>, TransportError>> + Send + 'static>>;
 
-    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Ok(()).into()
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<Request<BoxBody>>::poll_ready(&mut self.client, cx).map_err(TransportError::from)
     }
 
     fn call(&mut self, mut req: Request<BoxBody>) -> Self::Future {
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .cloned()
+            .unwrap_or_else(|| PathAndQuery::from_static("/"));
         let uri = Uri::builder()
-            .scheme(self.uri.scheme().unwrap().clone())
-            .authority(self.uri.authority().unwrap().clone())
-            .path_and_query(req.uri().path_and_query().unwrap().clone())
-            .build()
-            .unwrap();
+            .scheme(self.scheme.clone())
+            .authority(self.authority.clone())
+            .path_and_query(path_and_query)
+            .build();
+        let uri = match uri {
+            Ok(uri) => uri,
+            Err(error) => return Box::pin(async move { Err(TransportError::InvalidUri(error)) }),
+        };
+        let timeout = transport::grpc_timeout(req.headers()).or(self.transport.request_timeout);
+        let retry = self.transport.retry.clone();
+        let idempotent = transport::is_idempotent(uri.path());
         *req.uri_mut() = uri;
-        match &self.client {
-            MyClient::ClearText(client) => client.request(req),
-            MyClient::Tls(client) => client.request(req),
+        let client = self.client.clone();
+        if idempotent && retry.max_retries > 0 {
+            Box::pin(transport::send_with_retry(client, timeout, retry, req))
+        } else {
+            Box::pin(transport::send(client, timeout, req))
         }
     }
 }
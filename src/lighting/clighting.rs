@@ -1,110 +1,159 @@
-use std::{
-    collections::HashMap,
-    thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::str::FromStr;
 
-use bitcoin_hashes::{hex::FromHex, Hash};
-use bitcoincore_rpc::jsonrpc::serde_json::{self, json};
+use bitcoin::Txid;
 use clightningrpc::{
-    lightningrpc::PayOptions,
-    requests::{self, AmountOrAll},
-    responses::{self, Connect, GetInfo, ListChannels, ListInvoice, ListInvoices, ListPeers},
-    responses::{FundChannel, Invoice, Pay},
-    Error, LightningRPC, Response,
+    responses::{self, FundChannel, GetInfo, ListInvoice, Pay},
+    LightningRPC, Response,
 };
-use hyper::http::response;
 use serde::{Deserialize, Serialize};
-use tokio::task;
-use tonic::{async_trait, codegen::InterceptedService};
-use traproot_bdk::{
-    connect_lightning,
-    lnrpc::{
-        lightning_client::LightningClient, ConnectPeerRequest, LightningAddress,
-        ListInvoiceRequest, ListPeersRequest, NewAddressRequest, OpenChannelRequest,
-    },
-    MacaroonInterceptor, MyChannel,
-};
+use tonic::async_trait;
 
-use crate::{
-    bitcoin_wallet::{constants::SEED, input_data::regtest_call::RegtestCall},
-    simple_wallet::{p2tr_key::P2TR, single_output_with_value, Wallet},
+use super::{
+    settings::ClnSettings, AddrType, Channel, Invoice, LightningError, LightningNode, NodeInfo,
+    Payment, Peer,
 };
 
-use super::{AddrType, LNChannel, LNCommon, LNInvoice, LNPeers};
-
 pub struct Lightingd {
     client: LightningRPC,
 }
 
-#[async_trait]
-impl LNCommon<GetInfo> for Lightingd {
-    async fn get_info(&mut self) -> GetInfo {
-        return self.client.getinfo().unwrap();
+impl From<GetInfo> for NodeInfo {
+    fn from(info: GetInfo) -> Self {
+        return NodeInfo {
+            id: info.id,
+            alias: info.alias,
+            num_peers: info.num_peers,
+            num_active_channels: info.num_active_channels,
+            num_pending_channels: info.num_pending_channels,
+            block_height: info.blockheight,
+        };
     }
 }
-#[async_trait]
-impl LNPeers<Connect, ListPeers> for Lightingd {
-    async fn connect(&mut self, id: String, host: String) -> Connect {
-        return self.client.connect(&id, Some(&host)).unwrap();
+
+impl From<ListInvoice> for Invoice {
+    fn from(invoice: ListInvoice) -> Self {
+        return Invoice {
+            bolt11: invoice.bolt11,
+            payment_hash: invoice.payment_hash,
+            amount_msat: invoice.amount_msat.map(|amount| amount.0),
+            description: invoice.description,
+            settled: invoice.status == "paid",
+        };
     }
+}
+
+// core lightning keeps the channels under the peer they are open with
+pub fn peer_channels(peer: &responses::Peer) -> Vec<Channel> {
+    return peer
+        .channels
+        .iter()
+        .map(|channel| Channel {
+            peer_id: peer.id.clone(),
+            funding_txid: channel.funding_txid.clone(),
+            short_channel_id: channel.short_channel_id.clone(),
+            capacity_msat: channel.total_msat.0,
+            local_balance_msat: channel.to_us_msat.0,
+            active: peer.connected && channel.state == "CHANNELD_NORMAL",
+        })
+        .collect();
+}
 
-    async fn list_peers(&mut self) -> ListPeers {
-        return self.client.listpeers(None, None).unwrap();
+pub fn payment(pay: Pay) -> Result<Payment, LightningError> {
+    if pay.status != "complete" {
+        return Err(LightningError::Payment(format!(
+            "{} is {}",
+            pay.payment_hash, pay.status
+        )));
     }
+    return Ok(Payment {
+        payment_hash: pay.payment_hash,
+        preimage: pay.payment_preimage,
+        amount_msat: pay.msatoshi,
+        fee_msat: pay.msatoshi_sent.saturating_sub(pay.msatoshi),
+    });
 }
 
 #[async_trait]
-impl LNChannel<FundChannel, ListChannels> for Lightingd {
-    async fn new_address(&mut self, addr_type: AddrType) -> String {
-        let address_mapping = |address: &str| self.client.newaddr(Some(address)).unwrap();
-
-        return match addr_type {
-            AddrType::Bech32 => address_mapping("bech32").bech32.unwrap(),
-            AddrType::TR => address_mapping("all").address.unwrap(),
-            AddrType::P2SH => address_mapping("p2sh-segwit").p2sh_segwit.unwrap(),
+impl LightningNode for Lightingd {
+    async fn get_info(&mut self) -> Result<NodeInfo, LightningError> {
+        let info = self.client.getinfo()?;
+        return Ok(info.into());
+    }
+
+    async fn connect(&mut self, id: &str, host: &str) -> Result<(), LightningError> {
+        self.client.connect(id, Some(host))?;
+        return Ok(());
+    }
+
+    async fn list_peers(&mut self) -> Result<Vec<Peer>, LightningError> {
+        let peers = self.client.listpeers(None, None)?;
+        return Ok(peers
+            .peers
+            .into_iter()
+            .map(|peer| Peer {
+                id: peer.id,
+                address: peer
+                    .netaddr
+                    .and_then(|addresses| addresses.into_iter().next()),
+                connected: peer.connected,
+            })
+            .collect());
+    }
+
+    async fn new_address(&mut self, addr_type: AddrType) -> Result<String, LightningError> {
+        let address = match addr_type {
+            AddrType::Bech32 => self.client.newaddr(Some("bech32"))?.bech32,
+            AddrType::TR => self.client.newaddr(Some("all"))?.address,
+            AddrType::P2SH => self.client.newaddr(Some("p2sh-segwit"))?.p2sh_segwit,
         };
+        return address.ok_or(LightningError::UnexpectedResponse(
+            "no address of the requested type",
+        ));
     }
 
-    async fn open_channel(&mut self, id: String, amt: Option<u64>) -> FundChannel {
+    async fn open_channel(&mut self, id: &str, amt: Option<u64>) -> Result<Txid, LightningError> {
         let amount = amt.map(|i| i.to_string()).unwrap_or("all".to_string());
 
-        let request = OpenChannel::new(id.as_str(), &amount);
-        let result: Result<Response<FundChannel>, Error> = self
-            .client
-            .client()
-            .send_request("fundchannel", request.clone());
+        let request = OpenChannel::new(id, &amount);
+        let result: Response<FundChannel> =
+            self.client.client().send_request("fundchannel", request)?;
 
-        return result.unwrap().clone().result.unwrap();
+        return Txid::from_str(&result.into_result()?.txid)
+            .map_err(|_| LightningError::UnexpectedResponse("invalid funding txid"));
     }
 
-    async fn list_channels(&mut self) -> ListChannels {
-        return self.client.listchannels(None).unwrap();
+    async fn list_channels(&mut self) -> Result<Vec<Channel>, LightningError> {
+        let peers = self.client.listpeers(None, None)?;
+        return Ok(peers.peers.iter().flat_map(peer_channels).collect());
     }
-}
 
-#[async_trait]
-impl LNInvoice<Invoice, ListInvoices, Pay> for Lightingd {
     async fn create_invoice(
         &mut self,
-        msatoshi: u64,
+        amount_msat: u64,
         label: &str,
         description: &str,
         expiry: Option<u64>,
-    ) -> clightningrpc::responses::Invoice {
-        return self
+    ) -> Result<Invoice, LightningError> {
+        let invoice = self
             .client
-            .invoice(msatoshi, label, description, expiry)
-            .unwrap();
+            .invoice(amount_msat, label, description, expiry)?;
+        return Ok(Invoice {
+            bolt11: invoice.bolt11,
+            payment_hash: invoice.payment_hash,
+            amount_msat: Some(amount_msat),
+            description: Some(description.to_owned()),
+            settled: false,
+        });
     }
 
-    async fn list_invoices(&mut self) -> ListInvoices {
-        return self.client.listinvoices(None).unwrap();
+    async fn list_invoices(&mut self) -> Result<Vec<Invoice>, LightningError> {
+        let invoices = self.client.listinvoices(None)?;
+        return Ok(invoices.invoices.into_iter().map(Invoice::from).collect());
     }
 
-    async fn send_payment<'a>(&mut self, bolt11: &'a String) -> Pay {
-        let result = self.client.pay(bolt11, Default::default());
-        return result.unwrap();
+    async fn send_payment(&mut self, bolt11: &str) -> Result<Payment, LightningError> {
+        let pay = self.client.pay(bolt11, Default::default())?;
+        return payment(pay);
     }
 }
 
@@ -113,6 +162,49 @@ pub async fn clighting_sends_open_channel_request() {
     let mut lightingd = Lightingd::new().await;
     dbg!(lightingd.get_info().await);
 }
+
+#[test]
+fn cln_results_convert_to_node_neutral_types() {
+    use clightningrpc::common::MSat;
+
+    let invoice = Invoice::from(ListInvoice {
+        label: "label".to_owned(),
+        bolt11: "lnbcrt10u1".to_owned(),
+        payment_hash: "ab".repeat(32),
+        amount_msat: Some(MSat(1_000_000)),
+        status: "paid".to_owned(),
+        pay_index: Some(1),
+        amount_received_msat: Some(MSat(1_000_000)),
+        paid_at: Some(1_700_000_000),
+        payment_preimage: Some("cd".repeat(32)),
+        description: Some("coffee".to_owned()),
+        expires_at: 1_700_003_600,
+    });
+    assert!(invoice.settled);
+    assert_eq!(invoice.amount_msat, Some(1_000_000));
+    assert_eq!(invoice.description, Some("coffee".to_owned()));
+
+    let pay = |status: &str| Pay {
+        id: 1,
+        payment_hash: "ab".repeat(32),
+        destination: "02ab".to_owned(),
+        msatoshi: 1_000_000,
+        msatoshi_sent: 1_001_000,
+        created_at: 1_700_000_000,
+        status: status.to_owned(),
+        payment_preimage: "cd".repeat(32),
+        description: "coffee".to_owned(),
+        getroute_tries: 1,
+        sendpay_tries: 1,
+        route: vec![],
+        failures: vec![],
+    };
+    assert_eq!(payment(pay("complete")).unwrap().fee_msat, 1_000);
+    assert!(matches!(
+        payment(pay("failed")),
+        Err(LightningError::Payment(_))
+    ));
+}
 /// 'aundchannel' command
 #[derive(Debug, Clone, Deserialize, Serialize)]
 
@@ -157,15 +249,14 @@ impl<'a, 'b, 'c, 'd, 'e> OpenChannel<'a, 'b, 'c, 'd, 'e> {
     }
 }
 impl Lightingd {
-    pub async fn new() -> Self {
+    pub fn connect(settings: &ClnSettings) -> Self {
         return Lightingd {
-            client: clightningrpc::LightningRPC::new(
-                ".meta/lightningd_data/lightning-rpc",
-            ),
+            client: LightningRPC::new(&settings.rpc_path),
         };
     }
 
-    // pub async fn send_payment(&mut self, bolt11: &str)->Pay {
-    //     let result =self.client.pay(bolt11, Default::default());
-    //     return result.unwrap();
+    // the node of the regtest setup
+    pub async fn new() -> Self {
+        return Lightingd::connect(&ClnSettings::new(".meta/lightningd_data/lightning-rpc"));
+    }
 }
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, fmt::Debug, thread, time::Duration};
+use std::{thread, time::Duration};
 
-use bitcoin::secp256k1::Scalar;
+use bitcoin::{secp256k1::Scalar, Txid};
 use bitcoin_hashes::{hex::ToHex, Hash};
 use clightningrpc::LightningRPC;
 use hex::FromHex;
@@ -19,7 +19,7 @@ use crate::{
         constants::SEED,
         input_data::{regtest_call::RegtestCall, RpcCall},
     },
-    lighting::{AddrType, LNChannel, LNCommon, LNInvoice, LNPeers},
+    lighting::{AddrType, LightningError, LightningNode},
     simple_wallet::{
         p2tr_key::P2TR, p2wpkh::P2WPKH, single_output, single_output_with_value, Wallet,
     },
@@ -27,25 +27,19 @@ use crate::{
 
 use super::{clighting::Lightingd, lnd::Lnd};
 
-pub async fn connect_open_channel<R, P, F, C, L>(
-    mut ln_client: L,
+pub async fn connect_open_channel(
+    ln_client: &mut dyn LightningNode,
     client: RegtestCall,
     host: &str,
     pub_id: &str,
-) where
-    R: Sized + Debug,
-    P: Sized + Debug,
-    F: Debug,
-    L: LNPeers<R, P> + LNChannel<F, C>,
-{
+) -> Result<Txid, LightningError> {
     thread::sleep(Duration::from_secs(2));
 
-    let lnd_to_lightind = ln_client.connect(pub_id.to_owned(), host.to_owned()).await;
+    ln_client.connect(pub_id, host).await?;
 
-    println!("connect peer {:#?}", lnd_to_lightind);
-    dbg!(ln_client.list_peers().as_mut().await);
+    println!("connected peers {:#?}", ln_client.list_peers().await?);
     client.mine(20);
-    let new_address = ln_client.new_address(AddrType::TR).await;
+    let new_address = ln_client.new_address(AddrType::TR).await?;
     P2TR::new(Some(SEED), &client).send(single_output_with_value(new_address.clone()));
 
     client.mine(100);
@@ -57,20 +51,19 @@ pub async fn connect_open_channel<R, P, F, C, L>(
 
     thread::sleep(Duration::from_secs(3));
 
-    let open_channel_response = ln_client
-        .open_channel(pub_id.to_owned(), Some(10000000))
-        .await;
+    let funding_txid = ln_client.open_channel(pub_id, Some(10000000)).await?;
 
-    println!("open channel request {:#?}", open_channel_response);
+    println!("channel funded by {}", funding_txid);
 
     client.mine(20);
 
     println!("Testing layer 1 pay to tap root with key signature");
+    return Ok(funding_txid);
 }
 
 #[tokio::test]
 pub async fn open_channel_request() {
-    let lnd_client = Lnd::new().await;
+    let mut lnd_client = Lnd::new().await;
     let mut lnd_client_1 = Lnd::new_1().await;
 
     let client = RegtestCall::init(
@@ -79,21 +72,18 @@ pub async fn open_channel_request() {
         110,
     );
 
-    let id = lnd_client_1
-        .get_info()
-        .await
-        .get_ref()
-        .identity_pubkey
-        .clone();
+    let id = lnd_client_1.get_info().await.unwrap().id;
 
-    connect_open_channel(lnd_client, client, "10.5.0.7:9730", &id).await
+    connect_open_channel(&mut lnd_client, client, "10.5.0.7:9730", &id)
+        .await
+        .unwrap();
 }
 
 #[tokio::test]
 pub async fn clighting_sends_open_channel_request() {
     let mut lnd = Lnd::new().await;
     let mut lightingd = Lightingd::new().await;
-    let get_info = lnd.get_info().await;
+    let get_info = lnd.get_info().await.unwrap();
 
     let str_address = "bcrt1qzvsdwjay5x69088n27h0qgu0tm4u6gwqgxna9d";
 
@@ -102,18 +92,16 @@ pub async fn clighting_sends_open_channel_request() {
 
     thread::sleep(Duration::from_secs(2));
 
-    let lnd_to_lightind = lightingd
-        .connect(
-            get_info.get_ref().clone().identity_pubkey,
-            "10.5.0.6:10006".to_string(),
-        )
-        .await;
+    lightingd
+        .connect(&get_info.id, "10.5.0.6:10006")
+        .await
+        .unwrap();
 
     thread::sleep(Duration::from_secs(2));
-    println!("connect peer {:#?}", lnd_to_lightind);
+    println!("connected peers {:#?}", lightingd.list_peers().await);
 
     client.mine(20);
-    let new_address = lightingd.new_address(AddrType::Bech32).await;
+    let new_address = lightingd.new_address(AddrType::Bech32).await.unwrap();
 
     P2WPKH::new(Some(SEED), &client).send(single_output_with_value(new_address.clone()));
     client.mine(100);
@@ -124,9 +112,7 @@ pub async fn clighting_sends_open_channel_request() {
     );
 
     thread::sleep(Duration::from_secs(3));
-    let open_channel_response = lightingd
-        .open_channel(lnd.get_info().await.get_ref().identity_pubkey.clone(), None)
-        .await;
+    let open_channel_response = lightingd.open_channel(&get_info.id, None).await;
 
     client.mine(20);
     println!("open channel request {:#?}", open_channel_response);
@@ -136,7 +122,7 @@ pub async fn clighting_sends_open_channel_request() {
 pub async fn lnd_sends_open_channel_request() {
     let mut lnd = Lnd::new().await;
     let mut lightingd = Lightingd::new().await;
-    let get_info = lightingd.get_info().await;
+    let get_info = lightingd.get_info().await.unwrap();
 
     let client = RegtestCall::init(
         &vec!["bcrt1prnpxwf9tpjm4jll4ts72s2xscq66qxep6w9hf6sqnvwe9t4gvqasklfhyj"],
@@ -146,14 +132,12 @@ pub async fn lnd_sends_open_channel_request() {
 
     thread::sleep(Duration::from_secs(2));
 
-    let lnd_to_lightind = lnd
-        .connect(get_info.clone().id, "10.5.0.5:19846".to_string())
-        .await;
+    let lnd_to_lightind = lnd.connect(&get_info.id, "10.5.0.5:19846").await;
 
     println!("connect peer {:#?}", lnd_to_lightind);
 
     client.mine(20);
-    let new_address = lnd.new_address(AddrType::TR).await;
+    let new_address = lnd.new_address(AddrType::TR).await.unwrap();
     P2TR::new(Some(SEED), &client).send(single_output_with_value(new_address.clone()));
     client.mine(100);
 
@@ -164,9 +148,7 @@ pub async fn lnd_sends_open_channel_request() {
 
     thread::sleep(Duration::from_secs(3));
 
-    let open_channel_response = lnd
-        .open_channel(lightingd.get_info().await.id, Some(10000000))
-        .await;
+    let open_channel_response = lnd.open_channel(&get_info.id, Some(10000000)).await;
 
     println!("open channel request {:#?}", open_channel_response);
 
@@ -176,7 +158,7 @@ pub async fn lnd_sends_open_channel_request() {
 
     let invoice = lightingd
         .create_invoice(
-            1000,
+            1_000_000,
             "invoice from lightingd",
             "payment description!",
             Some(7200),
@@ -185,7 +167,7 @@ pub async fn lnd_sends_open_channel_request() {
 
     println!("print out invoice {:#?}", invoice);
 
-    let list_peer_request = lnd.list_peers().await.get_ref().clone();
+    let list_peer_request = lnd.list_peers().await;
 
     println!("list peers {:#?}", list_peer_request);
 
@@ -195,7 +177,7 @@ pub async fn lnd_sends_open_channel_request() {
 #[tokio::test]
 pub async fn lnd_list_peer() {
     let mut lnd = Lnd::new().await;
-    dbg!(lnd.list_channels().await);
+    dbg!(lnd.list_channels().await.unwrap());
 }
 
 #[tokio::test]
@@ -206,8 +188,9 @@ pub async fn lightingd_create_invoice_and_pay() {
     let random_data = Scalar::random().to_be_bytes().to_hex();
 
     let invoice = lighting_d
-        .create_invoice(1000, &random_data, "some description", None)
-        .await;
+        .create_invoice(1_000_000, &random_data, "some description", None)
+        .await
+        .unwrap();
 
     thread::sleep(Duration::from_secs(2));
 
@@ -226,18 +209,17 @@ pub async fn lnd_create_invoice_and_pay() {
     let random_data = Scalar::random().to_be_bytes().to_hex();
 
     let invoice = lnd
-        .create_invoice(20000, &random_data, "some description", None)
-        .await;
+        .create_invoice(20_000_000, &random_data, "some description", None)
+        .await
+        .unwrap();
 
     thread::sleep(Duration::from_secs(2));
 
-    dbg!(invoice.get_ref().clone());
+    dbg!(invoice.clone());
 
-    dbg!(&lnd.get_info().await.get_ref().identity_pubkey);
+    dbg!(&lnd.get_info().await.unwrap().id);
 
-    let payment_response = lighting_d
-        .send_payment(&invoice.get_ref().clone().payment_request)
-        .await;
+    let payment_response = lighting_d.send_payment(&invoice.bolt11).await;
 
     println!("invoice paid: {:#?}", payment_response);
 }
@@ -247,12 +229,7 @@ pub async fn quick_pay() {
     let mut lnd_client = Lnd::new().await;
     let mut lnd_client_1 = Lnd::new_1().await;
 
-    let pub_key_1 = lnd_client_1
-        .get_info()
-        .await
-        .get_ref()
-        .identity_pubkey
-        .clone();
+    let pub_key_1 = lnd_client_1.get_info().await.unwrap().id;
 
     let result = lnd_client
         .send_amp_payment(Vec::from_hex(pub_key_1).unwrap(), 10000)
@@ -1,131 +1,268 @@
 use std::collections::HashMap;
 
+use bitcoin::Txid;
 use bitcoin_hashes::Hash;
-use bitcoincore_rpc::jsonrpc::client;
-use hex::FromHex;
-use tonic::{async_trait, codegen::InterceptedService, Response, Streaming};
-use tower::util::Optional;
+use tonic::{async_trait, Response, Streaming};
 use traproot_bdk::{
-    connect_invoices, connect_lightning, connect_peers, connect_router,
     lnrpc::{
-        invoice, lightning_client::LightningClient, AddInvoiceResponse, Channel,
-        ConnectPeerRequest, ConnectPeerResponse, GetInfoRequest, GetInfoResponse, Invoice,
-        LightningAddress, ListChannelsRequest, ListChannelsResponse, ListInvoiceRequest,
-        ListInvoiceResponse, ListPeersRequest, ListPeersResponse, NewAddressRequest,
-        OpenChannelRequest, OpenStatusUpdate, Payment, Peer, SendRequest, SendResponse,
+        self, invoice, open_status_update::Update, ConnectPeerRequest, GetInfoRequest,
+        GetInfoResponse, LightningAddress, ListChannelsRequest, ListInvoiceRequest,
+        ListPeersRequest, NewAddressRequest, OpenChannelRequest, OpenStatusUpdate,
+        Payment as LndPayment, SendRequest, SendResponse, WalletBalanceRequest,
     },
-    routerrpc::{router_client::RouterClient, SendPaymentRequest},
-    LndRouterClient, MacaroonInterceptor, MyChannel,
+    routerrpc::SendPaymentRequest,
+    LndClient,
 };
 
-use super::{AddrType, LNChannel, LNCommon, LNInvoice, LNPeers};
+use super::{
+    settings::{LndSettings, SettingsError},
+    short_channel_id, AddrType, Channel, Invoice, LightningError, LightningNode, NodeInfo, Payment,
+    Peer,
+};
+
+// what open_channel pays for the funding transaction
+const FUNDING_SAT_PER_VBYTE: i64 = 30;
+// a funding transaction with a handful of inputs, lnd refuses amounts that leave nothing for fees
+const FUNDING_TX_VSIZE: i64 = 500;
+// lnd keeps this much in the wallet to fee bump anchor channels
+const ANCHOR_RESERVE: i64 = 10_000;
+// larger channels need wumbo on both sides
+const MAX_FUNDING: i64 = (1 << 24) - 1;
+// lnd's own default page size for list_invoices
+const INVOICE_PAGE: u64 = 100;
 
 pub struct Lnd {
-    client: LightningClient<InterceptedService<MyChannel, MacaroonInterceptor>>,
-    router: LndRouterClient,
+    client: LndClient,
 }
 
 impl Lnd {
+    pub async fn connect(settings: &LndSettings) -> Result<Self, SettingsError> {
+        let (channel, interceptor) = settings.connect().await?;
+        return Ok(Lnd {
+            client: LndClient::with_channel(channel, interceptor),
+        });
+    }
+
+    // the other lnd services for what the node neutral trait doesn't cover
+    pub fn client(&mut self) -> &mut LndClient {
+        return &mut self.client;
+    }
+
+    // the first node of the regtest setup
     pub async fn new() -> Self {
-        let router = connect_router(
-            "10.5.0.6".to_string(),
-            10006,
-            ".meta/lnd_data/tls.cert".to_owned(),
-            ".meta/lnd_data/admin.macaroon".to_owned(),
-        )
-        .await
-        .expect("failed to connect");
-
-        return Lnd {
-            router,
-            client: connect_lightning(
-                "10.5.0.6".to_string(),
-                10006,
-                ".meta/lnd_data/tls.cert".to_owned(),
-                ".meta/lnd_data/admin.macaroon".to_owned(),
-            )
-            .await
-            .expect("failed to connect"),
-        };
+        let settings = LndSettings::new("10.5.0.6", 10006)
+            .tls_cert_path(".meta/lnd_data/tls.cert")
+            .macaroon_path(".meta/lnd_data/admin.macaroon");
+        return Lnd::connect(&settings).await.expect("failed to connect");
     }
 
+    // the second node of the regtest setup
     pub async fn new_1() -> Self {
-        let router = connect_router(
-            "10.5.0.6".to_string(),
-            10006,
-            ".meta/lnd_data/tls.cert".to_owned(),
-            ".meta/lnd_data/admin.macaroon".to_owned(),
-        )
-        .await
-        .expect("failed to connect");
-
-        return Lnd {
-            router,
-
-            client: connect_lightning(
-                "10.5.0.7".to_string(),
-                10006,
-                ".meta/lnd_data2/tls.cert".to_owned(),
-                ".meta/lnd_data2/admin.macaroon".to_owned(),
-            )
-            .await
-            .expect("failed to connect"),
+        let settings = LndSettings::new("10.5.0.7", 10006)
+            .tls_cert_path(".meta/lnd_data2/tls.cert")
+            .macaroon_path(".meta/lnd_data2/admin.macaroon");
+        return Lnd::connect(&settings).await.expect("failed to connect");
+    }
+}
+
+impl From<GetInfoResponse> for NodeInfo {
+    fn from(info: GetInfoResponse) -> Self {
+        return NodeInfo {
+            id: info.identity_pubkey,
+            alias: info.alias,
+            num_peers: info.num_peers.into(),
+            num_active_channels: info.num_active_channels.into(),
+            num_pending_channels: info.num_pending_channels.into(),
+            block_height: info.block_height.into(),
+        };
+    }
+}
+
+impl From<lnrpc::Channel> for Channel {
+    fn from(channel: lnrpc::Channel) -> Self {
+        let funding_txid = channel
+            .channel_point
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        return Channel {
+            peer_id: channel.remote_pubkey,
+            funding_txid,
+            short_channel_id: Some(channel.chan_id)
+                .filter(|id| *id != 0)
+                .map(short_channel_id),
+            capacity_msat: channel.capacity as u64 * 1000,
+            local_balance_msat: channel.local_balance as u64 * 1000,
+            active: channel.active,
+        };
+    }
+}
+
+impl From<lnrpc::Invoice> for Invoice {
+    fn from(invoice: lnrpc::Invoice) -> Self {
+        return Invoice {
+            bolt11: invoice.payment_request,
+            payment_hash: hex::encode(invoice.r_hash),
+            amount_msat: Some(invoice.value_msat as u64).filter(|amount| *amount != 0),
+            description: Some(invoice.memo).filter(|memo| !memo.is_empty()),
+            settled: invoice.state == invoice::InvoiceState::Settled as i32,
         };
     }
 }
 
+// lnd has no "all" for channel funding, so it is the wallet balance less the funding fee and the
+// anchor reserve, capped at the largest channel without wumbo
+pub fn fund_all(total_balance: i64) -> Result<i64, LightningError> {
+    let amount = (total_balance - FUNDING_SAT_PER_VBYTE * FUNDING_TX_VSIZE - ANCHOR_RESERVE)
+        .min(MAX_FUNDING);
+    if amount <= 0 {
+        return Err(LightningError::Rpc(format!(
+            "wallet balance of {} sats does not fund a channel",
+            total_balance
+        )));
+    }
+    return Ok(amount);
+}
+
+// list_peers only has the peers lnd is connected to right now, peers we have a channel with are
+// added as disconnected so the result matches core lightning's
+pub fn peers(connected: Vec<lnrpc::Peer>, channels: &[Channel]) -> Vec<Peer> {
+    let mut peers = connected
+        .into_iter()
+        .map(|peer| Peer {
+            id: peer.pub_key,
+            address: Some(peer.address).filter(|address| !address.is_empty()),
+            connected: true,
+        })
+        .collect::<Vec<Peer>>();
+    for channel in channels {
+        if !peers.iter().any(|peer| peer.id == channel.peer_id) {
+            peers.push(Peer {
+                id: channel.peer_id.clone(),
+                address: None,
+                connected: false,
+            });
+        }
+    }
+    return peers;
+}
+
+// lnd takes the expiry and the amount as i64, anything above that is refused before the call
+pub fn invoice_request(
+    amount_msat: u64,
+    description: &str,
+    expiry: Option<u64>,
+) -> Result<lnrpc::Invoice, LightningError> {
+    let expiry = expiry.unwrap_or(6555);
+    let mut invoice = Lnd::new_invoice(
+        description.to_string(),
+        vec![],
+        0,
+        vec![],
+        expiry.try_into().map_err(|_| {
+            LightningError::Rpc(format!("{} seconds is not an invoice expiry", expiry))
+        })?,
+        "bcrt1qzvsdwjay5x69088n27h0qgu0tm4u6gwqgxna9d".to_string(),
+        6555,
+        false,
+        false,
+    );
+    invoice.value_msat = amount_msat.try_into().map_err(|_| {
+        LightningError::Rpc(format!("{} msat is not an invoice amount", amount_msat))
+    })?;
+    return Ok(invoice);
+}
+
+// lnd reports a failed payment inside an ok response
+pub fn payment(response: SendResponse) -> Result<Payment, LightningError> {
+    if !response.payment_error.is_empty() {
+        return Err(LightningError::Payment(response.payment_error));
+    }
+    let route = response
+        .payment_route
+        .ok_or(LightningError::UnexpectedResponse(
+            "payment without a route",
+        ))?;
+    return Ok(Payment {
+        payment_hash: hex::encode(response.payment_hash),
+        preimage: hex::encode(response.payment_preimage),
+        amount_msat: (route.total_amt_msat - route.total_fees_msat) as u64,
+        fee_msat: route.total_fees_msat as u64,
+    });
+}
+
 #[async_trait]
-impl LNPeers<Response<ConnectPeerResponse>, Response<ListPeersResponse>> for Lnd {
-    async fn connect(&mut self, id: String, host: String) -> Response<ConnectPeerResponse> {
-        let lightning_address = LightningAddress { pubkey: id, host };
+impl LightningNode for Lnd {
+    async fn get_info(&mut self) -> Result<NodeInfo, LightningError> {
+        let info = self.client.lightning.get_info(GetInfoRequest {}).await?;
+        return Ok(info.into_inner().into());
+    }
+
+    async fn connect(&mut self, id: &str, host: &str) -> Result<(), LightningError> {
+        let lightning_address = LightningAddress {
+            pubkey: id.to_owned(),
+            host: host.to_owned(),
+        };
         let connect_req = ConnectPeerRequest {
             addr: Some(lightning_address),
             perm: true,
             timeout: 30,
         };
-        return self.client.connect_peer(connect_req).await.unwrap();
+        self.client.lightning.connect_peer(connect_req).await?;
+        return Ok(());
     }
 
-    async fn list_peers(&mut self) -> Response<ListPeersResponse> {
-        return self
+    async fn list_peers(&mut self) -> Result<Vec<Peer>, LightningError> {
+        let connected = self
             .client
+            .lightning
             .list_peers(ListPeersRequest { latest_error: true })
-            .await
-            .unwrap();
+            .await?
+            .into_inner()
+            .peers;
+        let channels = self.list_channels().await?;
+        return Ok(peers(connected, &channels));
     }
-}
-#[async_trait]
-impl LNChannel<Response<Streaming<OpenStatusUpdate>>, Response<ListChannelsResponse>> for Lnd {
-    async fn new_address(&mut self, address_type: AddrType) -> String {
+
+    async fn new_address(&mut self, address_type: AddrType) -> Result<String, LightningError> {
         let address = match address_type {
             AddrType::Bech32 => 0,
             AddrType::P2SH => 1,
             AddrType::TR => 4,
         };
 
-        return self
+        let response = self
             .client
+            .lightning
             .new_address(NewAddressRequest {
                 account: "".to_string(),
                 r#type: address,
             })
-            .await
-            .unwrap()
-            .get_ref()
-            .address
-            .clone();
+            .await?;
+        return Ok(response.into_inner().address);
     }
 
-    async fn open_channel(
-        &mut self,
-        id: String,
-        amt: Option<u64>,
-    ) -> Response<Streaming<OpenStatusUpdate>> {
-        // self.client.open_channel(request)
+    async fn open_channel(&mut self, id: &str, amt: Option<u64>) -> Result<Txid, LightningError> {
+        let node_pubkey =
+            hex::decode(id).map_err(|_| LightningError::Rpc(format!("invalid node id {}", id)))?;
+        let local_funding_amount = match amt {
+            Some(amt) => i64::try_from(amt)
+                .map_err(|_| LightningError::Rpc(format!("{} sats is not a channel size", amt)))?,
+            None => {
+                let balance = self
+                    .client
+                    .lightning
+                    .wallet_balance(WalletBalanceRequest {})
+                    .await?
+                    .into_inner();
+                fund_all(balance.total_balance)?
+            }
+        };
         let open_channel_req = OpenChannelRequest {
-            sat_per_vbyte: 30,
-            node_pubkey: hex::decode(id).unwrap(),
-            local_funding_amount: amt.unwrap_or(0).try_into().unwrap(),
+            sat_per_vbyte: FUNDING_SAT_PER_VBYTE as u64,
+            node_pubkey,
+            local_funding_amount,
             node_pubkey_string: "".to_owned(),
             push_sat: 0,
             target_conf: 0,
@@ -150,67 +287,96 @@ impl LNChannel<Response<Streaming<OpenStatusUpdate>>, Response<ListChannelsRespo
             use_fee_rate: false,
             remote_chan_reserve_sat: 0,
         };
-        return self.client.open_channel(open_channel_req).await.unwrap();
+        let mut updates = self
+            .client
+            .lightning
+            .open_channel(open_channel_req)
+            .await?
+            .into_inner();
+        // the first update lnd streams is the published funding transaction
+        return match updates.message().await? {
+            Some(OpenStatusUpdate {
+                update: Some(Update::ChanPending(pending)),
+                ..
+            }) => Txid::from_slice(&pending.txid)
+                .map_err(|_| LightningError::UnexpectedResponse("invalid funding txid")),
+            _ => Err(LightningError::UnexpectedResponse(
+                "channel open without a pending update",
+            )),
+        };
     }
 
-    async fn list_channels(&mut self) -> Response<ListChannelsResponse> {
-        return self
+    async fn list_channels(&mut self) -> Result<Vec<Channel>, LightningError> {
+        let channels = self
             .client
+            .lightning
             .list_channels(ListChannelsRequest {
-                active_only: true,
+                active_only: false,
                 inactive_only: false,
                 public_only: false,
                 private_only: false,
                 peer: vec![],
             })
-            .await
-            .unwrap();
+            .await?;
+        return Ok(channels
+            .into_inner()
+            .channels
+            .into_iter()
+            .map(Channel::from)
+            .collect());
     }
-}
 
-#[async_trait]
-impl LNInvoice<Response<AddInvoiceResponse>, Response<ListInvoiceResponse>, SendResponse> for Lnd {
     async fn create_invoice(
         &mut self,
-        value: u64,
-        label: &str,
-        _description: &str,
+        amount_msat: u64,
+        _label: &str,
+        description: &str,
         expiry: Option<u64>,
-    ) -> Response<AddInvoiceResponse> {
-        let invoice = Self::new_invoice(
-            label.to_string(),
-            vec![],
-            value.try_into().unwrap(),
-            vec![],
-            expiry.unwrap_or(6555).try_into().unwrap(),
-            "bcrt1qzvsdwjay5x69088n27h0qgu0tm4u6gwqgxna9d".to_string(),
-            6555,
-            false,
-            false,
-        );
-
-        let invoice = self.client.add_invoice(invoice).await.unwrap();
-
-        println!("invoice response {:#?}", invoice);
-        return invoice;
+    ) -> Result<Invoice, LightningError> {
+        let invoice = invoice_request(amount_msat, description, expiry)?;
+        let response = self
+            .client
+            .lightning
+            .add_invoice(invoice)
+            .await?
+            .into_inner();
+        return Ok(Invoice {
+            bolt11: response.payment_request,
+            payment_hash: hex::encode(response.r_hash),
+            amount_msat: Some(amount_msat).filter(|amount| *amount != 0),
+            description: Some(description.to_owned()).filter(|memo| !memo.is_empty()),
+            settled: false,
+        });
     }
 
-    async fn list_invoices(&mut self) -> Response<ListInvoiceResponse> {
-        return self
-            .client
-            .list_invoices(ListInvoiceRequest {
-                pending_only: false,
-                index_offset: 0,
-                num_max_invoices: 100,
-                reversed: false,
-                creation_date_end: 0,
-                creation_date_start: 0,
-            })
-            .await
-            .unwrap();
+    // page by page, each request starts after the last invoice of the one before
+    async fn list_invoices(&mut self) -> Result<Vec<Invoice>, LightningError> {
+        let mut invoices = vec![];
+        let mut index_offset = 0;
+        loop {
+            let page = self
+                .client
+                .lightning
+                .list_invoices(ListInvoiceRequest {
+                    pending_only: false,
+                    index_offset,
+                    num_max_invoices: INVOICE_PAGE,
+                    reversed: false,
+                    creation_date_end: 0,
+                    creation_date_start: 0,
+                })
+                .await?
+                .into_inner();
+            let count = page.invoices.len() as u64;
+            invoices.extend(page.invoices.into_iter().map(Invoice::from));
+            if count < INVOICE_PAGE || page.last_index_offset <= index_offset {
+                return Ok(invoices);
+            }
+            index_offset = page.last_index_offset;
+        }
     }
 
-    async fn send_payment<'a>(&mut self, bolt11: &'a String) -> SendResponse {
+    async fn send_payment(&mut self, bolt11: &str) -> Result<Payment, LightningError> {
         let send_req = SendRequest {
             allow_self_payment: true,
             amt: 0,
@@ -227,23 +393,11 @@ impl LNInvoice<Response<AddInvoiceResponse>, Response<ListInvoiceResponse>, Send
             payment_addr: vec![],
             payment_hash: vec![],
             payment_hash_string: "".to_string(),
-            payment_request: bolt11.clone(),
+            payment_request: bolt11.to_owned(),
         };
 
-        return self
-            .client
-            .send_payment_sync(send_req)
-            .await
-            .unwrap()
-            .get_ref()
-            .clone();
-    }
-}
-
-#[async_trait]
-impl LNCommon<Response<GetInfoResponse>> for Lnd {
-    async fn get_info(&mut self) -> Response<GetInfoResponse> {
-        return self.client.get_info(GetInfoRequest {}).await.unwrap();
+        let response = self.client.lightning.send_payment_sync(send_req).await?;
+        return payment(response.into_inner());
     }
 }
 
@@ -258,8 +412,8 @@ impl Lnd {
         cltv_expiry: u64,
         private: bool,
         is_amp: bool,
-    ) -> Invoice {
-        return Invoice {
+    ) -> lnrpc::Invoice {
+        return lnrpc::Invoice {
             memo,
             r_preimage,
             r_hash: vec![],
@@ -295,7 +449,7 @@ impl Lnd {
         &mut self,
         dest: Vec<u8>,
         amt: i64,
-    ) -> Response<Streaming<Payment>> {
+    ) -> Response<Streaming<LndPayment>> {
         let send_payment_request = SendPaymentRequest {
             dest,
             amt,
@@ -322,9 +476,90 @@ impl Lnd {
             time_pref: 1.0,
         };
         return self
+            .client
             .router
             .send_payment_v2(send_payment_request)
             .await
             .unwrap();
     }
 }
+
+#[test]
+fn lnd_results_convert_to_node_neutral_types() {
+    use traproot_bdk::lnrpc::Route;
+
+    let channel = Channel::from(lnrpc::Channel {
+        active: true,
+        remote_pubkey: "02ab".to_owned(),
+        channel_point: "9f1c:1".to_owned(),
+        chan_id: (103 << 40) | (2 << 16) | 1,
+        capacity: 10_000_000,
+        local_balance: 9_990_000,
+        ..Default::default()
+    });
+    assert_eq!(channel.funding_txid, "9f1c");
+    assert_eq!(channel.short_channel_id, Some("103x2x1".to_owned()));
+    assert_eq!(channel.capacity_msat, 10_000_000_000);
+    assert_eq!(channel.local_balance_msat, 9_990_000_000);
+
+    let paid = payment(SendResponse {
+        payment_preimage: vec![1; 32],
+        payment_hash: vec![2; 32],
+        payment_route: Some(Route {
+            total_amt_msat: 1_001_000,
+            total_fees_msat: 1_000,
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(paid.amount_msat, 1_000_000);
+    assert_eq!(paid.fee_msat, 1_000);
+    assert_eq!(paid.preimage, "01".repeat(32));
+
+    // a channel peer lnd isn't connected to still shows up, as disconnected
+    let connected = lnrpc::Peer {
+        pub_key: "03cd".to_owned(),
+        address: "10.5.0.7:9735".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(
+        peers(vec![connected], &[channel]),
+        vec![
+            Peer {
+                id: "03cd".to_owned(),
+                address: Some("10.5.0.7:9735".to_owned()),
+                connected: true,
+            },
+            Peer {
+                id: "02ab".to_owned(),
+                address: None,
+                connected: false,
+            },
+        ]
+    );
+
+    // funding without an amount leaves the fee and the anchor reserve in the wallet
+    assert_eq!(fund_all(1_000_000).unwrap(), 1_000_000 - 15_000 - 10_000);
+    assert_eq!(fund_all(100_000_000).unwrap(), (1 << 24) - 1);
+    assert!(matches!(fund_all(20_000), Err(LightningError::Rpc(_))));
+
+    // lnd answers ok for a payment it could not route
+    let failed = payment(SendResponse {
+        payment_error: "no_route".to_owned(),
+        ..Default::default()
+    });
+    assert!(matches!(failed, Err(LightningError::Payment(err)) if err == "no_route"));
+
+    // amounts and expiries lnd can't hold are an error rather than a panic
+    let invoice = invoice_request(1_000_000, "coffee", None).unwrap();
+    assert_eq!((invoice.value_msat, invoice.expiry), (1_000_000, 6555));
+    assert!(matches!(
+        invoice_request(u64::MAX, "coffee", None),
+        Err(LightningError::Rpc(_))
+    ));
+    assert!(matches!(
+        invoice_request(1_000_000, "coffee", Some(u64::MAX)),
+        Err(LightningError::Rpc(_))
+    ));
+}
@@ -1,38 +1,121 @@
+use std::fmt;
+
+use bitcoin::Txid;
 use tonic::async_trait;
 
 pub mod clighting;
 pub mod lighting_demo;
 pub mod lnd;
+pub mod settings;
 
-#[async_trait]
-pub trait LNCommon<I> {
-    async fn get_info(&mut self) -> I;
+#[derive(Debug)]
+pub enum LightningError {
+    // the node could not be reached or refused the call
+    Rpc(String),
+    // the node answered but the payment did not go through
+    Payment(String),
+    UnexpectedResponse(&'static str),
 }
 
-#[async_trait]
-pub trait LNPeers<R, P> {
-    async fn connect(&mut self, id: String, host: String) -> R;
-    async fn list_peers(&mut self) -> P;
+impl fmt::Display for LightningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LightningError::Rpc(err) => write!(f, "lightning node: {}", err),
+            LightningError::Payment(err) => write!(f, "payment failed: {}", err),
+            LightningError::UnexpectedResponse(what) => {
+                write!(f, "unexpected response from the node: {}", what)
+            }
+        }
+    }
 }
 
-#[async_trait]
-pub trait LNChannel<F, C> {
-    async fn new_address(&mut self, address_type: AddrType) -> String;
-    async fn open_channel(&mut self, id: String, amt: Option<u64>) -> F;
-    async fn list_channels(&mut self) -> C;
+impl std::error::Error for LightningError {}
+
+impl From<tonic::Status> for LightningError {
+    fn from(status: tonic::Status) -> Self {
+        LightningError::Rpc(status.message().to_owned())
+    }
 }
 
+impl From<clightningrpc::Error> for LightningError {
+    fn from(err: clightningrpc::Error) -> Self {
+        LightningError::Rpc(err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub id: String,
+    pub alias: String,
+    pub num_peers: u64,
+    pub num_active_channels: u64,
+    pub num_pending_channels: u64,
+    pub block_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub id: String,
+    pub address: Option<String>,
+    pub connected: bool,
+}
+
+// amounts are in msat whatever unit the node reports them in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channel {
+    pub peer_id: String,
+    pub funding_txid: String,
+    // block x tx x output, unset until the funding transaction confirms
+    pub short_channel_id: Option<String>,
+    pub capacity_msat: u64,
+    pub local_balance_msat: u64,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    pub bolt11: String,
+    pub payment_hash: String,
+    pub amount_msat: Option<u64>,
+    pub description: Option<String>,
+    pub settled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    pub payment_hash: String,
+    pub preimage: String,
+    pub amount_msat: u64,
+    pub fee_msat: u64,
+}
+
+// what application code talks to, lnd and core lightning both sit behind it so a
+// Box<dyn LightningNode> can be either
 #[async_trait]
-pub trait LNInvoice<I, L, S> {
+pub trait LightningNode: Send {
+    async fn get_info(&mut self) -> Result<NodeInfo, LightningError>;
+    async fn connect(&mut self, id: &str, host: &str) -> Result<(), LightningError>;
+    async fn list_peers(&mut self) -> Result<Vec<Peer>, LightningError>;
+    async fn new_address(&mut self, address_type: AddrType) -> Result<String, LightningError>;
+    // funds the channel from the node wallet, all of it when no amount is given, and returns
+    // the funding transaction
+    async fn open_channel(&mut self, id: &str, amt: Option<u64>) -> Result<Txid, LightningError>;
+    async fn list_channels(&mut self) -> Result<Vec<Channel>, LightningError>;
+    // the label only has to be unique on core lightning, lnd has no equivalent and ignores it
     async fn create_invoice(
         &mut self,
-        value: u64,
+        amount_msat: u64,
         label: &str,
         description: &str,
         expiry: Option<u64>,
-    ) -> I;
-    async fn list_invoices(&mut self) -> L;
-    async fn send_payment<'a>(&mut self, bolt11: &'a String) -> S;
+    ) -> Result<Invoice, LightningError>;
+    async fn list_invoices(&mut self) -> Result<Vec<Invoice>, LightningError>;
+    async fn send_payment(&mut self, bolt11: &str) -> Result<Payment, LightningError>;
+}
+
+// lnd packs the short channel id into a u64, core lightning prints it as blockxtxxoutput
+pub fn short_channel_id(id: u64) -> String {
+    return format!("{}x{}x{}", id >> 40, (id >> 16) & 0xFF_FFFF, id & 0xFFFF);
 }
 
 pub enum AddrType {
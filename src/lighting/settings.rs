@@ -0,0 +1,388 @@
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use traproot_bdk::{LndConnectConfig, MacaroonInterceptor, MyChannel, TlsMode, TransportConfig};
+
+const DEFAULT_LND_PORT: u32 = 10009;
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Missing(&'static str),
+    InvalidUri(String),
+    InvalidCredential(&'static str),
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Config(String),
+    Connect(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Missing(setting) => write!(f, "missing setting {}", setting),
+            SettingsError::InvalidUri(err) => write!(f, "invalid lndconnect uri: {}", err),
+            SettingsError::InvalidCredential(what) => write!(f, "invalid {}", what),
+            SettingsError::Io { path, error } => {
+                write!(f, "failed to read {}: {}", path.display(), error)
+            }
+            SettingsError::Config(err) => write!(f, "invalid config file: {}", err),
+            SettingsError::Connect(err) => write!(f, "failed to connect: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+// a tls cert or macaroon, either on disk or already in memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl Credential {
+    pub fn read(&self) -> Result<Vec<u8>, SettingsError> {
+        return match self {
+            Credential::Path(path) => fs::read(path).map_err(|error| SettingsError::Io {
+                path: path.clone(),
+                error,
+            }),
+            Credential::Bytes(bytes) => Ok(bytes.clone()),
+        };
+    }
+}
+
+// where and how to reach an lnd node, without a tls cert the node has to present one the
+// system trusts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LndSettings {
+    pub host: String,
+    pub port: u32,
+    pub tls_cert: Option<Credential>,
+    // skips certificate and hostname checks, only for local testing
+    pub insecure_tls: bool,
+    pub macaroon: Option<Credential>,
+    pub transport: TransportConfig,
+}
+
+impl LndSettings {
+    pub fn new(host: &str, port: u32) -> Self {
+        return LndSettings {
+            host: host.to_owned(),
+            port,
+            tls_cert: None,
+            insecure_tls: false,
+            macaroon: None,
+            transport: TransportConfig::default(),
+        };
+    }
+
+    pub fn tls_cert_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tls_cert = Some(Credential::Path(path.into()));
+        return self;
+    }
+
+    pub fn tls_cert_pem(mut self, pem: Vec<u8>) -> Self {
+        self.tls_cert = Some(Credential::Bytes(pem));
+        return self;
+    }
+
+    pub fn insecure_tls(mut self) -> Self {
+        self.insecure_tls = true;
+        return self;
+    }
+
+    pub fn macaroon_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.macaroon = Some(Credential::Path(path.into()));
+        return self;
+    }
+
+    pub fn macaroon(mut self, macaroon: Vec<u8>) -> Self {
+        self.macaroon = Some(Credential::Bytes(macaroon));
+        return self;
+    }
+
+    // timeouts, keep-alive and how often read-only calls are retried
+    pub fn transport(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        return self;
+    }
+
+    pub fn from_lndconnect(uri: &str) -> Result<Self, SettingsError> {
+        let config = uri
+            .parse::<LndConnectConfig>()
+            .map_err(|err| SettingsError::InvalidUri(err.to_string()))?;
+        return Ok(LndSettings::from(config));
+    }
+
+    pub fn from_env() -> Result<Self, SettingsError> {
+        return LndSettings::from_vars(|key| env::var(key).ok());
+    }
+
+    // LNDCONNECT_URI wins over the separate LND_HOST, LND_PORT, LND_TLS_CERT_PATH,
+    // LND_TLS_INSECURE, LND_MACAROON_PATH, LND_MACAROON_HEX and LND_REQUEST_TIMEOUT_SECS
+    // variables
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, SettingsError> {
+        if let Some(uri) = var("LNDCONNECT_URI") {
+            return LndSettings::from_lndconnect(&uri);
+        }
+        let host = var("LND_HOST").ok_or(SettingsError::Missing("LND_HOST"))?;
+        let port = match var("LND_PORT") {
+            Some(port) => port
+                .parse::<u32>()
+                .map_err(|_| SettingsError::Config(format!("LND_PORT {} is not a port", port)))?,
+            None => DEFAULT_LND_PORT,
+        };
+        let mut settings = LndSettings::new(&host, port);
+        if let Some(path) = var("LND_TLS_CERT_PATH") {
+            settings = settings.tls_cert_path(path);
+        }
+        if matches!(var("LND_TLS_INSECURE").as_deref(), Some("1" | "true")) {
+            settings = settings.insecure_tls();
+        }
+        if let Some(path) = var("LND_MACAROON_PATH") {
+            settings = settings.macaroon_path(path);
+        }
+        if let Some(macaroon) = var("LND_MACAROON_HEX") {
+            let macaroon =
+                hex::decode(macaroon).map_err(|_| SettingsError::InvalidCredential("macaroon"))?;
+            settings = settings.macaroon(macaroon);
+        }
+        if let Some(timeout) = var("LND_REQUEST_TIMEOUT_SECS") {
+            let timeout = timeout.parse::<u64>().map_err(|_| {
+                SettingsError::Config(format!(
+                    "LND_REQUEST_TIMEOUT_SECS {} is not a number",
+                    timeout
+                ))
+            })?;
+            settings.transport.request_timeout = Some(Duration::from_secs(timeout));
+        }
+        return Ok(settings);
+    }
+
+    // reads the credentials once so every client built from the config shares them
+    pub fn config(&self) -> Result<LndConnectConfig, SettingsError> {
+        let macaroon = self
+            .macaroon
+            .as_ref()
+            .ok_or(SettingsError::Missing("macaroon"))?
+            .read()?;
+        let tls = match &self.tls_cert {
+            _ if self.insecure_tls => TlsMode::Insecure,
+            Some(cert) => TlsMode::Pinned(cert.read()?),
+            None => TlsMode::SystemRoots,
+        };
+        return Ok(
+            LndConnectConfig::new(self.host.clone(), self.port, tls, macaroon)
+                .with_transport(self.transport.clone()),
+        );
+    }
+
+    pub async fn connect(&self) -> Result<(MyChannel, MacaroonInterceptor), SettingsError> {
+        return self
+            .config()?
+            .connect()
+            .await
+            .map_err(|err| SettingsError::Connect(err.to_string()));
+    }
+}
+
+impl From<LndConnectConfig> for LndSettings {
+    fn from(config: LndConnectConfig) -> Self {
+        let settings = LndSettings::new(&config.host, config.port)
+            .macaroon(config.macaroon)
+            .transport(config.transport);
+        return match config.tls {
+            TlsMode::Pinned(pem) => settings.tls_cert_pem(pem),
+            TlsMode::SystemRoots => settings,
+            TlsMode::Insecure => settings.insecure_tls(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClnSettings {
+    pub rpc_path: PathBuf,
+}
+
+impl ClnSettings {
+    pub fn new(rpc_path: impl Into<PathBuf>) -> Self {
+        return ClnSettings {
+            rpc_path: rpc_path.into(),
+        };
+    }
+
+    pub fn from_env() -> Result<Self, SettingsError> {
+        let rpc_path =
+            env::var("CLN_RPC_PATH").map_err(|_| SettingsError::Missing("CLN_RPC_PATH"))?;
+        return Ok(ClnSettings::new(rpc_path));
+    }
+}
+
+#[derive(Deserialize)]
+struct LndConfig {
+    lndconnect: Option<String>,
+    host: Option<String>,
+    port: Option<u32>,
+    tls_cert_path: Option<PathBuf>,
+    #[serde(default)]
+    tls_insecure: bool,
+    macaroon_path: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ClnConfig {
+    rpc_path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct NodeConfig {
+    lnd: Option<LndConfig>,
+    cln: Option<ClnConfig>,
+}
+
+// the nodes a config file describes, either may be left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSettings {
+    pub lnd: Option<LndSettings>,
+    pub cln: Option<ClnSettings>,
+}
+
+impl NodeSettings {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path).map_err(|error| SettingsError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        return NodeSettings::from_json(&json);
+    }
+
+    // {"lnd": {"lndconnect": ..} or {"host", "port", "tls_cert_path", "macaroon_path"},
+    //  "cln": {"rpc_path": ..}}
+    pub fn from_json(json: &str) -> Result<Self, SettingsError> {
+        let config: NodeConfig =
+            serde_json::from_str(json).map_err(|err| SettingsError::Config(err.to_string()))?;
+        let lnd = match config.lnd {
+            Some(LndConfig {
+                lndconnect: Some(uri),
+                ..
+            }) => Some(LndSettings::from_lndconnect(&uri)?),
+            Some(lnd) => {
+                let host = lnd.host.ok_or(SettingsError::Missing("lnd.host"))?;
+                let mut settings = LndSettings::new(&host, lnd.port.unwrap_or(DEFAULT_LND_PORT));
+                settings.tls_cert = lnd.tls_cert_path.map(Credential::Path);
+                settings.insecure_tls = lnd.tls_insecure;
+                settings.macaroon = lnd.macaroon_path.map(Credential::Path);
+                Some(settings)
+            }
+            None => None,
+        };
+        return Ok(NodeSettings {
+            lnd,
+            cln: config.cln.map(|cln| ClnSettings::new(cln.rpc_path)),
+        });
+    }
+}
+
+#[test]
+fn settings_load_from_lndconnect_env_and_config() {
+    use std::collections::HashMap;
+
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::PKey,
+        x509::{X509NameBuilder, X509},
+    };
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+        .unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(365).unwrap())
+        .unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = cert.build();
+
+    let uri = format!(
+        "lndconnect://10.5.0.6:10006?cert={}&macaroon={}",
+        base64::encode_config(cert.to_der().unwrap(), base64::URL_SAFE_NO_PAD),
+        base64::encode_config([2u8, 1, 0xfb], base64::URL_SAFE_NO_PAD),
+    );
+    let config = uri.parse::<LndConnectConfig>().unwrap();
+    assert_eq!(config.to_lndconnect().unwrap(), uri);
+    assert!(!format!("{:?}", config).contains("251"));
+    let settings = LndSettings::from_lndconnect(&uri).unwrap();
+    assert_eq!(
+        settings,
+        LndSettings::new("10.5.0.6", 10006)
+            .tls_cert_pem(cert.to_pem().unwrap())
+            .macaroon(vec![2, 1, 0xfb])
+    );
+    assert!(matches!(
+        LndSettings::from_lndconnect("lndconnect://node:port"),
+        Err(SettingsError::InvalidUri(_))
+    ));
+
+    let vars = HashMap::from([
+        ("LND_HOST", "10.5.0.7"),
+        ("LND_MACAROON_PATH", ".meta/lnd_data2/admin.macaroon"),
+    ]);
+    let from_env = LndSettings::from_vars(|key| vars.get(key).map(|value| value.to_string()));
+    assert_eq!(
+        from_env.unwrap(),
+        LndSettings::new("10.5.0.7", 10009).macaroon_path(".meta/lnd_data2/admin.macaroon")
+    );
+    let with_uri = LndSettings::from_vars(|key| (key == "LNDCONNECT_URI").then(|| uri.clone()));
+    assert_eq!(with_uri.unwrap(), settings);
+    // without a cert the node needs a certificate the system trusts
+    let public = LndSettings::new("node.example.com", 10009).macaroon(vec![1]);
+    assert_eq!(public.config().unwrap().tls, TlsMode::SystemRoots);
+    assert_eq!(
+        public.insecure_tls().config().unwrap().tls,
+        TlsMode::Insecure
+    );
+    let missing = LndSettings::new("10.5.0.6", 10006)
+        .tls_cert_path("/nonexistent/tls.cert")
+        .macaroon(vec![1]);
+    assert!(matches!(missing.config(), Err(SettingsError::Io { .. })));
+
+    let config = NodeSettings::from_json(
+        r#"{"lnd": {"host": "10.5.0.6", "port": 10006, "tls_cert_path": ".meta/lnd_data/tls.cert",
+            "macaroon_path": ".meta/lnd_data/admin.macaroon"},
+            "cln": {"rpc_path": ".meta/lightningd_data/lightning-rpc"}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.lnd,
+        Some(
+            LndSettings::new("10.5.0.6", 10006)
+                .tls_cert_path(".meta/lnd_data/tls.cert")
+                .macaroon_path(".meta/lnd_data/admin.macaroon")
+        )
+    );
+    assert_eq!(
+        config.cln,
+        Some(ClnSettings::new(".meta/lightningd_data/lightning-rpc"))
+    );
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use openssl::x509::X509;
+
+use crate::error::{ConnectError, InternalConnectError};
+use crate::{MacaroonInterceptor, MyChannel, TlsMode, TransportConfig};
+
+const DEFAULT_PORT: u32 = 10009;
+const SCHEME: &str = "lndconnect://";
+
+/// Everything needed to reach an LND node, held in memory
+///
+/// Build it from an `lndconnect://host:port?cert=...&macaroon=...` URI, from the files LND writes
+/// or from bytes, e.g. credentials a container got through environment variables. The
+/// credentials are read once, so any number of clients can be created from one config.
+#[derive(Clone, PartialEq, Eq)]
+pub struct LndConnectConfig {
+    pub host: String,
+    pub port: u32,
+    /// lndconnect URIs leave the certificate out when it is signed by a public authority, such
+    /// configs trust the system roots
+    pub tls: TlsMode,
+    /// Raw (not hex-encoded) macaroon
+    pub macaroon: Vec<u8>,
+    pub transport: TransportConfig,
+}
+
+impl LndConnectConfig {
+    pub fn new(host: impl Into<String>, port: u32, tls: TlsMode, macaroon: Vec<u8>) -> Self {
+        LndConnectConfig {
+            host: host.into(),
+            port,
+            tls,
+            macaroon,
+            transport: TransportConfig::default(),
+        }
+    }
+
+    /// Replaces the default timeouts, keep-alive and retry policy
+    pub fn with_transport(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Reads the TLS certificate and macaroon files LND writes
+    pub async fn from_files(
+        host: impl Into<String>,
+        port: u32,
+        tls_cert_path: impl AsRef<Path> + Into<PathBuf>,
+        macaroon_path: impl AsRef<Path> + Into<PathBuf>,
+    ) -> Result<Self, ConnectError> {
+        let tls_cert = read_file(tls_cert_path).await?;
+        let macaroon = read_file(macaroon_path).await?;
+        Ok(LndConnectConfig::new(
+            host,
+            port,
+            TlsMode::Pinned(tls_cert),
+            macaroon,
+        ))
+    }
+
+    /// Parses an `lndconnect://` URI, the certificate in it is base64url encoded DER
+    pub fn from_lndconnect(uri: &str) -> Result<Self, ConnectError> {
+        let invalid = |reason| ConnectError::from(InternalConnectError::LndConnect { reason });
+        let rest = uri
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| invalid("not an lndconnect uri"))?;
+        let (authority, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let authority = authority.trim_end_matches('/');
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse::<u32>().map_err(|_| invalid("invalid port"))?,
+            ),
+            None => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+
+        let mut tls = TlsMode::SystemRoots;
+        let mut macaroon = None;
+        for (key, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+            match key {
+                "cert" => {
+                    let der =
+                        decode_base64url(value).ok_or_else(|| invalid("cert is not base64url"))?;
+                    let pem = X509::from_der(&der)
+                        .and_then(|cert| cert.to_pem())
+                        .map_err(InternalConnectError::Certificate)?;
+                    tls = TlsMode::Pinned(pem);
+                }
+                "macaroon" => {
+                    macaroon = Some(
+                        decode_base64url(value)
+                            .ok_or_else(|| invalid("macaroon is not base64url"))?,
+                    );
+                }
+                _ => {}
+            }
+        }
+        let macaroon = macaroon.ok_or_else(|| invalid("missing macaroon"))?;
+        Ok(LndConnectConfig::new(host, port, tls, macaroon))
+    }
+
+    /// Encodes the config as an `lndconnect://` URI, insecure configs can't be told apart from
+    /// ones trusting the system roots there
+    pub fn to_lndconnect(&self) -> Result<String, ConnectError> {
+        let mut uri = format!("{}{}:{}?", SCHEME, self.host, self.port);
+        if let TlsMode::Pinned(pem) = &self.tls {
+            let der = X509::from_pem(pem)
+                .and_then(|cert| cert.to_der())
+                .map_err(InternalConnectError::Certificate)?;
+            uri.push_str(&format!("cert={}&", encode_base64url(&der)));
+        }
+        uri.push_str(&format!("macaroon={}", encode_base64url(&self.macaroon)));
+        Ok(uri)
+    }
+
+    pub fn uri(&self) -> String {
+        format!("https://{}:{}", self.host, self.port)
+    }
+
+    /// Opens the channel and interceptor every LND client is built from
+    pub async fn connect(
+        &self,
+    ) -> Result<(MyChannel, MacaroonInterceptor), Box<dyn std::error::Error>> {
+        let channel = MyChannel::with_transport(
+            self.tls.clone(),
+            self.uri().parse()?,
+            self.transport.clone(),
+        )
+        .await?;
+        Ok((channel, MacaroonInterceptor::new(&self.macaroon)))
+    }
+}
+
+impl FromStr for LndConnectConfig {
+    type Err = ConnectError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        LndConnectConfig::from_lndconnect(uri)
+    }
+}
+
+// keeps the credentials out of logs
+impl fmt::Debug for LndConnectConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LndConnectConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("tls", &self.tls)
+            .field("macaroon", &"...")
+            .field("transport", &self.transport)
+            .finish()
+    }
+}
+
+pub(crate) async fn read_file(
+    path: impl AsRef<Path> + Into<PathBuf>,
+) -> Result<Vec<u8>, ConnectError> {
+    let contents =
+        tokio::fs::read(&path)
+            .await
+            .map_err(|error| InternalConnectError::ReadFile {
+                file: path.into(),
+                error,
+            })?;
+    Ok(contents)
+}
+
+fn decode_base64url(encoded: &str) -> Option<Vec<u8>> {
+    base64::decode_config(encoded.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()
+}
+
+fn encode_base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
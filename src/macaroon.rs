@@ -0,0 +1,583 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin_hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use prost::Message;
+
+use crate::lnrpc::{BakeMacaroonRequest, MacaroonPermission};
+use crate::LndClient;
+
+const VERSION: u8 = 2;
+const EOS: u8 = 0;
+const LOCATION: u8 = 1;
+const IDENTIFIER: u8 = 2;
+const VERIFICATION_ID: u8 = 4;
+const SIGNATURE: u8 = 6;
+
+/// Version byte LND puts in front of the protobuf encoded identifier
+const LND_ID_VERSION: u8 = 3;
+
+/// Error parsing a macaroon
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacaroonError {
+    Hex,
+    Truncated,
+    UnsupportedVersion(u8),
+    UnexpectedField(u8),
+    /// The identifier is not one LND issued, so it lists no permissions
+    InvalidIdentifier,
+}
+
+impl fmt::Display for MacaroonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacaroonError::Hex => write!(f, "macaroon is not hex encoded"),
+            MacaroonError::Truncated => write!(f, "macaroon is truncated"),
+            MacaroonError::UnsupportedVersion(version) => {
+                write!(f, "unsupported macaroon version {}", version)
+            }
+            MacaroonError::UnexpectedField(field) => {
+                write!(f, "unexpected macaroon field {}", field)
+            }
+            MacaroonError::InvalidIdentifier => write!(f, "not an LND macaroon identifier"),
+        }
+    }
+}
+
+impl std::error::Error for MacaroonError {}
+
+/// An entity and the action a macaroon allows on it, e.g. `invoices` and `read`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Permission {
+    pub entity: String,
+    pub action: String,
+}
+
+impl Permission {
+    pub fn new(entity: &str, action: &str) -> Self {
+        Permission {
+            entity: entity.to_owned(),
+            action: action.to_owned(),
+        }
+    }
+}
+
+/// Least-privilege permission sets to bake macaroons with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MacaroonPreset {
+    /// What LND's `readonly.macaroon` grants
+    ReadOnly,
+    /// What LND's `invoice.macaroon` grants, creating and looking up invoices
+    InvoiceOnly,
+    Custom(Vec<Permission>),
+}
+
+impl MacaroonPreset {
+    pub fn permissions(&self) -> Vec<Permission> {
+        match self {
+            MacaroonPreset::ReadOnly => [
+                "address", "info", "invoices", "macaroon", "message", "offchain", "onchain",
+                "peers", "signer",
+            ]
+            .iter()
+            .map(|entity| Permission::new(entity, "read"))
+            .collect(),
+            MacaroonPreset::InvoiceOnly => vec![
+                Permission::new("invoices", "read"),
+                Permission::new("invoices", "write"),
+                Permission::new("address", "read"),
+                Permission::new("address", "write"),
+                Permission::new("onchain", "read"),
+            ],
+            MacaroonPreset::Custom(permissions) => permissions.clone(),
+        }
+    }
+}
+
+/// A caveat of a macaroon, first-party ones have no verification id
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Caveat {
+    pub location: Option<String>,
+    pub id: Vec<u8>,
+    pub verification_id: Option<Vec<u8>>,
+}
+
+impl Caveat {
+    pub fn is_first_party(&self) -> bool {
+        self.verification_id.is_none()
+    }
+}
+
+/// A macaroon in the V2 binary format LND uses
+///
+/// Caveats can only be added, each one chains the signature, so a restricted macaroon is derived
+/// without asking the node.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Macaroon {
+    pub location: Option<String>,
+    pub identifier: Vec<u8>,
+    caveats: Vec<Caveat>,
+    signature: [u8; 32],
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MacaroonId {
+    #[prost(bytes = "vec", tag = "1")]
+    nonce: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    storage_id: Vec<u8>,
+    #[prost(message, repeated, tag = "3")]
+    ops: Vec<Op>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Op {
+    #[prost(string, tag = "1")]
+    entity: String,
+    #[prost(string, repeated, tag = "2")]
+    actions: Vec<String>,
+}
+
+impl Macaroon {
+    /// Mints a macaroon from the root key, what the node does when baking
+    pub fn new(root_key: &[u8], identifier: Vec<u8>, location: Option<String>) -> Self {
+        let key = hmac(b"macaroons-key-generator", root_key);
+        let signature = hmac(&key, &identifier);
+        Macaroon {
+            location,
+            identifier,
+            caveats: vec![],
+            signature,
+        }
+    }
+
+    pub fn from_hex(macaroon: &str) -> Result<Self, MacaroonError> {
+        let bytes = hex::decode(macaroon.trim()).map_err(|_| MacaroonError::Hex)?;
+        Macaroon::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MacaroonError> {
+        let mut reader = Reader { bytes, position: 0 };
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(MacaroonError::UnsupportedVersion(version));
+        }
+
+        let mut fields = reader.section()?.into_iter();
+        let mut location = None;
+        let identifier = match fields.next() {
+            Some((LOCATION, value)) => {
+                location = Some(String::from_utf8_lossy(&value).into_owned());
+                match fields.next() {
+                    Some((IDENTIFIER, value)) => value,
+                    _ => return Err(MacaroonError::Truncated),
+                }
+            }
+            Some((IDENTIFIER, value)) => value,
+            Some((field, _)) => return Err(MacaroonError::UnexpectedField(field)),
+            None => return Err(MacaroonError::Truncated),
+        };
+
+        let mut caveats = vec![];
+        loop {
+            let section = reader.section()?;
+            if section.is_empty() {
+                break;
+            }
+            let mut caveat = Caveat {
+                location: None,
+                id: vec![],
+                verification_id: None,
+            };
+            for (field, value) in section {
+                match field {
+                    LOCATION => {
+                        caveat.location = Some(String::from_utf8_lossy(&value).into_owned())
+                    }
+                    IDENTIFIER => caveat.id = value,
+                    VERIFICATION_ID => caveat.verification_id = Some(value),
+                    _ => return Err(MacaroonError::UnexpectedField(field)),
+                }
+            }
+            caveats.push(caveat);
+        }
+
+        let (field, value) = reader.field()?.ok_or(MacaroonError::Truncated)?;
+        if field != SIGNATURE {
+            return Err(MacaroonError::UnexpectedField(field));
+        }
+        let signature = value.try_into().map_err(|_| MacaroonError::Truncated)?;
+        Ok(Macaroon {
+            location,
+            identifier,
+            caveats,
+            signature,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![VERSION];
+        if let Some(location) = &self.location {
+            write_field(&mut bytes, LOCATION, location.as_bytes());
+        }
+        write_field(&mut bytes, IDENTIFIER, &self.identifier);
+        bytes.push(EOS);
+        for caveat in &self.caveats {
+            if let Some(location) = &caveat.location {
+                write_field(&mut bytes, LOCATION, location.as_bytes());
+            }
+            write_field(&mut bytes, IDENTIFIER, &caveat.id);
+            if let Some(verification_id) = &caveat.verification_id {
+                write_field(&mut bytes, VERIFICATION_ID, verification_id);
+            }
+            bytes.push(EOS);
+        }
+        bytes.push(EOS);
+        write_field(&mut bytes, SIGNATURE, &self.signature);
+        bytes
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    pub fn signature(&self) -> [u8; 32] {
+        self.signature
+    }
+
+    pub fn caveats(&self) -> &[Caveat] {
+        &self.caveats
+    }
+
+    /// The conditions of the first-party caveats, e.g. `time-before 2023-04-01T00:00:00Z`
+    pub fn conditions(&self) -> Vec<String> {
+        self.caveats
+            .iter()
+            .filter(|caveat| caveat.is_first_party())
+            .map(|caveat| String::from_utf8_lossy(&caveat.id).into_owned())
+            .collect()
+    }
+
+    /// The permissions LND encoded in the identifier when it baked the macaroon
+    pub fn permissions(&self) -> Result<Vec<Permission>, MacaroonError> {
+        let (version, id) = self
+            .identifier
+            .split_first()
+            .ok_or(MacaroonError::InvalidIdentifier)?;
+        if *version != LND_ID_VERSION {
+            return Err(MacaroonError::InvalidIdentifier);
+        }
+        let id = MacaroonId::decode(id).map_err(|_| MacaroonError::InvalidIdentifier)?;
+        Ok(id
+            .ops
+            .iter()
+            .flat_map(|op| {
+                op.actions
+                    .iter()
+                    .map(|action| Permission::new(&op.entity, action))
+            })
+            .collect())
+    }
+
+    /// Derives a macaroon that only holds while `condition` does
+    pub fn add_first_party_caveat(&self, condition: &str) -> Macaroon {
+        let mut restricted = self.clone();
+        restricted.signature = hmac(&self.signature, condition.as_bytes());
+        restricted.caveats.push(Caveat {
+            location: None,
+            id: condition.as_bytes().to_vec(),
+            verification_id: None,
+        });
+        restricted
+    }
+
+    /// Derives a macaroon LND rejects once `timeout` has passed
+    pub fn with_timeout(&self, timeout: Duration) -> Macaroon {
+        let expiry = SystemTime::now() + timeout;
+        let seconds = expiry
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.add_first_party_caveat(&format!("time-before {}", rfc3339(seconds)))
+    }
+
+    /// Derives a macaroon LND only accepts from `ip`
+    pub fn with_ip_lock(&self, ip: IpAddr) -> Macaroon {
+        self.add_first_party_caveat(&format!("ipaddr {}", ip))
+    }
+
+    /// Derives a macaroon carrying a custom caveat, which LND hands to the interceptor that
+    /// registered `name`
+    pub fn with_custom_caveat(&self, name: &str, condition: &str) -> Macaroon {
+        self.add_first_party_caveat(&format!("lnd-custom {} {}", name, condition))
+    }
+}
+
+// the signature is the credential, keep it out of logs
+impl fmt::Debug for Macaroon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Macaroon")
+            .field("location", &self.location)
+            .field("permissions", &self.permissions().unwrap_or_default())
+            .field("conditions", &self.conditions())
+            .finish()
+    }
+}
+
+impl LndClient {
+    /// Asks the node for a new macaroon with only these permissions, root key 0 is the one LND's
+    /// own macaroons use
+    pub async fn bake_macaroon(
+        &mut self,
+        preset: &MacaroonPreset,
+        root_key_id: u64,
+    ) -> Result<Macaroon, Box<dyn std::error::Error>> {
+        let permissions = preset
+            .permissions()
+            .into_iter()
+            .map(|permission| MacaroonPermission {
+                entity: permission.entity,
+                action: permission.action,
+            })
+            .collect();
+        let response = self
+            .lightning
+            .bake_macaroon(BakeMacaroonRequest {
+                permissions,
+                root_key_id,
+                allow_external_permissions: false,
+            })
+            .await?;
+        Ok(Macaroon::from_hex(&response.into_inner().macaroon)?)
+    }
+}
+
+/// Shared, swappable macaroon the interceptors of one connection send
+///
+/// Clones point at the same credential, so rotating it through any of them applies to every
+/// client built from the interceptor, including calls already queued.
+#[derive(Clone)]
+pub struct MacaroonHandle {
+    macaroon: Arc<RwLock<String>>,
+}
+
+impl MacaroonHandle {
+    /// Creates a handle from the raw (not hex-encoded) macaroon
+    pub fn new(macaroon: &[u8]) -> Self {
+        MacaroonHandle {
+            macaroon: Arc::new(RwLock::new(hex::encode(macaroon))),
+        }
+    }
+
+    /// Replaces the macaroon for every following call
+    pub fn rotate(&self, macaroon: &[u8]) {
+        // a writer can't panic halfway through swapping a string, so a poisoned lock still holds
+        // a whole macaroon
+        let mut current = self
+            .macaroon
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *current = hex::encode(macaroon);
+    }
+
+    /// The current macaroon, hex-encoded as LND expects it in the `macaroon` metadata
+    pub fn hex(&self) -> String {
+        self.macaroon
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+impl fmt::Debug for MacaroonHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MacaroonHandle(..)")
+    }
+}
+
+/// Wraps `message` in a request sent with `macaroon` instead of the connection's macaroon
+///
+/// ```ignore
+/// let request = with_macaroon(AddInvoiceRequest::default(), &invoice_macaroon.to_bytes());
+/// client.lightning.add_invoice(request).await?;
+/// ```
+pub fn with_macaroon<T>(message: T, macaroon: &[u8]) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(
+        "macaroon",
+        #[allow(deprecated)]
+        tonic::metadata::MetadataValue::from_str(&hex::encode(macaroon))
+            .expect("hex produced non-ascii"),
+    );
+    request
+}
+
+#[test]
+fn macaroon_rotates_and_is_overridden_per_call() {
+    use tonic::service::Interceptor;
+
+    use crate::MacaroonInterceptor;
+
+    let sent = |interceptor: &mut MacaroonInterceptor, request: tonic::Request<()>| {
+        let request = interceptor.call(request).unwrap();
+        request.metadata().get("macaroon").unwrap().to_owned()
+    };
+    let mut interceptor = MacaroonInterceptor::new(&[0xad, 0x31]);
+    // every client of a connection holds a clone of the interceptor
+    let mut invoices = interceptor.clone();
+    assert_eq!(sent(&mut invoices, tonic::Request::new(())), "ad31");
+
+    interceptor.handle().rotate(&[0x0e]);
+    assert_eq!(sent(&mut invoices, tonic::Request::new(())), "0e");
+    assert_eq!(sent(&mut interceptor, with_macaroon((), &[0xad])), "ad");
+    assert_eq!(interceptor.handle().hex(), "0e");
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    engine.input(data);
+    Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+}
+
+fn write_field(bytes: &mut Vec<u8>, field: u8, value: &[u8]) {
+    bytes.push(field);
+    let mut length = value.len() as u64;
+    while length >= 0x80 {
+        bytes.push(length as u8 | 0x80);
+        length >>= 7;
+    }
+    bytes.push(length as u8);
+    bytes.extend_from_slice(value);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, MacaroonError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or(MacaroonError::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<usize, MacaroonError> {
+        let mut value = 0usize;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(MacaroonError::Truncated)
+    }
+
+    /// `None` at the end of a section
+    fn field(&mut self) -> Result<Option<(u8, Vec<u8>)>, MacaroonError> {
+        let field = self.byte()?;
+        if field == EOS {
+            return Ok(None);
+        }
+        let length = self.varint()?;
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(MacaroonError::Truncated)?;
+        let value = self.bytes[self.position..end].to_vec();
+        self.position = end;
+        Ok(Some((field, value)))
+    }
+
+    fn section(&mut self) -> Result<Vec<(u8, Vec<u8>)>, MacaroonError> {
+        let mut fields = vec![];
+        while let Some(field) = self.field()? {
+            fields.push(field);
+        }
+        Ok(fields)
+    }
+}
+
+/// Formats seconds since the epoch the way LND's `time-before` caveat parses them
+fn rfc3339(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+    // civil from days, days since 1970-01-01 to a proleptic gregorian date
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+#[test]
+fn macaroons_list_permissions_and_restrict_locally() {
+    use std::net::Ipv4Addr;
+
+    // lnd's identifier, a version byte and a MacaroonId with one invoices read/write op
+    let mut identifier = vec![3, 0x1a, 23, 0x0a, 8];
+    identifier.extend_from_slice(b"invoices");
+    identifier.extend_from_slice(&[0x12, 4]);
+    identifier.extend_from_slice(b"read");
+    identifier.extend_from_slice(&[0x12, 5]);
+    identifier.extend_from_slice(b"write");
+    let macaroon = Macaroon::new(b"root key", identifier, Some("lnd".to_owned()));
+    assert_eq!(
+        macaroon.permissions().unwrap(),
+        vec![
+            Permission::new("invoices", "read"),
+            Permission::new("invoices", "write")
+        ]
+    );
+
+    let restricted = macaroon
+        .with_timeout(Duration::from_secs(60))
+        .with_ip_lock(Ipv4Addr::new(10, 5, 0, 6).into())
+        .with_custom_caveat("swap", "id=7");
+    let conditions = restricted.conditions();
+    assert_eq!(conditions.len(), 3);
+    assert!(conditions[0].starts_with("time-before 20") && conditions[0].ends_with('Z'));
+    assert_eq!(conditions[1], "ipaddr 10.5.0.6");
+    assert_eq!(conditions[2], "lnd-custom swap id=7");
+    // every caveat chains the signature, so the node can check it with only the root key
+    let signature = conditions.iter().fold(macaroon.signature(), |key, caveat| {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&key);
+        engine.input(caveat.as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+    });
+    assert_eq!(restricted.signature(), signature);
+
+    let parsed = Macaroon::from_hex(&restricted.to_hex()).unwrap();
+    assert_eq!(parsed, restricted);
+    assert_eq!(parsed.permissions(), macaroon.permissions());
+    assert!(!format!("{:?}", parsed).contains(&hex::encode(signature)));
+    assert_eq!(
+        Macaroon::from_bytes(&restricted.to_bytes()[..20]),
+        Err(MacaroonError::Truncated)
+    );
+    assert!(MacaroonPreset::ReadOnly
+        .permissions()
+        .iter()
+        .all(|permission| permission.action == "read"));
+}
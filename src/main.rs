@@ -13,6 +13,7 @@ use crate::{
     bitcoin_wallet::{
         constants::{MINE, NETWORK, SEED},
         input_data::regtest_call::RegtestCall,
+        scripts::timelock::RelativeLock,
     },
     simple_wallet::{
         freelancer::{bisq, bisq_script::BisqScript, bisq_key::BisqKey},
@@ -106,7 +107,12 @@ fn test_pay_2_taproot_script() {
 
     let preimage = preimage(bob_image);
 
-    let output = create_address(alice_xonly, bob_xonly, preimage);
+    let output = create_address(
+        alice_xonly,
+        bob_xonly,
+        preimage.clone(),
+        RelativeLock::Blocks(144),
+    );
 
     let address = Address::from_script(&output.clone().witness_script.unwrap(), NETWORK).unwrap();
 
@@ -114,7 +120,13 @@ fn test_pay_2_taproot_script() {
 
     let bob_wallet = P2trs::new(bob_seed, bob_image, &client);
 
-    bob_wallet.sign(&output, single_output());
+    bob_wallet
+        .sign(
+            &output,
+            &bob_scripts(&bob_xonly, &preimage),
+            single_output(),
+        )
+        .unwrap();
 }
 
 
@@ -147,7 +159,6 @@ fn bisq_with_tr_script() {
         &regtestcall,
         BisqScript {
             output: output.clone(),
-            input: vec![],
         },
     );
 
@@ -158,7 +169,6 @@ fn bisq_with_tr_script() {
         &regtestcall,
         BisqScript {
             output: output.clone(),
-            input: host_psbt.inputs.clone(),
         },
     );
     let client_psbt = client_wallet.sign(&output, Some(host_psbt), single_output());
@@ -9,6 +9,7 @@ use bitcoin::{
     Address, Script, XOnlyPublicKey,
 };
 use miniscript::ScriptContext;
+use serde::{Deserialize, Serialize};
 use std::{borrow::BorrowMut, ops::Add, str::FromStr};
 
 use bitcoin::{
@@ -29,6 +30,11 @@ use bitcoin_hashes::{
 
 use crate::bitcoin_wallet::constants::{secp, NETWORK};
 use crate::bitcoin_wallet::input_data::RpcCall;
+use crate::bitcoin_wallet::schnorr::ecdh::{contract_keys, ContractKeys, EcdhError};
+use crate::bitcoin_wallet::scripts::{
+    multisig::{k_of_n_tapscript, MultisigError},
+    timelock::RelativeLock,
+};
 
 use super::{bisq_key, bisq_script, ISigner};
 // https://github.com/ElementsProject/elements-miniscript/blob/dc1f5ee748191086095a2c31284161a917174494/src/miniscript/astelem.rs
@@ -54,11 +60,72 @@ pub fn create_address(
     host: &XOnlyPublicKey,
     client: &XOnlyPublicKey,
     support_key: &XOnlyPublicKey,
+) -> Output {
+    return create_contract_address(host, client, support_key, support_key);
+}
+
+// same tree as create_address with a separate internal key, a NUMS key leaves only the leaves
+pub fn create_contract_address(
+    host: &XOnlyPublicKey,
+    client: &XOnlyPublicKey,
+    support_key: &XOnlyPublicKey,
+    internal_key: &XOnlyPublicKey,
+) -> Output {
+    return create_address_with_leaves(host, client, support_key, internal_key, &[]);
+}
+
+// leaves that can go into the tree next to the 2-of-2 and the support leaf
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExtraLeaf {
+    // host and client pre sign a refund that can only be mined once the output is this old, so
+    // the deposits come back even when the support team disappears
+    Refund(RelativeLock),
+    // the support key together with either host or client
+    Dispute,
+}
+
+// <sequence> OP_CSV OP_DROP <host> OP_CHECKSIG <client> OP_CHECKSIGADD 2 OP_NUMEQUAL
+pub fn unlock_refund(host: &XOnlyPublicKey, client: &XOnlyPublicKey, lock: RelativeLock) -> Script {
+    return Builder::new()
+        .push_int(lock.to_sequence().to_consensus_u32() as i64)
+        .push_opcode(all::OP_CSV)
+        .push_opcode(all::OP_DROP)
+        .push_x_only_key(host)
+        .push_opcode(OP_CHECKSIG)
+        .push_x_only_key(client)
+        .push_opcode(OP_CHECKSIGADD)
+        .push_int(2)
+        .push_opcode(OP_NUMEQUAL)
+        .into_script();
+}
+
+pub fn unlock_dispute(
+    host: &XOnlyPublicKey,
+    client: &XOnlyPublicKey,
+    support_key: &XOnlyPublicKey,
+) -> Result<Script, MultisigError> {
+    return k_of_n_tapscript(2, &[*host, *client, *support_key]);
+}
+
+pub fn create_address_with_leaves(
+    host: &XOnlyPublicKey,
+    client: &XOnlyPublicKey,
+    support_key: &XOnlyPublicKey,
+    internal_key: &XOnlyPublicKey,
+    extra_leaves: &[ExtraLeaf],
 ) -> Output {
     let bond_script = unlock_bond(host, client);
     let support_script = unlock_support(support_key);
 
-    let combined_scripts = vec![(1, bond_script.clone()), (1, support_script.clone())];
+    let mut combined_scripts = vec![(1, bond_script.clone()), (1, support_script.clone())];
+    extra_leaves.iter().for_each(|leaf| {
+        let script = match leaf {
+            ExtraLeaf::Refund(lock) => unlock_refund(host, client, *lock),
+            ExtraLeaf::Dispute => unlock_dispute(host, client, support_key)
+                .expect("host, client and support keys must be distinct"),
+        };
+        combined_scripts.push((1, script));
+    });
 
     let tap_tree =
         TapTree::try_from(TaprootBuilder::with_huffman_tree(combined_scripts).unwrap()).unwrap();
@@ -66,7 +133,7 @@ pub fn create_address(
     let tap_root_spend_info = tap_tree
         .clone()
         .into_builder()
-        .finalize(&secp(), *support_key)
+        .finalize(&secp(), *internal_key)
         .unwrap();
 
     let script = Script::new_v1_p2tr_tweaked(tap_root_spend_info.output_key());
@@ -74,11 +141,22 @@ pub fn create_address(
     let mut output = Output::default();
 
     output.tap_tree = Some(tap_tree);
-    output.tap_internal_key = Some(*support_key);
+    output.tap_internal_key = Some(*internal_key);
     output.witness_script = Some(script);
     return output;
 }
 
+// host and client keys for one trade, both traders derive them from their long lived keys and the
+// trade id, the support key stays as it is since the support team only learns about disputes
+pub fn trade_keys(
+    secret_key: &SecretKey,
+    counterparty: &XOnlyPublicKey,
+    trade_id: &[u8],
+) -> Result<ContractKeys, EcdhError> {
+    let context = [&b"bisq/trade"[..], trade_id].concat();
+    return contract_keys(&secp(), secret_key, counterparty, &context, 0);
+}
+
 pub struct Bisq<'a, R: RpcCall, I: ISigner> {
     secret_key: SecretKey,
     client: &'a R,
@@ -125,26 +203,29 @@ where
 
         let tx_out = send_to(total - self.client.fee());
 
-        let unsigned_tx = Transaction {
+        let mut unsigned_tx = Transaction {
             version: 2,
             lock_time: PackedLockTime(0),
             input: tx_in_list,
             output: tx_out,
         };
+        self.signer.prepare_tx(&mut unsigned_tx);
 
         let mut psbt = maybe_psbt.unwrap_or_else(|| {
             PartiallySignedTransaction::from_unsigned_tx(unsigned_tx.clone()).unwrap()
         });
 
-        psbt.inputs = self
-            .signer
-            .sign_all_unsigned_tx(&self.secret_key, &prevouts, &unsigned_tx);
+        self.signer
+            .sign_psbt(&self.secret_key, &mut psbt, &prevouts)
+            .unwrap();
 
         return psbt;
     }
 
-    pub fn finalize_script(&self, psbt: PartiallySignedTransaction)  {
-        I::finalize_tx(self.client, psbt);
+    pub fn finalize_script(&self, psbt: PartiallySignedTransaction) -> Transaction {
+        let tx = self.signer.finalize(psbt).unwrap();
+        self.client.broadcasts_transacton(&tx);
+        return tx;
     }
 }
 pub fn seed_to_xonly(secret_string: &Option<&str>) -> bitcoin::XOnlyPublicKey {
@@ -0,0 +1,867 @@
+use std::fmt;
+
+use bitcoin::{
+    psbt::{Output, PartiallySignedTransaction},
+    secp256k1::SecretKey,
+    PackedLockTime, Script, Transaction, TxOut, XOnlyPublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::bitcoin_wallet::{
+    constants::{nums_x_only, secp},
+    input_data::RpcCall,
+    script_services::tap_finalizer::UnsatisfiableInput,
+};
+
+use super::{
+    bisq::{create_address_with_leaves, ExtraLeaf},
+    bisq_dispute::BisqDispute,
+    bisq_refund::BisqRefund,
+    bisq_script::{create_script_message, BisqScript},
+    leaf_script, ISigner, SignerError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractError {
+    InvalidTransition {
+        state: &'static str,
+        action: &'static str,
+    },
+    Underfunded {
+        expected: u64,
+        found: u64,
+    },
+    // the escrow has no change output, whatever is locked on top of the terms would go to fees
+    Overfunded {
+        expected: u64,
+        found: u64,
+    },
+    UnknownKey,
+    AlreadySigned,
+    InvalidSplit,
+    MissingLeaf(&'static str),
+    RefundNotSigned,
+    UnexpectedPsbt,
+    MissingSignature(usize),
+    Signer(SignerError),
+    Unsatisfiable(UnsatisfiableInput),
+}
+
+impl fmt::Display for ContractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContractError::InvalidTransition { state, action } => {
+                write!(f, "cannot {} a contract that is {}", action, state)
+            }
+            ContractError::Underfunded { expected, found } => write!(
+                f,
+                "contract needs {} sats but only {} are locked",
+                expected, found
+            ),
+            ContractError::Overfunded { expected, found } => write!(
+                f,
+                "contract needs {} sats but {} are locked",
+                expected, found
+            ),
+            ContractError::UnknownKey => write!(f, "key is not a party to the contract"),
+            ContractError::AlreadySigned => write!(f, "the other trader has to co-sign"),
+            ContractError::InvalidSplit => {
+                write!(f, "split does not add up to the funding minus the fee")
+            }
+            ContractError::MissingLeaf(leaf) => write!(f, "contract has no {} leaf", leaf),
+            ContractError::RefundNotSigned => write!(f, "nobody signed the refund yet"),
+            ContractError::UnexpectedPsbt => {
+                write!(
+                    f,
+                    "psbt does not spend the escrow the way the contract says"
+                )
+            }
+            ContractError::MissingSignature(index) => {
+                write!(
+                    f,
+                    "input {} is not signed by the key the psbt came from",
+                    index
+                )
+            }
+            ContractError::Signer(err) => write!(f, "{}", err),
+            ContractError::Unsatisfiable(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ContractError {}
+
+impl From<SignerError> for ContractError {
+    fn from(err: SignerError) -> Self {
+        ContractError::Signer(err)
+    }
+}
+
+impl From<UnsatisfiableInput> for ContractError {
+    fn from(err: UnsatisfiableInput) -> Self {
+        ContractError::Unsatisfiable(err)
+    }
+}
+
+// what both sides agree on before anything is locked, the seller locks the trade amount and its
+// deposit, the buyer its deposit, the fee is the mining fee of the payout transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractTerms {
+    pub trade_amount: u64,
+    pub buyer_deposit: u64,
+    pub seller_deposit: u64,
+    pub fee: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Split {
+    pub buyer: u64,
+    pub seller: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Settlement {
+    // the buyer paid, it gets the trade amount and both get their deposits back
+    Payout,
+    // the trade is cancelled, everybody gets back what they locked
+    Refund,
+}
+
+// a psbt one party signed and the other has to continue from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Proposal {
+    Payout(Settlement),
+    Refund,
+    Ruling,
+}
+
+impl ContractTerms {
+    pub fn funding_amount(&self) -> u64 {
+        return self.trade_amount + self.buyer_deposit + self.seller_deposit;
+    }
+
+    // both sides pay half the fee, the seller the odd sat
+    pub fn split(&self, settlement: Settlement) -> Option<Split> {
+        let buyer_fee = self.fee / 2;
+        let seller_fee = self.fee - buyer_fee;
+        let (buyer, seller) = match settlement {
+            Settlement::Payout => (self.trade_amount + self.buyer_deposit, self.seller_deposit),
+            Settlement::Refund => (self.buyer_deposit, self.trade_amount + self.seller_deposit),
+        };
+        return Some(Split {
+            buyer: buyer.checked_sub(buyer_fee)?,
+            seller: seller.checked_sub(seller_fee)?,
+        });
+    }
+}
+
+pub struct Party {
+    pub key: XOnlyPublicKey,
+    pub payout: Script,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractState {
+    Offered,
+    Funded,
+    PayoutProposed {
+        settlement: Settlement,
+        proposer: XOnlyPublicKey,
+        psbt: PartiallySignedTransaction,
+    },
+    CoSigned(Transaction),
+    Disputed,
+    RulingProposed(PartiallySignedTransaction),
+    Arbitrated(Transaction),
+    Refunded(Transaction),
+}
+
+impl ContractState {
+    // states the funds can still leave the escrow from
+    pub fn is_open(&self) -> bool {
+        return matches!(
+            self,
+            ContractState::Funded
+                | ContractState::PayoutProposed { .. }
+                | ContractState::Disputed
+                | ContractState::RulingProposed(_)
+        );
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            ContractState::Offered => "offered",
+            ContractState::Funded => "funded",
+            ContractState::PayoutProposed { .. } => "payout proposed",
+            ContractState::CoSigned(_) => "co-signed",
+            ContractState::Disputed => "disputed",
+            ContractState::RulingProposed(_) => "ruling proposed",
+            ContractState::Arbitrated(_) => "arbitrated",
+            ContractState::Refunded(_) => "refunded",
+        };
+    }
+}
+
+// the escrow of one trade, the buyer and seller spend together through the 2-of-2 leaf and the
+// arbitrator alone through the support leaf, the internal key is NUMS so nobody has a key path
+pub struct BisqContract<'a, R: RpcCall> {
+    pub terms: ContractTerms,
+    buyer: Party,
+    seller: Party,
+    arbitrator: XOnlyPublicKey,
+    extra_leaves: Vec<ExtraLeaf>,
+    output: Output,
+    state: ContractState,
+    // the pre signed refund through the CSV leaf, kept aside until one side claims it
+    refund: Option<PartiallySignedTransaction>,
+    client: &'a R,
+}
+
+impl<'a, R> BisqContract<'a, R>
+where
+    R: RpcCall,
+{
+    pub fn new(
+        terms: ContractTerms,
+        buyer: Party,
+        seller: Party,
+        arbitrator: XOnlyPublicKey,
+        client: &'a R,
+    ) -> Self {
+        let output =
+            create_address_with_leaves(&seller.key, &buyer.key, &arbitrator, &nums_x_only(), &[]);
+        return BisqContract {
+            terms,
+            buyer,
+            seller,
+            arbitrator,
+            extra_leaves: vec![],
+            output,
+            state: ContractState::Offered,
+            refund: None,
+            client,
+        };
+    }
+
+    // the leaves change the address, so they have to be agreed on before anything is funded
+    pub fn with_leaves(mut self, extra_leaves: &[ExtraLeaf]) -> Self {
+        self.extra_leaves = extra_leaves.to_vec();
+        self.output = create_address_with_leaves(
+            &self.seller.key,
+            &self.buyer.key,
+            &self.arbitrator,
+            &nums_x_only(),
+            extra_leaves,
+        );
+        return self;
+    }
+
+    pub fn output(&self) -> &Output {
+        return &self.output;
+    }
+
+    pub fn script_pubkey(&self) -> Script {
+        return self.output.witness_script.clone().unwrap();
+    }
+
+    pub fn state(&self) -> &ContractState {
+        return &self.state;
+    }
+
+    // offered -> funded, once exactly both deposits and the trade amount sit in the escrow output
+    pub fn fund(&mut self) -> Result<(), ContractError> {
+        self.expect("fund", |state| matches!(state, ContractState::Offered))?;
+        let found = self.funded();
+        let expected = self.terms.funding_amount();
+        if found < expected {
+            return Err(ContractError::Underfunded { expected, found });
+        }
+        if found > expected {
+            return Err(ContractError::Overfunded { expected, found });
+        }
+        self.state = ContractState::Funded;
+        return Ok(());
+    }
+
+    // funded -> payout proposed, the buyer or the seller signs its side of the 2-of-2 leaf
+    pub fn propose(
+        &mut self,
+        secret_key: &SecretKey,
+        settlement: Settlement,
+    ) -> Result<PartiallySignedTransaction, ContractError> {
+        self.expect("propose a payout for", |state| {
+            matches!(state, ContractState::Funded)
+        })?;
+        let proposer = self.trader(secret_key)?;
+        let split = self
+            .terms
+            .split(settlement)
+            .ok_or(ContractError::InvalidSplit)?;
+        let signer = self.script_signer();
+        let psbt = self.sign(&signer, secret_key, self.unsigned_psbt(&signer, split))?;
+        self.state = ContractState::PayoutProposed {
+            settlement,
+            proposer,
+            psbt: psbt.clone(),
+        };
+        return Ok(psbt);
+    }
+
+    // payout proposed -> co-signed or refunded, the other trader adds its signature and the
+    // spend gets broadcast
+    pub fn co_sign(&mut self, secret_key: &SecretKey) -> Result<Transaction, ContractError> {
+        let (settlement, proposer, psbt) = match &self.state {
+            ContractState::PayoutProposed {
+                settlement,
+                proposer,
+                psbt,
+            } => (*settlement, *proposer, psbt.clone()),
+            state => {
+                return Err(ContractError::InvalidTransition {
+                    state: state.name(),
+                    action: "co-sign",
+                })
+            }
+        };
+        if self.trader(secret_key)? == proposer {
+            return Err(ContractError::AlreadySigned);
+        }
+        let signer = self.script_signer();
+        let psbt = self.sign(&signer, secret_key, psbt)?;
+        let tx = signer.finalize(psbt)?;
+        self.client.broadcasts_transacton(&tx);
+        self.state = match settlement {
+            Settlement::Payout => ContractState::CoSigned(tx.clone()),
+            Settlement::Refund => ContractState::Refunded(tx.clone()),
+        };
+        return Ok(tx);
+    }
+
+    // funded or payout proposed -> disputed, either trader can stop cooperating
+    pub fn dispute(&mut self) -> Result<(), ContractError> {
+        self.expect("dispute", |state| {
+            matches!(
+                state,
+                ContractState::Funded | ContractState::PayoutProposed { .. }
+            )
+        })?;
+        self.state = ContractState::Disputed;
+        return Ok(());
+    }
+
+    // disputed -> arbitrated, the arbitrator decides the split and spends through its own leaf
+    pub fn arbitrate(
+        &mut self,
+        secret_key: &SecretKey,
+        split: Split,
+    ) -> Result<Transaction, ContractError> {
+        self.expect("arbitrate", |state| {
+            matches!(state, ContractState::Disputed)
+        })?;
+        self.arbitrator(secret_key)?;
+        self.check_split(split)?;
+        let signer = self.script_signer();
+        let psbt = self.sign(&signer, secret_key, self.unsigned_psbt(&signer, split))?;
+        let tx = signer.finalize(psbt)?;
+        self.client.broadcasts_transacton(&tx);
+        self.state = ContractState::Arbitrated(tx.clone());
+        return Ok(tx);
+    }
+
+    // disputed -> ruling proposed, with the 2-of-3 leaf the arbitrator only proposes the split
+    // and one of the traders has to sign it as well
+    pub fn rule(
+        &mut self,
+        secret_key: &SecretKey,
+        split: Split,
+    ) -> Result<PartiallySignedTransaction, ContractError> {
+        self.expect("rule on", |state| matches!(state, ContractState::Disputed))?;
+        if !self.extra_leaves.contains(&ExtraLeaf::Dispute) {
+            return Err(ContractError::MissingLeaf("dispute"));
+        }
+        self.arbitrator(secret_key)?;
+        self.check_split(split)?;
+        let signer = self.dispute_signer();
+        let psbt = self.sign(&signer, secret_key, self.unsigned_psbt(&signer, split))?;
+        self.state = ContractState::RulingProposed(psbt.clone());
+        return Ok(psbt);
+    }
+
+    // ruling proposed -> arbitrated
+    pub fn accept_ruling(&mut self, secret_key: &SecretKey) -> Result<Transaction, ContractError> {
+        let psbt = match &self.state {
+            ContractState::RulingProposed(psbt) => psbt.clone(),
+            state => {
+                return Err(ContractError::InvalidTransition {
+                    state: state.name(),
+                    action: "accept the ruling of",
+                })
+            }
+        };
+        self.trader(secret_key)?;
+        let signer = self.dispute_signer();
+        let psbt = self.sign(&signer, secret_key, psbt)?;
+        let tx = signer.finalize(psbt)?;
+        self.client.broadcasts_transacton(&tx);
+        self.state = ContractState::Arbitrated(tx.clone());
+        return Ok(tx);
+    }
+
+    // both traders sign the refund while they still cooperate, before a payout is agreed on
+    pub fn sign_refund(
+        &mut self,
+        secret_key: &SecretKey,
+    ) -> Result<PartiallySignedTransaction, ContractError> {
+        self.expect("sign a refund for", ContractState::is_open)?;
+        self.trader(secret_key)?;
+        let signer = self.refund_signer()?;
+        let psbt = match self.refund.take() {
+            Some(psbt) => psbt,
+            None => {
+                let split = self
+                    .terms
+                    .split(Settlement::Refund)
+                    .ok_or(ContractError::InvalidSplit)?;
+                self.unsigned_psbt(&signer, split)
+            }
+        };
+        let psbt = self.sign(&signer, secret_key, psbt)?;
+        self.refund = Some(psbt.clone());
+        return Ok(psbt);
+    }
+
+    // any open state -> refunded, once the CSV has passed either side can broadcast the refund
+    pub fn claim_refund(&mut self) -> Result<Transaction, ContractError> {
+        self.expect("claim the refund of", ContractState::is_open)?;
+        let signer = self.refund_signer()?;
+        let psbt = self.refund.clone().ok_or(ContractError::RefundNotSigned)?;
+        let tx = signer.finalize(psbt)?;
+        self.client.broadcasts_transacton(&tx);
+        self.state = ContractState::Refunded(tx.clone());
+        return Ok(tx);
+    }
+
+    // a psbt the other party signed in its own process, it has to spend the escrow exactly the
+    // way the contract says before the state moves on as if it had been signed here
+    pub fn import(
+        &mut self,
+        proposal: Proposal,
+        signer: &XOnlyPublicKey,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<(), ContractError> {
+        match proposal {
+            Proposal::Payout(settlement) => {
+                self.expect("propose a payout for", |state| {
+                    matches!(state, ContractState::Funded)
+                })?;
+                self.is_trader(signer)?;
+                let split = self
+                    .terms
+                    .split(settlement)
+                    .ok_or(ContractError::InvalidSplit)?;
+                let expected = self.unsigned_psbt(&self.script_signer(), split);
+                if psbt.unsigned_tx != expected.unsigned_tx {
+                    return Err(ContractError::UnexpectedPsbt);
+                }
+                self.check_signed(&psbt, signer)?;
+                self.state = ContractState::PayoutProposed {
+                    settlement,
+                    proposer: *signer,
+                    psbt,
+                };
+            }
+            Proposal::Refund => {
+                self.expect("sign a refund for", ContractState::is_open)?;
+                self.is_trader(signer)?;
+                let refund_signer = self.refund_signer()?;
+                let split = self
+                    .terms
+                    .split(Settlement::Refund)
+                    .ok_or(ContractError::InvalidSplit)?;
+                let mut refund = self
+                    .refund
+                    .clone()
+                    .unwrap_or_else(|| self.unsigned_psbt(&refund_signer, split));
+                if psbt.unsigned_tx != refund.unsigned_tx {
+                    return Err(ContractError::UnexpectedPsbt);
+                }
+                self.check_signed(&psbt, signer)?;
+                // combine checks the unsigned transactions match and keeps both signatures
+                refund
+                    .combine(psbt)
+                    .map_err(|_| ContractError::UnexpectedPsbt)?;
+                self.refund = Some(refund);
+            }
+            Proposal::Ruling => {
+                self.expect("rule on", |state| matches!(state, ContractState::Disputed))?;
+                if *signer != self.arbitrator {
+                    return Err(ContractError::UnknownKey);
+                }
+                if !self.extra_leaves.contains(&ExtraLeaf::Dispute) {
+                    return Err(ContractError::MissingLeaf("dispute"));
+                }
+                let paid = |script_pubkey: &Script| {
+                    return psbt
+                        .unsigned_tx
+                        .output
+                        .iter()
+                        .filter(|tx_out| tx_out.script_pubkey.eq(script_pubkey))
+                        .map(|tx_out| tx_out.value)
+                        .sum::<u64>();
+                };
+                let split = Split {
+                    buyer: paid(&self.buyer.payout),
+                    seller: paid(&self.seller.payout),
+                };
+                self.check_split(split)?;
+                let expected = self.unsigned_psbt(&self.dispute_signer(), split);
+                if psbt.unsigned_tx != expected.unsigned_tx {
+                    return Err(ContractError::UnexpectedPsbt);
+                }
+                self.check_signed(&psbt, signer)?;
+                self.state = ContractState::RulingProposed(psbt);
+            }
+        }
+        return Ok(());
+    }
+
+    fn expect(
+        &self,
+        action: &'static str,
+        allowed: impl Fn(&ContractState) -> bool,
+    ) -> Result<(), ContractError> {
+        if !allowed(&self.state) {
+            return Err(ContractError::InvalidTransition {
+                state: self.state.name(),
+                action,
+            });
+        }
+        return Ok(());
+    }
+
+    fn arbitrator(&self, secret_key: &SecretKey) -> Result<(), ContractError> {
+        if secret_key.x_only_public_key(&secp()).0 != self.arbitrator {
+            return Err(ContractError::UnknownKey);
+        }
+        return Ok(());
+    }
+
+    // against what is actually locked, the terms only say what should have been
+    fn check_split(&self, split: Split) -> Result<(), ContractError> {
+        let total = split
+            .buyer
+            .checked_add(split.seller)
+            .and_then(|total| total.checked_add(self.terms.fee));
+        if total != Some(self.funded()) {
+            return Err(ContractError::InvalidSplit);
+        }
+        return Ok(());
+    }
+
+    // every input carries a signature of signer over one of the escrow's leaves, so a psbt
+    // can't move the state on before the party it claims to come from signed it
+    fn check_signed(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        signer: &XOnlyPublicKey,
+    ) -> Result<(), ContractError> {
+        let prevouts = self.prevouts();
+        if prevouts.len() != psbt.inputs.len() {
+            return Err(ContractError::UnexpectedPsbt);
+        }
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            let signed = input
+                .tap_script_sigs
+                .iter()
+                .filter(|((x_only, _), _)| x_only == signer)
+                .any(|((_, leaf_hash), sig)| {
+                    let script = match leaf_script(&self.output, leaf_hash) {
+                        Ok(script) => script,
+                        Err(_) => return false,
+                    };
+                    let message =
+                        create_script_message(index, &psbt.unsigned_tx, &prevouts, &script);
+                    return secp().verify_schnorr(&sig.sig, &message, signer).is_ok();
+                });
+            if !signed {
+                return Err(ContractError::MissingSignature(index));
+            }
+        }
+        return Ok(());
+    }
+
+    fn script_signer(&self) -> BisqScript {
+        return BisqScript {
+            output: self.output.clone(),
+        };
+    }
+
+    fn dispute_signer(&self) -> BisqDispute {
+        return BisqDispute {
+            output: self.output.clone(),
+        };
+    }
+
+    fn refund_signer(&self) -> Result<BisqRefund, ContractError> {
+        return self
+            .extra_leaves
+            .iter()
+            .find_map(|leaf| match leaf {
+                ExtraLeaf::Refund(lock) => Some(BisqRefund {
+                    output: self.output.clone(),
+                    lock: *lock,
+                }),
+                _ => None,
+            })
+            .ok_or(ContractError::MissingLeaf("refund"));
+    }
+
+    fn trader(&self, secret_key: &SecretKey) -> Result<XOnlyPublicKey, ContractError> {
+        let x_only = secret_key.x_only_public_key(&secp()).0;
+        self.is_trader(&x_only)?;
+        return Ok(x_only);
+    }
+
+    fn is_trader(&self, x_only: &XOnlyPublicKey) -> Result<(), ContractError> {
+        if *x_only != self.buyer.key && *x_only != self.seller.key {
+            return Err(ContractError::UnknownKey);
+        }
+        return Ok(());
+    }
+
+    fn funded(&self) -> u64 {
+        return self.prevouts().iter().map(|tx_out| tx_out.value).sum();
+    }
+
+    fn prevouts(&self) -> Vec<TxOut> {
+        let script_pubkey = self.script_pubkey();
+        return self
+            .client
+            .contract_source()
+            .iter()
+            .flat_map(|tx| tx.output.clone())
+            .filter(|tx_out| tx_out.script_pubkey.eq(&script_pubkey))
+            .collect();
+    }
+
+    // a side that ends up with nothing gets no output
+    fn unsigned_psbt<I: ISigner>(&self, signer: &I, split: Split) -> PartiallySignedTransaction {
+        let output = [
+            (split.buyer, &self.buyer.payout),
+            (split.seller, &self.seller.payout),
+        ]
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, script_pubkey)| TxOut {
+            value: *value,
+            script_pubkey: (*script_pubkey).clone(),
+        })
+        .collect();
+        let mut unsigned_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: self.client.prev_input(),
+            output,
+        };
+        signer.prepare_tx(&mut unsigned_tx);
+        return PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+    }
+
+    fn sign<I: ISigner>(
+        &self,
+        signer: &I,
+        secret_key: &SecretKey,
+        mut psbt: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, ContractError> {
+        signer.sign_psbt(secret_key, &mut psbt, &self.prevouts())?;
+        return Ok(psbt);
+    }
+}
+
+#[test]
+fn cooperative_payout_and_refund_follow_the_terms() {
+    use super::bisq::create_contract_address;
+    use crate::bitcoin_wallet::input_data::mock_call::MockCall;
+
+    let buyer_secret = SecretKey::from_slice(&[31u8; 32]).unwrap();
+    let seller_secret = SecretKey::from_slice(&[32u8; 32]).unwrap();
+    let arbitrator = SecretKey::from_slice(&[33u8; 32]).unwrap();
+    let party = |secret: &SecretKey| {
+        let key = secret.x_only_public_key(&secp()).0;
+        return Party {
+            key,
+            payout: Script::new_v1_p2tr(&secp(), key, None),
+        };
+    };
+    let terms = ContractTerms {
+        trade_amount: 100_000,
+        buyer_deposit: 15_000,
+        seller_deposit: 15_000,
+        fee: 2_001,
+    };
+    let json = serde_json::to_string(&terms).unwrap();
+    assert_eq!(serde_json::from_str::<ContractTerms>(&json).unwrap(), terms);
+
+    let arbitrator_key = arbitrator.x_only_public_key(&secp()).0;
+    let escrow = create_contract_address(
+        &party(&seller_secret).key,
+        &party(&buyer_secret).key,
+        &arbitrator_key,
+        &nums_x_only(),
+    )
+    .witness_script
+    .unwrap();
+
+    [Settlement::Payout, Settlement::Refund]
+        .iter()
+        .for_each(|settlement| {
+            let client = MockCall::fund(&escrow, &[80_000, 50_000]);
+            let mut contract = BisqContract::new(
+                terms,
+                party(&buyer_secret),
+                party(&seller_secret),
+                arbitrator_key,
+                &client,
+            );
+            assert_eq!(
+                contract.co_sign(&buyer_secret),
+                Err(ContractError::InvalidTransition {
+                    state: "offered",
+                    action: "co-sign"
+                })
+            );
+            contract.fund().unwrap();
+            assert_eq!(
+                contract.propose(&arbitrator, *settlement),
+                Err(ContractError::UnknownKey)
+            );
+
+            // an imported proposal has to carry the signature of the trader it names
+            let split = terms.split(*settlement).unwrap();
+            let unsigned = contract.unsigned_psbt(&contract.script_signer(), split);
+            let seller_key = party(&seller_secret).key;
+            assert_eq!(
+                contract.import(Proposal::Payout(*settlement), &seller_key, unsigned.clone()),
+                Err(ContractError::MissingSignature(0))
+            );
+            let signed_by_buyer = contract
+                .sign(&contract.script_signer(), &buyer_secret, unsigned)
+                .unwrap();
+            assert_eq!(
+                contract.import(Proposal::Payout(*settlement), &seller_key, signed_by_buyer),
+                Err(ContractError::MissingSignature(0))
+            );
+
+            contract.propose(&seller_secret, *settlement).unwrap();
+            assert_eq!(
+                contract.co_sign(&seller_secret),
+                Err(ContractError::AlreadySigned)
+            );
+            let tx = contract.co_sign(&buyer_secret).unwrap();
+
+            assert_eq!(tx.output[0].value, split.buyer);
+            assert_eq!(tx.output[1].value, split.seller);
+            assert_eq!(
+                tx.output.iter().map(|tx_out| tx_out.value).sum::<u64>() + terms.fee,
+                terms.funding_amount()
+            );
+            assert_eq!(client.last_broadcast(), Some(tx.clone()));
+            let expected = match settlement {
+                Settlement::Payout => ContractState::CoSigned(tx),
+                Settlement::Refund => ContractState::Refunded(tx),
+            };
+            assert_eq!(contract.state(), &expected);
+        });
+}
+
+#[test]
+fn arbitrator_settles_a_disputed_contract() {
+    use super::bisq::create_contract_address;
+    use crate::bitcoin_wallet::input_data::mock_call::MockCall;
+
+    let buyer = SecretKey::from_slice(&[34u8; 32]).unwrap();
+    let seller = SecretKey::from_slice(&[35u8; 32]).unwrap();
+    let arbitrator = SecretKey::from_slice(&[36u8; 32]).unwrap();
+    let party = |secret: &SecretKey| {
+        let key = secret.x_only_public_key(&secp()).0;
+        return Party {
+            key,
+            payout: Script::new_v1_p2tr(&secp(), key, None),
+        };
+    };
+    let terms = ContractTerms {
+        trade_amount: 60_000,
+        buyer_deposit: 10_000,
+        seller_deposit: 10_000,
+        fee: 1_000,
+    };
+    let arbitrator_key = arbitrator.x_only_public_key(&secp()).0;
+    let escrow = create_contract_address(
+        &party(&seller).key,
+        &party(&buyer).key,
+        &arbitrator_key,
+        &nums_x_only(),
+    )
+    .witness_script
+    .unwrap();
+
+    let underfunded = MockCall::fund(&escrow, &[50_000]);
+    let mut contract = BisqContract::new(
+        terms,
+        party(&buyer),
+        party(&seller),
+        arbitrator_key,
+        &underfunded,
+    );
+    assert_eq!(
+        contract.fund(),
+        Err(ContractError::Underfunded {
+            expected: 80_000,
+            found: 50_000
+        })
+    );
+
+    let overfunded = MockCall::fund(&escrow, &[80_000, 1_000]);
+    let mut contract = BisqContract::new(
+        terms,
+        party(&buyer),
+        party(&seller),
+        arbitrator_key,
+        &overfunded,
+    );
+    assert_eq!(
+        contract.fund(),
+        Err(ContractError::Overfunded {
+            expected: 80_000,
+            found: 81_000
+        })
+    );
+
+    let client = MockCall::fund(&escrow, &[80_000]);
+    let mut contract = BisqContract::new(
+        terms,
+        party(&buyer),
+        party(&seller),
+        arbitrator_key,
+        &client,
+    );
+    contract.fund().unwrap();
+    contract.propose(&buyer, Settlement::Payout).unwrap();
+    contract.dispute().unwrap();
+
+    let split = Split {
+        buyer: 0,
+        seller: 79_000,
+    };
+    assert_eq!(
+        contract.arbitrate(&arbitrator, Split { buyer: 1, ..split }),
+        Err(ContractError::InvalidSplit)
+    );
+    let tx = contract.arbitrate(&arbitrator, split).unwrap();
+    // the buyer gets nothing so only the seller's output is left
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(tx.output[0].value, 79_000);
+    // a single signature for the support leaf, then the leaf and its control block
+    assert_eq!(tx.input[0].witness.len(), 3);
+    assert_eq!(contract.state(), &ContractState::Arbitrated(tx));
+}
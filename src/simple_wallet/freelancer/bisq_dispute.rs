@@ -0,0 +1,101 @@
+use bitcoin::{psbt::Output, secp256k1::SecretKey};
+
+use crate::bitcoin_wallet::{constants::secp, scripts::multisig::parse_k_of_n};
+
+use super::{bisq_script::contains_key, leaf_paths, ISigner, TrType};
+
+// the support team and one of host or client sign the 2-of-3 leaf, so a ruling needs one of the
+// traders to agree with it
+pub struct BisqDispute {
+    pub output: Output,
+}
+
+impl ISigner for BisqDispute {
+    fn output(&self) -> &Output {
+        return &self.output;
+    }
+
+    fn paths(&self, secret_key: &SecretKey) -> Vec<TrType> {
+        let x_only = secret_key.x_only_public_key(&secp()).0;
+        return leaf_paths(&self.output, |script| {
+            contains_key(script, &x_only)
+                && matches!(parse_k_of_n(script, 32), Some((2, keys)) if keys.len() == 3)
+        });
+    }
+}
+
+#[test]
+fn ruling_needs_the_arbitrator_and_one_trader() {
+    use bitcoin::Script;
+
+    use super::{
+        bisq::{create_address_with_leaves, ExtraLeaf},
+        bisq_contract::{BisqContract, ContractError, ContractState, ContractTerms, Party, Split},
+    };
+    use crate::bitcoin_wallet::{
+        constants::nums_x_only, input_data::mock_call::MockCall,
+        script_services::tap_finalizer::finalize_tap_psbt,
+    };
+
+    let buyer = SecretKey::from_slice(&[44u8; 32]).unwrap();
+    let seller = SecretKey::from_slice(&[45u8; 32]).unwrap();
+    let arbitrator = SecretKey::from_slice(&[46u8; 32]).unwrap();
+    let x_only = |secret: &SecretKey| secret.x_only_public_key(&secp()).0;
+    let party = |secret: &SecretKey| Party {
+        key: x_only(secret),
+        payout: Script::new_v1_p2tr(&secp(), x_only(secret), None),
+    };
+    let terms = ContractTerms {
+        trade_amount: 30_000,
+        buyer_deposit: 5_000,
+        seller_deposit: 5_000,
+        fee: 500,
+    };
+    let escrow = create_address_with_leaves(
+        &x_only(&seller),
+        &x_only(&buyer),
+        &x_only(&arbitrator),
+        &nums_x_only(),
+        &[ExtraLeaf::Dispute],
+    )
+    .witness_script
+    .unwrap();
+    let client = MockCall::fund(&escrow, &[40_000]);
+    let mut contract = BisqContract::new(
+        terms,
+        party(&buyer),
+        party(&seller),
+        x_only(&arbitrator),
+        &client,
+    )
+    .with_leaves(&[ExtraLeaf::Dispute]);
+    contract.fund().unwrap();
+    contract.dispute().unwrap();
+
+    let split = Split {
+        buyer: 34_500,
+        seller: 5_000,
+    };
+    let ruling = contract.rule(&arbitrator, split).unwrap();
+    // the arbitrator alone can't finalize the 2-of-3 leaf
+    assert!(finalize_tap_psbt(ruling).is_err());
+    assert_eq!(
+        contract.rule(&arbitrator, split).unwrap_err(),
+        ContractError::InvalidTransition {
+            state: "ruling proposed",
+            action: "rule on"
+        }
+    );
+
+    let tx = contract.accept_ruling(&buyer).unwrap();
+    assert_eq!(tx.output[0].value, 34_500);
+    // one empty placeholder for the seller next to the two signatures
+    let witness = tx.input[0].witness.to_vec();
+    assert_eq!(witness.len(), 5);
+    assert_eq!(
+        witness[..3].iter().filter(|item| item.is_empty()).count(),
+        1
+    );
+    assert_eq!(client.last_broadcast(), Some(tx.clone()));
+    assert_eq!(contract.state(), &ContractState::Arbitrated(tx));
+}
@@ -1,13 +1,12 @@
 use bitcoin::{
-    psbt::{Input, Output, PartiallySignedTransaction, Prevouts},
+    psbt::{Input, Output, Prevouts},
     schnorr::TapTweak,
     secp256k1::{Message, SecretKey},
     util::sighash::SighashCache,
-    SchnorrSig, Transaction, TxOut, Script,
+    SchnorrSig, Transaction, TxOut,
 };
-use miniscript::psbt::PsbtExt;
 
-use crate::bitcoin_wallet::{constants::secp, input_data::RpcCall};
+use crate::bitcoin_wallet::constants::secp;
 
 use super::{ISigner, TrType};
 
@@ -15,37 +14,18 @@ pub struct BisqKey {
     pub output: Output,
 }
 
+// the support team spending alone through the key path of create_address
 impl ISigner for BisqKey {
-    fn sign_all_unsigned_tx(
-        &self,
-        secret_key: &SecretKey,
-        prevouts: &Vec<TxOut>,
-        unsigned_tx: &Transaction,
-    ) -> Vec<Input> {
-        return prevouts
-            .iter()
-            .enumerate()
-            .map(|(index, tx_out)| {
-                let message = create_message(index, unsigned_tx, &prevouts);
-                return sign_tx(
-                    secret_key,
-                    tx_out,
-                    &Input::default(),
-                    &message,
-                    &self.output,
-                );
-            })
-            .collect();
+    fn output(&self) -> &Output {
+        return &self.output;
     }
 
-    fn finalize_tx<R: RpcCall>(rpc_call: &R, psbt: PartiallySignedTransaction) -> Transaction {
-        let tx = psbt.finalize(&secp()).unwrap().extract_tx();
-        rpc_call.broadcasts_transacton(&tx);
-        return tx;
+    fn paths(&self, _secret_key: &SecretKey) -> Vec<TrType> {
+        return vec![TrType::Key];
     }
 }
 
-pub fn create_message(index: usize, unsigned_tx: &Transaction, prevouts: &Vec<TxOut>) -> Message {
+pub fn create_message(index: usize, unsigned_tx: &Transaction, prevouts: &[TxOut]) -> Message {
     let sighash = SighashCache::new(&mut unsigned_tx.clone())
         .taproot_key_spend_signature_hash(
             index,
@@ -57,7 +37,7 @@ pub fn create_message(index: usize, unsigned_tx: &Transaction, prevouts: &Vec<Tx
     return message;
 }
 
-fn sign_tx(
+pub fn sign_tx(
     secret_key: &SecretKey,
     tx_out: &TxOut,
     input: &Input,
@@ -0,0 +1,591 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bitcoin::{
+    psbt::{Output, PartiallySignedTransaction},
+    secp256k1::{rand, schnorr::Signature, Message, SecretKey},
+    Script, XOnlyPublicKey,
+};
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::bitcoin_wallet::{
+    constants::{nums_x_only, secp},
+    input_data::RpcCall,
+    schnorr::tagged_hash,
+    script_services::psbt_service::{deserialize_psbt, serialize_psbt, PsbtFormat, PsbtIoError},
+};
+
+use super::{
+    bisq::{create_address_with_leaves, ExtraLeaf},
+    bisq_contract::{BisqContract, ContractError, ContractTerms, Party, Proposal},
+};
+
+// bumped whenever a message changes shape, parties refuse versions they don't know
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum OfferError {
+    Version(u32),
+    Json(serde_json::Error),
+    Encoding(&'static str),
+    Psbt(PsbtIoError),
+    InvalidSignature,
+    WrongSigner,
+    OfferMismatch,
+    Expired(u64),
+    TreeMismatch,
+    // the escrow has to hold exactly the funding amount, anything above it would go to fees
+    FundingMismatch { expected: u64, found: u64 },
+    Contract(ContractError),
+}
+
+impl fmt::Display for OfferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OfferError::Version(version) => write!(f, "unsupported message version {}", version),
+            OfferError::Json(err) => write!(f, "invalid message json: {}", err),
+            OfferError::Encoding(field) => write!(f, "invalid encoding of {}", field),
+            OfferError::Psbt(err) => write!(f, "invalid psbt in message: {}", err),
+            OfferError::InvalidSignature => write!(f, "message signature does not verify"),
+            OfferError::WrongSigner => write!(f, "message is not signed by the expected party"),
+            OfferError::OfferMismatch => write!(f, "message refers to a different offer"),
+            OfferError::Expired(expires_at) => write!(f, "offer expired at {}", expires_at),
+            OfferError::TreeMismatch => write!(f, "taproot tree does not match the offer"),
+            OfferError::FundingMismatch { expected, found } => write!(
+                f,
+                "funding psbt locks {} sats in the escrow instead of {}",
+                found, expected
+            ),
+            OfferError::Contract(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for OfferError {}
+
+impl From<serde_json::Error> for OfferError {
+    fn from(err: serde_json::Error) -> Self {
+        OfferError::Json(err)
+    }
+}
+
+impl From<PsbtIoError> for OfferError {
+    fn from(err: PsbtIoError) -> Self {
+        OfferError::Psbt(err)
+    }
+}
+
+impl From<ContractError> for OfferError {
+    fn from(err: ContractError) -> Self {
+        OfferError::Contract(err)
+    }
+}
+
+// every message body has its own tag so a signature over one kind can't be replayed as another
+pub trait BisqMessage: Serialize + DeserializeOwned {
+    const TAG: &'static str;
+    fn version(&self) -> u32;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    Buyer,
+    Seller,
+}
+
+// keys are hex x-only keys, scripts hex and psbts base64, so any language can read them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Offer {
+    pub version: u32,
+    // random, so two offers with the same terms still get different ids
+    pub nonce: String,
+    // unix seconds, nobody can take the offer after that
+    pub expires_at: u64,
+    pub role: Role,
+    pub maker: String,
+    pub maker_payout: String,
+    pub arbitrator: String,
+    pub terms: ContractTerms,
+    pub extra_leaves: Vec<ExtraLeaf>,
+}
+
+// the taker knows both keys, so it sends the whole tree and the psbt funding the escrow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acceptance {
+    pub version: u32,
+    pub offer_id: String,
+    pub taker: String,
+    pub taker_payout: String,
+    // (depth, leaf script) in the order TapTree::script_leaves walks them
+    pub tap_tree: Vec<(u8, String)>,
+    pub funding_psbt: String,
+}
+
+// a payout, refund or ruling psbt handed from one party to the next
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtMessage {
+    pub version: u32,
+    pub offer_id: String,
+    pub proposal: Proposal,
+    pub psbt: String,
+}
+
+impl BisqMessage for Offer {
+    const TAG: &'static str = "bisq/offer";
+    fn version(&self) -> u32 {
+        return self.version;
+    }
+}
+
+impl BisqMessage for Acceptance {
+    const TAG: &'static str = "bisq/acceptance";
+    fn version(&self) -> u32 {
+        return self.version;
+    }
+}
+
+impl BisqMessage for PsbtMessage {
+    const TAG: &'static str = "bisq/psbt";
+    fn version(&self) -> u32 {
+        return self.version;
+    }
+}
+
+// a BIP340 signature over the tagged hash of the body's json, the json travels as the exact
+// string that was signed so nobody has to reproduce the signer's serialization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signed<T: BisqMessage> {
+    body: T,
+    payload: String,
+    signer: String,
+    signature: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedJson {
+    payload: String,
+    signer: String,
+    signature: String,
+}
+
+impl<T: BisqMessage> Signed<T> {
+    pub fn sign(body: T, secret_key: &SecretKey) -> Result<Self, OfferError> {
+        let payload = serde_json::to_string(&body)?;
+        let digest = Self::digest(&payload);
+        let key_pair = secret_key.keypair(&secp());
+        let signature = secp().sign_schnorr(&Message::from_slice(&digest).unwrap(), &key_pair);
+        return Ok(Signed {
+            body,
+            payload,
+            signer: key_pair.x_only_public_key().0.to_hex(),
+            signature: signature.as_ref().to_hex(),
+        });
+    }
+
+    // always what the payload says, it can't be changed after signing
+    pub fn body(&self) -> &T {
+        return &self.body;
+    }
+
+    // the key that signed, once the version and the signature check out
+    pub fn verify(&self) -> Result<XOnlyPublicKey, OfferError> {
+        if self.body.version() != PROTOCOL_VERSION {
+            return Err(OfferError::Version(self.body.version()));
+        }
+        let signer = parse_key(&self.signer)?;
+        let signature = Vec::<u8>::from_hex(&self.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(OfferError::Encoding("signature"))?;
+        let digest = Self::digest(&self.payload);
+        secp()
+            .verify_schnorr(&signature, &Message::from_slice(&digest).unwrap(), &signer)
+            .map_err(|_| OfferError::InvalidSignature)?;
+        return Ok(signer);
+    }
+
+    // what later messages refer to this one by
+    pub fn id(&self) -> Result<String, OfferError> {
+        return Ok(Self::digest(&self.payload).to_hex());
+    }
+
+    pub fn to_json(&self) -> Result<String, OfferError> {
+        return Ok(serde_json::to_string(&SignedJson {
+            payload: self.payload.clone(),
+            signer: self.signer.clone(),
+            signature: self.signature.clone(),
+        })?);
+    }
+
+    // only messages with a valid signature get through, the body is read from the signed bytes
+    pub fn from_json(json: &str) -> Result<Self, OfferError> {
+        let signed: SignedJson = serde_json::from_str(json)?;
+        let signed = Signed {
+            body: serde_json::from_str(&signed.payload)?,
+            payload: signed.payload,
+            signer: signed.signer,
+            signature: signed.signature,
+        };
+        signed.verify()?;
+        return Ok(signed);
+    }
+
+    fn digest(payload: &str) -> [u8; 32] {
+        return tagged_hash(T::TAG, &[payload.as_bytes()]);
+    }
+}
+
+impl Offer {
+    pub fn new(
+        role: Role,
+        maker: &Party,
+        arbitrator: &XOnlyPublicKey,
+        terms: ContractTerms,
+        extra_leaves: &[ExtraLeaf],
+        expires_at: u64,
+    ) -> Self {
+        return Offer {
+            version: PROTOCOL_VERSION,
+            nonce: rand::random::<[u8; 32]>().to_hex(),
+            expires_at,
+            role,
+            maker: maker.key.to_hex(),
+            maker_payout: maker.payout.to_hex(),
+            arbitrator: arbitrator.to_hex(),
+            terms,
+            extra_leaves: extra_leaves.to_vec(),
+        };
+    }
+
+    pub fn check_expiry(&self, now: u64) -> Result<(), OfferError> {
+        if now >= self.expires_at {
+            return Err(OfferError::Expired(self.expires_at));
+        }
+        return Ok(());
+    }
+
+    pub fn maker(&self) -> Result<Party, OfferError> {
+        return Ok(Party {
+            key: parse_key(&self.maker)?,
+            payout: parse_script(&self.maker_payout)?,
+        });
+    }
+
+    // the escrow output once a taker is known, the seller is the host of the 2-of-2 leaf
+    pub fn escrow(&self, taker: &Party) -> Result<Output, OfferError> {
+        let maker = self.maker()?;
+        let (buyer, seller) = match self.role {
+            Role::Buyer => (maker.key, taker.key),
+            Role::Seller => (taker.key, maker.key),
+        };
+        return Ok(create_address_with_leaves(
+            &seller,
+            &buyer,
+            &parse_key(&self.arbitrator)?,
+            &nums_x_only(),
+            &self.extra_leaves,
+        ));
+    }
+
+    fn tap_tree(&self, taker: &Party) -> Result<Vec<(u8, String)>, OfferError> {
+        return Ok(self
+            .escrow(taker)?
+            .tap_tree
+            .unwrap()
+            .script_leaves()
+            .map(|leaf| (leaf.depth(), leaf.script().to_hex()))
+            .collect());
+    }
+}
+
+impl Acceptance {
+    // checks the offer before answering it, the funding psbt pays the escrow of the contract
+    pub fn new(
+        offer: &Signed<Offer>,
+        taker: &Party,
+        funding_psbt: &PartiallySignedTransaction,
+    ) -> Result<Self, OfferError> {
+        if offer.verify()? != offer.body().maker()?.key {
+            return Err(OfferError::WrongSigner);
+        }
+        offer.body().check_expiry(unix_now())?;
+        let funding_psbt = String::from_utf8(serialize_psbt(funding_psbt, PsbtFormat::Base64))
+            .map_err(|_| OfferError::Encoding("funding psbt"))?;
+        return Ok(Acceptance {
+            version: PROTOCOL_VERSION,
+            offer_id: offer.id()?,
+            taker: taker.key.to_hex(),
+            taker_payout: taker.payout.to_hex(),
+            tap_tree: offer.body().tap_tree(taker)?,
+            funding_psbt,
+        });
+    }
+
+    pub fn taker(&self) -> Result<Party, OfferError> {
+        return Ok(Party {
+            key: parse_key(&self.taker)?,
+            payout: parse_script(&self.taker_payout)?,
+        });
+    }
+
+    pub fn funding_psbt(&self) -> Result<PartiallySignedTransaction, OfferError> {
+        return Ok(deserialize_psbt(
+            self.funding_psbt.as_bytes(),
+            PsbtFormat::Base64,
+        )?);
+    }
+}
+
+impl PsbtMessage {
+    pub fn new(
+        offer: &Signed<Offer>,
+        proposal: Proposal,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Self, OfferError> {
+        let psbt = String::from_utf8(serialize_psbt(psbt, PsbtFormat::Base64))
+            .map_err(|_| OfferError::Encoding("psbt"))?;
+        return Ok(PsbtMessage {
+            version: PROTOCOL_VERSION,
+            offer_id: offer.id()?,
+            proposal,
+            psbt,
+        });
+    }
+
+    // hands the psbt to the local contract, the signer has to be the party the proposal needs
+    pub fn apply<R: RpcCall>(
+        signed: &Signed<PsbtMessage>,
+        offer: &Signed<Offer>,
+        contract: &mut BisqContract<R>,
+    ) -> Result<(), OfferError> {
+        let signer = signed.verify()?;
+        if signed.body().offer_id != offer.id()? {
+            return Err(OfferError::OfferMismatch);
+        }
+        let psbt = deserialize_psbt(signed.body().psbt.as_bytes(), PsbtFormat::Base64)?;
+        contract.import(signed.body().proposal, &signer, psbt)?;
+        return Ok(());
+    }
+}
+
+// what both processes run once the offer is accepted, every field of both messages is checked
+// so each side ends up with the same contract without trusting the other. expiry was checked
+// when the offer was accepted, an offer that runs out afterwards still makes a contract
+pub fn contract_from_messages<'a, R: RpcCall>(
+    offer: &Signed<Offer>,
+    acceptance: &Signed<Acceptance>,
+    client: &'a R,
+) -> Result<BisqContract<'a, R>, OfferError> {
+    let maker = offer.body().maker()?;
+    if offer.verify()? != maker.key {
+        return Err(OfferError::WrongSigner);
+    }
+    let taker = acceptance.body().taker()?;
+    if acceptance.verify()? != taker.key {
+        return Err(OfferError::WrongSigner);
+    }
+    if acceptance.body().offer_id != offer.id()? {
+        return Err(OfferError::OfferMismatch);
+    }
+    if acceptance.body().tap_tree != offer.body().tap_tree(&taker)? {
+        return Err(OfferError::TreeMismatch);
+    }
+
+    let terms = offer.body().terms;
+    let arbitrator = parse_key(&offer.body().arbitrator)?;
+    let (buyer, seller) = match offer.body().role {
+        Role::Buyer => (maker, taker),
+        Role::Seller => (taker, maker),
+    };
+    let contract = BisqContract::new(terms, buyer, seller, arbitrator, client)
+        .with_leaves(&offer.body().extra_leaves);
+
+    let locked = acceptance
+        .body()
+        .funding_psbt()?
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|tx_out| tx_out.script_pubkey.eq(&contract.script_pubkey()))
+        .map(|tx_out| tx_out.value)
+        .sum::<u64>();
+    if locked != terms.funding_amount() {
+        return Err(OfferError::FundingMismatch {
+            expected: terms.funding_amount(),
+            found: locked,
+        });
+    }
+    return Ok(contract);
+}
+
+fn unix_now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+}
+
+fn parse_key(hex: &str) -> Result<XOnlyPublicKey, OfferError> {
+    return XOnlyPublicKey::from_str(hex).map_err(|_| OfferError::Encoding("x-only key"));
+}
+
+fn parse_script(hex: &str) -> Result<Script, OfferError> {
+    return Script::from_str(hex).map_err(|_| OfferError::Encoding("script"));
+}
+
+#[test]
+fn offer_and_acceptance_drive_contracts_in_separate_processes() {
+    use bitcoin::{OutPoint, PackedLockTime, Sequence, Transaction, TxIn, TxOut, Witness};
+
+    use super::bisq_contract::{ContractState, Settlement};
+    use crate::bitcoin_wallet::input_data::mock_call::MockCall;
+
+    let seller = SecretKey::from_slice(&[51u8; 32]).unwrap();
+    let buyer = SecretKey::from_slice(&[52u8; 32]).unwrap();
+    let arbitrator = SecretKey::from_slice(&[53u8; 32]).unwrap();
+    let x_only = |secret: &SecretKey| secret.x_only_public_key(&secp()).0;
+    let party = |secret: &SecretKey| Party {
+        key: x_only(secret),
+        payout: Script::new_v1_p2tr(&secp(), x_only(secret), None),
+    };
+    let terms = ContractTerms {
+        trade_amount: 200_000,
+        buyer_deposit: 30_000,
+        seller_deposit: 30_000,
+        fee: 1_500,
+    };
+
+    // the seller publishes an offer, the buyer only ever sees its json
+    let offer = Offer::new(
+        Role::Seller,
+        &party(&seller),
+        &x_only(&arbitrator),
+        terms,
+        &[ExtraLeaf::Dispute],
+        unix_now() + 3_600,
+    );
+    let offer_json = Signed::sign(offer, &seller).unwrap().to_json().unwrap();
+    let tampered = offer_json.replace("200000", "100000");
+    assert!(matches!(
+        Signed::<Offer>::from_json(&tampered),
+        Err(OfferError::InvalidSignature)
+    ));
+    let offer = Signed::<Offer>::from_json(&offer_json).unwrap();
+    // the same terms offered twice are two different offers
+    let again = Offer {
+        nonce: rand::random::<[u8; 32]>().to_hex(),
+        ..offer.body().clone()
+    };
+    assert_ne!(
+        Signed::sign(again, &seller).unwrap().id().unwrap(),
+        offer.id().unwrap()
+    );
+
+    let escrow = offer
+        .body()
+        .escrow(&party(&buyer))
+        .unwrap()
+        .witness_script
+        .unwrap();
+    let funding_tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: terms.funding_amount(),
+            script_pubkey: escrow.clone(),
+        }],
+    };
+    let funding_psbt = PartiallySignedTransaction::from_unsigned_tx(funding_tx.clone()).unwrap();
+    let expired = Signed::sign(
+        Offer {
+            expires_at: 0,
+            ..offer.body().clone()
+        },
+        &seller,
+    )
+    .unwrap();
+    assert!(matches!(
+        Acceptance::new(&expired, &party(&buyer), &funding_psbt),
+        Err(OfferError::Expired(0))
+    ));
+    let acceptance = Acceptance::new(&offer, &party(&buyer), &funding_psbt).unwrap();
+    // a funding tx that puts more than the funding amount in the escrow is refused as well
+    let mut overfunded_tx = funding_tx.clone();
+    overfunded_tx.output[0].value += 1;
+    let overfunded = Signed::sign(
+        Acceptance::new(
+            &offer,
+            &party(&buyer),
+            &PartiallySignedTransaction::from_unsigned_tx(overfunded_tx.clone()).unwrap(),
+        )
+        .unwrap(),
+        &buyer,
+    )
+    .unwrap();
+    let overfunded_client = MockCall::from_transactions(vec![overfunded_tx], &escrow);
+    assert!(matches!(
+        contract_from_messages(&offer, &overfunded, &overfunded_client),
+        Err(OfferError::FundingMismatch { expected, found }) if found == expected + 1
+    ));
+    // an acceptance has to come from the taker it names
+    let forged = Signed::sign(acceptance.clone(), &arbitrator).unwrap();
+    let client = MockCall::from_transactions(vec![funding_tx], &escrow);
+    assert!(matches!(
+        contract_from_messages(&offer, &forged, &client),
+        Err(OfferError::WrongSigner)
+    ));
+    // an offer accepted in time still makes a contract after it runs out
+    let late = Signed::sign(
+        Acceptance {
+            offer_id: expired.id().unwrap(),
+            ..acceptance.clone()
+        },
+        &buyer,
+    )
+    .unwrap();
+    assert!(contract_from_messages(&expired, &late, &client).is_ok());
+    let acceptance_json = Signed::sign(acceptance, &buyer).unwrap().to_json().unwrap();
+    let acceptance = Signed::<Acceptance>::from_json(&acceptance_json).unwrap();
+
+    // each side builds its own contract from the same two messages
+    let mut seller_contract = contract_from_messages(&offer, &acceptance, &client).unwrap();
+    let mut buyer_contract = contract_from_messages(&offer, &acceptance, &client).unwrap();
+    assert_eq!(seller_contract.script_pubkey(), escrow);
+    seller_contract.fund().unwrap();
+    buyer_contract.fund().unwrap();
+
+    let proposal = Proposal::Payout(Settlement::Payout);
+    let psbt = seller_contract
+        .propose(&seller, Settlement::Payout)
+        .unwrap();
+    let message = PsbtMessage::new(&offer, proposal, &psbt).unwrap();
+    let message_json = Signed::sign(message, &seller).unwrap().to_json().unwrap();
+
+    let message = Signed::<PsbtMessage>::from_json(&message_json).unwrap();
+    // a payout that doesn't follow the terms is refused
+    let mut skewed = psbt.clone();
+    skewed.unsigned_tx.output[0].value -= 1_000;
+    let skewed = Signed::sign(
+        PsbtMessage::new(&offer, proposal, &skewed).unwrap(),
+        &seller,
+    )
+    .unwrap();
+    assert!(matches!(
+        PsbtMessage::apply(&skewed, &offer, &mut buyer_contract),
+        Err(OfferError::Contract(ContractError::UnexpectedPsbt))
+    ));
+    PsbtMessage::apply(&message, &offer, &mut buyer_contract).unwrap();
+    let tx = buyer_contract.co_sign(&buyer).unwrap();
+    assert_eq!(buyer_contract.state(), &ContractState::CoSigned(tx.clone()));
+    assert_eq!(client.last_broadcast(), Some(tx));
+}
@@ -0,0 +1,119 @@
+use bitcoin::{psbt::Output, secp256k1::SecretKey, Transaction};
+
+use crate::bitcoin_wallet::{
+    constants::secp,
+    scripts::timelock::{script_timelocks, RelativeLock, TimeLock},
+};
+
+use super::{bisq_script::contains_key, leaf_paths, ISigner, TrType};
+
+// host and client sign the CSV refund leaf, the refund spends every input with the leaf's
+// sequence so it can be signed long before it can be mined, the finalizer only takes the leaf
+// once the sequence satisfies the CSV and the node still refuses the transaction until the
+// funding output is old enough
+pub struct BisqRefund {
+    pub output: Output,
+    pub lock: RelativeLock,
+}
+
+impl ISigner for BisqRefund {
+    fn output(&self) -> &Output {
+        return &self.output;
+    }
+
+    fn paths(&self, secret_key: &SecretKey) -> Vec<TrType> {
+        let x_only = secret_key.x_only_public_key(&secp()).0;
+        let lock = TimeLock::Relative(self.lock);
+        return leaf_paths(&self.output, |script| {
+            contains_key(script, &x_only) && script_timelocks(script).contains(&lock)
+        });
+    }
+
+    fn prepare_tx(&self, unsigned_tx: &mut Transaction) {
+        (0..unsigned_tx.input.len()).for_each(|index| self.lock.apply(unsigned_tx, index));
+    }
+}
+
+#[test]
+fn pre_signed_refund_waits_for_the_csv() {
+    use bitcoin::{Script, Sequence};
+
+    use super::{
+        bisq::{create_address_with_leaves, ExtraLeaf},
+        bisq_contract::{BisqContract, ContractError, ContractState, ContractTerms, Party},
+    };
+    use crate::bitcoin_wallet::{constants::nums_x_only, input_data::mock_call::MockCall};
+
+    let buyer = SecretKey::from_slice(&[41u8; 32]).unwrap();
+    let seller = SecretKey::from_slice(&[42u8; 32]).unwrap();
+    let arbitrator = SecretKey::from_slice(&[43u8; 32]).unwrap();
+    let x_only = |secret: &SecretKey| secret.x_only_public_key(&secp()).0;
+    let party = |secret: &SecretKey| Party {
+        key: x_only(secret),
+        payout: Script::new_v1_p2tr(&secp(), x_only(secret), None),
+    };
+    let terms = ContractTerms {
+        trade_amount: 50_000,
+        buyer_deposit: 8_000,
+        seller_deposit: 8_000,
+        fee: 1_000,
+    };
+    let lock = RelativeLock::Blocks(1_008);
+    let leaves = [ExtraLeaf::Refund(lock)];
+    let escrow = create_address_with_leaves(
+        &x_only(&seller),
+        &x_only(&buyer),
+        &x_only(&arbitrator),
+        &nums_x_only(),
+        &leaves,
+    )
+    .witness_script
+    .unwrap();
+
+    let client = MockCall::fund(&escrow, &[40_000, 26_000]);
+    let mut contract = BisqContract::new(
+        terms,
+        party(&buyer),
+        party(&seller),
+        x_only(&arbitrator),
+        &client,
+    )
+    .with_leaves(&leaves);
+    assert_eq!(contract.script_pubkey(), escrow);
+    assert_eq!(
+        contract.sign_refund(&buyer).unwrap_err(),
+        ContractError::InvalidTransition {
+            state: "offered",
+            action: "sign a refund for"
+        }
+    );
+    contract.fund().unwrap();
+
+    contract.sign_refund(&buyer).unwrap();
+    assert!(matches!(
+        contract.claim_refund(),
+        Err(ContractError::Unsatisfiable(_))
+    ));
+    let refund = contract.sign_refund(&seller).unwrap();
+    assert!(refund
+        .unsigned_tx
+        .input
+        .iter()
+        .all(|tx_in| tx_in.sequence == Sequence::from_height(1_008)));
+
+    // the traders stop talking, the refund still goes through once the output is old enough
+    contract.dispute().unwrap();
+    let tx = contract.claim_refund().unwrap();
+    let split = terms
+        .split(super::bisq_contract::Settlement::Refund)
+        .unwrap();
+    assert_eq!(tx.output[0].value, split.buyer);
+    assert_eq!(tx.output[1].value, split.seller);
+    // two signatures, the refund leaf and its control block
+    assert_eq!(tx.input[0].witness.len(), 4);
+    assert_eq!(
+        tx.input[0].witness.to_vec()[2],
+        super::bisq::unlock_refund(&x_only(&seller), &x_only(&buyer), lock).to_bytes()
+    );
+    assert_eq!(contract.state(), &ContractState::Refunded(tx));
+}
@@ -1,102 +1,45 @@
 use bitcoin::{
-    psbt::{Input, Output, PartiallySignedTransaction, Prevouts},
+    blockdata::script::Instruction,
+    psbt::{Input, Output, Prevouts},
     secp256k1::{Message, SecretKey},
     util::{
         sighash::{ScriptPath, SighashCache},
         taproot::{LeafVersion, TapLeafHash},
     },
-    SchnorrSig, SchnorrSighashType, Script, Transaction, TxIn, TxOut, Witness,
+    SchnorrSig, SchnorrSighashType, Script, Transaction, TxOut, XOnlyPublicKey,
 };
 
-use crate::bitcoin_wallet::{constants::secp, input_data::RpcCall};
+use crate::bitcoin_wallet::constants::secp;
 
-use super::{ISigner, TrType};
+use super::{leaf_paths, ISigner, TrType};
 
 pub struct BisqScript {
     pub output: Output,
-    pub input: Vec<Input>,
 }
 
+// host or client signing every leaf its key is in, the finalizer picks one later
 impl ISigner for BisqScript {
-
-    fn sign_all_unsigned_tx(
-        &self,
-        secret_key: &SecretKey,
-        prevouts: &Vec<TxOut>,     
-        unsigned_tx: &Transaction, 
-    ) -> Vec<Input> {
-        return prevouts
-            .iter()
-            .enumerate()
-            .map(|(index, tx_out)| {
-                let binding = self.output.clone().tap_tree.unwrap();
-                let target_script = binding.script_leaves().next().unwrap().script();
-                let message = create_script_message(index, unsigned_tx, prevouts, target_script);
-                sign_tx(
-                    &secret_key,
-                    tx_out,
-                    &self
-                        .input
-                        .get(index)
-                        .clone()
-                        .unwrap_or(&Input::default())
-                        .clone(),
-                    &message,
-                    &self.output,
-                )
-                .clone()
-            })
-            .collect();
+    fn output(&self) -> &Output {
+        return &self.output;
     }
 
-    fn finalize_tx<R: RpcCall>(
-        rpc_call: &R,
-        psbt: PartiallySignedTransaction,
-    ) -> bitcoin::Transaction {
-        let tx = psbt.clone().extract_tx().clone();
-        let tx_in = psbt
-            .inputs
-            .iter()
-            .map(|input| {
-                let mut witness = Witness::new();
-
-                input.tap_script_sigs.iter().for_each(|sig| {
-                    let shnor = sig.1;
-                    witness.push(shnor.to_vec());
-                });
-
-                input.tap_scripts.iter().for_each(|control| {
-                    witness.push(control.1 .0.as_bytes());
-                    witness.push(control.0.serialize());
-                });
-                return witness;
-            })
-            .zip(tx.input)
-            .map(|(witness, tx_input)| {
-                return TxIn {
-                    previous_output: tx_input.previous_output,
-                    script_sig: tx_input.script_sig,
-                    sequence: tx_input.sequence,
-                    witness,
-                };
-            })
-            .collect::<Vec<TxIn>>();
-
-        let tx = Transaction {
-            version: tx.version,
-            lock_time: tx.lock_time,
-            input: tx_in,
-            output: tx.output,
-        };
-        rpc_call.broadcasts_transacton(&tx);
-        return tx;
+    fn paths(&self, secret_key: &SecretKey) -> Vec<TrType> {
+        let x_only = secret_key.x_only_public_key(&secp()).0;
+        return leaf_paths(&self.output, |script| contains_key(script, &x_only));
     }
 }
 
+pub fn contains_key(script: &Script, x_only: &XOnlyPublicKey) -> bool {
+    return script.instructions().any(|instruction| match instruction {
+        Ok(Instruction::PushBytes(bytes)) => bytes == x_only.serialize(),
+        _ => false,
+    });
+}
+
 pub fn create_script_message(
     index: usize,
     unsigned_tx: &Transaction,
-    prevouts: &Vec<TxOut>,
+    prevouts: &[TxOut],
     target_script: &Script,
 ) -> Message {
     let sighash = SighashCache::new(unsigned_tx)
@@ -111,12 +54,13 @@ pub fn create_script_message(
     return Message::from_slice(&sighash).unwrap();
 }
 
-fn sign_tx(
+pub fn sign_tx(
     secret_key: &SecretKey,
     tx_out: &TxOut,
     input: &Input,
     message: &Message,
     output: &Output,
+    target_script: &Script,
 ) -> Input {
     let tap_info = output
         .clone()
@@ -127,10 +71,6 @@ fn sign_tx(
         .finalize(&secp(), output.tap_internal_key.unwrap())
         .unwrap();
 
-    let binding = output.clone().tap_tree.unwrap();
-
-    let target_script = binding.script_leaves().next().unwrap().script();
-
     let control = tap_info.control_block(&(target_script.clone(), LeafVersion::TapScript));
 
     let verify = control.as_ref().unwrap().verify_taproot_commitment(
@@ -1,26 +1,269 @@
+use std::fmt;
+
 use bitcoin::{
-    psbt::{Input, Output, PartiallySignedTransaction},
-    secp256k1::{Message, SecretKey},
-    Transaction, TxOut,
+    psbt::{Output, PartiallySignedTransaction},
+    secp256k1::SecretKey,
+    util::taproot::{LeafVersion, TapLeafHash},
+    Script, Transaction, TxOut,
 };
 
-use crate::bitcoin_wallet::input_data::RpcCall;
+use crate::bitcoin_wallet::{
+    constants::secp,
+    script_services::tap_finalizer::{finalize_tap_psbt, UnsatisfiableInput},
+};
 
 pub mod bisq;
+pub mod bisq_contract;
+pub mod bisq_dispute;
 pub mod bisq_key;
+pub mod bisq_offer;
+pub mod bisq_refund;
 pub mod bisq_script;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignerError {
+    NoTapTree,
+    UnknownLeaf(TapLeafHash),
+    InputIndex(usize),
+    PrevoutsLength { prevouts: usize, inputs: usize },
+    // the key path only takes the internal key, a NUMS internal key has no key path at all
+    NotInternalKey,
+    KeyNotInLeaf(TapLeafHash),
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignerError::NoTapTree => write!(f, "contract output has no tap tree"),
+            SignerError::UnknownLeaf(leaf_hash) => {
+                write!(f, "leaf {} is not in the contract output", leaf_hash)
+            }
+            SignerError::InputIndex(index) => write!(f, "psbt has no input {}", index),
+            SignerError::PrevoutsLength { prevouts, inputs } => write!(
+                f,
+                "{} prevouts given for a psbt with {} inputs",
+                prevouts, inputs
+            ),
+            SignerError::NotInternalKey => {
+                write!(f, "key is not the internal key of the contract output")
+            }
+            SignerError::KeyNotInLeaf(leaf_hash) => {
+                write!(f, "key does not appear in leaf {}", leaf_hash)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+// which way an input of the contract output gets spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrType {
+    Script(TapLeafHash),
+    Key,
+}
+
+// a role in a contract, a new role only says which output it signs for and which paths its key
+// takes by default, signatures always go into the psbt next to the ones already there so every
+// party can sign the same psbt in turn
 pub trait ISigner {
-    //  fn sign_tx(secret_key:&SecretKey,tx_out:&TxOut, input:&Input, message:&Message,output:&Output)->Input;
-    fn sign_all_unsigned_tx(
+    fn output(&self) -> &Output;
+
+    // the paths sign_psbt signs for, callers that want something else per input use sign_input
+    fn paths(&self, secret_key: &SecretKey) -> Vec<TrType>;
+
+    // called on the unsigned transaction before any input is signed, for signers whose leaf
+    // needs something set on it like a relative lock
+    fn prepare_tx(&self, _unsigned_tx: &mut Transaction) {}
+
+    fn sign_input(
+        &self,
+        secret_key: &SecretKey,
+        psbt: &mut PartiallySignedTransaction,
+        prevouts: &[TxOut],
+        index: usize,
+        path: TrType,
+    ) -> Result<(), SignerError> {
+        if prevouts.len() != psbt.inputs.len() {
+            return Err(SignerError::PrevoutsLength {
+                prevouts: prevouts.len(),
+                inputs: psbt.inputs.len(),
+            });
+        }
+        let tx_out = prevouts.get(index).ok_or(SignerError::InputIndex(index))?;
+        let output = self.output();
+        if output.tap_tree.is_none() {
+            return Err(SignerError::NoTapTree);
+        }
+        let input = &psbt.inputs[index];
+        let x_only = secret_key.x_only_public_key(&secp()).0;
+        let signed = match path {
+            TrType::Key => {
+                if output.tap_internal_key != Some(x_only) {
+                    return Err(SignerError::NotInternalKey);
+                }
+                let message = bisq_key::create_message(index, &psbt.unsigned_tx, prevouts);
+                bisq_key::sign_tx(secret_key, tx_out, input, &message, output)
+            }
+            TrType::Script(leaf_hash) => {
+                let script = leaf_script(output, &leaf_hash)?;
+                if !bisq_script::contains_key(&script, &x_only) {
+                    return Err(SignerError::KeyNotInLeaf(leaf_hash));
+                }
+                let message =
+                    bisq_script::create_script_message(index, &psbt.unsigned_tx, prevouts, &script);
+                bisq_script::sign_tx(secret_key, tx_out, input, &message, output, &script)
+            }
+        };
+        psbt.inputs[index] = signed;
+        return Ok(());
+    }
+
+    fn sign_psbt(
         &self,
         secret_key: &SecretKey,
-        prevouts: &Vec<TxOut>,
-        unsigned_tx: &Transaction,
-    ) -> Vec<Input>;
+        psbt: &mut PartiallySignedTransaction,
+        prevouts: &[TxOut],
+    ) -> Result<(), SignerError> {
+        let paths = self.paths(secret_key);
+        for index in 0..psbt.inputs.len() {
+            for path in paths.iter() {
+                self.sign_input(secret_key, psbt, prevouts, index, *path)?;
+            }
+        }
+        return Ok(());
+    }
 
-    fn finalize_tx<R: RpcCall>(rpc_call: &R, psbt: PartiallySignedTransaction) -> Transaction;
+    // only builds the witnesses, broadcasting is up to the caller
+    fn finalize(
+        &self,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<Transaction, UnsatisfiableInput> {
+        return finalize_tap_psbt(psbt);
+    }
 }
-pub enum TrType {
-    Script,
-    Key,
+
+// the leaves of the output's tree the filter accepts, ready to be returned from ISigner::paths
+pub fn leaf_paths(output: &Output, filter: impl Fn(&Script) -> bool) -> Vec<TrType> {
+    return output
+        .tap_tree
+        .iter()
+        .flat_map(|tap_tree| tap_tree.script_leaves())
+        .filter(|leaf| filter(leaf.script()))
+        .map(|leaf| TrType::Script(TapLeafHash::from_script(leaf.script(), leaf.leaf_version())))
+        .collect();
+}
+
+pub fn leaf_script(output: &Output, leaf_hash: &TapLeafHash) -> Result<Script, SignerError> {
+    return output
+        .tap_tree
+        .as_ref()
+        .ok_or(SignerError::NoTapTree)?
+        .script_leaves()
+        .find(|leaf| TapLeafHash::from_script(leaf.script(), leaf.leaf_version()) == *leaf_hash)
+        .map(|leaf| leaf.script().clone())
+        .ok_or(SignerError::UnknownLeaf(*leaf_hash));
+}
+
+pub fn leaf_hash(script: &Script) -> TapLeafHash {
+    return TapLeafHash::from_script(script, LeafVersion::TapScript);
+}
+
+#[test]
+fn custom_role_picks_a_path_per_input() {
+    use bitcoin::{
+        blockdata::{opcodes::all::OP_CHECKSIG, script::Builder},
+        PackedLockTime,
+    };
+
+    use crate::bitcoin_wallet::input_data::{mock_call::MockCall, RpcCall};
+
+    // a role outside this module, it only says which output it signs for
+    struct Support {
+        output: Output,
+    }
+
+    impl ISigner for Support {
+        fn output(&self) -> &Output {
+            return &self.output;
+        }
+
+        fn paths(&self, _secret_key: &SecretKey) -> Vec<TrType> {
+            return vec![TrType::Key];
+        }
+    }
+
+    let host = SecretKey::from_slice(&[47u8; 32]).unwrap();
+    let client = SecretKey::from_slice(&[48u8; 32]).unwrap();
+    let support = SecretKey::from_slice(&[49u8; 32]).unwrap();
+    let x_only = |secret: &SecretKey| secret.x_only_public_key(&secp()).0;
+    let output = bisq::create_address(&x_only(&host), &x_only(&client), &x_only(&support));
+    let escrow = output.witness_script.clone().unwrap();
+    let mock = MockCall::fund(&escrow, &[20_000, 30_000]);
+    let prevouts: Vec<TxOut> = mock
+        .contract_source()
+        .iter()
+        .flat_map(|tx| tx.output.clone())
+        .filter(|tx_out| tx_out.script_pubkey.eq(&escrow))
+        .collect();
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: mock.prev_input(),
+        output: vec![TxOut {
+            value: 49_000,
+            script_pubkey: escrow.clone(),
+        }],
+    })
+    .unwrap();
+    let signer = Support { output };
+    let support_leaf = Builder::new()
+        .push_x_only_key(&x_only(&support))
+        .push_opcode(OP_CHECKSIG)
+        .into_script();
+    signer
+        .sign_input(&support, &mut psbt, &prevouts, 0, TrType::Key)
+        .unwrap();
+    signer
+        .sign_input(
+            &support,
+            &mut psbt,
+            &prevouts,
+            1,
+            TrType::Script(leaf_hash(&support_leaf)),
+        )
+        .unwrap();
+    // keys that can't produce a valid signature on the path are refused up front
+    assert_eq!(
+        signer.sign_input(&host, &mut psbt, &prevouts, 0, TrType::Key),
+        Err(SignerError::NotInternalKey)
+    );
+    assert_eq!(
+        signer.sign_input(
+            &host,
+            &mut psbt,
+            &prevouts,
+            1,
+            TrType::Script(leaf_hash(&support_leaf)),
+        ),
+        Err(SignerError::KeyNotInLeaf(leaf_hash(&support_leaf)))
+    );
+    let bogus = leaf_hash(&Script::new());
+    assert_eq!(
+        signer.sign_input(&support, &mut psbt, &prevouts, 1, TrType::Script(bogus)),
+        Err(SignerError::UnknownLeaf(bogus))
+    );
+    assert_eq!(
+        signer.sign_input(&support, &mut psbt, &prevouts, 2, TrType::Key),
+        Err(SignerError::InputIndex(2))
+    );
+
+    // finalizing doesn't broadcast
+    let tx = signer.finalize(psbt).unwrap();
+    assert_eq!(mock.last_broadcast(), None);
+    // the key path needs one signature, the support leaf a signature, the leaf and its control
+    // block
+    assert_eq!(tx.input[0].witness.len(), 1);
+    assert_eq!(tx.input[1].witness.len(), 3);
 }
@@ -0,0 +1,379 @@
+use std::fmt;
+
+use bitcoin::{
+    secp256k1::{All, Secp256k1, SecretKey},
+    KeyPair, Script, Transaction, XOnlyPublicKey,
+};
+use bitcoin_hashes::{sha256, Hash};
+
+use crate::bitcoin_wallet::{
+    input_data::RpcCall,
+    schnorr::ecdh::{contract_keys, ContractKeys, EcdhError},
+    script_services::{
+        output_service::new_witness_pub_k, psbt_factory::create_partially_signed_tx,
+    },
+    scripts::timelock::ChainTip,
+    spending_path::htlc_path::{timeout_precedes, Htlc, HtlcError, HtlcPath},
+};
+
+// fresh keys for the swap locking to this payment hash, derived from the long lived keys of both
+// sides so the htlc can be set up without sending new keys around
+pub fn swap_keys(
+    secp: &Secp256k1<All>,
+    secret_key: &SecretKey,
+    counterparty: &XOnlyPublicKey,
+    payment_hash: &sha256::Hash,
+) -> Result<ContractKeys, EcdhError> {
+    let context = [&b"htlc/swap"[..], &payment_hash.into_inner()].concat();
+    return contract_keys(secp, secret_key, counterparty, &context, 0);
+}
+
+// builds, finalizes and broadcasts a spend of every htlc utxo the client knows about
+pub fn spend_htlc<R: RpcCall>(
+    htlc: &Htlc,
+    client: &R,
+    path: HtlcPath,
+    key_pair: &KeyPair,
+    preimage: Option<Vec<u8>>,
+    send_to: Script,
+) -> Result<Transaction, HtlcError> {
+    let unlock_func = match (path, preimage) {
+        (HtlcPath::Claim, Some(preimage)) => htlc.claim_input_factory(key_pair, preimage),
+        (HtlcPath::Claim, None) => return Err(HtlcError::MissingPreimage),
+        (HtlcPath::Refund, _) => htlc.refund_input_factory(key_pair),
+    };
+    let psbt = create_partially_signed_tx(
+        vec![vec![new_witness_pub_k(send_to)]],
+        htlc.create_tx(path),
+        unlock_func,
+    )(client);
+    let tx = htlc.finalize(psbt)?;
+    client.broadcasts_transacton(&tx);
+    return Ok(tx);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SwapError {
+    PaymentHashMismatch,
+    // the participant has to be able to refund before the initiator can
+    TimeoutOrder,
+    // relative locks count from when each leg confirms, so they can't be ordered across chains
+    RelativeTimelock,
+}
+
+impl fmt::Display for SwapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SwapError::PaymentHashMismatch => {
+                write!(
+                    f,
+                    "both htlcs of a swap have to lock to the same payment hash"
+                )
+            }
+            SwapError::TimeoutOrder => write!(
+                f,
+                "the participant timeout has to expire before the initiator timeout"
+            ),
+            SwapError::RelativeTimelock => {
+                write!(f, "both htlcs of a swap have to use an absolute timelock")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SwapError {}
+
+// the initiator knows the preimage and locks on chain a, the participant locks on chain b with a
+// timeout that matures at least margin seconds earlier, so it can still refund after the
+// initiator claims at the last moment
+pub struct AtomicSwap<'a, A: RpcCall, B: RpcCall> {
+    pub initiator_htlc: Htlc,
+    pub participant_htlc: Htlc,
+    chain_a: &'a A,
+    chain_b: &'a B,
+}
+
+impl<'a, A, B> AtomicSwap<'a, A, B>
+where
+    A: RpcCall,
+    B: RpcCall,
+{
+    pub fn new(
+        initiator_htlc: Htlc,
+        participant_htlc: Htlc,
+        chain_a: &'a A,
+        chain_b: &'a B,
+        tips: [ChainTip; 2],
+        margin: u32,
+    ) -> Result<Self, SwapError> {
+        if initiator_htlc.payment_hash != participant_htlc.payment_hash {
+            return Err(SwapError::PaymentHashMismatch);
+        }
+        match timeout_precedes(
+            &participant_htlc.timeout,
+            &tips[1],
+            &initiator_htlc.timeout,
+            &tips[0],
+            margin,
+        ) {
+            Some(true) => {}
+            Some(false) => return Err(SwapError::TimeoutOrder),
+            None => return Err(SwapError::RelativeTimelock),
+        }
+        return Ok(AtomicSwap {
+            initiator_htlc,
+            participant_htlc,
+            chain_a,
+            chain_b,
+        });
+    }
+
+    // the initiator takes the participant's coins on chain b, revealing the preimage
+    pub fn initiator_claim(
+        &self,
+        key_pair: &KeyPair,
+        preimage: Vec<u8>,
+        send_to: Script,
+    ) -> Result<Transaction, HtlcError> {
+        return spend_htlc(
+            &self.participant_htlc,
+            self.chain_b,
+            HtlcPath::Claim,
+            key_pair,
+            Some(preimage),
+            send_to,
+        );
+    }
+
+    // the participant reads the preimage out of the initiator's claim and takes the coins on
+    // chain a, MissingPreimage when the claim doesn't reveal it
+    pub fn participant_claim(
+        &self,
+        key_pair: &KeyPair,
+        initiator_claim: &Transaction,
+        send_to: Script,
+    ) -> Result<Transaction, HtlcError> {
+        return spend_htlc(
+            &self.initiator_htlc,
+            self.chain_a,
+            HtlcPath::Claim,
+            key_pair,
+            self.participant_htlc.extract_preimage(initiator_claim),
+            send_to,
+        );
+    }
+
+    pub fn initiator_refund(
+        &self,
+        key_pair: &KeyPair,
+        send_to: Script,
+    ) -> Result<Transaction, HtlcError> {
+        return spend_htlc(
+            &self.initiator_htlc,
+            self.chain_a,
+            HtlcPath::Refund,
+            key_pair,
+            None,
+            send_to,
+        );
+    }
+
+    pub fn participant_refund(
+        &self,
+        key_pair: &KeyPair,
+        send_to: Script,
+    ) -> Result<Transaction, HtlcError> {
+        return spend_htlc(
+            &self.participant_htlc,
+            self.chain_b,
+            HtlcPath::Refund,
+            key_pair,
+            None,
+            send_to,
+        );
+    }
+}
+
+#[test]
+fn atomic_swap_reveals_preimage_across_chains() {
+    use bitcoin::secp256k1::Secp256k1;
+
+    use crate::bitcoin_wallet::{
+        constants::nums_x_only,
+        input_data::mock_call::MockCall,
+        scripts::timelock::{AbsoluteLock, RelativeLock, TimeLock},
+        spending_path::htlc_path::{payment_hash, HtlcKind},
+    };
+
+    let secp = Secp256k1::new();
+    let tips = [
+        ChainTip {
+            height: 800_000,
+            median_time: 1_700_000_000,
+            block_interval: 600,
+        },
+        ChainTip {
+            height: 2_500_000,
+            median_time: 1_700_000_000,
+            block_interval: 150,
+        },
+    ];
+    let margin = 6 * 3_600;
+    let initiator = KeyPair::from_seckey_slice(&secp, &[3u8; 32]).unwrap();
+    let participant = KeyPair::from_seckey_slice(&secp, &[4u8; 32]).unwrap();
+    let preimage = vec![9u8; 32];
+    let hash = payment_hash(&preimage);
+
+    let initiator_htlc = Htlc::new(
+        &secp,
+        HtlcKind::Taproot,
+        hash,
+        participant.public_key(),
+        initiator.public_key(),
+        TimeLock::Absolute(AbsoluteLock::Height(800_288)),
+    );
+    let participant_htlc = Htlc::new(
+        &secp,
+        HtlcKind::SegwitV0,
+        hash,
+        initiator.public_key(),
+        participant.public_key(),
+        TimeLock::Absolute(AbsoluteLock::Height(2_500_576)),
+    );
+
+    let chain_a = MockCall::fund(&initiator_htlc.script_pubkey(), &[100_000]);
+    let chain_b = MockCall::fund(&participant_htlc.script_pubkey(), &[80_000]);
+    // a relative lock only starts counting once its leg confirms, so the swap is refused
+    let mut relative_htlc = participant_htlc.clone();
+    relative_htlc.timeout = TimeLock::Relative(RelativeLock::Blocks(144));
+    assert_eq!(
+        AtomicSwap::new(
+            initiator_htlc.clone(),
+            relative_htlc,
+            &chain_a,
+            &chain_b,
+            tips,
+            margin
+        )
+        .err(),
+        Some(SwapError::RelativeTimelock)
+    );
+    // the participant's leg matures a day before the initiator's, less than a two day margin
+    assert_eq!(
+        AtomicSwap::new(
+            initiator_htlc.clone(),
+            participant_htlc.clone(),
+            &chain_a,
+            &chain_b,
+            tips,
+            2 * 24 * 3_600
+        )
+        .err(),
+        Some(SwapError::TimeoutOrder)
+    );
+    let swap = AtomicSwap::new(
+        initiator_htlc,
+        participant_htlc,
+        &chain_a,
+        &chain_b,
+        tips,
+        margin,
+    )
+    .unwrap();
+    let send_to = Script::new_v1_p2tr(&secp, nums_x_only(), None);
+
+    let claim = swap
+        .initiator_claim(&initiator, preimage.clone(), send_to.clone())
+        .unwrap();
+    assert_eq!(chain_b.last_broadcast(), Some(claim.clone()));
+
+    // an unrelated transaction reveals nothing to claim with
+    let funding = chain_a.contract_source()[0].clone();
+    assert_eq!(
+        swap.participant_claim(&participant, &funding, send_to.clone())
+            .err(),
+        Some(HtlcError::MissingPreimage)
+    );
+
+    let counter_claim = swap
+        .participant_claim(&participant, &claim, send_to)
+        .unwrap();
+    assert_eq!(chain_a.last_broadcast(), Some(counter_claim.clone()));
+    assert_eq!(
+        swap.initiator_htlc.extract_preimage(&counter_claim),
+        Some(preimage)
+    );
+}
+
+#[test]
+fn swap_keys_lock_both_sides_to_the_same_htlc() {
+    use crate::bitcoin_wallet::{
+        constants::nums_x_only,
+        input_data::mock_call::MockCall,
+        scripts::timelock::{RelativeLock, TimeLock},
+        spending_path::htlc_path::{payment_hash, HtlcKind},
+    };
+
+    let secp = Secp256k1::new();
+    let initiator = SecretKey::from_slice(&[5u8; 32]).unwrap();
+    let participant = SecretKey::from_slice(&[6u8; 32]).unwrap();
+    let preimage = vec![7u8; 32];
+    let hash = payment_hash(&preimage);
+
+    // each side only knows its own secret and the other side's long lived key
+    let ours = swap_keys(
+        &secp,
+        &initiator,
+        &participant.x_only_public_key(&secp).0,
+        &hash,
+    )
+    .unwrap();
+    let theirs = swap_keys(
+        &secp,
+        &participant,
+        &initiator.x_only_public_key(&secp).0,
+        &hash,
+    )
+    .unwrap();
+    let timeout = TimeLock::Relative(RelativeLock::Blocks(144));
+    let htlc = Htlc::new(
+        &secp,
+        HtlcKind::SegwitV0,
+        hash,
+        ours.ours,
+        ours.theirs,
+        timeout,
+    );
+    let their_htlc = Htlc::new(
+        &secp,
+        HtlcKind::SegwitV0,
+        hash,
+        theirs.theirs,
+        theirs.ours,
+        timeout,
+    );
+    assert_eq!(htlc.script_pubkey(), their_htlc.script_pubkey());
+    assert_ne!(htlc.receiver, initiator.public_key(&secp));
+
+    let client = MockCall::fund(&htlc.script_pubkey(), &[50_000]);
+    let key_pair = KeyPair::from_secret_key(&secp, &ours.secret_key);
+    let send_to = Script::new_v1_p2tr(&secp, nums_x_only(), None);
+    let claim = spend_htlc(
+        &htlc,
+        &client,
+        HtlcPath::Claim,
+        &key_pair,
+        Some(preimage.clone()),
+        send_to.clone(),
+    )
+    .unwrap();
+    assert_eq!(htlc.extract_preimage(&claim), Some(preimage));
+
+    // the receiver signing the refund path leaves nothing the finalizer can use
+    let psbt = create_partially_signed_tx(
+        vec![vec![new_witness_pub_k(send_to)]],
+        htlc.create_tx(HtlcPath::Refund),
+        htlc.refund_input_factory(&key_pair),
+    )(&client);
+    assert_eq!(htlc.finalize(psbt), Err(HtlcError::UnsatisfiableInput(0)));
+}
@@ -5,10 +5,14 @@ use bitcoin::{Address, TxOut};
 use crate::bitcoin_wallet::input_data::RpcCall;
 
 pub mod freelancer;
+pub mod htlc;
 pub mod p2tr_key;
 pub mod p2tr_script;
 pub mod p2wpkh;
 pub mod p2wsh;
+pub mod payjoin;
+pub mod scriptless_swap;
+pub mod silent_payment;
 
 pub struct SendToImpl {}
 
@@ -43,25 +47,3 @@ pub fn single_output_with_value(string: String) -> Box<dyn Fn(u64) -> Vec<TxOut>
         out_put
     });
 }
-
-pub fn bisq_output(trade: String, bond: String) -> Box<dyn Fn(u64) -> Vec<TxOut>> {
-    return Box::new(move |total| {
-        // 3/5
-        let trade_total = (total * 3) / 5;
-        let out_put = vec![
-            TxOut {
-                value: (total - trade_total),
-                script_pubkey: Address::from_str(&trade.to_string())
-                    .unwrap()
-                    .script_pubkey(),
-            },
-            TxOut {
-                value: trade_total,
-                script_pubkey: Address::from_str(&bond.to_string())
-                    .unwrap()
-                    .script_pubkey(),
-            },
-        ];
-        out_put
-    });
-}
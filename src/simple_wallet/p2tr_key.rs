@@ -1,4 +1,4 @@
-use std::{ops::BitXor, str::FromStr};
+use std::str::FromStr;
 
 use bitcoin::{
     psbt::{Input, PartiallySignedTransaction, Prevouts},
@@ -7,20 +7,19 @@ use bitcoin::{
     util::{
         bip32::{ExtendedPrivKey, ExtendedPubKey},
         sighash::SighashCache,
-        taproot::{TapBranchHash, TapSighashTag, TapTweakHash, TapTweakTag},
+        taproot::TapBranchHash,
     },
-    Address, KeyPair, PackedLockTime, SchnorrSig, Transaction, TxOut, XOnlyPublicKey,
-};
-use bitcoin_hashes::{
-    hex::ToHex,
-    sha256,
-    sha256t::{self, Tag},
-    Hash, HashEngine,
+    Address, KeyPair, PackedLockTime, SchnorrSig, SchnorrSighashType, Transaction, TxOut,
+    XOnlyPublicKey,
 };
 use miniscript::psbt::PsbtExt;
 use serde::Serialize;
 
-use crate::bitcoin_wallet::{constants::NETWORK, input_data::RpcCall};
+use crate::bitcoin_wallet::{
+    constants::NETWORK,
+    input_data::RpcCall,
+    schnorr::{bip340, bip341, tagged_hash},
+};
 
 use super::Wallet;
 pub struct P2TR<'a, R: RpcCall> {
@@ -146,7 +145,17 @@ fn sign_tx(secp: &Secp256k1<All>, message: Message, key_pair: &KeyPair, tx_out:
         .x_only_public_key()
         .0));
 
-    let schnorr_sig = info.whatis_shnorr(&message, &tweaked_key_pair.to_inner());
+    let schnorr_sig = info.whatis_shnorr(
+        &message,
+        &tweaked_key_pair.to_inner(),
+        SchnorrSighashType::AllPlusAnyoneCanPay,
+    );
+    P2TRInfo::how_is_shnorr_verified(
+        secp,
+        &schnorr_sig.sig,
+        &message,
+        &tweaked_key_pair.to_inner().x_only_public_key().0,
+    );
 
     // let sig = secp.sign_schnorr(&message, &tweaked_key_pair.to_inner());
 
@@ -180,10 +189,18 @@ pub fn create_message(index: usize, unsigned_tx: &Transaction, prevouts: &Vec<Tx
     return message;
 }
 
-struct P2TRInfo {
+// walks through what the library does when spending a key path output, every step is delegated
+// to the bip340 and bip341 modules so it can be checked against their test vectors
+pub struct P2TRInfo {
     secp: Secp256k1<All>,
 }
 
+impl Default for P2TRInfo {
+    fn default() -> Self {
+        return P2TRInfo::new();
+    }
+}
+
 impl P2TRInfo {
     pub fn new() -> Self {
         P2TRInfo {
@@ -198,152 +215,36 @@ impl P2TRInfo {
         x_only: &XOnlyPublicKey,
     ) -> () {
         secp.verify_schnorr(signature, message, x_only).unwrap();
-        let random_aux = signature[..32].to_vec();
-        let sig = signature[32..].to_vec();
-
-        let e = Self::tagged_hash(
-            &"BIP0340/challenge".to_owned(),
-            vec![
-                &random_aux,
-                &x_only.serialize().to_vec(),
-                &message[..].to_vec(),
-            ],
-        );
-
-        // PH(R|P|m)+R
-        let my_sig = x_only
-            .public_key(bitcoin::secp256k1::Parity::Even)
-            .mul_tweak(&secp, &Scalar::from_be_bytes(e).unwrap())
-            .unwrap()
-            .combine(
-                &XOnlyPublicKey::from_slice(&random_aux)
-                    .unwrap()
-                    .public_key(bitcoin::secp256k1::Parity::Even),
-            )
-            .unwrap();
-
-        // sG-PH(R|P|m)=R
-        let their_sig = SecretKey::from_slice(&sig).unwrap().public_key(&secp);
-        assert_eq!(their_sig.serialize(), my_sig.serialize());
-        println!(
-            "{}: bitcoin rust sig \n{}: my sig value\n",
-            their_sig.serialize().to_hex(),
-            my_sig.serialize().to_hex()
-        );
+        // sG-H(R|P|m)P=R
+        assert!(bip340::verify(
+            secp,
+            &x_only.serialize(),
+            &message[..],
+            signature.as_ref()
+        ));
     }
 
     pub fn tagged_hash(tag: &str, args: Vec<&Vec<u8>>) -> [u8; 32] {
         // SHA256(SHA256(tag) || SHA256(tag) || x).
-
-        let mut engine_tag = bitcoin::hashes::sha256::Hash::engine();
-        engine_tag.input(tag.as_bytes());
-        let sha_256_tag = bitcoin::hashes::sha256::Hash::from_engine(engine_tag).into_inner();
-
-        let mut sha_256 = bitcoin::hashes::sha256::Hash::engine();
-        sha_256.input(&sha_256_tag.to_vec());
-        sha_256.input(&sha_256_tag.to_vec());
-        for x in args {
-            sha_256.input(&x);
-        }
-        return bitcoin::hashes::sha256::Hash::from_engine(sha_256).into_inner();
+        let data = args.iter().map(|arg| &arg[..]).collect::<Vec<&[u8]>>();
+        return tagged_hash(tag, &data);
     }
 
-    pub fn even_secret(&self, secret: &SecretKey) -> SecretKey {
-        if secret
-            .x_only_public_key(&self.secp)
-            .1
-            .eq(&bitcoin::secp256k1::Parity::Even)
-        {
-            return secret.clone();
-        }
-        return secret.negate();
-    }
-    // The secret key sk: a 32-byte array
-    // The message m: a 32-byte array
-    // Auxiliary random data a: a 32-byte array
-
-    // The algorithm Sign(sk, m) is defined as:
-    // Let d' = int(sk)
-    // Fail if d' = 0 or d' ≥ n
-    // Let P = d'⋅G
-    // Let d = d' if has_even_y(P), otherwise let d = n - d' .
-    // Let t be the byte-wise xor of bytes(d) and hashBIP0340/aux(a)[11].
-    // Let rand = hashBIP0340/nonce(t || bytes(P) || m)[12].
-    // Let k' = int(rand) mod n[13].
-    // Fail if k' = 0.
-    // Let R = k'⋅G.
-
-    // Let k = k' if has_even_y(R), otherwise let k = n - k' .
-    // Let e = int(hashBIP0340/challenge(bytes(R) || bytes(P) || m)) mod n.
-    // Let sig = bytes(R) || bytes((k + ed) mod n).
-    // If Verify(bytes(P), m, sig) (see below) returns failure, abort[14].
-    // Return the signature sig.
-
-    pub fn whatis_shnorr(&self, message: &Message, key_pair: &KeyPair) -> SchnorrSig {
-        // let key_pair =secret_key.keypair(&self.secp);
-        let secret_key = self.even_secret(&key_pair.secret_key());
+    pub fn whatis_shnorr(
+        &self,
+        message: &Message,
+        key_pair: &KeyPair,
+        hash_ty: SchnorrSighashType,
+    ) -> SchnorrSig {
         let auxilary = Scalar::random();
-
-        let sig =
-            self.secp
-                .sign_schnorr_with_aux_rand(&message, &key_pair, &auxilary.to_be_bytes());
-
-        let x_only = secret_key.x_only_public_key(&self.secp).0;
-        let d = secret_key.secret_bytes().to_vec();
-
-        let t = d
-            .iter()
-            .zip(
-                Self::tagged_hash(
-                    &"BIP0340/aux".to_owned(),
-                    vec![&auxilary.to_be_bytes().to_vec()],
-                )
-                .iter(),
-            )
-            .map(|(&x1, &x2)| x1 ^ x2)
-            .collect();
-
-        let rand = Self::tagged_hash(
-            "BIP0340/nonce",
-            vec![&t, &x_only.serialize().to_vec(), &message[..].to_vec()],
-        );
-        let our_r = SecretKey::from_slice(&rand)
-            .unwrap()
-            .x_only_public_key(&self.secp)
-            .0;
-        let k = self.even_secret(&SecretKey::from_slice(&rand).unwrap());
-
-        let e = Self::tagged_hash(
-            &"BIP0340/challenge".to_owned(),
-            vec![
-                &our_r.serialize().to_vec(),
-                &x_only.serialize().to_vec(),
-                &message[..].to_vec(),
-            ],
-        );
-        let our_sig = SecretKey::from_slice(&e)
-            .unwrap()
-            .mul_tweak(&Scalar::from_be_bytes(d.try_into().unwrap()).unwrap())
-            .unwrap()
-            .add_tweak(&Scalar::from_be_bytes(k.secret_bytes()).unwrap())
-            .unwrap();
-
-        let mut our_signature = our_r.serialize().to_vec();
-
-        our_signature.extend(our_sig.secret_bytes());
-        our_signature.push(0x81 as u8);
-
-        let shnorr_sig = SchnorrSig {
-            sig: sig,
-            hash_ty: bitcoin::SchnorrSighashType::AllPlusAnyoneCanPay,
-        };
-        //    println!("{}: bitcoin rust R \n{}: our random R\n",
-        //         shnorr_sig.to_vec().to_hex(),
-        //         our_signature.to_hex()
-        //     );
-
-        return SchnorrSig::from_slice(&our_signature[..]).unwrap();
-        // return shnorr_sig;
+        let sig = bip340::sign(
+            &self.secp,
+            &key_pair.secret_key(),
+            &message[..],
+            auxilary.to_be_bytes(),
+        )
+        .unwrap();
+        return SchnorrSig { sig, hash_ty };
     }
 
     pub fn whatis_tap_tweak(
@@ -352,28 +253,9 @@ impl P2TRInfo {
         merkle_root: Option<TapBranchHash>,
     ) -> TweakedKeyPair {
         // q=p+H(P|c)
-
-        let x_only = key_pair.x_only_public_key().0;
-
-        let mut engine = TapTweakHash::engine();
-        engine.input(&x_only.serialize());
-
-        if merkle_root.is_some() {
-            engine.input(&merkle_root.unwrap())
-        }
-
-        // because our tapbranch is none we aren't hashing anything else
-        // so our equation looks more like q=p+H(P)
-
-        let tap_tweak_hash = TapTweakHash::from_engine(engine).to_scalar();
-
-        // tap_tweak_hash
-        let secret_key = key_pair.secret_key();
-        let tweak_pair = secret_key
-            .add_tweak(&tap_tweak_hash)
+        return bip341::tweak_secret_key(&self.secp, &key_pair.secret_key(), merkle_root)
             .unwrap()
             .keypair(&self.secp)
             .dangerous_assume_tweaked();
-        return tweak_pair;
     }
 }
@@ -9,12 +9,17 @@ use bitcoin::{
         sighash::{ScriptPath, SighashCache},
         taproot::{LeafVersion, TapLeafHash, TaprootBuilder},
     },
-    Address, KeyPair, PackedLockTime, SchnorrSig, SchnorrSighashType, Script, Transaction, TxIn,
-    TxOut, Witness, XOnlyPublicKey,
+    Address, KeyPair, PackedLockTime, SchnorrSig, SchnorrSighashType, Script, Transaction, TxOut,
+    XOnlyPublicKey,
 };
 use bitcoin_hashes::{hex::FromHex, Hash};
 
-use crate::bitcoin_wallet::{constants::NETWORK, input_data::RpcCall};
+use crate::bitcoin_wallet::{
+    constants::NETWORK,
+    input_data::RpcCall,
+    script_services::tap_finalizer::finalize_tap_psbt,
+    scripts::timelock::{relative_delay, script_timelocks, MixedLockUnits, RelativeLock},
+};
 
 pub struct P2TRS<'a, R: RpcCall> {
     secret_key: SecretKey,
@@ -44,7 +49,13 @@ impl<'a, R> P2TRS<'a, R>
 where
     R: RpcCall,
 {
-    pub fn sign(&self, output: &Output, send_to: Box<dyn Fn(u64) -> Vec<TxOut>>) {
+    // leaf is the script of the tree being spent, bob's preimage leaf or alice's delay leaf
+    pub fn sign(
+        &self,
+        output: &Output,
+        leaf: &Script,
+        send_to: Box<dyn Fn(u64) -> Vec<TxOut>>,
+    ) -> Result<Transaction, MixedLockUnits> {
         let tx_in_list = self.client.prev_input();
 
         let transaction_list = self.client.contract_source();
@@ -61,23 +72,39 @@ where
 
         let tx_out = send_to(total - self.client.fee());
 
-        let unsigned_tx = Transaction {
+        let mut unsigned_tx = Transaction {
             version: 2,
             lock_time: PackedLockTime(0),
             input: tx_in_list,
             output: tx_out,
         };
 
+        // the sighash commits to nSequence and nLockTime, so the locks of the spent leaf go in
+        // before signing
+        let leaf_locks = script_timelocks(leaf);
+        for index in 0..unsigned_tx.input.len() {
+            leaf_locks
+                .iter()
+                .try_for_each(|lock| lock.apply(&mut unsigned_tx, index))?;
+        }
+
         let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx.clone()).unwrap();
 
-        psbt.inputs =
-            self.sign_all_unsigned_tx(&self.secp, &prevouts, &unsigned_tx, &key_pair, &output);
+        psbt.inputs = self.sign_all_unsigned_tx(
+            &self.secp,
+            &prevouts,
+            &unsigned_tx,
+            &key_pair,
+            &output,
+            leaf,
+        );
 
         psbt.outputs = vec![output.clone()];
 
         let tx = self.finalize_script(psbt);
 
         self.client.broadcasts_transacton(&tx);
+        return Ok(tx);
     }
 
     fn sign_all_unsigned_tx(
@@ -87,6 +114,7 @@ where
         unsigned_tx: &Transaction,
         key_pair: &KeyPair,
         output: &Output,
+        leaf: &Script,
     ) -> Vec<Input> {
         return prevouts
             .iter()
@@ -100,6 +128,7 @@ where
                     key_pair,
                     tx_out,
                     output,
+                    leaf,
                 )
                 .clone()
             })
@@ -115,6 +144,7 @@ where
         key_pair: &KeyPair,
         tx_out: &TxOut,
         output: &Output,
+        leaf: &Script,
     ) -> Input {
         let tap_info = output
             .clone()
@@ -126,13 +156,12 @@ where
             .unwrap();
         let x_only = &self.secret_key.x_only_public_key(&secp).0;
 
-        let bob_script = bob_scripts(x_only, &preimage(&self.image));
-        let control = tap_info.control_block(&(bob_script.clone(), LeafVersion::TapScript));
+        let control = tap_info.control_block(&(leaf.clone(), LeafVersion::TapScript));
 
         let verify = control.as_ref().unwrap().verify_taproot_commitment(
             &secp,
             tap_info.output_key().to_inner(),
-            &bob_script,
+            leaf,
         );
 
         if (!verify) {
@@ -143,7 +172,7 @@ where
             .taproot_script_spend_signature_hash(
                 index,
                 &Prevouts::All(&prevouts),
-                ScriptPath::with_defaults(&bob_script),
+                ScriptPath::with_defaults(leaf),
                 SchnorrSighashType::AllPlusAnyoneCanPay,
             )
             .unwrap();
@@ -157,7 +186,7 @@ where
             hash_ty: bitcoin::SchnorrSighashType::AllPlusAnyoneCanPay,
         };
 
-        let tap_leaf_hash = TapLeafHash::from_script(&bob_script, LeafVersion::TapScript);
+        let tap_leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
 
         let mut input = Input::default();
 
@@ -166,55 +195,23 @@ where
         input.witness_utxo = Some(tx_out.clone());
 
         input.tap_merkle_root = tap_info.merkle_root();
-        input.tap_scripts.insert(
-            control.unwrap(),
-            (bob_script.clone(), LeafVersion::TapScript),
-        );
+        input
+            .tap_scripts
+            .insert(control.unwrap(), (leaf.clone(), LeafVersion::TapScript));
         input
             .tap_script_sigs
             .insert((x_only.clone(), tap_leaf_hash), schnorr_sig);
 
+        let image = Vec::from_hex(&self.image).unwrap();
+        input
+            .sha256_preimages
+            .insert(bitcoin_hashes::sha256::Hash::hash(&image), image);
+
         return input;
     }
 
     pub fn finalize_script(&self, psbt: PartiallySignedTransaction) -> Transaction {
-        let tx = psbt.clone().extract_tx().clone();
-        let tx_in = psbt
-            .inputs
-            .iter()
-            .map(|input| {
-                let mut witness = Witness::new();
-
-                input.tap_script_sigs.iter().for_each(|sig| {
-                    let shnor = sig.1;
-                    witness.push(shnor.to_vec());
-                });
-
-                witness.push(Vec::from_hex(&self.image).unwrap());
-
-                input.tap_scripts.iter().for_each(|control| {
-                    witness.push(control.1 .0.as_bytes());
-                    witness.push(control.0.serialize());
-                });
-                return witness;
-            })
-            .zip(tx.input)
-            .map(|(witness, tx_input)| {
-                return TxIn {
-                    previous_output: tx_input.previous_output,
-                    script_sig: tx_input.script_sig,
-                    sequence: tx_input.sequence,
-                    witness,
-                };
-            })
-            .collect::<Vec<TxIn>>();
-
-        return Transaction {
-            version: tx.version,
-            lock_time: tx.lock_time,
-            input: tx_in,
-            output: tx.output,
-        };
+        return finalize_tap_psbt(psbt).unwrap();
     }
 }
 pub fn seed_to_xonly(secret_string: &Option<&str>) -> bitcoin::XOnlyPublicKey {
@@ -246,24 +243,19 @@ pub fn bob_scripts(x_only: &XOnlyPublicKey, preimage: &[u8]) -> Script {
 }
 
 //  Script(OP_PUSHBYTES_2 9000 OP_CSV OP_DROP OP_PUSHBYTES_32 9997a497d964fc1a62885b05a51166a65a90df00492c8d7cf61d6accf54803be OP_CHECKSIG)
-pub fn alice_script(x_only: &XOnlyPublicKey) -> Script {
-    return Builder::new()
-        .push_int(0x0090)
-        .push_opcode(all::OP_CSV)
-        .push_opcode(all::OP_DROP)
-        .push_x_only_key(&x_only)
-        .push_opcode(all::OP_CHECKSIG)
-        .into_script();
+pub fn alice_script(x_only: &XOnlyPublicKey, lock: RelativeLock) -> Script {
+    return relative_delay(x_only, lock);
 }
 
 pub fn create_address(
     alice_x_only: XOnlyPublicKey,
     bob_x_only: XOnlyPublicKey,
     preimage: Vec<u8>,
+    lock: RelativeLock,
 ) -> Output {
     let mut output = Output::default();
     let secp = Secp256k1::new();
-    let alice = alice_script(&alice_x_only);
+    let alice = alice_script(&alice_x_only, lock);
     let bob = bob_scripts(&bob_x_only, &preimage);
     let combined_script = vec![(1, bob.clone()), (1, alice.clone())];
     let builder = TaprootBuilder::with_huffman_tree(combined_script).unwrap();
@@ -309,3 +301,68 @@ pub fn create_address(
 
 //  "Script(OP_SHA256 OP_PUSHBYTES_32 6c60f404f8167a38fc70eaf8aa17ac351023bef86bcb9d1086a19afe95bd5333 OP_EQUALVERIFY OP_PUSHBYTES_32 4edfcf9dfe6c0b5c83d1ab3f78d1b39a46ebac6798e08e19761f5ed89ec83c10 OP_CHECKSIG)"
 // "Script(OP_SHA256 OP_PUSHBYTES_32 6c60f404f8167a38fc70eaf8aa17ac351023bef86bcb9d1086a19afe95bd5333 OP_EQUALVERIFY OP_PUSHBYTES_32 4edfcf9dfe6c0b5c83d1ab3f78d1b39a46ebac6798e08e19761f5ed89ec83c10 OP_CHECKSIG)"
+
+#[test]
+fn alice_spends_the_delay_leaf_once_the_csv_is_set() {
+    use bitcoin::{Sequence, Witness};
+
+    use crate::{bitcoin_wallet::input_data::mock_call::MockCall, simple_wallet::single_output};
+
+    let alice_seed = "2bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e90";
+    let bob_seed = "81b637d8fcd2c6da6359e6963113a1170de795e4b725b84d1e0b4cfd9ec58ce9";
+    let bob_image = "107661134f21fc7c02223d50ab9eb3600bc3ffc3712423a1e47bb1f9a9dbf55f";
+    let lock = RelativeLock::Blocks(144);
+    let alice_xonly = seed_to_xonly(&Some(alice_seed));
+    let output = create_address(
+        alice_xonly,
+        seed_to_xonly(&Some(bob_seed)),
+        preimage(bob_image),
+        lock,
+    );
+    let script_pubkey = output.witness_script.clone().unwrap();
+    let client = MockCall::fund(&script_pubkey, &[50_000]);
+
+    let alice_leaf = alice_script(&alice_xonly, lock);
+    let tx = P2TRS::new(alice_seed, bob_image, &client)
+        .sign(&output, &alice_leaf, single_output())
+        .unwrap();
+    assert_eq!(client.last_broadcast(), Some(tx.clone()));
+
+    // OP_DROP keeps the leaf out of miniscript, so check what the interpreter would: BIP68 is on,
+    // the sequence carries the delay and the signature covers it
+    assert_eq!(tx.version, 2);
+    assert_eq!(tx.input[0].sequence, Sequence::from_height(144));
+    let witness = tx.input[0].witness.to_vec();
+    assert_eq!(witness.len(), 3);
+    assert_eq!(witness[1], alice_leaf.to_bytes());
+    let sig = SchnorrSig::from_slice(&witness[0]).unwrap();
+    let prevouts = client.contract_source()[0].output.clone();
+    let sighash = SighashCache::new(&tx)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&prevouts),
+            ScriptPath::with_defaults(&alice_leaf),
+            sig.hash_ty,
+        )
+        .unwrap();
+    let secp = Secp256k1::new();
+    let msg = Message::from_slice(&sighash).unwrap();
+    assert!(secp.verify_schnorr(&sig.sig, &msg, &alice_xonly).is_ok());
+
+    // with the delay taken out the signature no longer matches
+    let mut early = tx.clone();
+    early.input[0].sequence = Sequence::MAX;
+    early.input[0].witness = Witness::default();
+    let early_sighash = SighashCache::new(&early)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&prevouts),
+            ScriptPath::with_defaults(&alice_leaf),
+            sig.hash_ty,
+        )
+        .unwrap();
+    let early_msg = Message::from_slice(&early_sighash).unwrap();
+    assert!(secp
+        .verify_schnorr(&sig.sig, &early_msg, &alice_xonly)
+        .is_err());
+}
@@ -0,0 +1,753 @@
+use std::{
+    collections::HashMap, convert::Infallible, fmt, net::SocketAddr, str::FromStr, sync::Arc,
+};
+
+use bitcoin::{
+    psbt::{Input, PartiallySignedTransaction, Prevouts},
+    schnorr::TapTweak,
+    secp256k1::{rand, All, Message, Secp256k1},
+    util::sighash::SighashCache,
+    KeyPair, OutPoint, SchnorrSig, SchnorrSighashType, Script, Transaction, TxIn, TxOut, Witness,
+    XOnlyPublicKey,
+};
+use hyper::{
+    body::to_bytes,
+    service::{make_service_fn, service_fn},
+    Body, Client, Method, Request, Response, Server, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::bitcoin_wallet::input_data::RpcCall;
+
+// vbytes a taproot key path input adds, 41 bytes outside the witness and a 64 byte signature
+const KEY_PATH_INPUT_VSIZE: u64 = 58;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayjoinError {
+    Transport(String),
+    // the receiver answered with one of the BIP78 error codes
+    Rejected { code: String, message: String },
+    Psbt(String),
+    // the proposal failed one of the sender checks
+    Proposal(&'static str),
+}
+
+impl fmt::Display for PayjoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayjoinError::Transport(err) => write!(f, "payjoin endpoint: {}", err),
+            PayjoinError::Rejected { code, message } => {
+                write!(f, "receiver rejected the payjoin ({}): {}", code, message)
+            }
+            PayjoinError::Psbt(err) => write!(f, "invalid psbt: {}", err),
+            PayjoinError::Proposal(check) => write!(f, "payjoin proposal {}", check),
+        }
+    }
+}
+
+impl std::error::Error for PayjoinError {}
+
+impl From<hyper::Error> for PayjoinError {
+    fn from(err: hyper::Error) -> Self {
+        PayjoinError::Transport(err.to_string())
+    }
+}
+
+// the json body BIP78 receivers answer with when they don't pay join
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiverError {
+    pub error_code: String,
+    pub message: String,
+}
+
+impl ReceiverError {
+    fn new(error_code: &str, message: &str) -> Self {
+        return ReceiverError {
+            error_code: error_code.to_owned(),
+            message: message.to_owned(),
+        };
+    }
+
+    fn rejected(message: &str) -> Self {
+        return ReceiverError::new("original-psbt-rejected", message);
+    }
+}
+
+// the optional parameters of BIP78, the sender always disables output substitution since it
+// only accepts proposals that still pay the payee output it built
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayjoinParams {
+    pub additional_fee_output_index: Option<usize>,
+    pub max_additional_fee_contribution: u64,
+    pub min_fee_rate: f64,
+}
+
+impl PayjoinParams {
+    fn query(&self) -> String {
+        let mut query = format!(
+            "v=1&disableoutputsubstitution=true&minfeerate={}",
+            self.min_fee_rate
+        );
+        if let Some(index) = self.additional_fee_output_index {
+            query.push_str(&format!(
+                "&additionalfeeoutputindex={}&maxadditionalfeecontribution={}",
+                index, self.max_additional_fee_contribution
+            ));
+        }
+        return query;
+    }
+
+    fn from_query(query: &str) -> Result<Self, ReceiverError> {
+        let pairs = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .collect::<HashMap<&str, &str>>();
+        if pairs.get("v").copied().unwrap_or("1") != "1" {
+            return Err(ReceiverError::new(
+                "version-unsupported",
+                "this receiver only speaks version 1",
+            ));
+        }
+        let parse = |key: &str| pairs.get(key).map(|value| value.parse::<f64>());
+        let invalid = |_| ReceiverError::rejected("invalid query parameter");
+        return Ok(PayjoinParams {
+            additional_fee_output_index: parse("additionalfeeoutputindex")
+                .transpose()
+                .map_err(invalid)?
+                .map(|index| index as usize),
+            max_additional_fee_contribution: parse("maxadditionalfeecontribution")
+                .transpose()
+                .map_err(invalid)?
+                .unwrap_or_default() as u64,
+            min_fee_rate: parse("minfeerate")
+                .transpose()
+                .map_err(invalid)?
+                .unwrap_or_default(),
+        });
+    }
+}
+
+// the fee of a psbt whose inputs all carry their witness utxo
+fn fee(psbt: &PartiallySignedTransaction) -> Option<u64> {
+    let input_value = psbt
+        .inputs
+        .iter()
+        .map(|input| input.witness_utxo.as_ref().map(|tx_out| tx_out.value))
+        .sum::<Option<u64>>()?;
+    let output_value = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|tx_out| tx_out.value)
+        .sum::<u64>();
+    return input_value.checked_sub(output_value);
+}
+
+// sat per vbyte, tx is the psbt's transaction with its witnesses
+fn fee_rate(psbt: &PartiallySignedTransaction, tx: &Transaction) -> Option<f64> {
+    return Some(fee(psbt)? as f64 / tx.vsize() as f64);
+}
+
+// every input is a taproot key path spend whose signature verifies, anything else could not be
+// broadcast as it is or is a kind of input we can't check
+fn verify_key_path_inputs(secp: &Secp256k1<All>, tx: &Transaction, prevouts: &[TxOut]) -> bool {
+    if tx.input.is_empty() || tx.output.is_empty() || tx.input.len() != prevouts.len() {
+        return false;
+    }
+    let mut cache = SighashCache::new(tx);
+    for (index, (tx_in, prevout)) in tx.input.iter().zip(prevouts).enumerate() {
+        let witness = tx_in.witness.to_vec();
+        if !prevout.script_pubkey.is_v1_p2tr() || witness.len() != 1 {
+            return false;
+        }
+        let verified = SchnorrSig::from_slice(&witness[0])
+            .ok()
+            .and_then(|sig| {
+                let sighash = cache
+                    .taproot_key_spend_signature_hash(index, &Prevouts::All(prevouts), sig.hash_ty)
+                    .ok()?;
+                let output_key =
+                    XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..34]).ok()?;
+                let message = Message::from_slice(&sighash).ok()?;
+                return secp.verify_schnorr(&sig.sig, &message, &output_key).ok();
+            })
+            .is_some();
+        if !verified {
+            return false;
+        }
+    }
+    return true;
+}
+
+// the witness of every input, from the finalized inputs first and the key path signatures
+fn extract_tx(psbt: &PartiallySignedTransaction) -> Option<Transaction> {
+    let mut tx = psbt.unsigned_tx.clone();
+    for (tx_in, input) in tx.input.iter_mut().zip(&psbt.inputs) {
+        tx_in.witness = match (&input.final_script_witness, input.tap_key_sig) {
+            (Some(witness), _) => witness.clone(),
+            (None, Some(sig)) => Witness::from_vec(vec![sig.to_vec()]),
+            (None, None) => return None,
+        };
+    }
+    return Some(tx);
+}
+
+// signs and finalizes the taproot key path inputs the key pair owns, every input needs its
+// witness utxo for the sighash
+fn sign_key_path_inputs(
+    secp: &Secp256k1<All>,
+    key_pair: &KeyPair,
+    psbt: &mut PartiallySignedTransaction,
+) -> Option<()> {
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .map(|input| input.witness_utxo.clone())
+        .collect::<Option<Vec<TxOut>>>()?;
+    let tweaked = key_pair.tap_tweak(secp, None).to_inner();
+    let script_pubkey = Script::new_v1_p2tr(secp, key_pair.x_only_public_key().0, None);
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    for (index, prevout) in prevouts.iter().enumerate() {
+        if prevout.script_pubkey != script_pubkey
+            || psbt.inputs[index].final_script_witness.is_some()
+        {
+            continue;
+        }
+        let sighash = cache
+            .taproot_key_spend_signature_hash(
+                index,
+                &Prevouts::All(&prevouts),
+                SchnorrSighashType::Default,
+            )
+            .ok()?;
+        let message = Message::from_slice(&sighash).unwrap();
+        let sig = SchnorrSig {
+            sig: secp.sign_schnorr(&message, &tweaked),
+            hash_ty: SchnorrSighashType::Default,
+        };
+        psbt.inputs[index].tap_key_sig = Some(sig);
+        psbt.inputs[index].final_script_witness = Some(Witness::from_vec(vec![sig.to_vec()]));
+    }
+    return Some(());
+}
+
+// the merchant side, it owns taproot key path coins and adds one of them to every payment
+pub struct PayjoinReceiver {
+    secp: Secp256k1<All>,
+    key_pair: KeyPair,
+    utxos: Vec<(OutPoint, TxOut)>,
+}
+
+impl PayjoinReceiver {
+    pub fn new<R: RpcCall>(key_pair: KeyPair, client: &R) -> Self {
+        let secp = Secp256k1::new();
+        let script_pubkey = Script::new_v1_p2tr(&secp, key_pair.x_only_public_key().0, None);
+        let previous_tx = client.contract_source();
+        let utxos = client
+            .prev_input()
+            .iter()
+            .filter_map(|tx_in| {
+                let outpoint = tx_in.previous_output;
+                let tx_out = previous_tx
+                    .iter()
+                    .find(|tx| tx.txid() == outpoint.txid)?
+                    .output
+                    .get(outpoint.vout as usize)?
+                    .clone();
+                return Some((outpoint, tx_out));
+            })
+            .filter(|(_, tx_out)| tx_out.script_pubkey == script_pubkey)
+            .collect();
+        return PayjoinReceiver {
+            secp,
+            key_pair,
+            utxos,
+        };
+    }
+
+    pub fn script_pubkey(&self) -> Script {
+        return Script::new_v1_p2tr(&self.secp, self.key_pair.x_only_public_key().0, None);
+    }
+
+    // the checks on the original psbt, then one of our inputs goes in at a random position and
+    // our output grows by its value minus the fee for the extra input
+    pub fn process(&self, original: &str, query: &str) -> Result<String, ReceiverError> {
+        let params = PayjoinParams::from_query(query)?;
+        let original = PartiallySignedTransaction::from_str(original.trim())
+            .map_err(|_| ReceiverError::rejected("body is not a base64 psbt"))?;
+        let finalized = original
+            .inputs
+            .iter()
+            .all(|input| input.final_script_witness.is_some() && input.witness_utxo.is_some());
+        if !finalized || original.inputs.len() != original.unsigned_tx.input.len() {
+            return Err(ReceiverError::rejected("original psbt is not finalized"));
+        }
+        let ours = self.script_pubkey();
+        if original.inputs.iter().any(|input| {
+            input
+                .witness_utxo
+                .as_ref()
+                .map(|tx_out| &tx_out.script_pubkey)
+                == Some(&ours)
+        }) {
+            return Err(ReceiverError::rejected(
+                "original psbt spends our own coins",
+            ));
+        }
+        let payee = original
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|tx_out| tx_out.script_pubkey == ours)
+            .ok_or_else(|| ReceiverError::rejected("original psbt does not pay us"))?;
+        let original_tx = extract_tx(&original).unwrap();
+        let prevouts = original
+            .inputs
+            .iter()
+            .filter_map(|input| input.witness_utxo.clone())
+            .collect::<Vec<TxOut>>();
+        let mut spent = original_tx
+            .input
+            .iter()
+            .map(|tx_in| tx_in.previous_output)
+            .collect::<Vec<_>>();
+        spent.sort();
+        spent.dedup();
+        if spent.len() != original_tx.input.len()
+            || !verify_key_path_inputs(&self.secp, &original_tx, &prevouts)
+        {
+            return Err(ReceiverError::rejected(
+                "original psbt is not a valid signed transaction",
+            ));
+        }
+        let original_rate = fee_rate(&original, &original_tx)
+            .ok_or_else(|| ReceiverError::rejected("original psbt pays no fee"))?;
+        let (outpoint, tx_out) = self
+            .utxos
+            .iter()
+            .find(|(outpoint, _)| {
+                !original_tx
+                    .input
+                    .iter()
+                    .any(|tx_in| tx_in.previous_output == *outpoint)
+            })
+            .ok_or_else(|| ReceiverError::new("unavailable", "no coin left to pay join with"))?;
+
+        // the sender pays for our input up to what it allowed, we pay the rest
+        let input_fee =
+            (KEY_PATH_INPUT_VSIZE as f64 * original_rate.max(params.min_fee_rate)).ceil() as u64;
+        let mut unsigned_tx = original.unsigned_tx.clone();
+        let sender_share = match params.additional_fee_output_index {
+            Some(index) if index != payee && index < unsigned_tx.output.len() => {
+                let share = input_fee
+                    .min(params.max_additional_fee_contribution)
+                    .min(unsigned_tx.output[index].value);
+                unsigned_tx.output[index].value -= share;
+                share
+            }
+            _ => 0,
+        };
+        // a coin below the fee for its own input would take from the payment instead of adding
+        let added = input_fee
+            .checked_sub(sender_share)
+            .and_then(|receiver_fee| tx_out.value.checked_sub(receiver_fee))
+            .ok_or_else(|| {
+                ReceiverError::new(
+                    "unavailable",
+                    "our coin does not cover the fee of its input",
+                )
+            })?;
+        unsigned_tx.output[payee].value = unsigned_tx.output[payee]
+            .value
+            .checked_add(added)
+            .ok_or_else(|| ReceiverError::rejected("payee output overflows"))?;
+        let position = rand::random::<usize>() % (unsigned_tx.input.len() + 1);
+        unsigned_tx.input.insert(
+            position,
+            TxIn {
+                previous_output: *outpoint,
+                sequence: unsigned_tx.input[0].sequence,
+                ..Default::default()
+            },
+        );
+        unsigned_tx
+            .input
+            .iter_mut()
+            .for_each(|tx_in| tx_in.witness = Witness::default());
+
+        let mut proposal = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+        let mut sender_inputs = original.inputs.iter();
+        for (index, input) in proposal.inputs.iter_mut().enumerate() {
+            input.witness_utxo = match index == position {
+                true => Some(tx_out.clone()),
+                false => sender_inputs.next().unwrap().witness_utxo.clone(),
+            };
+        }
+        sign_key_path_inputs(&self.secp, &self.key_pair, &mut proposal)
+            .ok_or_else(|| ReceiverError::new("unavailable", "could not sign our input"))?;
+        // the sender inputs go back without utxos or signatures, the sender signs them again
+        for (index, input) in proposal.inputs.iter_mut().enumerate() {
+            *input = match index == position {
+                true => Input {
+                    witness_utxo: input.witness_utxo.clone(),
+                    final_script_witness: input.final_script_witness.clone(),
+                    ..Default::default()
+                },
+                false => Input::default(),
+            };
+        }
+        return Ok(proposal.to_string());
+    }
+}
+
+// a POST endpoint on addr, port 0 picks a free one, the handle runs until it is aborted
+pub async fn serve(
+    receiver: Arc<PayjoinReceiver>,
+    addr: SocketAddr,
+) -> Result<(SocketAddr, JoinHandle<()>), PayjoinError> {
+    let make_service = make_service_fn(move |_| {
+        let receiver = receiver.clone();
+        return async move {
+            return Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let receiver = receiver.clone();
+                return async move {
+                    return Ok::<_, Infallible>(handle(&receiver, request).await);
+                };
+            }));
+        };
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+    let handle = tokio::spawn(async move {
+        // an error only ends the task, senders see a closed endpoint and broadcast the original
+        let _ = server.await;
+    });
+    return Ok((local_addr, handle));
+}
+
+async fn handle(receiver: &PayjoinReceiver, request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::POST {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let query = request.uri().query().unwrap_or_default().to_owned();
+    let body = to_bytes(request.into_body())
+        .await
+        .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+        .unwrap_or_default();
+    return match receiver.process(&body, &query) {
+        Ok(proposal) => Response::new(Body::from(proposal)),
+        Err(err) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_string(&err).unwrap()))
+            .unwrap(),
+    };
+}
+
+// the paying side, it keeps the original so it can fall back to broadcasting it
+pub struct PayjoinSender<'a, R: RpcCall> {
+    secp: Secp256k1<All>,
+    key_pair: KeyPair,
+    original: PartiallySignedTransaction,
+    params: PayjoinParams,
+    client: &'a R,
+}
+
+impl<'a, R> PayjoinSender<'a, R>
+where
+    R: RpcCall,
+{
+    // takes a psbt from create_partially_signed_tx with the key path signatures in place and
+    // finalizes it into the original BIP78 wants
+    pub fn new(
+        key_pair: KeyPair,
+        mut original: PartiallySignedTransaction,
+        params: PayjoinParams,
+        client: &'a R,
+    ) -> Result<Self, PayjoinError> {
+        for input in original.inputs.iter_mut() {
+            let sig = input.tap_key_sig.ok_or(PayjoinError::Psbt(
+                "original input is not signed".to_owned(),
+            ))?;
+            input.final_script_witness = Some(Witness::from_vec(vec![sig.to_vec()]));
+        }
+        // psbt_factory marks its psbts as version 2, which BIP78 receivers don't parse
+        original.version = 0;
+        return Ok(PayjoinSender {
+            secp: Secp256k1::new(),
+            key_pair,
+            original,
+            params,
+            client,
+        });
+    }
+
+    pub fn original_tx(&self) -> Transaction {
+        return extract_tx(&self.original).unwrap();
+    }
+
+    pub async fn request(
+        &self,
+        endpoint: &str,
+    ) -> Result<PartiallySignedTransaction, PayjoinError> {
+        let uri = format!("{}?{}", endpoint, self.params.query());
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header("content-type", "text/plain")
+            .body(Body::from(self.original.to_string()))
+            .map_err(|err| PayjoinError::Transport(err.to_string()))?;
+        let response = Client::new().request(request).await?;
+        let status = response.status();
+        let body = to_bytes(response.into_body()).await?;
+        let body = String::from_utf8_lossy(&body).to_string();
+        if !status.is_success() {
+            let err = serde_json::from_str::<ReceiverError>(&body).unwrap_or(ReceiverError {
+                error_code: "unavailable".to_owned(),
+                message: body,
+            });
+            return Err(PayjoinError::Rejected {
+                code: err.error_code,
+                message: err.message,
+            });
+        }
+        return PartiallySignedTransaction::from_str(body.trim())
+            .map_err(|err| PayjoinError::Psbt(err.to_string()));
+    }
+
+    // the BIP78 sender checks, then our inputs get signed again and the payjoin is broadcast
+    pub fn process_proposal(
+        &self,
+        mut proposal: PartiallySignedTransaction,
+    ) -> Result<Transaction, PayjoinError> {
+        let original = &self.original.unsigned_tx;
+        let tx = &proposal.unsigned_tx;
+        if tx.version != original.version || tx.lock_time != original.lock_time {
+            return Err(PayjoinError::Proposal("changed the version or lock time"));
+        }
+        if proposal.inputs.len() != tx.input.len() || proposal.outputs.len() != tx.output.len() {
+            return Err(PayjoinError::Proposal("does not match its transaction"));
+        }
+        let ours = Script::new_v1_p2tr(&self.secp, self.key_pair.x_only_public_key().0, None);
+        let mut sender_inputs = 0;
+        for (tx_in, input) in tx.input.iter().zip(proposal.inputs.iter_mut()) {
+            match original
+                .input
+                .iter()
+                .position(|original_in| original_in.previous_output == tx_in.previous_output)
+            {
+                Some(index) => {
+                    if tx_in.sequence != original.input[index].sequence {
+                        return Err(PayjoinError::Proposal("changed the sequence of our input"));
+                    }
+                    if input.final_script_witness.is_some()
+                        || input.tap_key_sig.is_some()
+                        || input.witness_utxo.is_some()
+                    {
+                        return Err(PayjoinError::Proposal("kept data on our input"));
+                    }
+                    input.witness_utxo = self.original.inputs[index].witness_utxo.clone();
+                    sender_inputs += 1;
+                }
+                None => {
+                    let prevout = input
+                        .witness_utxo
+                        .as_ref()
+                        .ok_or(PayjoinError::Proposal("has a receiver input without utxo"))?;
+                    if input.final_script_witness.is_none() {
+                        return Err(PayjoinError::Proposal("has an unsigned receiver input"));
+                    }
+                    if !prevout.script_pubkey.is_v1_p2tr() {
+                        return Err(PayjoinError::Proposal("mixes input types"));
+                    }
+                    if prevout.script_pubkey == ours {
+                        return Err(PayjoinError::Proposal("claims one of our coins"));
+                    }
+                    if tx_in.sequence != original.input[0].sequence {
+                        return Err(PayjoinError::Proposal("uses another sequence"));
+                    }
+                }
+            }
+        }
+        if sender_inputs != original.input.len() {
+            return Err(PayjoinError::Proposal("dropped one of our inputs"));
+        }
+        if proposal
+            .outputs
+            .iter()
+            .any(|output| !output.bip32_derivation.is_empty() || !output.tap_key_origins.is_empty())
+        {
+            return Err(PayjoinError::Proposal("leaks key origins in an output"));
+        }
+
+        // every original output is still there, only the fee output may shrink and only by what
+        // we allowed
+        let mut contribution = 0;
+        for (index, original_out) in original.output.iter().enumerate() {
+            let tx_out = tx
+                .output
+                .iter()
+                .find(|tx_out| tx_out.script_pubkey == original_out.script_pubkey)
+                .ok_or(PayjoinError::Proposal("dropped one of our outputs"))?;
+            if tx_out.value < original_out.value {
+                if Some(index) != self.params.additional_fee_output_index {
+                    return Err(PayjoinError::Proposal("lowered one of our outputs"));
+                }
+                contribution += original_out.value - tx_out.value;
+            }
+        }
+        if contribution > self.params.max_additional_fee_contribution {
+            return Err(PayjoinError::Proposal("takes more fee than we allowed"));
+        }
+        // what we give up can only go to the fee, not to the receiver's outputs
+        let original_fee = fee(&self.original).ok_or(PayjoinError::Proposal("pays no fee"))?;
+        let proposal_fee = fee(&proposal).ok_or(PayjoinError::Proposal("pays no fee"))?;
+        if contribution > proposal_fee.saturating_sub(original_fee) {
+            return Err(PayjoinError::Proposal(
+                "takes more from us than the fee went up",
+            ));
+        }
+
+        sign_key_path_inputs(&self.secp, &self.key_pair, &mut proposal)
+            .ok_or(PayjoinError::Proposal("can not be signed"))?;
+        let payjoin = extract_tx(&proposal).ok_or(PayjoinError::Proposal("is not complete"))?;
+        let rate = fee_rate(&proposal, &payjoin).ok_or(PayjoinError::Proposal("pays no fee"))?;
+        if rate < self.params.min_fee_rate {
+            return Err(PayjoinError::Proposal(
+                "pays less than the minimum fee rate",
+            ));
+        }
+        self.client.broadcasts_transacton(&payjoin);
+        return Ok(payjoin);
+    }
+}
+
+#[tokio::test]
+async fn payjoin_over_a_local_endpoint() {
+    use crate::bitcoin_wallet::{
+        input_data::mock_call::MockCall, script_services::psbt_factory::create_partially_signed_tx,
+        spending_path::p2tr_key_path::P2tr,
+    };
+
+    let secp = Secp256k1::new();
+    let sender_key = KeyPair::from_seckey_slice(&secp, &[71u8; 32]).unwrap();
+    let receiver_key = KeyPair::from_seckey_slice(&secp, &[72u8; 32]).unwrap();
+    let sender_script = Script::new_v1_p2tr(&secp, sender_key.x_only_public_key().0, None);
+    let receiver_script = Script::new_v1_p2tr(&secp, receiver_key.x_only_public_key().0, None);
+    let sender_client = MockCall::fund(&sender_script, &[100_000]);
+    let receiver_client = MockCall::fund(&receiver_script, &[60_000]);
+
+    let p2tr = P2tr::new(&secp);
+    let original = create_partially_signed_tx(
+        p2tr.output_factory(receiver_script.clone(), sender_script.clone()),
+        P2tr::create_tx(30_000),
+        p2tr.input_factory(&sender_key, sender_script.clone()),
+    )(&sender_client);
+    // payee first, change second
+    assert_eq!(original.unsigned_tx.output[1].script_pubkey, sender_script);
+    let params = PayjoinParams {
+        additional_fee_output_index: Some(1),
+        max_additional_fee_contribution: 300,
+        min_fee_rate: 1.0,
+    };
+    let sender = PayjoinSender::new(sender_key, original, params, &sender_client).unwrap();
+
+    let receiver = Arc::new(PayjoinReceiver::new(receiver_key, &receiver_client));
+    let (addr, server) = serve(receiver.clone(), ([127, 0, 0, 1], 0).into())
+        .await
+        .unwrap();
+    let endpoint = format!("http://{}/payjoin", addr);
+
+    let proposal = sender.request(&endpoint).await.unwrap();
+    let payjoin = sender.process_proposal(proposal.clone()).unwrap();
+    assert_eq!(sender_client.last_broadcast(), Some(payjoin.clone()));
+    assert_eq!(payjoin.input.len(), 2);
+    // the payee output got the receiver's coin on top, our change paid part of its fee
+    let original_tx = sender.original_tx();
+    let paid = |tx: &Transaction, script: &Script| {
+        return tx
+            .output
+            .iter()
+            .find(|tx_out| tx_out.script_pubkey == *script)
+            .unwrap()
+            .value;
+    };
+    assert!(paid(&payjoin, &receiver_script) > paid(&original_tx, &receiver_script) + 59_000);
+    assert!(paid(&original_tx, &sender_script) - paid(&payjoin, &sender_script) <= 300);
+    assert!(payjoin.input.iter().all(|tx_in| tx_in.witness.len() == 1));
+
+    // the receiver keeping part of what we paid towards the fee is refused, even while our
+    // contribution stays below the maximum
+    let receiver_in = proposal
+        .inputs
+        .iter()
+        .find_map(|input| input.witness_utxo.as_ref())
+        .unwrap()
+        .value;
+    let total = |tx: &Transaction| tx.output.iter().map(|tx_out| tx_out.value).sum::<u64>();
+    let increase = receiver_in + total(&sender.original.unsigned_tx) - total(&proposal.unsigned_tx);
+    assert_eq!(
+        paid(&original_tx, &sender_script) - paid(&proposal.unsigned_tx, &sender_script),
+        300
+    );
+    let mut skimmed = proposal.clone();
+    skimmed.unsigned_tx.output[0].value += increase - 299;
+    assert_eq!(
+        sender.process_proposal(skimmed),
+        Err(PayjoinError::Proposal(
+            "takes more from us than the fee went up"
+        ))
+    );
+
+    // taking more out of our change than we allowed is refused
+    let mut greedy = proposal;
+    greedy.unsigned_tx.output[1].value -= 1_000;
+    assert_eq!(
+        sender.process_proposal(greedy),
+        Err(PayjoinError::Proposal("takes more fee than we allowed"))
+    );
+
+    // the receiver refuses a psbt that isn't signed
+    let unsigned =
+        PartiallySignedTransaction::from_unsigned_tx(sender.original.unsigned_tx.clone()).unwrap();
+    let rejected = Client::new()
+        .request(
+            Request::builder()
+                .method(Method::POST)
+                .uri(format!("{}?v=1", endpoint))
+                .body(Body::from(unsigned.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(rejected.into_body()).await.unwrap();
+    let err = serde_json::from_slice::<ReceiverError>(&body).unwrap();
+    assert_eq!(err.error_code, "original-psbt-rejected");
+
+    // so is one whose signature doesn't verify
+    let mut forged = sender.original.clone();
+    let mut witness = forged.inputs[0]
+        .final_script_witness
+        .as_ref()
+        .unwrap()
+        .to_vec();
+    witness[0][0] ^= 1;
+    forged.inputs[0].final_script_witness = Some(Witness::from_vec(witness));
+    let err = receiver.process(&forged.to_string(), "v=1").unwrap_err();
+    assert_eq!(err.error_code, "original-psbt-rejected");
+
+    // a coin smaller than the fee for its own input is not joined
+    let dust_client = MockCall::fund(&receiver_script, &[10]);
+    let dust = PayjoinReceiver::new(receiver_key, &dust_client);
+    let err = dust
+        .process(&sender.original.to_string(), "v=1")
+        .unwrap_err();
+    assert_eq!(err.error_code, "unavailable");
+    server.abort();
+}
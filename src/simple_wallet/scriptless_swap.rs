@@ -0,0 +1,696 @@
+use bitcoin::{
+    psbt::{PartiallySignedTransaction, Prevouts},
+    secp256k1::{All, PublicKey, Secp256k1, SecretKey},
+    util::{
+        sighash::SighashCache,
+        taproot::{TaprootBuilder, TaprootSpendInfo},
+    },
+    KeyPair, SchnorrSig, SchnorrSighashType, Script, Transaction, TxIn, TxOut, XOnlyPublicKey,
+};
+use bitcoin_hashes::Hash;
+
+use crate::bitcoin_wallet::{
+    input_data::RpcCall,
+    schnorr::{
+        adaptor::AdaptorSignature,
+        musig2::{
+            nonce_agg, random_nonce, KeyAggContext, MusigError, PartialSig, PubNonce, SecNonce,
+            Session, SigningRound,
+        },
+    },
+    script_services::{
+        input_service::{
+            insert_control_block, insert_witness, insert_witness_tx_out, sign_tapleaf,
+        },
+        psbt_factory::{create_partially_signed_tx, SpendFn, UnlockFn},
+        tap_finalizer::{finalize_tap_psbt, UnsatisfiableInput},
+    },
+    scripts::timelock::{absolute_delay, relative_delay, ChainTip, TimeLock},
+    spending_path::{
+        htlc_path::timeout_precedes, single_create_tx, single_output, timelocked_create_tx,
+    },
+};
+
+use super::htlc::SwapError;
+
+// the initiator funds chain a and the participant funds chain b
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapChain {
+    A,
+    B,
+}
+
+// the unsigned spend of one chain together with a pre signature for each of its inputs
+pub struct SwapLeg {
+    pub psbt: PartiallySignedTransaction,
+    pub pre_sigs: Vec<AdaptorSignature>,
+}
+
+// the MuSig2 key of both parties on the key path and a refund leaf for whoever funded the chain
+struct SwapOutput {
+    ctx: KeyAggContext,
+    refund_script: Script,
+    spend_info: TaprootSpendInfo,
+    timeout: TimeLock,
+}
+
+// both chains lock to a MuSig2 key of the two parties, the spends are pre signed with the same
+// adaptor point, the initiator completes the spend on chain b with the adaptor secret and the
+// participant reads that secret back from the signature to complete the spend on chain a, on
+// chain both spends look like ordinary single key payments. if either side walks away the funder
+// takes its coins back through the refund leaf, the participant's refund on chain b has to
+// mature at least margin seconds first so the initiator can't claim chain b and refund chain a
+pub struct ScriptlessSwap<'a, A: RpcCall, B: RpcCall> {
+    secp: Secp256k1<All>,
+    // initiator first, partial signatures and nonces are passed around in this order
+    pub_keys: [PublicKey; 2],
+    output_a: SwapOutput,
+    output_b: SwapOutput,
+    pub adaptor_point: PublicKey,
+    chain_a: &'a A,
+    chain_b: &'a B,
+}
+
+impl<'a, A, B> ScriptlessSwap<'a, A, B>
+where
+    A: RpcCall,
+    B: RpcCall,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        secp: &Secp256k1<All>,
+        initiator: PublicKey,
+        participant: PublicKey,
+        adaptor_point: PublicKey,
+        timeout_a: TimeLock,
+        timeout_b: TimeLock,
+        chain_a: &'a A,
+        chain_b: &'a B,
+        tips: [ChainTip; 2],
+        margin: u32,
+    ) -> Result<Self, SwapError> {
+        match timeout_precedes(&timeout_b, &tips[1], &timeout_a, &tips[0], margin) {
+            Some(true) => {}
+            Some(false) => return Err(SwapError::TimeoutOrder),
+            None => return Err(SwapError::RelativeTimelock),
+        }
+        let pub_keys = [initiator, participant];
+        let output_a = swap_output(secp, &pub_keys, &initiator.x_only_public_key().0, timeout_a);
+        let output_b = swap_output(
+            secp,
+            &pub_keys,
+            &participant.x_only_public_key().0,
+            timeout_b,
+        );
+        return Ok(ScriptlessSwap {
+            secp: secp.clone(),
+            pub_keys,
+            output_a,
+            output_b,
+            adaptor_point,
+            chain_a,
+            chain_b,
+        });
+    }
+
+    pub fn script_pubkey(&self, chain: SwapChain) -> Script {
+        return Script::new_v1_p2tr_tweaked(self.output(chain).spend_info.output_key());
+    }
+
+    // the spend both parties pre sign, the participant is paid on chain a and the initiator on
+    // chain b
+    pub fn unsigned_leg(&self, chain: SwapChain, send_to: Script) -> PartiallySignedTransaction {
+        let outputs = vec![single_output(&send_to)];
+        let inputs = key_path_inputs(self.script_pubkey(chain));
+        return match chain {
+            SwapChain::A => {
+                create_partially_signed_tx(outputs, single_create_tx(), inputs)(self.chain_a)
+            }
+            SwapChain::B => {
+                create_partially_signed_tx(outputs, single_create_tx(), inputs)(self.chain_b)
+            }
+        };
+    }
+
+    // first round, each party runs it with its own key, the pubnonces go to the other side and
+    // the secnonces never leave
+    pub fn nonces(
+        &self,
+        chain: SwapChain,
+        psbt: &PartiallySignedTransaction,
+        key_pair: &KeyPair,
+    ) -> Result<(Vec<SecNonce>, Vec<PubNonce>), MusigError> {
+        let mut sec_nonces = vec![];
+        let mut pub_nonces = vec![];
+        for index in 0..psbt.inputs.len() {
+            let (sec_nonce, pub_nonce) = random_nonce(
+                &self.secp,
+                &key_pair.secret_key(),
+                &self.output(chain).ctx,
+                &key_spend_sighash(psbt, index),
+            )?;
+            sec_nonces.push(sec_nonce);
+            pub_nonces.push(pub_nonce);
+        }
+        return Ok((sec_nonces, pub_nonces));
+    }
+
+    // second round, signs every input with only our own secret, pub_nonces holds the pubnonces
+    // of both parties for each input
+    pub fn partial_sign(
+        &self,
+        chain: SwapChain,
+        psbt: &PartiallySignedTransaction,
+        key_pair: &KeyPair,
+        sec_nonces: Vec<SecNonce>,
+        pub_nonces: &[[PubNonce; 2]],
+    ) -> Result<Vec<PartialSig>, MusigError> {
+        return sec_nonces
+            .into_iter()
+            .enumerate()
+            .map(|(index, sec_nonce)| {
+                self.session(chain, psbt, index, &pub_nonces[index]).sign(
+                    &self.secp,
+                    sec_nonce,
+                    &key_pair.secret_key(),
+                )
+            })
+            .collect();
+    }
+
+    // checks the partial signatures of both parties and aggregates them into a pre signature
+    // for every input, each side does this before it funds its chain
+    pub fn pre_sign(
+        &self,
+        chain: SwapChain,
+        psbt: PartiallySignedTransaction,
+        rounds: &[SigningRound],
+    ) -> Result<SwapLeg, MusigError> {
+        let output_key = self.output(chain).ctx.x_only();
+        let mut pre_sigs = vec![];
+        for (index, round) in rounds.iter().enumerate() {
+            let session = self.session(chain, &psbt, index, &round.pub_nonces);
+            session.verify_all(
+                &self.secp,
+                &self.pub_keys,
+                &round.pub_nonces,
+                &round.partial_sigs,
+            )?;
+            let pre_sig = session.aggregate_adaptor(&round.partial_sigs);
+            let msg = key_spend_sighash(&psbt, index);
+            if !pre_sig.verify(&self.secp, &output_key, &msg, &self.adaptor_point) {
+                panic!("pre signature for input {} does not verify", index);
+            }
+            pre_sigs.push(pre_sig);
+        }
+        return Ok(SwapLeg { psbt, pre_sigs });
+    }
+
+    pub fn initiator_claim(&self, leg_b: &SwapLeg, adaptor_secret: &SecretKey) -> Transaction {
+        let tx = self.complete(leg_b, adaptor_secret);
+        self.chain_b.broadcasts_transacton(&tx);
+        return tx;
+    }
+
+    // None when the claim on chain b doesn't spend with a completion of our pre signature
+    pub fn participant_claim(
+        &self,
+        leg_a: &SwapLeg,
+        leg_b: &SwapLeg,
+        initiator_claim: &Transaction,
+    ) -> Option<Transaction> {
+        let witness = initiator_claim.input.first()?.witness.to_vec();
+        let sig = SchnorrSig::from_slice(witness.first()?).ok()?;
+        let adaptor_secret = leg_b.pre_sigs.first()?.extract(&sig.sig)?;
+        if PublicKey::from_secret_key(&self.secp, &adaptor_secret) != self.adaptor_point {
+            return None;
+        }
+        let tx = self.complete(leg_a, &adaptor_secret);
+        self.chain_a.broadcasts_transacton(&tx);
+        return Some(tx);
+    }
+
+    // the funder of the chain takes its coins back through the refund leaf, the tx carries the
+    // timeout so it can only be mined once the leaf allows it
+    pub fn refund(
+        &self,
+        chain: SwapChain,
+        key_pair: &KeyPair,
+        send_to: Script,
+    ) -> Result<Transaction, UnsatisfiableInput> {
+        let output = self.output(chain);
+        let outputs = vec![single_output(&send_to)];
+        let create_tx = timelocked_create_tx(single_create_tx(), output.timeout);
+        let inputs = self.refund_inputs(chain, key_pair);
+        let tx = match chain {
+            SwapChain::A => {
+                let psbt = create_partially_signed_tx(outputs, create_tx, inputs)(self.chain_a);
+                let tx = finalize_tap_psbt(psbt)?;
+                self.chain_a.broadcasts_transacton(&tx);
+                tx
+            }
+            SwapChain::B => {
+                let psbt = create_partially_signed_tx(outputs, create_tx, inputs)(self.chain_b);
+                let tx = finalize_tap_psbt(psbt)?;
+                self.chain_b.broadcasts_transacton(&tx);
+                tx
+            }
+        };
+        return Ok(tx);
+    }
+
+    // the sighash commits to the prevout of every input in order, so an input that doesn't spend
+    // the swap output leaves all of them unsigned and the refund fails to finalize
+    fn refund_inputs<'b>(&'b self, chain: SwapChain, key_pair: &'b KeyPair) -> SpendFn<'b> {
+        let output = self.output(chain);
+        let script_pubkey = self.script_pubkey(chain);
+        let secp = &self.secp;
+        return Box::new(
+            move |previous_list: Vec<Transaction>, current_tx: Transaction| {
+                let prevouts = current_tx
+                    .input
+                    .iter()
+                    .map(|tx_in| swap_prevout(&previous_list, tx_in, &script_pubkey))
+                    .collect::<Option<Vec<TxOut>>>();
+                let prevouts = match prevouts {
+                    Some(prevouts) => prevouts,
+                    None => return current_tx.input.iter().map(|_| vec![]).collect(),
+                };
+                return prevouts
+                    .iter()
+                    .enumerate()
+                    .map(|(index, tx_out)| {
+                        let unlock_vec: Vec<UnlockFn> = vec![
+                            insert_witness_tx_out(tx_out.clone()),
+                            insert_control_block(
+                                secp,
+                                output.refund_script.clone(),
+                                output.spend_info.clone(),
+                            ),
+                            sign_tapleaf(
+                                secp,
+                                key_pair,
+                                current_tx.clone(),
+                                prevouts.clone(),
+                                index,
+                                output.refund_script.clone(),
+                            ),
+                        ];
+                        unlock_vec
+                    })
+                    .collect();
+            },
+        );
+    }
+
+    fn output(&self, chain: SwapChain) -> &SwapOutput {
+        return match chain {
+            SwapChain::A => &self.output_a,
+            SwapChain::B => &self.output_b,
+        };
+    }
+
+    fn session(
+        &self,
+        chain: SwapChain,
+        psbt: &PartiallySignedTransaction,
+        index: usize,
+        pub_nonces: &[PubNonce],
+    ) -> Session {
+        return Session::with_adaptor(
+            &self.secp,
+            &self.output(chain).ctx,
+            &nonce_agg(pub_nonces),
+            &key_spend_sighash(psbt, index),
+            Some(self.adaptor_point),
+        );
+    }
+
+    fn complete(&self, leg: &SwapLeg, adaptor_secret: &SecretKey) -> Transaction {
+        let mut psbt = leg.psbt.clone();
+        psbt.inputs
+            .iter_mut()
+            .zip(leg.pre_sigs.iter())
+            .for_each(|(input, pre_sig)| {
+                input.tap_key_sig = Some(SchnorrSig {
+                    sig: pre_sig.complete(adaptor_secret),
+                    hash_ty: SchnorrSighashType::AllPlusAnyoneCanPay,
+                });
+            });
+        return finalize_tap_psbt(psbt).unwrap();
+    }
+}
+
+// the key path spend of every swap utxo, the signature is filled in once the pre signature is
+// completed
+fn key_path_inputs<'a>(script_pubkey: Script) -> SpendFn<'a> {
+    return Box::new(move |previous_list: Vec<Transaction>, _: Transaction| {
+        return previous_list
+            .iter()
+            .map(|prev| {
+                let tx_out = prev
+                    .output
+                    .iter()
+                    .find(|tx_out| tx_out.script_pubkey.eq(&script_pubkey))
+                    .unwrap();
+                let unlock_vec: Vec<UnlockFn> = vec![
+                    insert_witness_tx_out(tx_out.clone()),
+                    insert_witness(tx_out.script_pubkey.clone()),
+                ];
+                unlock_vec
+            })
+            .collect();
+    });
+}
+
+// the output an input spends, as long as it is locked to the swap
+fn swap_prevout(
+    previous_list: &[Transaction],
+    tx_in: &TxIn,
+    script_pubkey: &Script,
+) -> Option<TxOut> {
+    let outpoint = tx_in.previous_output;
+    return previous_list
+        .iter()
+        .find(|tx| tx.txid() == outpoint.txid)
+        .and_then(|tx| tx.output.get(outpoint.vout as usize))
+        .filter(|tx_out| tx_out.script_pubkey.eq(script_pubkey))
+        .cloned();
+}
+
+// <timeout> OP_CSV or OP_CLTV OP_DROP <refund key> OP_CHECKSIG
+pub fn refund_script(refund_key: &XOnlyPublicKey, timeout: TimeLock) -> Script {
+    return match timeout {
+        TimeLock::Relative(lock) => relative_delay(refund_key, lock),
+        TimeLock::Absolute(lock) => absolute_delay(refund_key, lock),
+    };
+}
+
+fn swap_output(
+    secp: &Secp256k1<All>,
+    pub_keys: &[PublicKey],
+    refund_key: &XOnlyPublicKey,
+    timeout: TimeLock,
+) -> SwapOutput {
+    let internal = KeyAggContext::sorted(secp, pub_keys).unwrap();
+    let refund_script = refund_script(refund_key, timeout);
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, refund_script.clone())
+        .unwrap()
+        .finalize(secp, internal.x_only())
+        .unwrap();
+    let ctx = internal
+        .apply_taproot_tweak(secp, spend_info.merkle_root())
+        .unwrap();
+    return SwapOutput {
+        ctx,
+        refund_script,
+        spend_info,
+        timeout,
+    };
+}
+
+fn key_spend_sighash(psbt: &PartiallySignedTransaction, index: usize) -> [u8; 32] {
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .map(|input| input.witness_utxo.clone().unwrap())
+        .collect::<Vec<TxOut>>();
+    return SighashCache::new(&psbt.unsigned_tx)
+        .taproot_key_spend_signature_hash(
+            index,
+            &Prevouts::All(&prevouts),
+            SchnorrSighashType::AllPlusAnyoneCanPay,
+        )
+        .unwrap()
+        .into_inner();
+}
+
+#[test]
+fn scriptless_swap_reveals_the_adaptor_secret() {
+    use crate::bitcoin_wallet::{
+        constants::nums_x_only,
+        input_data::mock_call::MockCall,
+        scripts::timelock::{AbsoluteLock, RelativeLock},
+    };
+    use bitcoin::PackedLockTime;
+
+    let secp = Secp256k1::new();
+    let initiator = KeyPair::from_seckey_slice(&secp, &[21u8; 32]).unwrap();
+    let participant = KeyPair::from_seckey_slice(&secp, &[22u8; 32]).unwrap();
+    let adaptor_secret = SecretKey::from_slice(&[23u8; 32]).unwrap();
+    let adaptor_point = PublicKey::from_secret_key(&secp, &adaptor_secret);
+    let tip = ChainTip {
+        height: 800_000,
+        median_time: 1_700_000_000,
+        block_interval: 600,
+    };
+    let tips = [tip, tip];
+    let margin = 6 * 3_600;
+    let timeout_a = TimeLock::Absolute(AbsoluteLock::Height(800_288));
+    let timeout_b = TimeLock::Absolute(AbsoluteLock::Height(800_144));
+
+    // the participant has to be able to refund first, and relative locks can't be ordered
+    let unfunded = MockCall::fund(&Script::new(), &[]);
+    for (timeout_a, timeout_b, error) in [
+        (timeout_b, timeout_a, SwapError::TimeoutOrder),
+        (
+            timeout_a,
+            TimeLock::Relative(RelativeLock::Blocks(144)),
+            SwapError::RelativeTimelock,
+        ),
+    ] {
+        assert_eq!(
+            ScriptlessSwap::new(
+                &secp,
+                initiator.public_key(),
+                participant.public_key(),
+                adaptor_point,
+                timeout_a,
+                timeout_b,
+                &unfunded,
+                &unfunded,
+                tips,
+                margin,
+            )
+            .err(),
+            Some(error)
+        );
+    }
+
+    // the script pubkeys only depend on the keys and timeouts, so fund them first
+    let script_pubkeys = {
+        let swap = ScriptlessSwap::new(
+            &secp,
+            initiator.public_key(),
+            participant.public_key(),
+            adaptor_point,
+            timeout_a,
+            timeout_b,
+            &unfunded,
+            &unfunded,
+            tips,
+            margin,
+        )
+        .unwrap();
+        [
+            swap.script_pubkey(SwapChain::A),
+            swap.script_pubkey(SwapChain::B),
+        ]
+    };
+    let chain_a = MockCall::fund(&script_pubkeys[0], &[70_000]);
+    let chain_b = MockCall::fund(&script_pubkeys[1], &[90_000]);
+    let swap = ScriptlessSwap::new(
+        &secp,
+        initiator.public_key(),
+        participant.public_key(),
+        adaptor_point,
+        timeout_a,
+        timeout_b,
+        &chain_a,
+        &chain_b,
+        tips,
+        margin,
+    )
+    .unwrap();
+
+    // each party only ever touches its own key pair, nonces and partial signatures are what
+    // crosses the wire
+    let send_to = Script::new_v1_p2tr(&secp, nums_x_only(), None);
+    let legs = [SwapChain::A, SwapChain::B].map(|chain| {
+        let psbt = swap.unsigned_leg(chain, send_to.clone());
+        let (initiator_sec, initiator_pub) = swap.nonces(chain, &psbt, &initiator).unwrap();
+        let (participant_sec, participant_pub) = swap.nonces(chain, &psbt, &participant).unwrap();
+        let pub_nonces = initiator_pub
+            .iter()
+            .zip(participant_pub.iter())
+            .map(|(initiator, participant)| [*initiator, *participant])
+            .collect::<Vec<_>>();
+        let initiator_sigs = swap
+            .partial_sign(chain, &psbt, &initiator, initiator_sec, &pub_nonces)
+            .unwrap();
+        let participant_sigs = swap
+            .partial_sign(chain, &psbt, &participant, participant_sec, &pub_nonces)
+            .unwrap();
+        let rounds = pub_nonces
+            .iter()
+            .zip(initiator_sigs.iter().zip(participant_sigs.iter()))
+            .map(|(pub_nonces, (initiator, participant))| SigningRound {
+                pub_nonces: pub_nonces.to_vec(),
+                partial_sigs: vec![*initiator, *participant],
+            })
+            .collect::<Vec<_>>();
+
+        // a partial signature from the wrong side is caught before anyone funds
+        let mut swapped = rounds.clone();
+        swapped[0].partial_sigs.reverse();
+        assert_eq!(
+            swap.pre_sign(chain, psbt.clone(), &swapped).err(),
+            Some(MusigError::InvalidPartialSig(0))
+        );
+        swap.pre_sign(chain, psbt, &rounds).unwrap()
+    });
+    let [leg_a, leg_b] = legs;
+
+    let claim = swap.initiator_claim(&leg_b, &adaptor_secret);
+    assert_eq!(chain_b.last_broadcast(), Some(claim.clone()));
+    // a key path spend, no hash or script shows up in the witness
+    assert_eq!(claim.input[0].witness.len(), 1);
+
+    let counter_claim = swap.participant_claim(&leg_a, &leg_b, &claim).unwrap();
+    assert_eq!(chain_a.last_broadcast(), Some(counter_claim));
+
+    // had the participant never shown up, the initiator takes chain a back once it timed out
+    let refund = swap
+        .refund(SwapChain::A, &initiator, send_to.clone())
+        .unwrap();
+    assert_eq!(refund.lock_time, PackedLockTime(800_288));
+    assert_eq!(refund.input[0].witness.len(), 3);
+    assert_eq!(
+        refund.input[0].witness.to_vec()[1],
+        refund_script(&initiator.x_only_public_key().0, timeout_a).to_bytes()
+    );
+}
+
+#[test]
+fn scriptless_swap_refund_signs_every_input_against_its_own_prevout() {
+    use crate::bitcoin_wallet::{
+        constants::nums_x_only, input_data::mock_call::MockCall, scripts::timelock::AbsoluteLock,
+    };
+    use bitcoin::{
+        secp256k1::Message, util::sighash::ScriptPath, OutPoint, PackedLockTime, Sequence, Witness,
+    };
+
+    let secp = Secp256k1::new();
+    let initiator = KeyPair::from_seckey_slice(&secp, &[24u8; 32]).unwrap();
+    let participant = KeyPair::from_seckey_slice(&secp, &[25u8; 32]).unwrap();
+    let adaptor_point =
+        PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[26u8; 32]).unwrap());
+    let tip = ChainTip {
+        height: 800_000,
+        median_time: 1_700_000_000,
+        block_interval: 600,
+    };
+    let timeout_a = TimeLock::Absolute(AbsoluteLock::Height(800_288));
+    let timeout_b = TimeLock::Absolute(AbsoluteLock::Height(800_144));
+    let change = Script::new_v1_p2tr(&secp, initiator.x_only_public_key().0, None);
+
+    // the first funding tx pays the swap twice after its change, the second once
+    let pub_keys = [initiator.public_key(), participant.public_key()];
+    let output = swap_output(
+        &secp,
+        &pub_keys,
+        &initiator.x_only_public_key().0,
+        timeout_a,
+    );
+    let script_pubkey = Script::new_v1_p2tr_tweaked(output.spend_info.output_key());
+    let funding = [vec![40_000, 15_000], vec![10_000]]
+        .iter()
+        .enumerate()
+        .map(|(index, swap_values)| {
+            let mut output = vec![TxOut {
+                value: 30_000,
+                script_pubkey: change.clone(),
+            }];
+            output.extend(swap_values.iter().map(|value| TxOut {
+                value: *value,
+                script_pubkey: script_pubkey.clone(),
+            }));
+            return Transaction {
+                version: 2,
+                lock_time: PackedLockTime(0),
+                input: vec![bitcoin::TxIn {
+                    previous_output: OutPoint::new(bitcoin::Txid::all_zeros(), index as u32),
+                    script_sig: Script::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::default(),
+                }],
+                output,
+            };
+        })
+        .collect::<Vec<Transaction>>();
+    let chain_a = MockCall::from_transactions(funding.clone(), &script_pubkey);
+    let swap = ScriptlessSwap::new(
+        &secp,
+        pub_keys[0],
+        pub_keys[1],
+        adaptor_point,
+        timeout_a,
+        timeout_b,
+        &chain_a,
+        &chain_a,
+        [tip, tip],
+        3_600,
+    )
+    .unwrap();
+    let send_to = Script::new_v1_p2tr(&secp, nums_x_only(), None);
+
+    let refund = swap
+        .refund(SwapChain::A, &initiator, send_to.clone())
+        .unwrap();
+    let prevouts = funding
+        .iter()
+        .flat_map(|tx| tx.output[1..].to_vec())
+        .collect::<Vec<TxOut>>();
+    assert_eq!(refund.input.len(), 3);
+    // the leaf isn't miniscript, so check each signature against the sighash of its own input
+    let refund_leaf = refund_script(&initiator.x_only_public_key().0, timeout_a);
+    let mut cache = SighashCache::new(&refund);
+    for index in 0..refund.input.len() {
+        let witness = refund.input[index].witness.to_vec();
+        assert_eq!(witness[1], refund_leaf.to_bytes());
+        let sig = SchnorrSig::from_slice(&witness[0]).unwrap();
+        let sighash = cache
+            .taproot_script_spend_signature_hash(
+                index,
+                &Prevouts::All(&prevouts),
+                ScriptPath::with_defaults(&refund_leaf),
+                sig.hash_ty,
+            )
+            .unwrap();
+        let msg = Message::from_slice(&sighash.into_inner()).unwrap();
+        assert!(secp
+            .verify_schnorr(&sig.sig, &msg, &initiator.x_only_public_key().0)
+            .is_ok());
+    }
+
+    // coins that were never locked to the swap can't be refunded through it
+    let elsewhere = MockCall::fund(&change, &[20_000]);
+    let swap = ScriptlessSwap::new(
+        &secp,
+        pub_keys[0],
+        pub_keys[1],
+        adaptor_point,
+        timeout_a,
+        timeout_b,
+        &elsewhere,
+        &elsewhere,
+        [tip, tip],
+        3_600,
+    )
+    .unwrap();
+    assert_eq!(
+        swap.refund(SwapChain::A, &initiator, send_to).err(),
+        Some(UnsatisfiableInput(0))
+    );
+}
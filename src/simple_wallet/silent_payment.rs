@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+
+use bitcoin::{
+    psbt::{PartiallySignedTransaction, Prevouts},
+    secp256k1::{All, Message, Secp256k1},
+    util::sighash::SighashCache,
+    KeyPair, OutPoint, PackedLockTime, SchnorrSig, SchnorrSighashType, Sequence, Transaction, TxIn,
+    TxOut, Witness,
+};
+
+use crate::bitcoin_wallet::{
+    address_formats::silent_payment::{
+        output_script, sender_outputs, ReceivedOutput, SenderInput, SilentPaymentAddress,
+        SilentPaymentError, SilentPaymentReceiver,
+    },
+    input_data::{ChainFeed, FeedError, RpcCall},
+    script_services::tap_finalizer::finalize_tap_psbt,
+};
+
+// the outputs paying each recipient its amount, the sender still has to spend exactly these
+// outpoints or the receiver derives different keys
+pub fn silent_payment_outputs(
+    secp: &Secp256k1<All>,
+    inputs: &[SenderInput],
+    outpoints: &[OutPoint],
+    recipients: &[(SilentPaymentAddress, u64)],
+) -> Result<Vec<TxOut>, SilentPaymentError> {
+    let addresses = recipients
+        .iter()
+        .map(|(address, _)| *address)
+        .collect::<Vec<_>>();
+    return Ok(sender_outputs(secp, inputs, outpoints, &addresses)?
+        .iter()
+        .zip(recipients)
+        .map(|(output_key, (_, value))| TxOut {
+            value: *value,
+            script_pubkey: output_script(output_key),
+        })
+        .collect());
+}
+
+// keeps the outputs the scan key found, a chain feed hands it every block since the last
+// refresh and the mempool together with the outputs those transactions spend
+pub struct SilentPaymentWallet<'a, R: RpcCall> {
+    receiver: SilentPaymentReceiver,
+    secp: Secp256k1<All>,
+    client: &'a R,
+    // the first block the next refresh scans
+    next_height: u32,
+    owned: Vec<ReceivedOutput>,
+    // outputs we had and spent, so syncing the transaction paying them again doesn't bring
+    // them back
+    spent: Vec<OutPoint>,
+}
+
+impl<'a, R> SilentPaymentWallet<'a, R>
+where
+    R: RpcCall,
+{
+    // nothing can pay the wallet before its keys existed, so scanning starts at birth_height
+    pub fn new(receiver: SilentPaymentReceiver, client: &'a R, birth_height: u32) -> Self {
+        return SilentPaymentWallet {
+            receiver,
+            secp: Secp256k1::new(),
+            client,
+            next_height: birth_height,
+            owned: vec![],
+            spent: vec![],
+        };
+    }
+
+    pub fn address(&self, label: Option<u32>) -> Result<SilentPaymentAddress, SilentPaymentError> {
+        return self.receiver.address(&self.secp, label);
+    }
+
+    pub fn owned(&self) -> &[ReceivedOutput] {
+        return &self.owned;
+    }
+
+    pub fn balance(&self) -> u64 {
+        return self
+            .owned
+            .iter()
+            .map(|received| received.tx_out.value)
+            .sum();
+    }
+
+    // drops what the transactions spend and adds what they pay us, transactions whose prevouts
+    // are unknown or that have no usable input are skipped
+    pub fn sync(
+        &mut self,
+        transactions: &[Transaction],
+        prevout: impl Fn(&OutPoint) -> Option<TxOut>,
+    ) -> usize {
+        let before = self.owned.len();
+        for tx in transactions {
+            let spent = &mut self.spent;
+            self.owned.retain(|received| {
+                let is_spent = tx
+                    .input
+                    .iter()
+                    .any(|tx_in| tx_in.previous_output == received.outpoint);
+                if is_spent {
+                    spent.push(received.outpoint);
+                }
+                return !is_spent;
+            });
+            let prevouts = tx
+                .input
+                .iter()
+                .map(|tx_in| prevout(&tx_in.previous_output))
+                .collect::<Option<Vec<TxOut>>>();
+            let received = prevouts
+                .and_then(|prevouts| self.receiver.scan(&self.secp, tx, &prevouts).ok())
+                .unwrap_or_default();
+            for received in received {
+                let known = self.spent.contains(&received.outpoint)
+                    || self
+                        .owned
+                        .iter()
+                        .any(|owned| owned.outpoint == received.outpoint);
+                if !known {
+                    self.owned.push(received);
+                }
+            }
+        }
+        return self.owned.len().saturating_sub(before);
+    }
+
+    // syncs the blocks mined since the last refresh and the mempool, every prevout is looked up
+    // through the feed since the input keys come from whatever the transactions spend. mempool
+    // transactions are scanned again each time, sync skips what it already knows
+    pub fn refresh<F: ChainFeed>(&mut self, feed: &F) -> Result<usize, FeedError> {
+        let tip = feed.tip_height()?;
+        let mut transactions = vec![];
+        for height in self.next_height..=tip {
+            transactions.extend(feed.block_transactions(height)?);
+        }
+        transactions.extend(feed.mempool_transactions()?);
+
+        let mut prevouts = HashMap::new();
+        for tx in transactions.iter().filter(|tx| !tx.is_coin_base()) {
+            for tx_in in &tx.input {
+                if let Some(tx_out) = feed.prevout(&tx_in.previous_output)? {
+                    prevouts.insert(tx_in.previous_output, tx_out);
+                }
+            }
+        }
+        let found = self.sync(&transactions, |outpoint| prevouts.get(outpoint).cloned());
+        self.next_height = self.next_height.max(tip + 1);
+        return Ok(found);
+    }
+
+    // spends every output the last refresh found through its key path, the output key is the
+    // tweaked spend key itself so it signs without a taproot tweak
+    pub fn send(
+        &mut self,
+        send_to: Box<dyn Fn(u64) -> Vec<TxOut>>,
+    ) -> Result<Transaction, SilentPaymentError> {
+        let (balance, fee) = (self.balance(), self.client.fee());
+        let value = balance
+            .checked_sub(fee)
+            .ok_or(SilentPaymentError::InsufficientFunds { balance, fee })?;
+        let prevouts = self
+            .owned
+            .iter()
+            .map(|received| received.tx_out.clone())
+            .collect::<Vec<TxOut>>();
+        let unsigned_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: self
+                .owned
+                .iter()
+                .map(|received| TxIn {
+                    previous_output: received.outpoint,
+                    script_sig: Default::default(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::default(),
+                })
+                .collect(),
+            output: send_to(value),
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx.clone()).unwrap();
+        let mut cache = SighashCache::new(&unsigned_tx);
+        for (index, received) in self.owned.iter().enumerate() {
+            let key_pair =
+                KeyPair::from_secret_key(&self.secp, &self.receiver.spend_key(received)?);
+            let sighash = cache
+                .taproot_key_spend_signature_hash(
+                    index,
+                    &Prevouts::All(&prevouts),
+                    SchnorrSighashType::Default,
+                )
+                .unwrap();
+            let message = Message::from_slice(&sighash).unwrap();
+            psbt.inputs[index].witness_utxo = Some(received.tx_out.clone());
+            psbt.inputs[index].tap_key_sig = Some(SchnorrSig {
+                sig: self.secp.sign_schnorr(&message, &key_pair),
+                hash_ty: SchnorrSighashType::Default,
+            });
+        }
+        let tx = finalize_tap_psbt(psbt).unwrap();
+        self.client.broadcasts_transacton(&tx);
+        self.spent
+            .extend(self.owned.iter().map(|received| received.outpoint));
+        self.owned.clear();
+        return Ok(tx);
+    }
+}
+
+#[test]
+fn silent_payment_is_found_by_the_scan_key_and_spent() {
+    use bitcoin::{
+        secp256k1::{PublicKey, SecretKey},
+        Network, Script, Txid,
+    };
+    use bitcoin_hashes::Hash;
+
+    use crate::bitcoin_wallet::{
+        address_formats::silent_payment::input_public_key,
+        constants::nums_x_only,
+        input_data::{chain_feed::fake_node, mock_call::MockCall},
+    };
+
+    let secp = Secp256k1::new();
+    let scan = SecretKey::from_slice(&[61u8; 32]).unwrap();
+    let spend = SecretKey::from_slice(&[62u8; 32]).unwrap();
+    let mut receiver = SilentPaymentReceiver::new(scan, spend, Network::Regtest);
+    receiver.add_label(&secp, 0).unwrap();
+
+    // a p2wpkh and a taproot input, the taproot key has an odd y so its secret gets negated
+    let wpkh_secret = SecretKey::from_slice(&[63u8; 32]).unwrap();
+    let tr_secret = (1u8..)
+        .map(|byte| SecretKey::from_slice(&[byte; 32]).unwrap())
+        .find(|secret| secret.x_only_public_key(&secp).1 == bitcoin::secp256k1::Parity::Odd)
+        .unwrap();
+    let wpkh_key = bitcoin::PublicKey::new(wpkh_secret.public_key(&secp));
+    let funding = [
+        TxOut {
+            value: 30_000,
+            script_pubkey: Script::new_v0_p2wpkh(&wpkh_key.wpubkey_hash().unwrap()),
+        },
+        TxOut {
+            value: 40_000,
+            script_pubkey: output_script(&tr_secret.x_only_public_key(&secp).0),
+        },
+    ];
+    let funding_txs = funding
+        .iter()
+        .enumerate()
+        .map(|(index, tx_out)| Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), index as u32),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::default(),
+            }],
+            output: vec![tx_out.clone()],
+        })
+        .collect::<Vec<_>>();
+    let outpoints = [
+        OutPoint::new(funding_txs[0].txid(), 0),
+        OutPoint::new(funding_txs[1].txid(), 0),
+    ];
+    let inputs = [
+        SenderInput {
+            outpoint: outpoints[0],
+            secret_key: wpkh_secret,
+            taproot: false,
+        },
+        SenderInput {
+            outpoint: outpoints[1],
+            secret_key: tr_secret,
+            taproot: true,
+        },
+    ];
+    let address = receiver.address(&secp, None).unwrap();
+    let change = receiver.address(&secp, Some(0)).unwrap();
+    assert_eq!(address.to_string().parse(), Ok(address));
+    assert!(address.to_string().starts_with("sprt1q"));
+    assert_ne!(address.spend, change.spend);
+    let stranger = SilentPaymentAddress {
+        scan: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[64u8; 32]).unwrap()),
+        ..address
+    };
+
+    let output = silent_payment_outputs(
+        &secp,
+        &inputs,
+        &outpoints,
+        &[(address, 20_000), (stranger, 15_000), (change, 34_000)],
+    )
+    .unwrap();
+    // the scanner only needs the keys the inputs reveal, the signatures don't matter here
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: outpoints
+            .iter()
+            .zip([
+                Witness::from_vec(vec![vec![0u8; 71], wpkh_key.to_bytes()]),
+                Witness::from_vec(vec![vec![0u8; 64]]),
+            ])
+            .map(|(outpoint, witness)| TxIn {
+                previous_output: *outpoint,
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness,
+            })
+            .collect(),
+        output,
+    };
+    assert_eq!(
+        input_public_key(&tx.input[1], &funding[1]),
+        Some(crate::bitcoin_wallet::schnorr::lift_x(
+            &tr_secret.x_only_public_key(&secp).0
+        ))
+    );
+
+    // the wallet was created at block 1 which mined the funding, the payment still sits in the
+    // mempool
+    let coinbase = Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: vec![TxIn::default()],
+        output: vec![],
+    };
+    let node = fake_node(
+        vec![
+            vec![coinbase.clone()],
+            [&[coinbase][..], &funding_txs[..]].concat(),
+        ],
+        vec![tx.clone()],
+    );
+    let client = MockCall::from_transactions(vec![], &Script::new());
+    let mut wallet = SilentPaymentWallet::new(receiver, &client, 1);
+    // nothing to spend before the wallet synced
+    assert_eq!(
+        wallet.send(Box::new(|_| vec![])),
+        Err(SilentPaymentError::InsufficientFunds {
+            balance: 0,
+            fee: client.fee()
+        })
+    );
+    assert_eq!(wallet.refresh(&node), Ok(2));
+    assert_eq!(wallet.balance(), 54_000);
+    assert_eq!(wallet.owned()[1].label, Some(0));
+    // nothing changes for a transaction the wallet already saw
+    let lookup = |outpoint: &OutPoint| {
+        return outpoints
+            .iter()
+            .position(|known| known == outpoint)
+            .map(|index| funding[index].clone());
+    };
+    assert_eq!(wallet.sync(std::slice::from_ref(&tx), lookup), 0);
+    assert_eq!(wallet.sync(&[], lookup), 0);
+
+    let send_to = Script::new_v1_p2tr(&secp, nums_x_only(), None);
+    let spend_tx = wallet
+        .send(Box::new(move |value| {
+            vec![TxOut {
+                value,
+                script_pubkey: send_to.clone(),
+            }]
+        }))
+        .unwrap();
+    assert_eq!(client.last_broadcast(), Some(spend_tx.clone()));
+    assert_eq!(wallet.balance(), 0);
+    // the payment is still in the mempool, what was spent stays spent
+    assert_eq!(wallet.refresh(&node), Ok(0));
+
+    // both key path signatures verify against the output keys the sender derived
+    let prevouts = [tx.output[0].clone(), tx.output[2].clone()];
+    let mut cache = SighashCache::new(&spend_tx);
+    for (index, prevout) in prevouts.iter().enumerate() {
+        let sighash = cache
+            .taproot_key_spend_signature_hash(
+                index,
+                &Prevouts::All(&prevouts),
+                SchnorrSighashType::Default,
+            )
+            .unwrap();
+        let sig = bitcoin::secp256k1::schnorr::Signature::from_slice(
+            &spend_tx.input[index].witness.to_vec()[0],
+        )
+        .unwrap();
+        let output_key =
+            bitcoin::XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..]).unwrap();
+        secp.verify_schnorr(&sig, &Message::from_slice(&sighash).unwrap(), &output_key)
+            .unwrap();
+    }
+}
@@ -0,0 +1,331 @@
+use std::cmp;
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use hyper::client::connect::HttpConnector;
+use hyper::header::HeaderMap;
+use hyper::{Body, Client, Request, Response};
+use hyper_openssl::HttpsConnector;
+use tokio::time::Instant;
+use tonic::body::BoxBody;
+
+use crate::error::TransportError;
+
+/// Deadlines, keep-alive and retries of [`MyChannel`](crate::MyChannel)
+///
+/// The client reconnects on its own, a call that finds the node gone fails and the next one
+/// dials again. Read-only calls are sent again with exponential backoff, so a daemon survives
+/// the node restarting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransportConfig {
+    /// Deadline for the response of a call, calls set with `tonic::Request::set_timeout` use
+    /// their own
+    ///
+    /// It covers every attempt of a retried call together with the backoff in between. Only the
+    /// response headers are awaited, so streams stay open past it. `None` waits forever.
+    pub request_timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    /// Interval of the HTTP/2 pings that find a dead connection before a call does
+    pub keep_alive_interval: Option<Duration>,
+    /// How long a ping may go unanswered before the connection is dropped
+    pub keep_alive_timeout: Duration,
+    pub retry: RetryPolicy,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig {
+            request_timeout: None,
+            connect_timeout: Some(Duration::from_secs(10)),
+            keep_alive_interval: Some(Duration::from_secs(30)),
+            keep_alive_timeout: Duration::from_secs(10),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl TransportConfig {
+    pub(crate) fn client(
+        &self,
+        mut http: HttpConnector,
+        connector: openssl::ssl::SslConnectorBuilder,
+    ) -> Result<Client<HttpsConnector<HttpConnector>, BoxBody>, openssl::error::ErrorStack> {
+        http.set_connect_timeout(self.connect_timeout);
+        let https = HttpsConnector::with_connector(http, connector)?;
+        let mut builder = Client::builder();
+        builder
+            .http2_only(true)
+            .http2_keep_alive_interval(self.keep_alive_interval)
+            .http2_keep_alive_timeout(self.keep_alive_timeout)
+            .http2_keep_alive_while_idle(true);
+        Ok(builder.build(https))
+    }
+}
+
+/// How often and how fast failed idempotent calls are sent again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, `0` turns retrying off
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before retry number `attempt`, counting from zero
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        cmp::min(
+            self.initial_backoff.saturating_mul(factor),
+            self.max_backoff,
+        )
+    }
+
+    /// The wait before retry number `attempt` when `remaining` is left of the call's deadline
+    ///
+    /// `None` once the retries are used up or the retry would start after the deadline.
+    pub fn retry_after(&self, attempt: u32, remaining: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let backoff = self.backoff(attempt);
+        match remaining {
+            Some(remaining) if backoff >= remaining => None,
+            _ => Some(backoff),
+        }
+    }
+}
+
+/// Unary calls that only read state, by their full gRPC path
+///
+/// Anything not listed is sent once, so a method added to the protos is never retried until
+/// someone checked it here.
+const READ_ONLY_METHODS: &[&str] = &[
+    "/lnrpc.Lightning/WalletBalance",
+    "/lnrpc.Lightning/ChannelBalance",
+    "/lnrpc.Lightning/GetTransactions",
+    "/lnrpc.Lightning/EstimateFee",
+    "/lnrpc.Lightning/ListUnspent",
+    "/lnrpc.Lightning/VerifyMessage",
+    "/lnrpc.Lightning/ListPeers",
+    "/lnrpc.Lightning/GetInfo",
+    "/lnrpc.Lightning/GetRecoveryInfo",
+    "/lnrpc.Lightning/PendingChannels",
+    "/lnrpc.Lightning/ListChannels",
+    "/lnrpc.Lightning/ClosedChannels",
+    "/lnrpc.Lightning/ListInvoices",
+    "/lnrpc.Lightning/LookupInvoice",
+    "/lnrpc.Lightning/DecodePayReq",
+    "/lnrpc.Lightning/ListPayments",
+    "/lnrpc.Lightning/DescribeGraph",
+    "/lnrpc.Lightning/GetNodeMetrics",
+    "/lnrpc.Lightning/GetChanInfo",
+    "/lnrpc.Lightning/GetNodeInfo",
+    "/lnrpc.Lightning/QueryRoutes",
+    "/lnrpc.Lightning/GetNetworkInfo",
+    "/lnrpc.Lightning/FeeReport",
+    "/lnrpc.Lightning/ForwardingHistory",
+    "/lnrpc.Lightning/ExportChannelBackup",
+    "/lnrpc.Lightning/ExportAllChannelBackups",
+    "/lnrpc.Lightning/VerifyChanBackup",
+    "/lnrpc.Lightning/ListMacaroonIDs",
+    "/lnrpc.Lightning/ListPermissions",
+    "/lnrpc.Lightning/CheckMacaroonPermissions",
+    "/lnrpc.Lightning/ListAliases",
+    "/lnrpc.Lightning/LookupHtlc",
+    "/lnrpc.State/GetState",
+    "/autopilotrpc.Autopilot/Status",
+    "/autopilotrpc.Autopilot/QueryScores",
+    "/invoicesrpc.Invoices/LookupInvoiceV2",
+    "/neutrinorpc.NeutrinoKit/Status",
+    "/neutrinorpc.NeutrinoKit/IsBanned",
+    "/neutrinorpc.NeutrinoKit/GetBlockHeader",
+    "/neutrinorpc.NeutrinoKit/GetBlock",
+    "/neutrinorpc.NeutrinoKit/GetCFilter",
+    "/routerrpc.Router/QueryMissionControl",
+    "/routerrpc.Router/GetMissionControlConfig",
+    "/routerrpc.Router/QueryProbability",
+    "/routerrpc.Router/BuildRoute",
+    "/signrpc.Signer/VerifyMessage",
+    "/verrpc.Versioner/GetVersion",
+    "/walletrpc.WalletKit/ListUnspent",
+    "/walletrpc.WalletKit/ListLeases",
+    "/walletrpc.WalletKit/ListAccounts",
+    "/walletrpc.WalletKit/RequiredReserve",
+    "/walletrpc.WalletKit/EstimateFee",
+    "/walletrpc.WalletKit/PendingSweeps",
+    "/walletrpc.WalletKit/ListSweeps",
+    "/watchtowerrpc.Watchtower/GetInfo",
+    "/wtclientrpc.WatchtowerClient/ListTowers",
+    "/wtclientrpc.WatchtowerClient/GetTowerInfo",
+    "/wtclientrpc.WatchtowerClient/Stats",
+    "/wtclientrpc.WatchtowerClient/Policy",
+];
+
+/// Whether the gRPC method at `path` only reads state, so sending it twice is harmless
+///
+/// Only these calls are retried. Streaming subscriptions and anything that moves funds are not.
+pub fn is_idempotent(path: &str) -> bool {
+    READ_ONLY_METHODS.contains(&path)
+}
+
+/// The deadline a caller put on the request through the `grpc-timeout` header
+pub(crate) fn grpc_timeout(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get("grpc-timeout")?.to_str().ok()?;
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount = amount.parse::<u64>().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount.saturating_mul(3600))),
+        "M" => Some(Duration::from_secs(amount.saturating_mul(60))),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+pub(crate) async fn send(
+    client: Client<HttpsConnector<HttpConnector>, BoxBody>,
+    timeout: Option<Duration>,
+    request: Request<BoxBody>,
+) -> Result<Response<Body>, TransportError> {
+    let response = client.request(request);
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, response)
+            .await
+            .map_err(|_| TransportError::Timeout(timeout))?
+            .map_err(TransportError::from),
+        None => response.await.map_err(TransportError::from),
+    }
+}
+
+/// Buffers the request so it can be sent again after a transport failure
+///
+/// `timeout` is the budget of the whole call, each attempt gets what is left of it.
+pub(crate) async fn send_with_retry(
+    client: Client<HttpsConnector<HttpConnector>, BoxBody>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    request: Request<BoxBody>,
+) -> Result<Response<Body>, TransportError> {
+    let deadline = timeout.map(|timeout| (Instant::now() + timeout, timeout));
+    let (parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(TransportError::Body)?;
+    let mut attempt = 0;
+    loop {
+        let mut request = Request::new(
+            Body::from(body.clone())
+                .map_err(|error| tonic::Status::from_error(Box::new(error)))
+                .boxed_unsync(),
+        );
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = parts.uri.clone();
+        *request.version_mut() = parts.version;
+        *request.headers_mut() = parts.headers.clone();
+
+        let response = client.request(request);
+        let result = match deadline {
+            Some((deadline, timeout)) => match tokio::time::timeout_at(deadline, response).await {
+                Ok(response) => response.map_err(TransportError::from),
+                Err(_) => Err(TransportError::Timeout(timeout)),
+            },
+            None => response.await.map_err(TransportError::from),
+        };
+        let remaining =
+            deadline.map(|(deadline, _)| deadline.saturating_duration_since(Instant::now()));
+        match result {
+            Err(error) if error.is_retryable() => match retry.retry_after(attempt, remaining) {
+                Some(backoff) => {
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                None => return Err(error),
+            },
+            result => return result,
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn transport_times_out_and_retries_only_reads() {
+    use tokio::net::TcpListener;
+
+    use crate::{
+        lnrpc::{GetInfoRequest, NewAddressRequest},
+        LndClient, LndConnectConfig, TlsMode,
+    };
+
+    // the list is exact, a read-looking name isn't enough
+    assert!(is_idempotent("/lnrpc.Lightning/GetInfo"));
+    assert!(is_idempotent("/walletrpc.WalletKit/ListUnspent"));
+    assert!(!is_idempotent("/lnrpc.Lightning/SendCoins"));
+    assert!(!is_idempotent("/routerrpc.Router/EstimateRouteFee"));
+    assert!(!is_idempotent("/lnrpc.WalletUnlocker/GenSeed"));
+    assert!(!is_idempotent("/example.Service/GetOrCreate"));
+
+    let transport = TransportConfig {
+        request_timeout: Some(Duration::from_millis(100)),
+        retry: RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_millis(100),
+        },
+        ..Default::default()
+    };
+
+    // a node that accepts the connection and never answers, the clock is paused and only moves
+    // on to the next deadline, so every attempt takes exactly the request timeout
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port().into();
+    tokio::spawn(async move {
+        let mut open = vec![];
+        while let Ok((socket, _)) = listener.accept().await {
+            open.push(socket);
+        }
+    });
+    let config = LndConnectConfig::new("127.0.0.1", port, TlsMode::Insecure, vec![1])
+        .with_transport(transport.clone());
+    let mut client = LndClient::connect(&config).await.unwrap();
+    let started = Instant::now();
+    let status = client
+        .lightning
+        .new_address(NewAddressRequest::default())
+        .await
+        .unwrap_err();
+    assert!(status.message().contains("did not respond within"));
+    assert_eq!(started.elapsed(), Duration::from_millis(100));
+
+    // the timeout is the budget of the whole call, a read that used it up isn't sent again
+    let started = Instant::now();
+    let status = client
+        .lightning
+        .get_info(GetInfoRequest {})
+        .await
+        .unwrap_err();
+    assert!(status.message().contains("did not respond within 100ms"));
+    assert_eq!(started.elapsed(), Duration::from_millis(100));
+
+    // reads that fail fast are retried with 50 + 100 + 100ms of backoff while the budget lasts
+    let retry = &transport.retry;
+    assert_eq!(retry.retry_after(0, None), Some(Duration::from_millis(50)));
+    assert_eq!(retry.retry_after(2, None), Some(Duration::from_millis(100)));
+    assert_eq!(retry.retry_after(3, None), None);
+    assert_eq!(
+        retry.retry_after(1, Some(Duration::from_millis(150))),
+        Some(Duration::from_millis(100))
+    );
+    assert_eq!(retry.retry_after(1, Some(Duration::from_millis(100))), None);
+}